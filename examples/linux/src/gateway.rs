@@ -0,0 +1,54 @@
+//! Receives packets on a Raspberry Pi with an RFM69 on SPI0 CE0, RESET on GPIO 25
+//! and DIO0 on GPIO 24, and prints them.
+
+use embedded_hal_async::digital::Wait;
+use linux_embedded_hal::gpio_cdev::{Chip, LineRequestFlags};
+use linux_embedded_hal::spidev::{SpiModeFlags, SpidevOptions};
+use linux_embedded_hal::{CdevPin, Delay, SpidevDevice};
+
+use rfm69_rs::interrupt::RadioEvent;
+use rfm69_rs::rfm69::Rfm69;
+
+const RESET_LINE: u32 = 25;
+const DIO0_LINE: u32 = 24;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spi = SpidevDevice::open("/dev/spidev0.0")?;
+    let options = SpidevOptions::new()
+        .bits_per_word(8)
+        .max_speed_hz(1_000_000)
+        .mode(SpiModeFlags::SPI_MODE_0)
+        .build();
+    spi.configure(&options)?;
+
+    let mut chip = Chip::new("/dev/gpiochip0")?;
+    let reset = chip
+        .get_line(RESET_LINE)?
+        .request(LineRequestFlags::OUTPUT, 0, "rfm69-reset")?;
+    let dio0 = chip
+        .get_line(DIO0_LINE)?
+        .request(LineRequestFlags::INPUT, 0, "rfm69-dio0")?;
+
+    let mut rfm69 = Rfm69::new(spi, CdevPin::new(reset)?, CdevPin::new(dio0)?, Delay);
+
+    rfm69.init().await?;
+    println!("RFM69 {:?}", rfm69.chip_version());
+
+    rfm69.start_receive()?;
+    loop {
+        rfm69.intr_pin.wait_for_high().await?;
+        rfm69.on_interrupt()?;
+
+        match rfm69.poll_event() {
+            Some(RadioEvent::PacketReceived(packet)) => println!(
+                "From {}: {:02X?} ({:?} dBm)",
+                packet.header.from,
+                packet.payload(),
+                rfm69.packet_rssi()
+            ),
+            Some(event) => println!("{:?}", event),
+            None => {}
+        }
+    }
+}
@@ -14,6 +14,7 @@ use {defmt_rtt as _, panic_probe as _};
 use embassy_embedded_hal::shared_bus::blocking::spi::SpiDevice;
 
 use rfm69_rs::rfm69::Rfm69;
+use rfm69_rs::units::TxPowerDbm;
 use embassy_sync::blocking_mutex::{raw::NoopRawMutex, Mutex};
 
 
@@ -53,7 +54,7 @@ async fn main(_spawner: Spawner) {
     let mut rfm69 = Rfm69::new(spi_device, reset_pin, delay);
 
     rfm69.init().unwrap();
-    rfm69.set_tx_power(13).unwrap();
+    rfm69.set_tx_power(TxPowerDbm::new(13)).unwrap();
 
     let registers = rfm69.read_all_registers().unwrap();
     registers.iter().for_each(|register| {
@@ -0,0 +1,120 @@
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use crate::settings::RF69_FIFO_SIZE;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// A packet encoded by `Rfm69::prepare_beacon`, written to the FIFO as is by
+/// `Rfm69::send_beacon`. It holds the length byte, CRC and MAC of the packet
+/// format and keys at the time, prepare it again after changing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BeaconFrame {
+    pub(crate) fifo: [u8; RF69_FIFO_SIZE + 1],
+    pub(crate) length: usize,
+    pub(crate) airtime: u32,
+}
+
+impl BeaconFrame {
+    /// Time on air of the frame, preamble and sync word included.
+    pub fn airtime_ms(&self) -> u32 {
+        self.airtime
+    }
+}
+
+/// Fire-and-forget telemetry: sends a prepared frame at a fixed interval and
+/// never receives, the radio sleeps between the frames.
+///
+/// The frame comes from `Rfm69::prepare_beacon`, replace it with `set_frame`
+/// when the readings change.
+pub struct Beacon {
+    frame: BeaconFrame,
+    interval_ms: u32,
+}
+
+impl Beacon {
+    pub fn new(frame: BeaconFrame, interval_ms: u32) -> Self {
+        Beacon { frame, interval_ms }
+    }
+
+    pub fn frame(&self) -> &BeaconFrame {
+        &self.frame
+    }
+
+    pub fn set_frame(&mut self, frame: BeaconFrame) {
+        self.frame = frame;
+    }
+
+    pub fn interval_ms(&self) -> u32 {
+        self.interval_ms
+    }
+
+    pub fn set_interval_ms(&mut self, interval_ms: u32) {
+        self.interval_ms = interval_ms;
+    }
+
+    /// Sends the frame `count` times, `interval_ms` apart from the start of one
+    /// frame to the start of the next, and leaves the radio in Sleep.
+    pub async fn run<SPI, RESET, INTR, D>(
+        &self,
+        radio: &mut Rfm69<SPI, RESET, INTR, D>,
+        count: u32,
+    ) -> Result<(), Rfm69Error>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        for sent in 0..count {
+            if sent > 0 {
+                let idle_ms = self.interval_ms.saturating_sub(self.frame.airtime);
+                radio.delay.delay_ms(idle_ms).await;
+            }
+            radio.send_beacon(&self.frame).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::header::Header;
+    use crate::rfm69::Rfm69Mode;
+    use crate::sim::SimChannel;
+    use embassy_futures::join::join;
+
+    #[tokio::test]
+    async fn test_beacon() {
+        let channel = SimChannel::new(1);
+        let mut node = channel.radio();
+        let mut gateway = channel.radio();
+        node.init().await.unwrap();
+        gateway.init().await.unwrap();
+        node.sleep().await.unwrap();
+
+        let frame = node.prepare_beacon(Header::default(), b"21.5C").unwrap();
+        assert!(frame.airtime_ms() > 0);
+        let beacon = Beacon::new(frame, 1_000);
+
+        gateway.set_mode(Rfm69Mode::Rx).await.unwrap();
+        let receive = async {
+            let mut buffer = [0u8; 8];
+            for _ in 0..3 {
+                gateway.wait_for_message().await.unwrap();
+                let (_, length) = gateway.receive(&mut buffer).await.unwrap();
+                assert_eq!(&buffer[..length], b"21.5C");
+                gateway.set_mode(Rfm69Mode::Rx).await.unwrap();
+            }
+        };
+        let (_, result) = join(receive, beacon.run(&mut node, 3)).await;
+        result.unwrap();
+
+        assert_eq!(channel.transmissions(), 3);
+        assert_eq!(node.stats().packets_sent, 3);
+        assert_eq!(
+            node.prepare_beacon(Header::default(), &[0; 70]),
+            Err(Rfm69Error::MessageTooLarge)
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::test_pattern::Pn9;
+
+/// Payload of a BER test packet, the first bytes of the PN9 sequence.
+pub const BER_PACKET_LENGTH: usize = 32;
+/// RSSI bands of `BerReport::by_rssi`, 10 dB wide from -130 dBm up.
+pub const RSSI_BANDS: usize = 12;
+const RSSI_BAND_DB: i16 = 10;
+const LOWEST_RSSI_DBM: i16 = -130;
+
+/// Bits compared and bits received wrong.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct BitErrors {
+    pub bits: u64,
+    pub errors: u64,
+}
+
+impl BitErrors {
+    /// Bit error rate in errors per million bits, `None` before any bit was received.
+    pub fn ber_ppm(&self) -> Option<u64> {
+        (self.errors * 1_000_000).checked_div(self.bits)
+    }
+
+    fn add(&mut self, bits: u64, errors: u64) {
+        self.bits += bits;
+        self.errors += errors;
+    }
+}
+
+/// Outcome of `Rfm69::receive_ber`.
+///
+/// Only packets whose sync word was detected are counted, compare `packets` to
+/// the number of packets sent for the packet error rate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct BerReport {
+    pub packets: u32,
+    pub total: BitErrors,
+    /// Bit errors by the RSSI of the packet, see `rssi_band`.
+    pub by_rssi: [BitErrors; RSSI_BANDS],
+}
+
+impl BerReport {
+    /// Index in `by_rssi` of the band containing `rssi_dbm`. The lowest and
+    /// highest bands also hold the RSSI below and above the range.
+    pub fn rssi_band(rssi_dbm: i16) -> usize {
+        let band = (rssi_dbm - LOWEST_RSSI_DBM).div_euclid(RSSI_BAND_DB);
+        band.clamp(0, RSSI_BANDS as i16 - 1) as usize
+    }
+
+    /// Lowest RSSI of the band `index` in dBm.
+    pub fn band_rssi_dbm(index: usize) -> i16 {
+        LOWEST_RSSI_DBM + index as i16 * RSSI_BAND_DB
+    }
+
+    /// Compares a received `packet` with the PN9 sequence.
+    pub(crate) fn record(&mut self, rssi_dbm: i16, packet: &[u8]) {
+        let errors = packet
+            .iter()
+            .zip(Pn9::new())
+            .map(|(received, expected)| (received ^ expected).count_ones() as u64)
+            .sum();
+        let bits = packet.len() as u64 * 8;
+        self.packets += 1;
+        self.total.add(bits, errors);
+        self.by_rssi[Self::rssi_band(rssi_dbm)].add(bits, errors);
+    }
+}
+
+/// The payload sent by `Rfm69::transmit_ber`.
+pub(crate) fn ber_packet() -> [u8; BER_PACKET_LENGTH] {
+    let mut pn9 = Pn9::new();
+    core::array::from_fn(|_| pn9.next().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut report = BerReport::default();
+        let mut packet = ber_packet();
+        report.record(-90, &packet);
+        packet[0] ^= 0x81;
+        packet[31] ^= 0x10;
+        report.record(-135, &packet);
+
+        assert_eq!(report.packets, 2);
+        assert_eq!(report.total.bits, 512);
+        assert_eq!(report.total.errors, 3);
+        assert_eq!(report.total.ber_ppm(), Some(5_859));
+        assert_eq!(
+            report.by_rssi[4],
+            BitErrors {
+                bits: 256,
+                errors: 0
+            }
+        );
+        assert_eq!(report.by_rssi[0].errors, 3);
+        assert_eq!(BitErrors::default().ber_ppm(), None);
+    }
+
+    #[test]
+    fn test_rssi_band() {
+        assert_eq!(BerReport::rssi_band(-130), 0);
+        assert_eq!(BerReport::rssi_band(-121), 0);
+        assert_eq!(BerReport::rssi_band(-120), 1);
+        assert_eq!(BerReport::rssi_band(-150), 0);
+        assert_eq!(BerReport::rssi_band(0), RSSI_BANDS - 1);
+        assert_eq!(BerReport::band_rssi_dbm(4), -90);
+    }
+}
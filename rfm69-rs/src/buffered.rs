@@ -0,0 +1,143 @@
+use crate::interrupt::{RadioEvent, ReceivedPacket};
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+use heapless::Deque;
+
+/// Receiver that copies every packet out of the FIFO as soon as DIO0 signals
+/// PayloadReady, and queues up to `N` of them until the application reads them.
+///
+/// Without it, a packet arriving while the previous one still sits in the FIFO
+/// is lost. Call `on_interrupt` from the DIO0 interrupt handler and `pop` from
+/// the application, or use `receive` when the radio isn't shared with an
+/// interrupt handler.
+pub struct BufferedReceiver<SPI, RESET, INTR, D, const N: usize> {
+    radio: Rfm69<SPI, RESET, INTR, D>,
+    queue: Deque<ReceivedPacket, N>,
+    dropped: u32,
+}
+
+impl<SPI, RESET, INTR, D, const N: usize> BufferedReceiver<SPI, RESET, INTR, D, N>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    /// Puts the radio in Rx, see `Rfm69::start_receive`.
+    pub fn new(mut radio: Rfm69<SPI, RESET, INTR, D>) -> Result<Self, Rfm69Error> {
+        radio.start_receive()?;
+        Ok(BufferedReceiver {
+            radio,
+            queue: Deque::new(),
+            dropped: 0,
+        })
+    }
+
+    /// Returns the radio, still in Rx, and drops the queued packets.
+    pub fn release(self) -> Rfm69<SPI, RESET, INTR, D> {
+        self.radio
+    }
+
+    /// Reads the packet signalled by DIO0 into the queue, to be called from the
+    /// interrupt handler. A packet arriving while the queue is full is dropped.
+    pub fn on_interrupt(&mut self) -> Result<(), Rfm69Error> {
+        self.radio.on_interrupt()?;
+        if let Some(RadioEvent::PacketReceived(packet)) = self.radio.poll_event() {
+            if self.queue.push_back(packet).is_err() {
+                self.dropped = self.dropped.saturating_add(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the oldest queued packet.
+    pub fn pop(&mut self) -> Option<ReceivedPacket> {
+        self.queue.pop_front()
+    }
+
+    /// Waits for DIO0 until a packet is queued, then takes the oldest one.
+    pub async fn receive(&mut self) -> Result<ReceivedPacket, Rfm69Error> {
+        loop {
+            if let Some(packet) = self.queue.pop_front() {
+                return Ok(packet);
+            }
+            self.radio.wait_for_interrupt().await?;
+            self.on_interrupt()?;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Packets dropped because the queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registers::Register;
+    use crate::test_utils::{
+        check_expectations, read_many, read_register, setup_rfm, write_register, GpioTransaction,
+        SpiTransaction, State,
+    };
+
+    // PayloadReady, one byte of payload, then the receiver is restarted
+    fn packet(from: u8, payload: u8) -> Vec<SpiTransaction<u8>> {
+        [
+            read_register(Register::IrqFlags2, 0x06),
+            read_register(Register::Fifo, 5),
+            read_many(Register::Fifo, &[0x01, from, 0x00, 0x00, payload]),
+            read_register(Register::PacketConfig2, 0x02),
+            write_register(Register::PacketConfig2, 0x06),
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let spi_expectations = [
+            write_register(Register::DioMapping1, 0x40).to_vec(),
+            write_register(Register::OpMode, 0x10).to_vec(),
+            packet(0x02, 0xA1),
+            packet(0x03, 0xA2),
+            packet(0x04, 0xA3),
+            packet(0x05, 0xA4),
+        ]
+        .concat();
+        let mut radio = setup_rfm();
+        radio.spi.update_expectations(&spi_expectations);
+        radio
+            .intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+        let mut receiver = BufferedReceiver::<_, _, _, _, 2>::new(radio).unwrap();
+
+        // Three packets back to back, the last one doesn't fit
+        for _ in 0..3 {
+            receiver.on_interrupt().unwrap();
+        }
+        assert_eq!(receiver.len(), 2);
+        assert_eq!(receiver.dropped(), 1);
+
+        let first = receiver.pop().unwrap();
+        assert_eq!((first.header.from, first.payload()), (0x02, &[0xA1][..]));
+        let second = receiver.receive().await.unwrap();
+        assert_eq!((second.header.from, second.payload()), (0x03, &[0xA2][..]));
+
+        // The queue is empty, wait for DIO0
+        let third = receiver.receive().await.unwrap();
+        assert_eq!((third.header.from, third.payload()), (0x05, &[0xA4][..]));
+        assert!(receiver.is_empty());
+
+        check_expectations(&mut receiver.release());
+    }
+}
@@ -0,0 +1,644 @@
+use crate::crc::Crc32;
+use crate::read_write::ReadWrite;
+use crate::reliable::ReliableDatagram;
+use crate::rfm69::Rfm69Error;
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+const OP_MANIFEST: u8 = 0x11;
+const OP_CHUNK: u8 = 0x12;
+const OP_STATUS: u8 = 0x13;
+const OP_COMMIT: u8 = 0x14;
+
+// Opcode, transfer id, chunk index and chunk CRC in front of every chunk
+const CHUNK_HEADER_LENGTH: usize = 9;
+/// Blob bytes carried by a single chunk.
+pub const CHUNK_SIZE: usize = RF69_MAX_MESSAGE_LEN - CHUNK_HEADER_LENGTH;
+// Chunks covered by the missing chunk bitmap of a status response
+const STATUS_WINDOW: usize = 256;
+// Opcode, status and first missing chunk in front of the bitmap
+const RESPONSE_HEADER_LENGTH: usize = 4;
+/// Maximum length of a response, a status response with its bitmap.
+pub const RESPONSE_LENGTH: usize = RESPONSE_HEADER_LENGTH + STATUS_WINDOW / 8;
+// Status requests answered with missing chunks before `send_blob` gives up
+const MAX_REPAIR_ROUNDS: u8 = 16;
+// Block size used to read the blob back for the CRC check
+const VERIFY_BLOCK_SIZE: usize = 64;
+
+/// Answer of the receiving node to a bulk transfer message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BulkStatus {
+    Ok = 0x00,
+    /// Chunks are missing, the sender repeats the ones marked in the response.
+    Incomplete = 0x01,
+    /// The stored blob doesn't match the CRC of the manifest.
+    CrcMismatch = 0x02,
+    /// The blob doesn't fit, or the store failed to write it.
+    StoreError = 0x03,
+    /// The message belongs to another transfer than the current one.
+    UnknownTransfer = 0x04,
+    /// The message is not a valid bulk transfer message.
+    Malformed = 0x05,
+}
+
+impl BulkStatus {
+    fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(BulkStatus::Ok),
+            0x01 => Some(BulkStatus::Incomplete),
+            0x02 => Some(BulkStatus::CrcMismatch),
+            0x03 => Some(BulkStatus::StoreError),
+            0x04 => Some(BulkStatus::UnknownTransfer),
+            0x05 => Some(BulkStatus::Malformed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, defmt::Format)]
+pub enum BulkError {
+    Radio(Rfm69Error),
+    /// The receiving node refused the transfer.
+    Rejected(BulkStatus),
+    /// The response was not a valid bulk transfer response.
+    InvalidResponse,
+    /// Chunks were still missing after the last repair round.
+    Incomplete,
+}
+
+impl From<Rfm69Error> for BulkError {
+    fn from(error: Rfm69Error) -> Self {
+        BulkError::Radio(error)
+    }
+}
+
+/// Storage for a received blob.
+pub trait BlobStore {
+    type Error;
+
+    /// Prepares to store a blob of `size` bytes with the CRC-32 `crc`. Returns
+    /// the number of bytes at the start already stored by an interrupted
+    /// transfer of the same blob, or 0 to start over.
+    fn begin(&mut self, size: u32, crc: u32) -> Result<u32, Self::Error>;
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Called once the whole blob is stored and its CRC verified.
+    fn finish(&mut self) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+struct Manifest {
+    id: u16,
+    size: u32,
+    crc: u32,
+}
+
+impl Manifest {
+    fn chunk_count(&self) -> usize {
+        (self.size as usize).div_ceil(CHUNK_SIZE)
+    }
+}
+
+/// Receiving side of a bulk transfer, writing the blob to a `BlobStore`.
+///
+/// A transfer starts with a manifest of the transfer id, the blob size and its
+/// CRC. The chunks follow without acknowledgements, each with its own CRC so a
+/// corrupted chunk is dropped even with the radio CRC disabled. The sender then
+/// asks for the missing chunks and repeats them until none is left, and commits
+/// the transfer with a CRC check of the stored blob.
+///
+/// Received chunks are tracked in a bitmap of `N` bytes, so a blob has at most
+/// `8 * N` chunks. Sending the same manifest again resumes the transfer.
+pub struct BulkReceiver<S, const N: usize> {
+    store: S,
+    manifest: Option<Manifest>,
+    received: [u8; N],
+    complete: bool,
+}
+
+impl<S: BlobStore, const N: usize> BulkReceiver<S, N> {
+    pub fn new(store: S) -> Self {
+        BulkReceiver {
+            store,
+            manifest: None,
+            received: [0; N],
+            complete: false,
+        }
+    }
+
+    pub fn store(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn release(self) -> S {
+        self.store
+    }
+
+    /// True once a blob has been stored and verified.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Chunks received and total chunks of the transfer in progress.
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        let manifest = self.manifest?;
+        let received = (0..manifest.chunk_count())
+            .filter(|&index| self.is_received(index))
+            .count();
+        Some((received, manifest.chunk_count()))
+    }
+
+    /// Handles the bulk transfer message `request` and writes the response into
+    /// `response`, returning its length. Chunks are not answered and return 0.
+    pub fn handle(&mut self, request: &[u8], response: &mut [u8; RESPONSE_LENGTH]) -> usize {
+        let op = request.first().copied().unwrap_or(0);
+        if op == OP_CHUNK {
+            self.chunk(&request[1..]);
+            return 0;
+        }
+
+        let mut length = RESPONSE_HEADER_LENGTH;
+        let status = match op {
+            OP_MANIFEST => self.begin(&request[1..]),
+            OP_STATUS => match self.status(&request[1..], &mut response[length..]) {
+                Ok(status) => {
+                    length = RESPONSE_LENGTH;
+                    status
+                }
+                Err(status) => status,
+            },
+            OP_COMMIT => self.commit(&request[1..]),
+            _ => BulkStatus::Malformed,
+        };
+
+        response[0] = op;
+        response[1] = status as u8;
+        response[2..4].copy_from_slice(&(self.first_missing() as u16).to_be_bytes());
+        length
+    }
+
+    /// Receives the next bulk transfer message through `network` and answers it.
+    /// Returns true once the blob has been stored and verified.
+    pub async fn receive<SPI, RESET, INTR, D>(
+        &mut self,
+        network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    ) -> Result<bool, Rfm69Error>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+        let request = network.receive_request(&mut buffer).await?;
+
+        let mut response = [0u8; RESPONSE_LENGTH];
+        let length = self.handle(&buffer[..request.length], &mut response);
+        network.respond(&request, &response[..length]).await?;
+        Ok(self.complete)
+    }
+
+    fn is_received(&self, index: usize) -> bool {
+        self.received[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn set_received(&mut self, index: usize) {
+        self.received[index / 8] |= 1 << (index % 8);
+    }
+
+    fn first_missing(&self) -> usize {
+        self.manifest.map_or(0, |manifest| {
+            (0..manifest.chunk_count())
+                .find(|&index| !self.is_received(index))
+                .unwrap_or(manifest.chunk_count())
+        })
+    }
+
+    // The transfer in progress, if `data` starts with its id
+    fn transfer(&self, data: &[u8]) -> Result<Manifest, BulkStatus> {
+        let id = read_u16(data, 0).ok_or(BulkStatus::Malformed)?;
+        self.manifest
+            .filter(|manifest| manifest.id == id)
+            .ok_or(BulkStatus::UnknownTransfer)
+    }
+
+    fn begin(&mut self, data: &[u8]) -> BulkStatus {
+        let (Some(id), Some(size), Some(crc)) =
+            (read_u16(data, 0), read_u32(data, 2), read_u32(data, 6))
+        else {
+            return BulkStatus::Malformed;
+        };
+        let manifest = Manifest { id, size, crc };
+
+        self.complete = false;
+        if self.manifest == Some(manifest) {
+            return BulkStatus::Ok;
+        }
+
+        self.manifest = None;
+        self.received = [0; N];
+        if manifest.chunk_count() > 8 * N {
+            return BulkStatus::StoreError;
+        }
+        match self.store.begin(size, crc) {
+            Ok(stored) => {
+                for index in 0..(stored.min(size) as usize / CHUNK_SIZE) {
+                    self.set_received(index);
+                }
+                self.manifest = Some(manifest);
+                BulkStatus::Ok
+            }
+            Err(_) => BulkStatus::StoreError,
+        }
+    }
+
+    // Corrupted or unexpected chunks are dropped, the sender repeats them
+    fn chunk(&mut self, data: &[u8]) {
+        let Ok(manifest) = self.transfer(data) else {
+            return;
+        };
+        let (Some(index), Some(crc)) = (read_u16(data, 2), read_u32(data, 4)) else {
+            return;
+        };
+        let index = index as usize;
+        let chunk = &data[CHUNK_HEADER_LENGTH - 1..];
+
+        let offset = index * CHUNK_SIZE;
+        let expected_length = (manifest.size as usize)
+            .saturating_sub(offset)
+            .min(CHUNK_SIZE);
+        if index >= manifest.chunk_count()
+            || self.is_received(index)
+            || chunk.len() != expected_length
+            || Crc32::checksum(chunk) != crc
+        {
+            return;
+        }
+        if self.store.write(offset as u32, chunk).is_ok() {
+            self.set_received(index);
+        }
+    }
+
+    // Writes the bitmap of missing chunks from the first missing one
+    fn status(&mut self, data: &[u8], bitmap: &mut [u8]) -> Result<BulkStatus, BulkStatus> {
+        let manifest = self.transfer(data)?;
+        let first_missing = self.first_missing();
+
+        bitmap.fill(0);
+        for bit in 0..STATUS_WINDOW {
+            let index = first_missing + bit;
+            if index < manifest.chunk_count() && !self.is_received(index) {
+                bitmap[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+
+        match first_missing == manifest.chunk_count() {
+            true => Ok(BulkStatus::Ok),
+            false => Ok(BulkStatus::Incomplete),
+        }
+    }
+
+    fn commit(&mut self, data: &[u8]) -> BulkStatus {
+        let manifest = match self.transfer(data) {
+            Ok(manifest) => manifest,
+            // The response to the last commit was lost
+            Err(BulkStatus::UnknownTransfer) if self.complete => return BulkStatus::Ok,
+            Err(status) => return status,
+        };
+        if self.first_missing() != manifest.chunk_count() {
+            return BulkStatus::Incomplete;
+        }
+
+        let mut crc = Crc32::new();
+        let mut block = [0u8; VERIFY_BLOCK_SIZE];
+        let mut offset = 0;
+        while offset < manifest.size {
+            let length = (manifest.size - offset).min(VERIFY_BLOCK_SIZE as u32) as usize;
+            if self.store.read(offset, &mut block[..length]).is_err() {
+                return BulkStatus::StoreError;
+            }
+            crc.update(&block[..length]);
+            offset += length as u32;
+        }
+
+        // Start over on a mismatch, resuming would keep the corrupted bytes
+        self.manifest = None;
+        self.received = [0; N];
+        if crc.finish() != manifest.crc {
+            return BulkStatus::CrcMismatch;
+        }
+        if self.store.finish().is_err() {
+            return BulkStatus::StoreError;
+        }
+
+        self.complete = true;
+        BulkStatus::Ok
+    }
+}
+
+/// Sends `blob` to the node `to` running a `BulkReceiver`, as transfer `id`.
+///
+/// When the transfer fails, e.g. with `Rfm69Error::AckTimeout`, calling it
+/// again with the same id and blob resumes where the receiver stopped.
+pub async fn send_blob<SPI, RESET, INTR, D>(
+    network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    to: u8,
+    id: u16,
+    blob: &[u8],
+) -> Result<(), BulkError>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let chunk_count = blob.len().div_ceil(CHUNK_SIZE);
+    let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+    let mut response = [0u8; RESPONSE_LENGTH];
+    message[1..3].copy_from_slice(&id.to_be_bytes());
+
+    message[0] = OP_MANIFEST;
+    message[3..7].copy_from_slice(&(blob.len() as u32).to_be_bytes());
+    message[7..11].copy_from_slice(&Crc32::checksum(blob).to_be_bytes());
+    let (_, first_missing) = exchange(network, to, &message[..11], &mut response).await?;
+    for index in first_missing..chunk_count {
+        send_chunk(network, to, id, blob, index).await?;
+    }
+
+    for _ in 0..MAX_REPAIR_ROUNDS {
+        message[0] = OP_STATUS;
+        let (status, first_missing) = exchange(network, to, &message[..3], &mut response).await?;
+        if status == BulkStatus::Incomplete {
+            let bitmap = &response[RESPONSE_HEADER_LENGTH..];
+            for bit in 0..STATUS_WINDOW {
+                if bitmap[bit / 8] & (1 << (bit % 8)) != 0 {
+                    send_chunk(network, to, id, blob, first_missing + bit).await?;
+                }
+            }
+            continue;
+        }
+
+        message[0] = OP_COMMIT;
+        match exchange(network, to, &message[..3], &mut response).await? {
+            (BulkStatus::Ok, _) => return Ok(()),
+            // Chunks went missing since the status, e.g. the receiver restarted
+            _ => continue,
+        }
+    }
+
+    Err(BulkError::Incomplete)
+}
+
+async fn send_chunk<SPI, RESET, INTR, D>(
+    network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    to: u8,
+    id: u16,
+    blob: &[u8],
+    index: usize,
+) -> Result<(), BulkError>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let start = index * CHUNK_SIZE;
+    let Some(chunk) = blob.get(start..(start + CHUNK_SIZE).min(blob.len())) else {
+        return Err(BulkError::InvalidResponse);
+    };
+
+    let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+    message[0] = OP_CHUNK;
+    message[1..3].copy_from_slice(&id.to_be_bytes());
+    message[3..5].copy_from_slice(&(index as u16).to_be_bytes());
+    message[5..9].copy_from_slice(&Crc32::checksum(chunk).to_be_bytes());
+    message[CHUNK_HEADER_LENGTH..][..chunk.len()].copy_from_slice(chunk);
+
+    let length = CHUNK_HEADER_LENGTH + chunk.len();
+    network.send_to_no_ack(to, &message[..length]).await?;
+    Ok(())
+}
+
+// Sends a bulk transfer message and returns the status and the first chunk
+// missing at the receiver
+async fn exchange<SPI, RESET, INTR, D>(
+    network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    to: u8,
+    message: &[u8],
+    response: &mut [u8; RESPONSE_LENGTH],
+) -> Result<(BulkStatus, usize), BulkError>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let length = network.send_to_wait_response(to, message, response).await?;
+    if length < RESPONSE_HEADER_LENGTH || response[0] != message[0] {
+        return Err(BulkError::InvalidResponse);
+    }
+    if message[0] == OP_STATUS && length != RESPONSE_LENGTH {
+        return Err(BulkError::InvalidResponse);
+    }
+
+    let first_missing = read_u16(&response[..], 2).ok_or(BulkError::InvalidResponse)? as usize;
+    match BulkStatus::from_value(response[1]) {
+        Some(status @ (BulkStatus::Ok | BulkStatus::Incomplete)) => Ok((status, first_missing)),
+        Some(status) => Err(BulkError::Rejected(status)),
+        None => Err(BulkError::InvalidResponse),
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct RamStore {
+        blob: [u8; 256],
+        stored: u32,
+        finished: bool,
+    }
+
+    impl BlobStore for RamStore {
+        type Error = ();
+
+        fn begin(&mut self, size: u32, _crc: u32) -> Result<u32, ()> {
+            match size as usize <= self.blob.len() {
+                true => Ok(self.stored),
+                false => Err(()),
+            }
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            self.blob[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            buffer.copy_from_slice(&self.blob[offset..offset + buffer.len()]);
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), ()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    fn receiver() -> BulkReceiver<RamStore, 1> {
+        BulkReceiver::new(RamStore {
+            blob: [0; 256],
+            stored: 0,
+            finished: false,
+        })
+    }
+
+    // 4 chunks, the last one is short
+    fn blob() -> [u8; 160] {
+        core::array::from_fn(|i| i as u8)
+    }
+
+    fn manifest(id: u16, blob: &[u8]) -> Vec<u8> {
+        let mut message = vec![OP_MANIFEST];
+        message.extend_from_slice(&id.to_be_bytes());
+        message.extend_from_slice(&(blob.len() as u32).to_be_bytes());
+        message.extend_from_slice(&Crc32::checksum(blob).to_be_bytes());
+        message
+    }
+
+    fn chunk(blob: &[u8], index: usize) -> Vec<u8> {
+        let start = index * CHUNK_SIZE;
+        let data = &blob[start..(start + CHUNK_SIZE).min(blob.len())];
+        let mut message = vec![OP_CHUNK, 0x00, 0x01];
+        message.extend_from_slice(&(index as u16).to_be_bytes());
+        message.extend_from_slice(&Crc32::checksum(data).to_be_bytes());
+        message.extend_from_slice(data);
+        message
+    }
+
+    // Status and first missing chunk, and the missing chunk bitmap of a status response
+    fn handle(receiver: &mut BulkReceiver<RamStore, 1>, request: &[u8]) -> (BulkStatus, u16, u8) {
+        let mut response = [0u8; RESPONSE_LENGTH];
+        let length = receiver.handle(request, &mut response);
+        assert_eq!(response[0], request[0]);
+        let bitmap = match length {
+            RESPONSE_LENGTH => response[RESPONSE_HEADER_LENGTH],
+            _ => 0,
+        };
+        (
+            BulkStatus::from_value(response[1]).unwrap(),
+            read_u16(&response, 2).unwrap(),
+            bitmap,
+        )
+    }
+
+    #[test]
+    fn test_repaired_transfer() {
+        let blob = blob();
+        let mut receiver = receiver();
+
+        assert_eq!(
+            handle(&mut receiver, &manifest(1, &blob)),
+            (BulkStatus::Ok, 0, 0)
+        );
+
+        // Chunk 1 is lost and chunk 2 is corrupted
+        let mut response = [0u8; RESPONSE_LENGTH];
+        let mut corrupted = chunk(&blob, 2);
+        corrupted[20] ^= 0x01;
+        for request in [chunk(&blob, 0), corrupted, chunk(&blob, 3)] {
+            assert_eq!(receiver.handle(&request, &mut response), 0);
+        }
+        assert_eq!(receiver.progress(), Some((2, 4)));
+
+        let status = [OP_STATUS, 0x00, 0x01];
+        assert_eq!(
+            handle(&mut receiver, &status),
+            (BulkStatus::Incomplete, 1, 0b011)
+        );
+        assert_eq!(
+            handle(&mut receiver, &[OP_COMMIT, 0x00, 0x01]),
+            (BulkStatus::Incomplete, 1, 0)
+        );
+
+        receiver.handle(&chunk(&blob, 2), &mut response);
+        assert_eq!(
+            handle(&mut receiver, &status),
+            (BulkStatus::Incomplete, 1, 0b001)
+        );
+        receiver.handle(&chunk(&blob, 1), &mut response);
+        assert_eq!(handle(&mut receiver, &status), (BulkStatus::Ok, 4, 0));
+
+        assert_eq!(
+            handle(&mut receiver, &[OP_COMMIT, 0x00, 0x01]),
+            (BulkStatus::Ok, 0, 0)
+        );
+        assert!(receiver.is_complete());
+        assert!(receiver.store().finished);
+        assert_eq!(receiver.store().blob[..160], blob);
+        // Lost response to the commit
+        assert_eq!(
+            handle(&mut receiver, &[OP_COMMIT, 0x00, 0x01]),
+            (BulkStatus::Ok, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_resume_from_store() {
+        let blob = blob();
+        let mut receiver = receiver();
+        receiver.store().blob[..60].copy_from_slice(&blob[..60]);
+        receiver.store().stored = 60;
+
+        // Only the first chunk is complete
+        assert_eq!(
+            handle(&mut receiver, &manifest(1, &blob)),
+            (BulkStatus::Ok, 1, 0)
+        );
+    }
+
+    #[test]
+    fn test_rejected_transfer() {
+        let blob = blob();
+        let mut receiver = receiver();
+
+        assert_eq!(
+            handle(&mut receiver, &[OP_STATUS, 0x00, 0x01]),
+            (BulkStatus::UnknownTransfer, 0, 0)
+        );
+        assert_eq!(
+            handle(&mut receiver, &[OP_MANIFEST, 0x00]),
+            (BulkStatus::Malformed, 0, 0)
+        );
+        // More chunks than the bitmap tracks
+        assert_eq!(
+            handle(&mut receiver, &manifest(1, &[0; 8 * CHUNK_SIZE + 1])),
+            (BulkStatus::StoreError, 0, 0)
+        );
+
+        assert_eq!(
+            handle(&mut receiver, &manifest(1, &blob)),
+            (BulkStatus::Ok, 0, 0)
+        );
+        assert_eq!(
+            handle(&mut receiver, &[OP_STATUS, 0x00, 0x02]),
+            (BulkStatus::UnknownTransfer, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+/// CRC-32 as used by zlib and Ethernet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Crc32(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.finish()
+    }
+}
+
+/// MSB first CRC-16 without final XOR, the variants differ in `polynomial` and `init`.
+pub fn crc16(polynomial: u16, init: u16, data: &[u8]) -> u16 {
+    data.iter().fold(init, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => crc << 1 ^ polynomial,
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(Crc32::checksum(b"123456789"), 0xCBF4_3926);
+        assert_eq!(Crc32::checksum(&[]), 0);
+    }
+
+    #[test]
+    fn test_crc16() {
+        // CRC-16/CCITT-FALSE
+        assert_eq!(crc16(0x1021, 0xFFFF, b"123456789"), 0x29B1);
+        assert_eq!(crc16(0x1021, 0xFFFF, &[]), 0xFFFF);
+    }
+}
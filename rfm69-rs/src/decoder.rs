@@ -0,0 +1,310 @@
+/// Longest message a decoder can return.
+pub const MAX_MESSAGE_BITS: usize = 128;
+
+/// The carrier on for `high_us`, then off for `low_us`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Pulse {
+    pub high_us: u32,
+    pub low_us: u32,
+}
+
+/// Bits decoded from a pulse train, MSB first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Message {
+    pub protocol: &'static str,
+    pub bit_count: usize,
+    bits: [u8; MAX_MESSAGE_BITS / 8],
+}
+
+impl Message {
+    fn new(protocol: &'static str) -> Self {
+        Message {
+            protocol,
+            bit_count: 0,
+            bits: [0; MAX_MESSAGE_BITS / 8],
+        }
+    }
+
+    fn push(&mut self, one: bool) {
+        if one {
+            self.bits[self.bit_count / 8] |= 0x80 >> (self.bit_count % 8);
+        }
+        self.bit_count += 1;
+    }
+
+    /// The message bits, the last byte padded with zeros.
+    pub fn bits(&self) -> &[u8] {
+        &self.bits[..self.bit_count.div_ceil(8)]
+    }
+
+    /// `count` bits from bit `start` as a number, the first bit most significant.
+    pub fn value(&self, start: usize, count: usize) -> u64 {
+        (start..start + count.min(64)).fold(0, |value, bit| {
+            let one = bit < self.bit_count && self.bits[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            value << 1 | one as u64
+        })
+    }
+}
+
+/// A protocol decoder, run on every captured pulse train by `decode`.
+pub trait Decoder {
+    /// Returns the first message of the protocol found in `pulses`.
+    fn decode(&self, pulses: &[Pulse]) -> Option<Message>;
+}
+
+/// Runs `decoders` on `pulses` in order and returns the first message found.
+pub fn decode(decoders: &[&dyn Decoder], pulses: &[Pulse]) -> Option<Message> {
+    decoders.iter().find_map(|decoder| decoder.decode(pulses))
+}
+
+/// Turns the demodulated signal sampled every `sample_us`, MSB first, into
+/// pulses, e.g. bytes received in OOK unlimited length mode with the bitrate as
+/// the sample rate. Leading silence is skipped. Returns the number of pulses
+/// written to `pulses`.
+pub fn pulses_from_samples(samples: &[u8], sample_us: u32, pulses: &mut [Pulse]) -> usize {
+    let mut count = 0;
+    let mut high = 0;
+    let mut low = 0;
+    let bits = samples
+        .iter()
+        .flat_map(|byte| (0..8).map(move |bit| byte & (0x80 >> bit) != 0));
+    for on in bits {
+        match on {
+            true if low > 0 => {
+                if count == pulses.len() {
+                    return count;
+                }
+                pulses[count] = Pulse {
+                    high_us: high * sample_us,
+                    low_us: low * sample_us,
+                };
+                count += 1;
+                (high, low) = (1, 0);
+            }
+            true => high += 1,
+            false if high > 0 => low += 1,
+            false => {}
+        }
+    }
+
+    if high > 0 && count < pulses.len() {
+        pulses[count] = Pulse {
+            high_us: high * sample_us,
+            low_us: low * sample_us,
+        };
+        count += 1;
+    }
+    count
+}
+
+// Within 50% of `nominal_us`
+fn matches(duration_us: u32, nominal_us: u32) -> bool {
+    duration_us >= nominal_us / 2 && duration_us <= nominal_us + nominal_us / 2
+}
+
+/// Pulse width modulation: a short pulse and a long gap for 0, a long pulse and
+/// a short gap for 1. A gap longer than a bit ends the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PwmDecoder {
+    pub protocol: &'static str,
+    pub short_us: u32,
+    pub long_us: u32,
+    pub bit_count: usize,
+}
+
+impl Decoder for PwmDecoder {
+    fn decode(&self, pulses: &[Pulse]) -> Option<Message> {
+        let threshold_us = (self.short_us + self.long_us) / 2;
+        let end_gap_us = self.short_us + self.long_us * 2;
+
+        let mut message = Message::new(self.protocol);
+        for pulse in pulses {
+            let one = pulse.high_us > threshold_us;
+            let (high_us, low_us) = match one {
+                true => (self.long_us, self.short_us),
+                false => (self.short_us, self.long_us),
+            };
+            let low_matches = matches(pulse.low_us, low_us) || pulse.low_us > end_gap_us;
+            if matches(pulse.high_us, high_us) && low_matches {
+                message.push(one);
+            } else {
+                message = Message::new(self.protocol);
+                continue;
+            }
+
+            if pulse.low_us > end_gap_us || message.bit_count == self.bit_count {
+                if message.bit_count == self.bit_count {
+                    return Some(message);
+                }
+                message = Message::new(self.protocol);
+            }
+        }
+        None
+    }
+}
+
+/// Pulse position modulation: pulses of a fixed length, the gap after them
+/// carries the bit. A gap longer than a 1 ends the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PpmDecoder {
+    pub protocol: &'static str,
+    pub pulse_us: u32,
+    pub zero_gap_us: u32,
+    pub one_gap_us: u32,
+    pub bit_count: usize,
+}
+
+impl Decoder for PpmDecoder {
+    fn decode(&self, pulses: &[Pulse]) -> Option<Message> {
+        let threshold_us = (self.zero_gap_us + self.one_gap_us) / 2;
+
+        let mut message = Message::new(self.protocol);
+        for pulse in pulses {
+            let gap_is_bit =
+                matches(pulse.low_us, self.zero_gap_us) || matches(pulse.low_us, self.one_gap_us);
+            if !matches(pulse.high_us, self.pulse_us) {
+                message = Message::new(self.protocol);
+            } else if gap_is_bit && message.bit_count < self.bit_count {
+                message.push(pulse.low_us > threshold_us);
+            } else if message.bit_count == self.bit_count {
+                // The pulse before the end gap
+                return Some(message);
+            } else {
+                message = Message::new(self.protocol);
+            }
+        }
+        None
+    }
+}
+
+/// EV1527 and PT2262 remotes, 24 bits. PT2262 trits take 2 bits each.
+pub const EV1527: PwmDecoder = PwmDecoder {
+    protocol: "EV1527",
+    short_us: 350,
+    long_us: 1_050,
+    bit_count: 24,
+};
+
+/// Nexus compatible temperature and humidity sensors, 36 bits, see `NexusReading`.
+pub const NEXUS_TH: PpmDecoder = PpmDecoder {
+    protocol: "Nexus-TH",
+    pulse_us: 500,
+    zero_gap_us: 1_000,
+    one_gap_us: 2_000,
+    bit_count: 36,
+};
+
+/// A reading of a Nexus compatible sensor.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct NexusReading {
+    /// Random id chosen when the batteries are inserted.
+    pub id: u8,
+    pub channel: u8,
+    pub battery_ok: bool,
+    pub temperature_celsius: f32,
+    pub humidity: u8,
+}
+
+impl NexusReading {
+    /// Reads the fields of a `NEXUS_TH` message, `None` for other messages.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        // The 4 bits between temperature and humidity are always set
+        if message.protocol != NEXUS_TH.protocol || message.value(24, 4) != 0x0F {
+            return None;
+        }
+
+        // 12 bit two's complement in 0.1 degrees
+        let temperature = ((message.value(12, 12) as i16) << 4) >> 4;
+        Some(NexusReading {
+            id: message.value(0, 8) as u8,
+            battery_ok: message.value(8, 1) == 1,
+            channel: message.value(10, 2) as u8 + 1,
+            temperature_celsius: temperature as f32 / 10.0,
+            humidity: message.value(28, 8) as u8,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::remote::{ev1527_frame, DEFAULT_PULSE_US};
+
+    #[test]
+    fn test_pulses_from_samples() {
+        let mut pulses = [Pulse {
+            high_us: 0,
+            low_us: 0,
+        }; 4];
+        let count = pulses_from_samples(&[0x0E, 0x8C], 100, &mut pulses);
+        assert_eq!(count, 3);
+        assert_eq!(
+            pulses[..3],
+            [
+                Pulse {
+                    high_us: 300,
+                    low_us: 100
+                },
+                Pulse {
+                    high_us: 100,
+                    low_us: 300
+                },
+                Pulse {
+                    high_us: 200,
+                    low_us: 200
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ev1527() {
+        // Captured at the pulse length, as sent by `remote::send_ev1527`
+        let frame = ev1527_frame(0xA5F0F, 0x3).unwrap();
+        let samples = [frame, frame].concat();
+        let mut pulses = [Pulse {
+            high_us: 0,
+            low_us: 0,
+        }; 64];
+        let count = pulses_from_samples(&samples, DEFAULT_PULSE_US, &mut pulses);
+
+        let message = decode(&[&NEXUS_TH, &EV1527], &pulses[..count]).unwrap();
+        assert_eq!(message.protocol, "EV1527");
+        assert_eq!(message.value(0, 20), 0xA5F0F);
+        assert_eq!(message.value(20, 4), 0x3);
+        assert_eq!(message.bits(), [0xA5, 0xF0, 0xF3]);
+    }
+
+    #[test]
+    fn test_nexus() {
+        // Id 0x5A, battery ok, channel 2, -12.3 degrees, 45%
+        let bits: u64 = 0x5A << 28 | 0b1001 << 24 | (-123i64 as u64 & 0xFFF) << 12 | 0xF << 8 | 45;
+        let mut pulses: Vec<Pulse> = (0..36)
+            .rev()
+            .map(|bit| Pulse {
+                high_us: 480,
+                low_us: match bits & (1 << bit) != 0 {
+                    true => 2_050,
+                    false => 990,
+                },
+            })
+            .collect();
+        pulses.push(Pulse {
+            high_us: 510,
+            low_us: 4_000,
+        });
+
+        let message = decode(&[&EV1527, &NEXUS_TH], &pulses).unwrap();
+        assert_eq!(message.bit_count, 36);
+        assert_eq!(
+            NexusReading::from_message(&message),
+            Some(NexusReading {
+                id: 0x5A,
+                channel: 2,
+                battery_ok: true,
+                temperature_celsius: -12.3,
+                humidity: 45,
+            })
+        );
+    }
+}
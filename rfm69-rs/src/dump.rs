@@ -0,0 +1,166 @@
+use crate::register_decoder::{
+    bitrate_bps, fdev_hz, frequency_hz, pa_config, packet_flags, rssi_dbm, rx_bw_hz,
+};
+use crate::registers::{AddressFiltering, DataMode, DcFree, FifoThresh, Modulation, Register};
+use crate::rfm69::Rfm69Mode;
+
+/// Human readable view of the radio registers, decoded from a raw register dump.
+#[derive(Debug, Clone, PartialEq, defmt::Format)]
+pub struct RegisterDump {
+    pub version: u8,
+    pub mode: Option<Rfm69Mode>,
+    pub sequencer_off: bool,
+    pub listen_on: bool,
+    pub data_mode: Option<DataMode>,
+    pub modulation: Option<Modulation>,
+    pub modulation_shaping: u8,
+    pub bitrate_bps: u32,
+    pub fdev_hz: u32,
+    pub frequency_hz: u32,
+    pub pa0_on: bool,
+    pub pa1_on: bool,
+    pub pa2_on: bool,
+    pub output_power: u8,
+    pub ocp_on: bool,
+    pub rx_bw_hz: u32,
+    pub rssi_threshold_dbm: i16,
+    pub preamble_length: u16,
+    pub sync_on: bool,
+    pub sync_size: u8,
+    pub sync_tolerance: u8,
+    pub variable_length: bool,
+    pub dc_free: Option<DcFree>,
+    pub crc_on: bool,
+    pub address_filtering: Option<AddressFiltering>,
+    pub payload_length: u8,
+    pub node_address: u8,
+    pub broadcast_address: u8,
+    pub tx_start_fifo_not_empty: bool,
+    pub fifo_threshold: u8,
+    pub aes_on: bool,
+}
+
+impl RegisterDump {
+    /// Decodes `(address, value)` pairs as returned by `Rfm69::read_all_registers`.
+    /// Registers missing from `registers` are decoded as 0.
+    pub fn from_registers(registers: &[(u8, u8)]) -> Self {
+        let reg = |register: Register| {
+            registers
+                .iter()
+                .find(|(addr, _)| *addr == register.addr())
+                .map_or(0, |(_, value)| *value)
+        };
+
+        let op_mode = reg(Register::OpMode);
+        let data_modul = reg(Register::DataModul);
+        let pa = pa_config(
+            reg(Register::PaLevel),
+            reg(Register::Ocp),
+            reg(Register::TestPa1),
+            reg(Register::TestPa2),
+        );
+        let sync_config = reg(Register::SyncConfig);
+        let packet = packet_flags(reg(Register::PacketConfig1), reg(Register::PacketConfig2));
+        let fifo_thresh = FifoThresh::from_bits(reg(Register::FifoThresh));
+        let modulation = Modulation::from_bits(data_modul);
+
+        RegisterDump {
+            version: reg(Register::Version),
+            mode: Rfm69Mode::from_op_mode(op_mode),
+            sequencer_off: op_mode & 0x80 != 0,
+            listen_on: op_mode & 0x40 != 0,
+            data_mode: DataMode::from_bits(data_modul),
+            modulation,
+            modulation_shaping: data_modul & 0x03,
+            bitrate_bps: bitrate_bps(reg(Register::BitrateMsb), reg(Register::BitrateLsb)),
+            fdev_hz: fdev_hz(reg(Register::FdevMsb), reg(Register::FdevLsb)),
+            frequency_hz: frequency_hz(
+                reg(Register::FrfMsb),
+                reg(Register::FrfMid),
+                reg(Register::FrfLsb),
+            ),
+            pa0_on: pa.pa_level.pa0_on,
+            pa1_on: pa.pa_level.pa1_on,
+            pa2_on: pa.pa_level.pa2_on,
+            output_power: pa.pa_level.output_power,
+            ocp_on: pa.ocp_on,
+            rx_bw_hz: rx_bw_hz(reg(Register::RxBw), modulation == Some(Modulation::Ook)),
+            rssi_threshold_dbm: rssi_dbm(reg(Register::RssiThresh)),
+            preamble_length: u16::from_be_bytes([
+                reg(Register::PreambleMsb),
+                reg(Register::PreambleLsb),
+            ]),
+            sync_on: sync_config & 0x80 != 0,
+            sync_size: ((sync_config >> 3) & 0x07) + 1,
+            sync_tolerance: sync_config & 0x07,
+            variable_length: packet.variable_length,
+            dc_free: packet.dc_free,
+            crc_on: packet.crc_on,
+            address_filtering: packet.address_filtering,
+            payload_length: reg(Register::PayloadLength),
+            node_address: reg(Register::NodeAddrs),
+            broadcast_address: reg(Register::BroadcastAddrs),
+            tx_start_fifo_not_empty: fifo_thresh.tx_start_fifo_not_empty,
+            fifo_threshold: fifo_thresh.fifo_threshold,
+            aes_on: packet.aes_on,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_register_dump() {
+        let registers = [
+            (0x01, 0x04),
+            (0x02, 0x01),
+            (0x03, 0x00),
+            (0x04, 0x80),
+            (0x05, 0x10),
+            (0x06, 0x00),
+            (0x07, 0xE4),
+            (0x08, 0xC0),
+            (0x09, 0x00),
+            (0x10, 0x24),
+            (0x11, 0x5F),
+            (0x13, 0x1A),
+            (0x19, 0xE0),
+            (0x29, 0xE4),
+            (0x2C, 0x00),
+            (0x2D, 0x04),
+            (0x2E, 0x88),
+            (0x37, 0xD0),
+            (0x38, 0x40),
+            (0x3C, 0x8F),
+        ];
+
+        let dump = RegisterDump::from_registers(&registers);
+
+        assert_eq!(dump.version, 0x24);
+        assert_eq!(dump.mode, Some(Rfm69Mode::Standby));
+        assert_eq!(dump.data_mode, Some(DataMode::Packet));
+        assert_eq!(dump.modulation, Some(Modulation::Fsk));
+        assert_eq!(dump.modulation_shaping, 1);
+        assert_eq!(dump.bitrate_bps, 250_000);
+        assert_eq!(dump.fdev_hz, 250_000);
+        assert_eq!(dump.frequency_hz, 915_000_000);
+        assert!(dump.pa1_on && !dump.pa0_on && !dump.pa2_on);
+        assert_eq!(dump.output_power, 31);
+        assert!(dump.ocp_on);
+        assert_eq!(dump.rx_bw_hz, 500_000);
+        assert_eq!(dump.rssi_threshold_dbm, -114);
+        assert_eq!(dump.preamble_length, 4);
+        assert!(dump.sync_on);
+        assert_eq!(dump.sync_size, 2);
+        assert!(dump.variable_length);
+        assert_eq!(dump.dc_free, Some(DcFree::Whitening));
+        assert!(dump.crc_on);
+        assert_eq!(dump.address_filtering, Some(AddressFiltering::None));
+        assert_eq!(dump.payload_length, 0x40);
+        assert!(dump.tx_start_fifo_not_empty);
+        assert_eq!(dump.fifo_threshold, 15);
+        assert!(!dump.aes_on);
+    }
+}
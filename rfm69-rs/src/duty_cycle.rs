@@ -0,0 +1,312 @@
+// Airtime is accounted in buckets, a transmission stays in the budget until
+// its whole bucket has left the one hour window.
+const WINDOW_MS: u64 = 3_600_000;
+const BUCKET_MS: u64 = 300_000;
+const BUCKETS: usize = (WINDOW_MS / BUCKET_MS) as usize;
+
+/// A regulatory sub-band with its own duty cycle budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct SubBand {
+    pub min_hz: u32,
+    pub max_hz: u32,
+    /// Allowed duty cycle in parts per thousand (10 = 1%).
+    pub duty_cycle_permille: u16,
+}
+
+impl SubBand {
+    pub fn contains(&self, frequency_hz: u32) -> bool {
+        frequency_hz >= self.min_hz && frequency_hz <= self.max_hz
+    }
+
+    /// Time on air allowed per hour in milliseconds.
+    pub fn budget_ms(&self) -> u32 {
+        (WINDOW_MS * self.duty_cycle_permille as u64 / 1000) as u32
+    }
+}
+
+/// ETSI EN 300 220 sub-bands for the EU 868 MHz band. The first match applies,
+/// the last entry limits the gaps between the sub-bands like the most restrictive
+/// one.
+pub const EU868_SUB_BANDS: [SubBand; 7] = [
+    SubBand {
+        min_hz: 863_000_000,
+        max_hz: 864_999_999,
+        duty_cycle_permille: 1,
+    },
+    SubBand {
+        min_hz: 865_000_000,
+        max_hz: 867_999_999,
+        duty_cycle_permille: 10,
+    },
+    SubBand {
+        min_hz: 868_000_000,
+        max_hz: 868_600_000,
+        duty_cycle_permille: 10,
+    },
+    SubBand {
+        min_hz: 868_700_000,
+        max_hz: 869_200_000,
+        duty_cycle_permille: 1,
+    },
+    SubBand {
+        min_hz: 869_400_000,
+        max_hz: 869_650_000,
+        duty_cycle_permille: 100,
+    },
+    SubBand {
+        min_hz: 869_700_000,
+        max_hz: 870_000_000,
+        duty_cycle_permille: 10,
+    },
+    SubBand {
+        min_hz: 863_000_000,
+        max_hz: 870_000_000,
+        duty_cycle_permille: 1,
+    },
+];
+
+/// Duty cycle allowed with the +18 to +20 dBm settings of the high power modules,
+/// in parts per thousand, on any frequency.
+pub const PA_BOOST_DUTY_CYCLE_PERMILLE: u16 = 10;
+
+/// What `send()` does when a transmission would exceed the budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DutyCycleAction {
+    /// Return `Rfm69Error::DutyCycleExceeded`.
+    Error,
+    /// Wait until enough airtime is available, then send.
+    Delay,
+}
+
+#[derive(Clone, Copy)]
+struct SubBandUsage {
+    buckets: [u32; BUCKETS],
+    current: u64,
+}
+
+impl SubBandUsage {
+    const fn new() -> Self {
+        SubBandUsage {
+            buckets: [0; BUCKETS],
+            current: 0,
+        }
+    }
+
+    fn advance(&mut self, now_ms: u64) {
+        let bucket = now_ms / BUCKET_MS;
+        if bucket <= self.current {
+            return;
+        }
+
+        if bucket - self.current >= BUCKETS as u64 {
+            self.buckets = [0; BUCKETS];
+        } else {
+            for index in self.current + 1..=bucket {
+                self.buckets[index as usize % BUCKETS] = 0;
+            }
+        }
+        self.current = bucket;
+    }
+
+    fn used(&self) -> u32 {
+        self.buckets.iter().sum()
+    }
+
+    // Milliseconds until `airtime_ms` fits in `budget_ms`, see `DutyCycleLimiter::wait_time_ms`
+    fn wait_time_ms(&mut self, now_ms: u64, budget_ms: u32, airtime_ms: u32) -> Option<u64> {
+        if airtime_ms > budget_ms {
+            return None;
+        }
+
+        self.advance(now_ms);
+
+        let mut used = self.used();
+        if used + airtime_ms <= budget_ms {
+            return Some(0);
+        }
+
+        // Expire buckets from the oldest until the transmission fits
+        let oldest = (self.current + 1).saturating_sub(BUCKETS as u64);
+        for bucket in oldest..=self.current {
+            used -= self.buckets[bucket as usize % BUCKETS];
+            if used + airtime_ms <= budget_ms {
+                return Some((bucket + BUCKETS as u64) * BUCKET_MS - now_ms);
+            }
+        }
+
+        None
+    }
+
+    fn record(&mut self, now_ms: u64, airtime_ms: u32) {
+        self.advance(now_ms);
+        self.buckets[self.current as usize % BUCKETS] += airtime_ms;
+    }
+}
+
+/// Tracks the time on air per EU868 sub-band over a sliding hour, and at high
+/// power on any frequency, see `PA_BOOST_DUTY_CYCLE_PERMILLE`.
+///
+/// The limiter has no time source of its own, `clock` must return a
+/// monotonic time in milliseconds.
+pub struct DutyCycleLimiter {
+    clock: fn() -> u64,
+    action: DutyCycleAction,
+    usage: [SubBandUsage; EU868_SUB_BANDS.len()],
+    pa_boost: SubBandUsage,
+}
+
+impl DutyCycleLimiter {
+    pub fn new(clock: fn() -> u64, action: DutyCycleAction) -> Self {
+        DutyCycleLimiter {
+            clock,
+            action,
+            usage: [SubBandUsage::new(); EU868_SUB_BANDS.len()],
+            pa_boost: SubBandUsage::new(),
+        }
+    }
+
+    pub fn action(&self) -> DutyCycleAction {
+        self.action
+    }
+
+    fn sub_band(frequency_hz: u32) -> Option<usize> {
+        EU868_SUB_BANDS
+            .iter()
+            .position(|band| band.contains(frequency_hz))
+    }
+
+    /// Airtime left in the current window, `None` if the frequency is not duty cycle limited.
+    pub fn remaining_airtime_ms(&mut self, frequency_hz: u32) -> Option<u32> {
+        let band = Self::sub_band(frequency_hz)?;
+        let now = (self.clock)();
+        let usage = &mut self.usage[band];
+        usage.advance(now);
+        Some(
+            EU868_SUB_BANDS[band]
+                .budget_ms()
+                .saturating_sub(usage.used()),
+        )
+    }
+
+    /// Milliseconds to wait before `airtime_ms` can be transmitted, `Some(0)` if it can
+    /// be sent immediately and `None` if it never fits in the budget.
+    pub fn wait_time_ms(&mut self, frequency_hz: u32, airtime_ms: u32) -> Option<u64> {
+        let Some(band) = Self::sub_band(frequency_hz) else {
+            return Some(0);
+        };
+        let budget = EU868_SUB_BANDS[band].budget_ms();
+        let now = (self.clock)();
+        self.usage[band].wait_time_ms(now, budget, airtime_ms)
+    }
+
+    /// Accounts a completed transmission.
+    pub fn record(&mut self, frequency_hz: u32, airtime_ms: u32) {
+        if let Some(band) = Self::sub_band(frequency_hz) {
+            let now = (self.clock)();
+            self.usage[band].record(now, airtime_ms);
+        }
+    }
+
+    /// Like `wait_time_ms`, for a transmission with the high power settings.
+    pub fn pa_boost_wait_time_ms(&mut self, airtime_ms: u32) -> Option<u64> {
+        let budget = (WINDOW_MS * PA_BOOST_DUTY_CYCLE_PERMILLE as u64 / 1000) as u32;
+        let now = (self.clock)();
+        self.pa_boost.wait_time_ms(now, budget, airtime_ms)
+    }
+
+    /// Accounts a completed transmission with the high power settings.
+    pub fn record_pa_boost(&mut self, airtime_ms: u32) {
+        let now = (self.clock)();
+        self.pa_boost.record(now, airtime_ms);
+    }
+}
+
+/// Time on air in milliseconds, rounded up, of `bytes` bytes at `bitrate` bits per second.
+pub fn airtime_ms(bytes: usize, bitrate: u32) -> u32 {
+    let bits = bytes as u64 * 8 * 1000;
+    bits.div_ceil(bitrate as u64) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_unlimited_frequency() {
+        fn clock() -> u64 {
+            0
+        }
+        let mut limiter = DutyCycleLimiter::new(clock, DutyCycleAction::Error);
+
+        assert_eq!(limiter.wait_time_ms(915_000_000, 1_000_000), Some(0));
+        assert_eq!(limiter.remaining_airtime_ms(915_000_000), None);
+    }
+
+    #[test]
+    fn test_sub_bands() {
+        fn clock() -> u64 {
+            0
+        }
+        let mut limiter = DutyCycleLimiter::new(clock, DutyCycleAction::Error);
+
+        // 0.1% below 865 MHz, 1% above
+        assert_eq!(limiter.remaining_airtime_ms(864_000_000), Some(3_600));
+        assert_eq!(limiter.remaining_airtime_ms(866_000_000), Some(36_000));
+        // Between the sub-bands
+        assert_eq!(limiter.remaining_airtime_ms(868_650_000), Some(3_600));
+        assert_eq!(limiter.remaining_airtime_ms(869_300_000), Some(3_600));
+        assert_eq!(limiter.remaining_airtime_ms(869_500_000), Some(360_000));
+    }
+
+    #[test]
+    fn test_budget_exhausted() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        fn clock() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+        let mut limiter = DutyCycleLimiter::new(clock, DutyCycleAction::Error);
+
+        // 1% of an hour is 36 seconds
+        assert_eq!(limiter.remaining_airtime_ms(868_100_000), Some(36_000));
+        limiter.record(868_100_000, 30_000);
+
+        NOW.store(600_000, Ordering::Relaxed);
+        limiter.record(868_100_000, 5_000);
+        assert_eq!(limiter.remaining_airtime_ms(868_100_000), Some(1_000));
+        assert_eq!(limiter.wait_time_ms(868_100_000, 1_000), Some(0));
+
+        // The first transmission leaves the window one hour after its bucket started
+        assert_eq!(limiter.wait_time_ms(868_100_000, 2_000), Some(3_000_000));
+
+        // Other sub-bands have their own budget
+        assert_eq!(limiter.wait_time_ms(868_800_000, 3_600), Some(0));
+        assert_eq!(limiter.wait_time_ms(868_800_000, 3_601), None);
+
+        NOW.store(3_600_000, Ordering::Relaxed);
+        assert_eq!(limiter.remaining_airtime_ms(868_100_000), Some(31_000));
+    }
+
+    #[test]
+    fn test_pa_boost() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        fn clock() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+        let mut limiter = DutyCycleLimiter::new(clock, DutyCycleAction::Error);
+
+        // 1% on any frequency, separate from the sub-bands
+        limiter.record_pa_boost(35_000);
+        assert_eq!(limiter.pa_boost_wait_time_ms(1_000), Some(0));
+        assert_eq!(limiter.pa_boost_wait_time_ms(2_000), Some(3_600_000));
+        assert_eq!(limiter.pa_boost_wait_time_ms(36_001), None);
+        assert_eq!(limiter.remaining_airtime_ms(868_100_000), Some(36_000));
+    }
+
+    #[test]
+    fn test_airtime() {
+        assert_eq!(airtime_ms(25, 250_000), 1);
+        assert_eq!(airtime_ms(25, 2_000), 100);
+        assert_eq!(airtime_ms(26, 2_000), 104);
+    }
+}
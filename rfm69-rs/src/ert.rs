@@ -0,0 +1,308 @@
+use crate::crc::crc16;
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use crate::units::Frequency;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// OOK chip rate of ERT broadcasts. Bits are Manchester coded, so the bitrate
+/// is half of it.
+pub const CHIP_RATE: u32 = 32_768;
+/// Channel of the receive profile. Meters hop between 910 and 920 MHz, a receiver
+/// on a single channel catches a part of their broadcasts.
+pub const FREQUENCY: Frequency = Frequency::from_mhz(912);
+/// Longest message, SCM+.
+pub const MAX_MESSAGE_LENGTH: usize = 16;
+
+// Generator of the BCH code checking SCM messages
+const SCM_POLYNOMIAL: u16 = 0x6F63;
+// SCM+ uses CRC-16/GENIBUS: CCITT starting at 0xFFFF, inverted
+const SCM_PLUS_POLYNOMIAL: u16 = 0x1021;
+
+/// Why a received ERT message was dropped.
+#[derive(Debug, PartialEq, defmt::Format)]
+pub enum ErtError {
+    Radio(Rfm69Error),
+    /// Fewer chips than a message of the protocol.
+    Truncated,
+    /// A chip pair isn't a Manchester coded bit, e.g. noise after the sync word.
+    Manchester,
+    /// The checksum doesn't match.
+    Checksum,
+}
+
+impl From<Rfm69Error> for ErtError {
+    fn from(error: Rfm69Error) -> Self {
+        ErtError::Radio(error)
+    }
+}
+
+/// Message formats of Itron ERT meters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ErtProtocol {
+    /// Standard Consumption Message, 96 bits.
+    Scm,
+    /// SCM+, 128 bits with a 32 bit meter id and consumption.
+    ScmPlus,
+}
+
+impl ErtProtocol {
+    // Message bits up to the end of the sync word, and their count
+    fn preamble(self) -> (u32, usize) {
+        match self {
+            ErtProtocol::Scm => (0x1F2A60, 21),
+            ErtProtocol::ScmPlus => (0x16A3, 16),
+        }
+    }
+
+    /// Length of a decoded message in bytes, preamble included.
+    pub fn message_length(self) -> usize {
+        match self {
+            ErtProtocol::Scm => 12,
+            ErtProtocol::ScmPlus => 16,
+        }
+    }
+
+    // The last 16 preamble bits as Manchester chips, matched by the radio
+    pub(crate) fn sync_words(self) -> [u8; 4] {
+        let (preamble, _) = self.preamble();
+        let chips = (0..16)
+            .rev()
+            .fold(0u32, |chips, bit| match preamble & (1 << bit) {
+                0 => chips << 2 | 0b01,
+                _ => chips << 2 | 0b10,
+            });
+        chips.to_be_bytes()
+    }
+
+    /// Chips following the sync word, in bytes. The radio receives them as a
+    /// fixed length packet.
+    pub fn payload_length(self) -> usize {
+        let (_, preamble_bits) = self.preamble();
+        ((self.message_length() * 8 - preamble_bits) * 2).div_ceil(8)
+    }
+
+    /// Decodes the Manchester coded `chips` following the sync word, a 1 is sent
+    /// as 10 and a 0 as 01.
+    pub fn decode(self, chips: &[u8]) -> Result<ErtMessage, ErtError> {
+        let (preamble, preamble_bits) = self.preamble();
+        let message_bits = self.message_length() * 8;
+        if chips.len() * 4 < message_bits - preamble_bits {
+            return Err(ErtError::Truncated);
+        }
+
+        let mut message = [0u8; MAX_MESSAGE_LENGTH];
+        let shift = 32 - preamble_bits;
+        message[..4].copy_from_slice(&(preamble << shift).to_be_bytes());
+        for bit in preamble_bits..message_bits {
+            let chip = (bit - preamble_bits) * 2;
+            let pair = chips[chip / 8] >> (6 - chip % 8) & 0b11;
+            match pair {
+                0b10 => message[bit / 8] |= 0x80 >> (bit % 8),
+                0b01 => {}
+                _ => return Err(ErtError::Manchester),
+            }
+        }
+
+        let message = &message[..self.message_length()];
+        match self {
+            ErtProtocol::Scm => ScmMessage::from_bytes(message).map(ErtMessage::Scm),
+            ErtProtocol::ScmPlus => ScmPlusMessage::from_bytes(message).map(ErtMessage::ScmPlus),
+        }
+    }
+}
+
+// `count` bits from bit `start`, MSB first
+fn field(bytes: &[u8], start: usize, count: usize) -> u32 {
+    (start..start + count).fold(0, |value, bit| {
+        value << 1 | (bytes[bit / 8] >> (7 - bit % 8) & 1) as u32
+    })
+}
+
+/// Standard Consumption Message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ScmMessage {
+    pub id: u32,
+    /// Meter type, e.g. 4, 5, 7 and 8 for electricity, 2 and 9 for gas, 11 and 13 for water.
+    pub ert_type: u8,
+    pub physical_tamper: u8,
+    pub encoder_tamper: u8,
+    pub consumption: u32,
+}
+
+impl ScmMessage {
+    /// Parses the 12 bytes of a message, preamble included.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErtError> {
+        if bytes.len() < 12 {
+            return Err(ErtError::Truncated);
+        }
+        // The checksum covers everything after the first two bytes, the rest of
+        // the preamble are zeros
+        if crc16(SCM_POLYNOMIAL, 0, &bytes[2..10]) != u16::from_be_bytes([bytes[10], bytes[11]]) {
+            return Err(ErtError::Checksum);
+        }
+
+        Ok(ScmMessage {
+            id: field(bytes, 21, 2) << 24 | field(bytes, 56, 24),
+            physical_tamper: field(bytes, 24, 2) as u8,
+            ert_type: field(bytes, 26, 4) as u8,
+            encoder_tamper: field(bytes, 30, 2) as u8,
+            consumption: field(bytes, 32, 24),
+        })
+    }
+}
+
+/// SCM+ message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ScmPlusMessage {
+    /// 0x1E for SCM+.
+    pub protocol_id: u8,
+    pub endpoint_type: u8,
+    pub endpoint_id: u32,
+    pub consumption: u32,
+    pub tamper: u16,
+}
+
+impl ScmPlusMessage {
+    /// Parses the 16 bytes of a message, frame sync included.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ErtError> {
+        if bytes.len() < 16 {
+            return Err(ErtError::Truncated);
+        }
+        let checksum = !crc16(SCM_PLUS_POLYNOMIAL, 0xFFFF, &bytes[2..14]);
+        if checksum != u16::from_be_bytes([bytes[14], bytes[15]]) {
+            return Err(ErtError::Checksum);
+        }
+
+        Ok(ScmPlusMessage {
+            protocol_id: bytes[2],
+            endpoint_type: bytes[3],
+            endpoint_id: field(bytes, 32, 32),
+            consumption: field(bytes, 64, 32),
+            tamper: field(bytes, 96, 16) as u16,
+        })
+    }
+}
+
+/// A decoded meter broadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ErtMessage {
+    Scm(ScmMessage),
+    ScmPlus(ScmPlusMessage),
+}
+
+/// Reads and decodes the received broadcast. The radio has to be configured with
+/// `Rfm69::set_ert_profile` for the same protocol, call it as soon as
+/// `Rfm69::is_message_available` returns true.
+pub fn receive_ert<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    protocol: ErtProtocol,
+) -> Result<ErtMessage, ErtError>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let mut chips = [0u8; 32];
+    let length = radio.receive_raw(&mut chips)?;
+    protocol.decode(&chips[..length])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Manchester codes `message` after the preamble, as received after the sync word
+    fn chips(protocol: ErtProtocol, message: &[u8]) -> [u8; 32] {
+        let (_, preamble_bits) = protocol.preamble();
+        let mut chips = [0u8; 32];
+        for bit in preamble_bits..message.len() * 8 {
+            let chip = (bit - preamble_bits) * 2;
+            let pair = match field(message, bit, 1) {
+                0 => 0b01,
+                _ => 0b10,
+            };
+            chips[chip / 8] |= pair << (6 - chip % 8);
+        }
+        chips
+    }
+
+    #[test]
+    fn test_sync_words() {
+        // 0010 1010 0110 0000
+        assert_eq!(ErtProtocol::Scm.sync_words(), [0x59, 0x99, 0x69, 0x55]);
+        assert_eq!(ErtProtocol::Scm.payload_length(), 19);
+        assert_eq!(ErtProtocol::ScmPlus.payload_length(), 28);
+    }
+
+    #[test]
+    fn test_scm() {
+        // Id 0x1234_5678 truncated to 26 bits, type 7, consumption 123456
+        let mut message = [
+            0xF9, 0x53, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0, 0,
+        ];
+        let id: u32 = 0x0234_5678;
+        message[2] = (id >> 24 << 1) as u8;
+        message[3] = 0x40 | 7 << 2 | 0x01;
+        message[4..7].copy_from_slice(&123_456u32.to_be_bytes()[1..]);
+        message[7..10].copy_from_slice(&id.to_be_bytes()[1..]);
+        let checksum = crc16(SCM_POLYNOMIAL, 0, &message[2..10]);
+        message[10..].copy_from_slice(&checksum.to_be_bytes());
+
+        let chips = chips(ErtProtocol::Scm, &message);
+        let length = ErtProtocol::Scm.payload_length();
+        assert_eq!(
+            ErtProtocol::Scm.decode(&chips[..length]),
+            Ok(ErtMessage::Scm(ScmMessage {
+                id,
+                ert_type: 7,
+                physical_tamper: 1,
+                encoder_tamper: 1,
+                consumption: 123_456,
+            }))
+        );
+
+        message[5] ^= 0x10;
+        let chips = self::chips(ErtProtocol::Scm, &message);
+        assert_eq!(
+            ErtProtocol::Scm.decode(&chips[..length]),
+            Err(ErtError::Checksum)
+        );
+        assert_eq!(
+            ErtProtocol::Scm.decode(&chips[..10]),
+            Err(ErtError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_scm_plus() {
+        let mut message = [0u8; 16];
+        message[..4].copy_from_slice(&[0x16, 0xA3, 0x1E, 0x07]);
+        message[4..8].copy_from_slice(&0x1234_5678u32.to_be_bytes());
+        message[8..12].copy_from_slice(&987_654u32.to_be_bytes());
+        message[12..14].copy_from_slice(&0x0102u16.to_be_bytes());
+        let checksum = !crc16(SCM_PLUS_POLYNOMIAL, 0xFFFF, &message[2..14]);
+        message[14..].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut chips = chips(ErtProtocol::ScmPlus, &message);
+        let length = ErtProtocol::ScmPlus.payload_length();
+        assert_eq!(
+            ErtProtocol::ScmPlus.decode(&chips[..length]),
+            Ok(ErtMessage::ScmPlus(ScmPlusMessage {
+                protocol_id: 0x1E,
+                endpoint_type: 0x07,
+                endpoint_id: 0x1234_5678,
+                consumption: 987_654,
+                tamper: 0x0102,
+            }))
+        );
+
+        // 11 isn't a Manchester coded bit
+        chips[3] |= 0xC0;
+        assert_eq!(
+            ErtProtocol::ScmPlus.decode(&chips[..length]),
+            Err(ErtError::Manchester)
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use crate::read_write::{ReadWrite, SpiBusError};
+use crate::registers::Register;
+use core::fmt::Debug;
+use embedded_hal::digital::{self, ErrorKind, ErrorType};
+use embedded_hal_02::blocking::delay::DelayUs;
+use embedded_hal_02::blocking::spi::{Transfer, Write};
+use embedded_hal_02::digital::v2;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+
+// Adapters for HALs still on embedded-hal 0.2, the driver itself only uses 1.0:
+//
+//     Rfm69::new(Spi02::new(spi, cs)?, OutputPin02(reset), InputPin02(dio0), Delay02(delay))
+
+/// Transport for an embedded-hal 0.2 SPI bus owned by the radio, with the chip
+/// select driven through `cs`, like `SpiBusDevice`.
+pub struct Spi02<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+}
+
+impl<SPI, CS, E> Spi02<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: v2::OutputPin,
+{
+    /// Creates the transport and deasserts the chip select.
+    pub fn new(spi: SPI, mut cs: CS) -> Result<Self, CS::Error> {
+        cs.set_high()?;
+        Ok(Spi02 { spi, cs })
+    }
+
+    /// Returns the bus and the chip select pin.
+    pub fn release(self) -> (SPI, CS) {
+        (self.spi, self.cs)
+    }
+
+    fn transaction(
+        &mut self,
+        reg: u8,
+        operation: impl FnOnce(&mut SPI) -> Result<(), E>,
+    ) -> Result<(), SpiBusError<E, CS::Error>> {
+        self.cs.set_low().map_err(SpiBusError::ChipSelect)?;
+
+        let result = self
+            .spi
+            .write(&[reg])
+            .and_then(|_| operation(&mut self.spi));
+
+        // Always release the chip select, even if the transfer failed
+        let deassert = self.cs.set_high();
+        result.map_err(SpiBusError::Bus)?;
+        deassert.map_err(SpiBusError::ChipSelect)
+    }
+}
+
+impl<SPI, CS, E> ReadWrite for Spi02<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: v2::OutputPin,
+{
+    type Error = SpiBusError<E, CS::Error>;
+
+    fn write_many(&mut self, reg: Register, data: &[u8]) -> core::result::Result<(), Self::Error> {
+        self.transaction(reg.write(), |spi| spi.write(data))
+    }
+
+    fn read_many(
+        &mut self,
+        reg: Register,
+        buffer: &mut [u8],
+    ) -> core::result::Result<(), Self::Error> {
+        self.transaction(reg.read(), |spi| spi.transfer(buffer).map(|_| ()))
+    }
+}
+
+/// Error of an embedded-hal 0.2 pin.
+#[derive(Debug)]
+pub struct PinError<E>(pub E);
+
+impl<E: Debug> digital::Error for PinError<E> {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// An embedded-hal 0.2 output, e.g. for the reset pin.
+pub struct OutputPin02<P>(pub P);
+
+impl<P> ErrorType for OutputPin02<P>
+where
+    P: v2::OutputPin,
+    P::Error: Debug,
+{
+    type Error = PinError<P::Error>;
+}
+
+impl<P> digital::OutputPin for OutputPin02<P>
+where
+    P: v2::OutputPin,
+    P::Error: Debug,
+{
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_low().map_err(PinError)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_high().map_err(PinError)
+    }
+}
+
+/// An embedded-hal 0.2 input, e.g. for DIO0. Without interrupt support in the
+/// HAL, `Wait` polls the pin and yields to the executor between reads.
+pub struct InputPin02<P>(pub P);
+
+impl<P> InputPin02<P>
+where
+    P: v2::InputPin,
+    P::Error: Debug,
+{
+    async fn wait_for_level(&mut self, high: bool) -> Result<(), PinError<P::Error>> {
+        while self.0.is_high().map_err(PinError)? != high {
+            embassy_futures::yield_now().await;
+        }
+        Ok(())
+    }
+}
+
+impl<P> ErrorType for InputPin02<P>
+where
+    P: v2::InputPin,
+    P::Error: Debug,
+{
+    type Error = PinError<P::Error>;
+}
+
+impl<P> digital::InputPin for InputPin02<P>
+where
+    P: v2::InputPin,
+    P::Error: Debug,
+{
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.0.is_high().map_err(PinError)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        self.0.is_low().map_err(PinError)
+    }
+}
+
+impl<P> Wait for InputPin02<P>
+where
+    P: v2::InputPin,
+    P::Error: Debug,
+{
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.wait_for_level(true).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.wait_for_level(false).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for_level(false).await?;
+        self.wait_for_level(true).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for_level(true).await?;
+        self.wait_for_level(false).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        let high = self.0.is_high().map_err(PinError)?;
+        self.wait_for_level(!high).await
+    }
+}
+
+/// An embedded-hal 0.2 blocking delay. It blocks the executor for the whole
+/// delay, so other tasks don't run while the driver waits.
+pub struct Delay02<D>(pub D);
+
+impl<D: DelayUs<u32>> DelayNs for Delay02<D> {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.0.delay_us(ns.div_ceil(1_000));
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        self.0.delay_us(us);
+    }
+
+    async fn delay_ms(&mut self, ms: u32) {
+        for _ in 0..ms {
+            self.0.delay_us(1_000);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rfm69::Rfm69;
+    use crate::units::Frequency;
+    use embedded_hal_mock::eh0::delay::NoopDelay;
+    use embedded_hal_mock::eh0::digital::{Mock as PinMock, State, Transaction as PinTransaction};
+    use embedded_hal_mock::eh0::spi::{Mock as SpiMock, Transaction as SpiTransaction};
+
+    #[tokio::test]
+    async fn test_rfm69_on_hal02() {
+        let spi = SpiMock::new(&[
+            SpiTransaction::write(vec![Register::FrfMsb.write()]),
+            SpiTransaction::write(vec![0xE4, 0xC0, 0x00]),
+            SpiTransaction::write(vec![Register::Version.read()]),
+            SpiTransaction::transfer(vec![0x00], vec![0x24]),
+        ]);
+        let cs = PinMock::new(&[
+            PinTransaction::set(State::High),
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+        let reset = PinMock::new(&[]);
+        let dio0 = PinMock::new(&[
+            PinTransaction::get(State::Low),
+            PinTransaction::get(State::High),
+        ]);
+
+        let mut rfm = Rfm69::new(
+            Spi02::new(spi, cs).unwrap(),
+            OutputPin02(reset),
+            InputPin02(dio0),
+            Delay02(NoopDelay::new()),
+        );
+        rfm.set_frequency(Frequency::from_mhz(915)).unwrap();
+        let mut version = [0u8; 1];
+        rfm.spi.read_many(Register::Version, &mut version).unwrap();
+        assert_eq!(version, [0x24]);
+        rfm.intr_pin.wait_for_high().await.unwrap();
+
+        let (spi, OutputPin02(mut reset), InputPin02(mut dio0), _) = rfm.release();
+        let (mut spi, mut cs) = spi.release();
+        spi.done();
+        cs.done();
+        reset.done();
+        dio0.done();
+    }
+}
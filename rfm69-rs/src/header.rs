@@ -0,0 +1,123 @@
+/// Address matching every node.
+pub const BROADCAST_ADDRESS: u8 = 0xFF;
+
+/// Length of the header sent in front of every payload.
+pub const HEADER_LENGTH: usize = 4;
+
+/// Set in `Header::flags` on acknowledgements.
+pub const FLAGS_ACK: u8 = 0x80;
+
+/// Set in `Header::flags` on packets sent with `Rfm69::broadcast`. Like the
+/// other driver flags it is in the low nibble, RadioHead reserves the high one.
+pub const FLAGS_BROADCAST: u8 = 0x02;
+
+/// Set in `Header::flags` on time synchronization beacons, see `time_sync`.
+pub const FLAGS_BEACON: u8 = 0x01;
+
+/// Set in `Header::flags` on segments of a byte stream, see `stream`.
+pub const FLAGS_STREAM: u8 = 0x08;
+
+/// Set in `Header::flags` on datagrams sent with `ReliableDatagram::send_to_no_ack`.
+pub const FLAGS_NO_ACK: u8 = 0x04;
+
+/// Decides from the header and the RSSI in dBm whether a received packet is
+/// kept, see `Rfm69::set_packet_filter`.
+pub type PacketFilter = fn(&Header, i16) -> bool;
+
+/// Packet header, following the RadioHead layout of destination, source,
+/// sequence number and flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Header {
+    pub to: u8,
+    pub from: u8,
+    pub id: u8,
+    pub flags: u8,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header {
+            to: BROADCAST_ADDRESS,
+            from: BROADCAST_ADDRESS,
+            id: 0,
+            flags: 0,
+        }
+    }
+}
+
+impl Header {
+    pub fn from_bytes(bytes: [u8; HEADER_LENGTH]) -> Self {
+        Header {
+            to: bytes[0],
+            from: bytes[1],
+            id: bytes[2],
+            flags: bytes[3],
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; HEADER_LENGTH] {
+        [self.to, self.from, self.id, self.flags]
+    }
+
+    pub fn is_ack(&self) -> bool {
+        self.flags & FLAGS_ACK != 0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        self.flags & FLAGS_BROADCAST != 0
+    }
+
+    pub fn is_beacon(&self) -> bool {
+        self.flags & FLAGS_BEACON != 0
+    }
+
+    pub fn is_stream(&self) -> bool {
+        self.flags & FLAGS_STREAM != 0
+    }
+
+    pub fn is_no_ack(&self) -> bool {
+        self.flags & FLAGS_NO_ACK != 0
+    }
+}
+
+/// A datagram received by `Rfm69::receive_from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Datagram {
+    pub from: u8,
+    /// Number of payload bytes written to the receive buffer.
+    pub length: usize,
+    /// Sent to every node rather than to this one.
+    pub broadcast: bool,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_bytes() {
+        let header = Header {
+            to: 0x02,
+            from: 0x01,
+            id: 0x10,
+            flags: FLAGS_ACK,
+        };
+        assert_eq!(header.to_bytes(), [0x02, 0x01, 0x10, 0x80]);
+        assert_eq!(Header::from_bytes(header.to_bytes()), header);
+        assert!(header.is_ack());
+        assert!(!header.is_broadcast());
+
+        let broadcast = Header {
+            flags: FLAGS_BROADCAST,
+            ..Header::default()
+        };
+        assert_eq!(broadcast.to_bytes()[3], 0x02);
+        assert!(broadcast.is_broadcast() && !broadcast.is_ack());
+
+        // The driver flags are distinct and leave the RadioHead nibble alone
+        let flags = [FLAGS_BROADCAST, FLAGS_BEACON, FLAGS_STREAM, FLAGS_NO_ACK];
+        assert_eq!(flags.iter().fold(0, |all, flag| all | flag), 0x0F);
+
+        assert_eq!(Header::default().to_bytes(), [0xFF, 0xFF, 0x00, 0x00]);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error, Rfm69Mode};
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+const OP_PING: u8 = 0x31;
+const OP_PONG: u8 = 0x32;
+const OP_BURST: u8 = 0x33;
+
+/// Opcode and sequence number in front of every test packet, the rest of the
+/// payload is filler.
+pub const TEST_HEADER_LENGTH: usize = 5;
+/// Largest `HilConfig::count` of `receive_burst`.
+pub const MAX_BURST_COUNT: u32 = 1024;
+
+/// Parameters of a test run, both radios must use the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct HilConfig {
+    /// Packets sent by the test.
+    pub count: u32,
+    /// Payload length of every packet, `TEST_HEADER_LENGTH` at least.
+    pub payload_length: usize,
+    /// Time to wait for a reply, or for the next packet of a burst.
+    pub timeout_ms: u32,
+    /// Pause between the packets of a burst, for receivers that can't keep up.
+    pub gap_ms: u32,
+}
+
+impl Default for HilConfig {
+    fn default() -> Self {
+        HilConfig {
+            count: 100,
+            payload_length: 32,
+            timeout_ms: 100,
+            gap_ms: 0,
+        }
+    }
+}
+
+/// Result of `ping`. Round trip times are in microseconds of the `clock` passed in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct PingReport {
+    pub sent: u32,
+    pub received: u32,
+    pub min_rtt_us: u64,
+    pub max_rtt_us: u64,
+    pub total_rtt_us: u64,
+}
+
+impl PingReport {
+    pub fn average_rtt_us(&self) -> Option<u64> {
+        self.total_rtt_us.checked_div(self.received as u64)
+    }
+
+    pub fn lost(&self) -> u32 {
+        self.sent - self.received
+    }
+
+    fn record(&mut self, rtt_us: u64) {
+        if self.received == 0 || rtt_us < self.min_rtt_us {
+            self.min_rtt_us = rtt_us;
+        }
+        self.max_rtt_us = self.max_rtt_us.max(rtt_us);
+        self.total_rtt_us += rtt_us;
+        self.received += 1;
+    }
+}
+
+/// Result of `send_burst`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct ThroughputReport {
+    pub packets: u32,
+    /// Payload bytes sent.
+    pub bytes: u64,
+    pub elapsed_us: u64,
+}
+
+impl ThroughputReport {
+    /// Payload throughput, the headers and preambles on air aren't counted.
+    pub fn bits_per_second(&self) -> Option<u64> {
+        (self.bytes * 8 * 1_000_000).checked_div(self.elapsed_us)
+    }
+}
+
+/// Result of `receive_burst`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct LossReport {
+    pub expected: u32,
+    /// Distinct packets received.
+    pub received: u32,
+    pub duplicates: u32,
+    /// Packets dropped by the driver, corrupted or overrun.
+    pub errors: u32,
+    /// From the first packet to the last one received.
+    pub elapsed_us: u64,
+    pub min_rssi_dbm: Option<i16>,
+    pub max_rssi_dbm: Option<i16>,
+}
+
+impl LossReport {
+    pub fn lost(&self) -> u32 {
+        self.expected - self.received
+    }
+
+    /// Packet loss in hundredths of a percent.
+    pub fn loss_basis_points(&self) -> u32 {
+        match self.expected {
+            0 => 0,
+            expected => (self.lost() as u64 * 10_000 / expected as u64) as u32,
+        }
+    }
+}
+
+// Sequence numbers seen by `receive_burst`, one bit each
+struct Seen([u32; MAX_BURST_COUNT as usize / 32]);
+
+impl Seen {
+    // Returns true if `sequence` was already seen
+    fn insert(&mut self, sequence: u32) -> bool {
+        let (word, bit) = (sequence as usize / 32, 1 << (sequence % 32));
+        let seen = self.0[word] & bit != 0;
+        self.0[word] |= bit;
+        seen
+    }
+}
+
+fn test_packet(op: u8, sequence: u32, length: usize) -> [u8; RF69_MAX_MESSAGE_LEN] {
+    let mut packet = [0u8; RF69_MAX_MESSAGE_LEN];
+    packet[0] = op;
+    packet[1..TEST_HEADER_LENGTH].copy_from_slice(&sequence.to_be_bytes());
+    for (i, byte) in packet[TEST_HEADER_LENGTH..length].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    packet
+}
+
+// The opcode and sequence number of a test packet
+fn parse(packet: &[u8]) -> Option<(u8, u32)> {
+    let sequence = packet.get(1..TEST_HEADER_LENGTH)?;
+    Some((packet[0], u32::from_be_bytes(sequence.try_into().ok()?)))
+}
+
+fn check_config<SPI, RESET, INTR, D>(
+    radio: &Rfm69<SPI, RESET, INTR, D>,
+    config: &HilConfig,
+) -> Result<(), Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    if config.payload_length < TEST_HEADER_LENGTH {
+        return Err(Rfm69Error::ConfigurationError);
+    }
+    if config.payload_length > radio.max_payload_length() {
+        return Err(Rfm69Error::MessageTooLarge);
+    }
+    Ok(())
+}
+
+// Waits up to `timeout_ms` for a test packet, other packets are skipped
+async fn receive_test_packet<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    timeout_ms: u32,
+    errors: &mut u32,
+) -> Result<Option<(u8, u8, u32)>, Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+    loop {
+        radio.set_mode(Rfm69Mode::Rx).await?;
+        if !radio.wait_for_message_timeout(Some(timeout_ms)).await? {
+            return Ok(None);
+        }
+        match radio.receive(&mut buffer).await {
+            Ok((header, length)) => {
+                if let Some((op, sequence)) = parse(&buffer[..length]) {
+                    return Ok(Some((header.from, op, sequence)));
+                }
+            }
+            Err(Rfm69Error::CrcFailure | Rfm69Error::FifoOverrun) => *errors += 1,
+            Err(Rfm69Error::NoMessage) => {}
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Sends `config.count` pings to the node `to` running `pong`, one at a time,
+/// and measures the round trip times with `clock`, in microseconds.
+pub async fn ping<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    clock: fn() -> u64,
+    to: u8,
+    config: HilConfig,
+) -> Result<PingReport, Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    check_config(radio, &config)?;
+    let mut report = PingReport::default();
+    let mut errors = 0;
+    for sequence in 0..config.count {
+        let packet = test_packet(OP_PING, sequence, config.payload_length);
+        let start = clock();
+        radio.send_to(to, &packet[..config.payload_length]).await?;
+        report.sent += 1;
+
+        // Late pongs of earlier pings are skipped
+        while let Some((from, op, echoed)) =
+            receive_test_packet(radio, config.timeout_ms, &mut errors).await?
+        {
+            if from == to && op == OP_PONG && echoed == sequence {
+                report.record(clock().saturating_sub(start));
+                break;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Answers the pings of `ping` until none arrives for `timeout_ms`. Returns the
+/// number of pings answered.
+pub async fn pong<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    timeout_ms: u32,
+) -> Result<u32, Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let mut answered = 0;
+    let mut errors = 0;
+    while let Some((from, op, sequence)) =
+        receive_test_packet(radio, timeout_ms, &mut errors).await?
+    {
+        if op == OP_PING {
+            let packet = test_packet(OP_PONG, sequence, TEST_HEADER_LENGTH);
+            radio.send_to(from, &packet[..TEST_HEADER_LENGTH]).await?;
+            answered += 1;
+        }
+    }
+    Ok(answered)
+}
+
+/// Sends `config.count` packets to `to` back to back, e.g. to a node running
+/// `receive_burst`, and measures the time it took with `clock`.
+pub async fn send_burst<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    clock: fn() -> u64,
+    to: u8,
+    config: HilConfig,
+) -> Result<ThroughputReport, Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    check_config(radio, &config)?;
+    let start = clock();
+    for sequence in 0..config.count {
+        let packet = test_packet(OP_BURST, sequence, config.payload_length);
+        radio.send_to(to, &packet[..config.payload_length]).await?;
+        if config.gap_ms > 0 {
+            radio.delay.delay_ms(config.gap_ms).await;
+        }
+    }
+    Ok(ThroughputReport {
+        packets: config.count,
+        bytes: config.count as u64 * config.payload_length as u64,
+        elapsed_us: clock().saturating_sub(start),
+    })
+}
+
+/// Receives the burst of `send_burst` and counts the packets lost. It ends with
+/// the last packet of the burst, or when none arrives for `config.timeout_ms`.
+pub async fn receive_burst<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    clock: fn() -> u64,
+    config: HilConfig,
+) -> Result<LossReport, Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    if config.count > MAX_BURST_COUNT {
+        return Err(Rfm69Error::ConfigurationError);
+    }
+    let mut report = LossReport {
+        expected: config.count,
+        ..LossReport::default()
+    };
+    let mut seen = Seen([0; MAX_BURST_COUNT as usize / 32]);
+    let mut start = None;
+    while let Some((_, op, sequence)) =
+        receive_test_packet(radio, config.timeout_ms, &mut report.errors).await?
+    {
+        if op != OP_BURST || sequence >= config.count {
+            continue;
+        }
+        let now = clock();
+        report.elapsed_us = now - *start.get_or_insert(now);
+        if seen.insert(sequence) {
+            report.duplicates += 1;
+            continue;
+        }
+        report.received += 1;
+        if let Some(rssi_dbm) = radio.packet_rssi() {
+            report.min_rssi_dbm = Some(
+                report
+                    .min_rssi_dbm
+                    .map_or(rssi_dbm, |min| min.min(rssi_dbm)),
+            );
+            report.max_rssi_dbm = Some(
+                report
+                    .max_rssi_dbm
+                    .map_or(rssi_dbm, |max| max.max(rssi_dbm)),
+            );
+        }
+        if sequence == config.count - 1 {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::{SimChannel, SimRfm69};
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use embassy_futures::join::join;
+
+    // Advances 1 ms on every read
+    fn clock() -> u64 {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        NOW.fetch_add(1_000, Ordering::Relaxed)
+    }
+
+    async fn radio(channel: &SimChannel, address: u8) -> SimRfm69 {
+        let mut radio = channel.radio();
+        radio.init().await.unwrap();
+        radio.set_node_address(Some(address)).unwrap();
+        radio
+    }
+
+    fn config(count: u32) -> HilConfig {
+        HilConfig {
+            count,
+            payload_length: 16,
+            timeout_ms: 10,
+            // Lets the receiver run between the packets
+            gap_ms: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ping_pong() {
+        let channel = SimChannel::new(1);
+        let mut pinger = radio(&channel, 0x01).await;
+        let mut ponger = radio(&channel, 0x02).await;
+
+        // The responder goes first, to be in Rx for the first ping
+        let (answered, report) = join(
+            pong(&mut ponger, 50),
+            ping(&mut pinger, clock, 0x02, config(5)),
+        )
+        .await;
+        let report = report.unwrap();
+        assert_eq!(answered, Ok(5));
+        assert_eq!((report.sent, report.received, report.lost()), (5, 5, 0));
+        assert!(report.min_rtt_us > 0);
+        assert!(report.average_rtt_us().unwrap() <= report.max_rtt_us);
+
+        assert_eq!(
+            ping(
+                &mut pinger,
+                clock,
+                0x02,
+                HilConfig {
+                    payload_length: 4,
+                    ..config(1)
+                }
+            )
+            .await,
+            Err(Rfm69Error::ConfigurationError)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_burst() {
+        let channel = SimChannel::new(1);
+        let mut sender = radio(&channel, 0x01).await;
+        let mut receiver = radio(&channel, 0x02).await;
+        channel.set_rssi_dbm(-75);
+
+        let (received, sent) = join(
+            receive_burst(&mut receiver, clock, config(20)),
+            send_burst(&mut sender, clock, 0x02, config(20)),
+        )
+        .await;
+        let sent = sent.unwrap();
+        assert_eq!((sent.packets, sent.bytes), (20, 320));
+        assert!(sent.bits_per_second().is_some());
+        let received = received.unwrap();
+        assert_eq!((received.received, received.lost()), (20, 0));
+        assert_eq!(received.min_rssi_dbm, Some(-75));
+
+        channel.set_loss_percent(50);
+        let (received, _) = join(
+            receive_burst(&mut receiver, clock, config(100)),
+            send_burst(&mut sender, clock, 0x02, config(100)),
+        )
+        .await;
+        let received = received.unwrap();
+        assert!(received.lost() > 0);
+        assert_eq!(received.loss_basis_points(), received.lost() * 10_000 / 100);
+    }
+}
@@ -0,0 +1,137 @@
+/// Why a buffer can't be interleaved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum InterleaveError {
+    /// The output buffer is not as long as the input.
+    LengthMismatch,
+    /// The input is not a whole number of codewords.
+    PartialCodeword,
+}
+
+/// Bit interleaver for packets made of FEC codewords.
+///
+/// The codewords are the rows of a matrix that is sent column by column: the
+/// first bit of every codeword, then the second bit of every codeword, and so
+/// on. A burst of errors on air up to one bit per codeword long is spread
+/// across the codewords after deinterleaving, one bit each, so a code
+/// correcting single bit errors repairs it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Interleaver {
+    codeword_bits: usize,
+}
+
+impl Interleaver {
+    pub fn new(codeword_bits: usize) -> Self {
+        Interleaver {
+            codeword_bits: codeword_bits.max(1),
+        }
+    }
+
+    pub fn codeword_bits(&self) -> usize {
+        self.codeword_bits
+    }
+
+    /// Writes `data` to `output` in transmit order.
+    pub fn interleave(&self, data: &[u8], output: &mut [u8]) -> Result<(), InterleaveError> {
+        let codewords = self.codewords(data, output)?;
+        output.fill(0);
+        for bit in 0..data.len() * 8 {
+            let (codeword, position) = (bit / self.codeword_bits, bit % self.codeword_bits);
+            set_bit(output, position * codewords + codeword, get_bit(data, bit));
+        }
+        Ok(())
+    }
+
+    /// Restores the codewords of `data` received in transmit order into `output`.
+    pub fn deinterleave(&self, data: &[u8], output: &mut [u8]) -> Result<(), InterleaveError> {
+        let codewords = self.codewords(data, output)?;
+        output.fill(0);
+        for bit in 0..data.len() * 8 {
+            let (codeword, position) = (bit / self.codeword_bits, bit % self.codeword_bits);
+            set_bit(output, bit, get_bit(data, position * codewords + codeword));
+        }
+        Ok(())
+    }
+
+    fn codewords(&self, data: &[u8], output: &[u8]) -> Result<usize, InterleaveError> {
+        if data.len() != output.len() {
+            return Err(InterleaveError::LengthMismatch);
+        }
+        if !(data.len() * 8).is_multiple_of(self.codeword_bits) {
+            return Err(InterleaveError::PartialCodeword);
+        }
+        Ok(data.len() * 8 / self.codeword_bits)
+    }
+}
+
+// Bits are numbered MSB first, the order they are sent in
+fn get_bit(data: &[u8], bit: usize) -> bool {
+    data[bit / 8] & (0x80 >> (bit % 8)) != 0
+}
+
+fn set_bit(data: &mut [u8], bit: usize, value: bool) {
+    if value {
+        data[bit / 8] |= 0x80 >> (bit % 8);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interleave() {
+        // Four 8 bit codewords
+        let interleaver = Interleaver::new(8);
+        let data = [0xFF, 0x00, 0x00, 0x00];
+        let mut interleaved = [0u8; 4];
+        interleaver.interleave(&data, &mut interleaved).unwrap();
+        assert_eq!(interleaved, [0x88, 0x88, 0x88, 0x88]);
+
+        let mut restored = [0u8; 4];
+        interleaver
+            .deinterleave(&interleaved, &mut restored)
+            .unwrap();
+        assert_eq!(restored, data);
+    }
+
+    #[test]
+    fn test_burst_spread() {
+        // Eight 7 bit codewords
+        let interleaver = Interleaver::new(7);
+        let data: [u8; 7] = core::array::from_fn(|i| (i as u8).wrapping_mul(0x3B));
+        let mut interleaved = [0u8; 7];
+        interleaver.interleave(&data, &mut interleaved).unwrap();
+
+        // An 8 bit burst on air
+        interleaved[2] ^= 0x0F;
+        interleaved[3] ^= 0xF0;
+        let mut received = [0u8; 7];
+        interleaver
+            .deinterleave(&interleaved, &mut received)
+            .unwrap();
+
+        for codeword in 0..8 {
+            let errors = (0..7)
+                .filter(|position| {
+                    let bit = codeword * 7 + position;
+                    get_bit(&data, bit) != get_bit(&received, bit)
+                })
+                .count();
+            assert_eq!(errors, 1);
+        }
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        let interleaver = Interleaver::new(7);
+        let mut output = [0u8; 2];
+        assert_eq!(
+            interleaver.interleave(&[0; 3], &mut output),
+            Err(InterleaveError::LengthMismatch)
+        );
+        assert_eq!(
+            interleaver.interleave(&[0; 2], &mut output),
+            Err(InterleaveError::PartialCodeword)
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use crate::header::{Header, HEADER_LENGTH};
+use crate::settings::RF69_FIFO_SIZE;
+
+// Fixed length packets can fill the FIFO
+pub(crate) const MAX_PAYLOAD_LENGTH: usize = RF69_FIFO_SIZE - HEADER_LENGTH;
+
+/// Operation in progress for `Rfm69::on_interrupt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum InterruptState {
+    Idle,
+    /// Started by `Rfm69::start_transmit`, DIO0 signals PacketSent.
+    Transmitting,
+    /// Started by `Rfm69::start_receive`, DIO0 signals PayloadReady.
+    Receiving,
+}
+
+/// A packet read from the FIFO by `Rfm69::on_interrupt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ReceivedPacket {
+    pub header: Header,
+    pub length: usize,
+    payload: [u8; MAX_PAYLOAD_LENGTH],
+}
+
+impl ReceivedPacket {
+    pub(crate) fn new(header: Header, payload: &[u8]) -> Self {
+        let mut packet = ReceivedPacket {
+            header,
+            length: payload.len(),
+            payload: [0; MAX_PAYLOAD_LENGTH],
+        };
+        packet.payload[..payload.len()].copy_from_slice(payload);
+        packet
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.length]
+    }
+}
+
+/// Completed operation reported by `Rfm69::poll_event`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RadioEvent {
+    PacketSent,
+    PacketReceived(ReceivedPacket),
+    /// A packet failed the CRC check and was dropped, the receiver keeps listening.
+    CrcError,
+    /// The FIFO overflowed and was flushed, the receiver keeps listening.
+    FifoOverrun,
+}
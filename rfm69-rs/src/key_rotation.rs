@@ -0,0 +1,315 @@
+use crate::read_write::ReadWrite;
+use crate::reliable::ReliableDatagram;
+use crate::rfm69::Rfm69Error;
+use crate::session::MAC_LENGTH;
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+const OP_ROTATE: u8 = 0x21;
+
+/// Length of a rotation request: opcode, epoch, the wrapped key and the MAC.
+pub const REQUEST_LENGTH: usize = 3 + 16 + MAC_LENGTH;
+/// Length of a response: opcode, status, epoch and the MAC.
+pub const RESPONSE_LENGTH: usize = 4 + MAC_LENGTH;
+
+/// Answer of the peer to a key rotation request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RotationStatus {
+    Ok = 0x00,
+    /// The request isn't authenticated with the current key of the peer.
+    BadMac = 0x01,
+    /// The epoch isn't newer than the one of the current key, e.g. a replayed request.
+    StaleEpoch = 0x02,
+    /// The message is not a valid key rotation request.
+    Malformed = 0x03,
+}
+
+impl RotationStatus {
+    fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(RotationStatus::Ok),
+            0x01 => Some(RotationStatus::BadMac),
+            0x02 => Some(RotationStatus::StaleEpoch),
+            0x03 => Some(RotationStatus::Malformed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, defmt::Format)]
+pub enum RotationError {
+    Radio(Rfm69Error),
+    /// The peer refused the new key.
+    Rejected(RotationStatus),
+    /// The response was not a valid response, or wasn't authenticated with the new key.
+    InvalidResponse,
+}
+
+impl From<Rfm69Error> for RotationError {
+    fn from(error: Rfm69Error) -> Self {
+        RotationError::Radio(error)
+    }
+}
+
+fn tag(key: &[u8; 16], data: &[u8]) -> [u8; MAC_LENGTH] {
+    let mut mac = <Cmac<Aes128> as Mac>::new(key.into());
+    mac.update(data);
+    let mut tag = [0u8; MAC_LENGTH];
+    tag.copy_from_slice(&mac.finalize().into_bytes()[..MAC_LENGTH]);
+    tag
+}
+
+// Compares in constant time, like `SecureSession::open`
+fn verify(key: &[u8; 16], data: &[u8], mac: &[u8]) -> bool {
+    let mut cmac = <Cmac<Aes128> as Mac>::new(key.into());
+    cmac.update(data);
+    cmac.verify_truncated_left(mac).is_ok()
+}
+
+/// Peer side of a key rotation, holding the current key and its epoch.
+///
+/// A request carries the new key encrypted with the current key, its epoch and
+/// a MAC under the current key. The peer takes the new key if its epoch is
+/// newer than the current one, and confirms with a MAC under the new key, which
+/// proves to the initiator that the key arrived intact.
+///
+/// The keys are the application's to use, e.g. for `Rfm69::set_encryption_key`
+/// or a `session::SecureSession`, and to persist along with the epoch once
+/// `receive` returns true. If the response is lost the initiator repeats the
+/// request under the old key, so keep accepting it until the new key is in use.
+///
+/// With the `zeroize` feature the keys are wiped when the receiver is dropped.
+pub struct KeyRotationReceiver {
+    key: [u8; 16],
+    epoch: u16,
+    // Replaced by the last rotation, to confirm it again if the response was lost
+    previous_key: Option<[u8; 16]>,
+}
+
+impl KeyRotationReceiver {
+    pub fn new(key: &[u8; 16], epoch: u16) -> Self {
+        KeyRotationReceiver {
+            key: *key,
+            epoch,
+            previous_key: None,
+        }
+    }
+
+    pub fn key(&self) -> &[u8; 16] {
+        &self.key
+    }
+
+    pub fn epoch(&self) -> u16 {
+        self.epoch
+    }
+
+    /// Handles the rotation request `request` and writes the response into
+    /// `response`, returning its length.
+    pub fn handle(&mut self, request: &[u8], response: &mut [u8; RESPONSE_LENGTH]) -> usize {
+        let status = self.rotate(request);
+        response[0] = OP_ROTATE;
+        response[1] = status as u8;
+        response[2..4].copy_from_slice(&self.epoch.to_be_bytes());
+        let mac = tag(&self.key, &response[..4]);
+        response[4..].copy_from_slice(&mac);
+        RESPONSE_LENGTH
+    }
+
+    /// Receives the next rotation request through `network` and answers it.
+    /// Returns true if the key was replaced.
+    pub async fn receive<SPI, RESET, INTR, D>(
+        &mut self,
+        network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    ) -> Result<bool, Rfm69Error>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+        let request = network.receive_request(&mut buffer).await?;
+
+        let epoch = self.epoch;
+        let mut response = [0u8; RESPONSE_LENGTH];
+        let length = self.handle(&buffer[..request.length], &mut response);
+        network.respond(&request, &response[..length]).await?;
+        Ok(self.epoch != epoch)
+    }
+
+    fn rotate(&mut self, request: &[u8]) -> RotationStatus {
+        if request.len() != REQUEST_LENGTH || request[0] != OP_ROTATE {
+            return RotationStatus::Malformed;
+        }
+        let (body, mac) = request.split_at(REQUEST_LENGTH - MAC_LENGTH);
+        let epoch = u16::from_be_bytes([body[1], body[2]]);
+
+        if !verify(&self.key, body, mac) {
+            // Our confirmation of this rotation was lost, confirm it again
+            let repeated = self.previous_key.is_some_and(|previous| {
+                epoch == self.epoch
+                    && verify(&previous, body, mac)
+                    && unwrap_key(&previous, &body[3..]) == self.key
+            });
+            return match repeated {
+                true => RotationStatus::Ok,
+                false => RotationStatus::BadMac,
+            };
+        }
+        if epoch <= self.epoch {
+            return RotationStatus::StaleEpoch;
+        }
+
+        self.previous_key = Some(self.key);
+        self.key = unwrap_key(&self.key, &body[3..]);
+        self.epoch = epoch;
+        RotationStatus::Ok
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for KeyRotationReceiver {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+        self.key.zeroize();
+        if let Some(previous) = self.previous_key.as_mut() {
+            previous.zeroize();
+        }
+    }
+}
+
+fn wrap_key(key: &[u8; 16], new_key: &[u8; 16]) -> [u8; 16] {
+    let mut block = (*new_key).into();
+    Aes128::new(key.into()).encrypt_block(&mut block);
+    block.into()
+}
+
+fn unwrap_key(key: &[u8; 16], wrapped: &[u8]) -> [u8; 16] {
+    let mut block = *aes::Block::from_slice(wrapped);
+    Aes128::new(key.into()).decrypt_block(&mut block);
+    block.into()
+}
+
+fn rotation_request(key: &[u8; 16], new_key: &[u8; 16], epoch: u16) -> [u8; REQUEST_LENGTH] {
+    let mut request = [0u8; REQUEST_LENGTH];
+    request[0] = OP_ROTATE;
+    request[1..3].copy_from_slice(&epoch.to_be_bytes());
+    request[3..19].copy_from_slice(&wrap_key(key, new_key));
+    let mac = tag(key, &request[..19]);
+    request[19..].copy_from_slice(&mac);
+    request
+}
+
+fn check_response(new_key: &[u8; 16], epoch: u16, response: &[u8]) -> Result<(), RotationError> {
+    if response.len() != RESPONSE_LENGTH || response[0] != OP_ROTATE {
+        return Err(RotationError::InvalidResponse);
+    }
+    match RotationStatus::from_value(response[1]) {
+        Some(RotationStatus::Ok) => {}
+        Some(status) => return Err(RotationError::Rejected(status)),
+        None => return Err(RotationError::InvalidResponse),
+    }
+    let confirmed = u16::from_be_bytes([response[2], response[3]]) == epoch
+        && verify(new_key, &response[..4], &response[4..]);
+    match confirmed {
+        true => Ok(()),
+        false => Err(RotationError::InvalidResponse),
+    }
+}
+
+/// Replaces `key`, shared with the node `to` running a `KeyRotationReceiver`,
+/// with `new_key`. `epoch` must be newer than the epoch of `key`.
+///
+/// Switch to `new_key` only once this returns `Ok`. On an error the peer may
+/// still have taken the new key, e.g. if its response was lost: call it again
+/// with the same arguments, the peer confirms a rotation it already made.
+pub async fn rotate_key<SPI, RESET, INTR, D>(
+    network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    to: u8,
+    key: &[u8; 16],
+    new_key: &[u8; 16],
+    epoch: u16,
+) -> Result<(), RotationError>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let request = rotation_request(key, new_key, epoch);
+    let mut response = [0u8; RESPONSE_LENGTH];
+    let length = network
+        .send_to_wait_response(to, &request, &mut response)
+        .await?;
+    check_response(new_key, epoch, &response[..length])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+    const NEW_KEY: [u8; 16] = *b"fedcba9876543210";
+
+    fn handle(receiver: &mut KeyRotationReceiver, request: &[u8]) -> [u8; RESPONSE_LENGTH] {
+        let mut response = [0u8; RESPONSE_LENGTH];
+        assert_eq!(receiver.handle(request, &mut response), RESPONSE_LENGTH);
+        response
+    }
+
+    #[test]
+    fn test_rotation() {
+        let mut receiver = KeyRotationReceiver::new(&KEY, 1);
+        let request = rotation_request(&KEY, &NEW_KEY, 2);
+        // The new key doesn't go over the air in clear
+        assert!(!request.windows(16).any(|window| window == NEW_KEY));
+
+        let response = handle(&mut receiver, &request);
+        assert_eq!(check_response(&NEW_KEY, 2, &response), Ok(()));
+        assert_eq!(receiver.key(), &NEW_KEY);
+        assert_eq!(receiver.epoch(), 2);
+        // Only the new key confirms
+        assert_eq!(
+            check_response(&KEY, 2, &response),
+            Err(RotationError::InvalidResponse)
+        );
+
+        // The response was lost and the request is sent again
+        let response = handle(&mut receiver, &request);
+        assert_eq!(check_response(&NEW_KEY, 2, &response), Ok(()));
+        assert_eq!(receiver.key(), &NEW_KEY);
+    }
+
+    #[test]
+    fn test_rejected_rotation() {
+        let mut receiver = KeyRotationReceiver::new(&KEY, 5);
+
+        let response = handle(&mut receiver, &rotation_request(&KEY, &NEW_KEY, 5));
+        assert_eq!(
+            check_response(&NEW_KEY, 5, &response),
+            Err(RotationError::Rejected(RotationStatus::StaleEpoch))
+        );
+        let response = handle(&mut receiver, &rotation_request(&NEW_KEY, &KEY, 6));
+        assert_eq!(
+            check_response(&NEW_KEY, 6, &response),
+            Err(RotationError::Rejected(RotationStatus::BadMac))
+        );
+
+        let mut request = rotation_request(&KEY, &NEW_KEY, 6);
+        request[5] ^= 0x01;
+        assert_eq!(
+            handle(&mut receiver, &request)[1],
+            RotationStatus::BadMac as u8
+        );
+        assert_eq!(
+            handle(&mut receiver, &request[..10])[1],
+            RotationStatus::Malformed as u8
+        );
+        assert_eq!(receiver.key(), &KEY);
+        assert_eq!(receiver.epoch(), 5);
+    }
+}
@@ -1,8 +1,59 @@
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
 
 
+pub mod beacon;
+pub mod ber;
+pub mod buffered;
+pub mod bulk;
+pub mod crc;
+pub mod decoder;
+pub mod dump;
+pub mod duty_cycle;
+pub mod ert;
+#[cfg(feature = "embedded-hal-02")]
+pub mod hal02;
+pub mod header;
+#[cfg(feature = "hil")]
+pub mod hil;
+pub mod interleave;
+pub mod interrupt;
+pub mod key_rotation;
+pub mod link_stats;
+pub mod listen;
+pub mod modulation;
+pub mod mysensors;
+pub mod network_id;
+pub mod ota;
+#[cfg(feature = "radio")]
+pub mod radio_hal;
+pub mod range_test;
+pub mod raw;
+pub mod region;
+pub mod register_decoder;
+pub mod remote;
 pub mod rfm69;
 pub mod registers;
-mod read_write;
-mod settings;
+pub mod reliable;
+pub mod retry;
+pub mod rolling;
+pub mod read_write;
+pub mod self_test;
+pub mod session;
+pub mod settings;
+pub mod shared;
+#[cfg(any(test, feature = "std"))]
+pub mod sim;
+#[cfg(feature = "smoltcp")]
+pub mod smoltcp_phy;
+pub mod socket;
+pub mod stats;
+pub mod stream;
+pub mod temperature;
+pub mod test_pattern;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod time_sync;
+pub mod transceiver;
+pub mod units;
+pub mod watchdog;
@@ -0,0 +1,162 @@
+/// Number of peers tracked by `LinkStats`.
+pub const MAX_PEERS: usize = 16;
+
+// Weight of a new sample in the RSSI average, 1/2^RSSI_AVERAGE_SHIFT
+const RSSI_AVERAGE_SHIFT: u32 = 3;
+// Sequence numbers further ahead than this are treated as a restarted sender
+const MAX_SEQUENCE_GAP: u8 = 64;
+
+/// Link quality of the packets received from one peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PeerStats {
+    pub address: u8,
+    pub packets_received: u32,
+    /// Packets missing from gaps in the sequence numbers.
+    pub packets_lost: u32,
+    pub last_rssi_dbm: i16,
+    pub last_fei_hz: i32,
+    // Average RSSI in 1/16 dBm
+    average_rssi: i32,
+    last_id: u8,
+}
+
+impl PeerStats {
+    fn new(address: u8, id: u8, rssi_dbm: i16, fei_hz: i32) -> Self {
+        PeerStats {
+            address,
+            packets_received: 1,
+            packets_lost: 0,
+            last_rssi_dbm: rssi_dbm,
+            last_fei_hz: fei_hz,
+            average_rssi: rssi_dbm as i32 * 16,
+            last_id: id,
+        }
+    }
+
+    /// Exponential moving average of the RSSI.
+    pub fn average_rssi_dbm(&self) -> i16 {
+        (self.average_rssi / 16) as i16
+    }
+
+    /// Estimated packet loss in parts per thousand.
+    pub fn loss_permille(&self) -> u16 {
+        let sent = self.packets_received as u64 + self.packets_lost as u64;
+        (self.packets_lost as u64 * 1000 / sent) as u16
+    }
+
+    fn record(&mut self, id: u8, rssi_dbm: i16, fei_hz: i32) {
+        let gap = id.wrapping_sub(self.last_id);
+        if gap == 0 {
+            // Retransmission or a sender without sequence numbers
+        } else if gap <= MAX_SEQUENCE_GAP {
+            self.packets_lost += gap as u32 - 1;
+        }
+
+        self.packets_received += 1;
+        self.last_id = id;
+        self.last_rssi_dbm = rssi_dbm;
+        self.last_fei_hz = fei_hz;
+        self.average_rssi += (rssi_dbm as i32 * 16 - self.average_rssi) >> RSSI_AVERAGE_SHIFT;
+    }
+}
+
+/// Per peer link statistics, see `Rfm69::set_link_stats`.
+///
+/// Once `MAX_PEERS` peers are tracked, the peer with the fewest received
+/// packets makes room for a new one.
+pub struct LinkStats {
+    peers: [Option<PeerStats>; MAX_PEERS],
+}
+
+impl Default for LinkStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LinkStats {
+    pub const fn new() -> Self {
+        LinkStats {
+            peers: [None; MAX_PEERS],
+        }
+    }
+
+    pub fn peer(&self, address: u8) -> Option<&PeerStats> {
+        self.peers().find(|peer| peer.address == address)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerStats> {
+        self.peers.iter().flatten()
+    }
+
+    pub fn clear(&mut self) {
+        self.peers = [None; MAX_PEERS];
+    }
+
+    /// Accounts a packet with sequence number `id` received from `address`.
+    pub fn record(&mut self, address: u8, id: u8, rssi_dbm: i16, fei_hz: i32) {
+        if let Some(peer) = self
+            .peers
+            .iter_mut()
+            .flatten()
+            .find(|peer| peer.address == address)
+        {
+            peer.record(id, rssi_dbm, fei_hz);
+            return;
+        }
+
+        let slot = match self.peers.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => self
+                .peers
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, peer)| peer.map_or(0, |peer| peer.packets_received))
+                .map_or(0, |(slot, _)| slot),
+        };
+        self.peers[slot] = Some(PeerStats::new(address, id, rssi_dbm, fei_hz));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sequence_gaps() {
+        let mut stats = LinkStats::new();
+        stats.record(0x02, 1, -60, 100);
+        stats.record(0x02, 2, -60, 100);
+        // Two packets lost
+        stats.record(0x02, 5, -70, -200);
+        // Retransmission
+        stats.record(0x02, 5, -70, -200);
+        // Sender restarted
+        stats.record(0x02, 1, -70, -200);
+
+        let peer = stats.peer(0x02).unwrap();
+        assert_eq!(peer.packets_received, 5);
+        assert_eq!(peer.packets_lost, 2);
+        assert_eq!(peer.loss_permille(), 285);
+        assert_eq!(peer.last_rssi_dbm, -70);
+        assert_eq!(peer.last_fei_hz, -200);
+        assert!(peer.average_rssi_dbm() < -60 && peer.average_rssi_dbm() > -70);
+        assert_eq!(stats.peer(0x03), None);
+    }
+
+    #[test]
+    fn test_table_full() {
+        let mut stats = LinkStats::new();
+        for address in 0..MAX_PEERS as u8 {
+            stats.record(address, 1, -60, 0);
+            stats.record(address, 2, -60, 0);
+        }
+        stats.record(0, 3, -60, 0);
+        stats.record(0x80, 1, -60, 0);
+
+        assert_eq!(stats.peers().count(), MAX_PEERS);
+        assert!(stats.peer(0).is_some());
+        assert!(stats.peer(0x80).is_some());
+        assert!(stats.peer(1).is_none());
+    }
+}
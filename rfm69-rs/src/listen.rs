@@ -0,0 +1,211 @@
+/// Time unit of the Listen mode idle and receive durations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ListenResolution {
+    Us64 = 0b01,
+    Us4100 = 0b10,
+    Us262000 = 0b11,
+}
+
+impl ListenResolution {
+    const ALL: [ListenResolution; 3] = [Self::Us64, Self::Us4100, Self::Us262000];
+
+    pub fn micros(self) -> u32 {
+        match self {
+            Self::Us64 => 64,
+            Self::Us4100 => 4_100,
+            Self::Us262000 => 262_000,
+        }
+    }
+}
+
+/// Condition for a Listen mode receive window to accept a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ListenCriteria {
+    /// The RSSI goes above the threshold.
+    Rssi = 0x00,
+    /// The RSSI goes above the threshold and the sync address matches.
+    RssiAndSyncAddress = 0x08,
+}
+
+/// What the radio does once a receive window accepted a packet. RSSI only
+/// criteria wake it up on any noise above the threshold, so with those the
+/// actions returning to Listen mode keep false wake-ups short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ListenEnd {
+    /// Stay in Rx until Listen mode is turned off, the lowest latency.
+    StayInRx = 0b00 << 1,
+    /// Stay in Rx until PayloadReady or Timeout, then switch to the mode set in
+    /// RegOpMode and leave Listen mode.
+    ModeAfterPacket = 0b01 << 1,
+    /// Stay in Rx until PayloadReady or Timeout, then resume Listen mode. The
+    /// packet is lost unless read before the next receive window.
+    ResumeListen = 0b10 << 1,
+}
+
+/// Why a Listen mode duration can't be programmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum ListenError {
+    #[cfg_attr(feature = "std", error("listen duration is zero"))]
+    ZeroDuration,
+    /// Longer than 255 times the 262 ms resolution, about 66.8 s.
+    #[cfg_attr(feature = "std", error("listen duration too long"))]
+    DurationTooLong,
+}
+
+/// Duty cycle of Listen mode, in which the radio alternates between a short
+/// receive window and a long idle period on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ListenConfig {
+    pub idle_resolution: ListenResolution,
+    pub idle_coefficient: u8,
+    pub rx_resolution: ListenResolution,
+    pub rx_coefficient: u8,
+    pub criteria: ListenCriteria,
+    pub end: ListenEnd,
+}
+
+impl Default for ListenConfig {
+    /// About 250 ms asleep for every 1 ms listening.
+    fn default() -> Self {
+        ListenConfig {
+            idle_resolution: ListenResolution::Us4100,
+            idle_coefficient: 61,
+            rx_resolution: ListenResolution::Us64,
+            rx_coefficient: 16,
+            criteria: ListenCriteria::RssiAndSyncAddress,
+            end: ListenEnd::StayInRx,
+        }
+    }
+}
+
+impl ListenConfig {
+    /// Picks the finest resolutions able to represent `idle_us` and `rx_us`,
+    /// rounding up to a multiple of the resolution.
+    pub fn new(idle_us: u32, rx_us: u32) -> Result<Self, ListenError> {
+        let (idle_resolution, idle_coefficient) = Self::coefficient(idle_us)?;
+        let (rx_resolution, rx_coefficient) = Self::coefficient(rx_us)?;
+        Ok(ListenConfig {
+            idle_resolution,
+            idle_coefficient,
+            rx_resolution,
+            rx_coefficient,
+            criteria: ListenCriteria::RssiAndSyncAddress,
+            end: ListenEnd::StayInRx,
+        })
+    }
+
+    /// `new` with the durations in milliseconds.
+    pub fn from_millis(idle_ms: u32, rx_ms: u32) -> Result<Self, ListenError> {
+        let micros = |ms: u32| ms.checked_mul(1_000).ok_or(ListenError::DurationTooLong);
+        Self::new(micros(idle_ms)?, micros(rx_ms)?)
+    }
+
+    fn coefficient(duration_us: u32) -> Result<(ListenResolution, u8), ListenError> {
+        if duration_us == 0 {
+            return Err(ListenError::ZeroDuration);
+        }
+        ListenResolution::ALL
+            .into_iter()
+            .find_map(|resolution| {
+                let coefficient = duration_us.div_ceil(resolution.micros());
+                match coefficient {
+                    1..=255 => Some((resolution, coefficient as u8)),
+                    _ => None,
+                }
+            })
+            .ok_or(ListenError::DurationTooLong)
+    }
+
+    pub fn idle_us(&self) -> u32 {
+        self.idle_coefficient as u32 * self.idle_resolution.micros()
+    }
+
+    pub fn rx_us(&self) -> u32 {
+        self.rx_coefficient as u32 * self.rx_resolution.micros()
+    }
+
+    /// Idle and receive duration, the longest a packet may wait for the receiver.
+    pub fn period_us(&self) -> u32 {
+        self.idle_us() + self.rx_us()
+    }
+
+    /// Preamble length in bytes covering a whole listen period at `bitrate`, so a
+    /// listening receiver is guaranteed to wake up during the preamble. Saturates
+    /// at the longest preamble, see `covering_preamble_length`.
+    pub fn wake_preamble_length(&self, bitrate: u32) -> u16 {
+        covering_preamble_length(self.period_us(), bitrate).unwrap_or(u16::MAX)
+    }
+
+    pub(crate) fn listen1(&self) -> u8 {
+        (self.idle_resolution as u8) << 6
+            | (self.rx_resolution as u8) << 4
+            | self.criteria as u8
+            | self.end as u8
+    }
+}
+
+/// Preamble length in bytes lasting `period_us` at `bitrate`, plus a few bytes
+/// for the receiver to lock once awake. `None` if it takes more than the 65535
+/// bytes of RegPreamble, the receiver could then sleep through the whole preamble.
+pub fn covering_preamble_length(period_us: u32, bitrate: u32) -> Option<u16> {
+    let bytes = (period_us as u64 * bitrate as u64).div_ceil(8_000_000);
+    u16::try_from(bytes + 4).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_listen_config() {
+        let config = ListenConfig::new(2_000_000, 2_000).unwrap();
+        assert_eq!(config.idle_resolution, ListenResolution::Us262000);
+        assert_eq!(config.idle_coefficient, 8);
+        assert_eq!(config.rx_resolution, ListenResolution::Us64);
+        assert_eq!(config.rx_coefficient, 32);
+        assert_eq!(config.listen1(), 0xD8);
+
+        let config = ListenConfig {
+            criteria: ListenCriteria::Rssi,
+            end: ListenEnd::ResumeListen,
+            ..config
+        };
+        assert_eq!(config.listen1(), 0xD4);
+
+        assert_eq!(
+            ListenConfig::from_millis(2_000, 2),
+            ListenConfig::new(2_000_000, 2_000)
+        );
+        assert_eq!(ListenConfig::new(0, 2_000), Err(ListenError::ZeroDuration));
+        assert_eq!(
+            ListenConfig::new(100_000_000, 2_000),
+            Err(ListenError::DurationTooLong)
+        );
+        assert_eq!(
+            ListenConfig::from_millis(5_000_000, 2),
+            Err(ListenError::DurationTooLong)
+        );
+    }
+
+    #[test]
+    fn test_wake_preamble_length() {
+        let config = ListenConfig::default();
+        assert_eq!(config.idle_us(), 250_100);
+        assert_eq!(config.rx_us(), 1_024);
+        // 251.124 ms at 19.2 kbit/s is 603 bytes
+        assert_eq!(config.wake_preamble_length(19_200), 607);
+        assert_eq!(config.period_us(), 251_124);
+    }
+
+    #[test]
+    fn test_covering_preamble_length() {
+        // 1 s at 4.8 kbit/s is 600 bytes
+        assert_eq!(covering_preamble_length(1_000_000, 4_800), Some(604));
+        assert_eq!(covering_preamble_length(0, 4_800), Some(4));
+        // 2 s at 300 kbit/s is 75000 bytes
+        assert_eq!(covering_preamble_length(2_000_000, 300_000), None);
+        let config = ListenConfig::new(60_000_000, 2_000).unwrap();
+        assert_eq!(config.wake_preamble_length(300_000), u16::MAX);
+    }
+}
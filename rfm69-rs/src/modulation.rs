@@ -0,0 +1,257 @@
+use crate::registers::Modulation;
+use crate::settings::{RF69_FSTEP, RF69_FXOSC_HZ};
+
+// Frequency deviation limits of the SX1231
+const MIN_DEVIATION_HZ: u32 = 600;
+const MAX_DEVIATION_PLUS_HALF_BITRATE_HZ: u32 = 500_000;
+// FSK bitrate range of the SX1231
+const MIN_BITRATE: u32 = 1_200;
+const MAX_BITRATE: u32 = 300_000;
+// DccFreq used by the presets, the cutoff is 0.125% of the bandwidth
+const RX_BW_DCC_FREQ: u8 = 0xE0;
+const RX_BW_MANTISSAS: [(u32, u8); 3] = [(16, 0b00), (20, 0b01), (24, 0b10)];
+
+/// Why a set of FSK modulation parameters can't be demodulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum ModulationError {
+    /// The bitrate is outside 1.2 to 300 kbit/s.
+    #[cfg_attr(feature = "std", error("bitrate out of range"))]
+    BitrateOutOfRange,
+    /// The deviation is below 600 Hz, or the deviation plus half the bitrate
+    /// exceeds 500 kHz.
+    #[cfg_attr(feature = "std", error("frequency deviation out of range"))]
+    DeviationOutOfRange,
+    /// The modulation index 2 * Fdev / BR is below 0.5.
+    #[cfg_attr(feature = "std", error("modulation index below 0.5"))]
+    ModulationIndexTooLow,
+    /// The receiver bandwidth is above 500 kHz.
+    #[cfg_attr(feature = "std", error("Rx bandwidth out of range"))]
+    RxBandwidthOutOfRange,
+    /// The receiver bandwidth is narrower than Fdev + BR / 2, so the receiver
+    /// can't see both FSK tones.
+    #[cfg_attr(
+        feature = "std",
+        error("Rx bandwidth too narrow for the deviation and bitrate")
+    )]
+    RxBandwidthTooNarrow,
+}
+
+/// ModulationShaping of RegDataModul, the filter shaping the transmitted pulses.
+/// Narrower filters reduce the occupied bandwidth and add intersymbol interference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Shaping {
+    None,
+    /// FSK Gaussian filter with BT = 1.0, used by the GFSK presets.
+    GaussianBt1_0,
+    GaussianBt0_5,
+    GaussianBt0_3,
+    /// OOK filter with its cutoff at the bitrate.
+    OokCutoffBitrate,
+    /// OOK filter with its cutoff at twice the bitrate.
+    OokCutoff2Bitrate,
+}
+
+impl Shaping {
+    pub fn bits(self) -> u8 {
+        match self {
+            Self::None => 0b00,
+            Self::GaussianBt1_0 | Self::OokCutoffBitrate => 0b01,
+            Self::GaussianBt0_5 | Self::OokCutoff2Bitrate => 0b10,
+            Self::GaussianBt0_3 => 0b11,
+        }
+    }
+
+    /// True if the shaping can be used with `modulation`.
+    pub fn supports(self, modulation: Modulation) -> bool {
+        match self {
+            Self::None => true,
+            Self::GaussianBt1_0 | Self::GaussianBt0_5 | Self::GaussianBt0_3 => {
+                modulation == Modulation::Fsk
+            }
+            Self::OokCutoffBitrate | Self::OokCutoff2Bitrate => modulation == Modulation::Ook,
+        }
+    }
+}
+
+/// Custom FSK modulation parameters for `Rfm69::set_fsk_modulation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FskModulation {
+    pub bitrate: u32,
+    pub deviation_hz: u32,
+    /// Single sided receiver bandwidth, rounded up to the next bandwidth supported
+    /// by the radio.
+    pub rx_bandwidth_hz: u32,
+}
+
+impl FskModulation {
+    /// Validated parameters, for custom modem profiles defined as constants like
+    /// the presets. Panics if `validate` fails, which is a compile error in a constant.
+    pub const fn new(bitrate: u32, deviation_hz: u32, rx_bandwidth_hz: u32) -> Self {
+        let modulation = FskModulation {
+            bitrate,
+            deviation_hz,
+            rx_bandwidth_hz,
+        };
+        match modulation.validate() {
+            Ok(()) => modulation,
+            Err(ModulationError::BitrateOutOfRange) => panic!("bitrate out of range"),
+            Err(ModulationError::DeviationOutOfRange) => panic!("deviation out of range"),
+            Err(ModulationError::ModulationIndexTooLow) => panic!("modulation index too low"),
+            Err(ModulationError::RxBandwidthOutOfRange) => panic!("rx bandwidth out of range"),
+            Err(ModulationError::RxBandwidthTooNarrow) => panic!("rx bandwidth too narrow"),
+        }
+    }
+
+    /// Checks that a receiver with these parameters can demodulate the signal.
+    pub const fn validate(&self) -> Result<(), ModulationError> {
+        if self.bitrate < MIN_BITRATE || self.bitrate > MAX_BITRATE {
+            return Err(ModulationError::BitrateOutOfRange);
+        }
+        let occupied_hz = self.deviation_hz + self.bitrate / 2;
+        if self.deviation_hz < MIN_DEVIATION_HZ || occupied_hz > MAX_DEVIATION_PLUS_HALF_BITRATE_HZ
+        {
+            return Err(ModulationError::DeviationOutOfRange);
+        }
+        // 2 * Fdev / BR >= 0.5
+        if self.deviation_hz * 4 < self.bitrate {
+            return Err(ModulationError::ModulationIndexTooLow);
+        }
+        match rx_bandwidth(self.rx_bandwidth_hz) {
+            Ok((rx_bandwidth_hz, _)) if rx_bandwidth_hz < occupied_hz => {
+                Err(ModulationError::RxBandwidthTooNarrow)
+            }
+            Ok(_) => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// RegBitrateMsb to RegFdevLsb.
+    pub const fn bitrate_deviation_values(&self) -> [u8; 4] {
+        let divider = ((RF69_FXOSC_HZ + self.bitrate / 2) / self.bitrate) as u16;
+        let steps = (self.deviation_hz as u64 * RF69_FSTEP as u64 + RF69_FXOSC_HZ as u64 / 2)
+            / RF69_FXOSC_HZ as u64;
+        let [bitrate_msb, bitrate_lsb] = divider.to_be_bytes();
+        let [fdev_msb, fdev_lsb] = (steps as u16).to_be_bytes();
+        [bitrate_msb, bitrate_lsb, fdev_msb, fdev_lsb]
+    }
+
+    /// RegRxBw, also used for RegAfcBw.
+    pub const fn rx_bw_value(&self) -> Result<u8, ModulationError> {
+        match rx_bandwidth(self.rx_bandwidth_hz) {
+            Ok((_, value)) => Ok(value),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+// Narrowest FSK receiver bandwidth of at least `bandwidth_hz`, with its RegRxBw value
+const fn rx_bandwidth(bandwidth_hz: u32) -> Result<(u32, u8), ModulationError> {
+    let mut exponent = 8;
+    while exponent > 0 {
+        exponent -= 1;
+        let mut index = RX_BW_MANTISSAS.len();
+        while index > 0 {
+            index -= 1;
+            let (mantissa, bits) = RX_BW_MANTISSAS[index];
+            let hz = RF69_FXOSC_HZ / (mantissa << (exponent + 2));
+            if hz >= bandwidth_hz {
+                return Ok((hz, RX_BW_DCC_FREQ | bits << 3 | exponent as u8));
+            }
+        }
+    }
+    Err(ModulationError::RxBandwidthOutOfRange)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let modulation = FskModulation {
+            bitrate: 19_200,
+            deviation_hz: 38_400,
+            rx_bandwidth_hz: 50_000,
+        };
+        assert_eq!(modulation.validate(), Ok(()));
+
+        let error = |bitrate, deviation_hz, rx_bandwidth_hz| {
+            FskModulation {
+                bitrate,
+                deviation_hz,
+                rx_bandwidth_hz,
+            }
+            .validate()
+            .unwrap_err()
+        };
+        assert_eq!(
+            error(500, 5_000, 50_000),
+            ModulationError::BitrateOutOfRange
+        );
+        assert_eq!(
+            error(19_200, 500, 50_000),
+            ModulationError::DeviationOutOfRange
+        );
+        assert_eq!(
+            error(250_000, 400_000, 500_000),
+            ModulationError::DeviationOutOfRange
+        );
+        assert_eq!(
+            error(100_000, 20_000, 200_000),
+            ModulationError::ModulationIndexTooLow
+        );
+        assert_eq!(
+            error(19_200, 38_400, 600_000),
+            ModulationError::RxBandwidthOutOfRange
+        );
+        assert_eq!(
+            error(19_200, 38_400, 20_000),
+            ModulationError::RxBandwidthTooNarrow
+        );
+    }
+
+    #[test]
+    fn test_register_values() {
+        let modulation = FskModulation {
+            bitrate: 19_200,
+            deviation_hz: 38_400,
+            rx_bandwidth_hz: 40_000,
+        };
+        // Same as the FskRb19_2Fd38_4 preset
+        assert_eq!(
+            modulation.bitrate_deviation_values(),
+            [0x06, 0x83, 0x02, 0x75]
+        );
+        assert_eq!(modulation.rx_bw_value(), Ok(0xF3));
+
+        assert_eq!(rx_bandwidth(1), Ok((2_604, 0xF7)));
+        assert_eq!(rx_bandwidth(500_000), Ok((500_000, 0xE0)));
+    }
+
+    #[test]
+    fn test_shaping() {
+        assert_eq!(Shaping::GaussianBt0_3.bits(), 0b11);
+        assert_eq!(Shaping::OokCutoff2Bitrate.bits(), 0b10);
+        assert!(Shaping::GaussianBt0_5.supports(Modulation::Fsk));
+        assert!(!Shaping::GaussianBt0_5.supports(Modulation::Ook));
+        assert!(Shaping::OokCutoffBitrate.supports(Modulation::Ook));
+        assert!(Shaping::None.supports(Modulation::Ook));
+    }
+
+    #[test]
+    fn test_const_profile() {
+        const PROFILE: FskModulation = FskModulation::new(19_200, 38_400, 50_000);
+        const VALUES: [u8; 4] = PROFILE.bitrate_deviation_values();
+        const RX_BW: Result<u8, ModulationError> = PROFILE.rx_bw_value();
+        assert_eq!(VALUES, [0x06, 0x83, 0x02, 0x75]);
+        assert_eq!(RX_BW, Ok(0xEB));
+    }
+
+    #[test]
+    #[should_panic(expected = "modulation index too low")]
+    fn test_invalid_profile() {
+        FskModulation::new(100_000, 20_000, 200_000);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::header::Datagram;
+use crate::modulation::FskModulation;
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error, Rfm69Mode};
+use crate::settings::RF69_FIFO_SIZE;
+use crate::units::Frequency;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+// Settings of the MySensors RFM69 driver, the default one built on the LowPowerLab
+// library rather than the one enabled by MY_RFM69_NEW_DRIVER
+
+/// Network id of MySensors networks that don't set MY_RFM69_NETWORKID.
+pub const DEFAULT_NETWORK_ID: u8 = 100;
+/// Carrier frequency of MySensors networks that don't set MY_RFM69_FREQUENCY.
+pub const DEFAULT_FREQUENCY: Frequency = Frequency::from_mhz(868);
+/// First sync word byte, the network id is the second.
+pub const SYNC_WORD: u8 = 0x2D;
+pub const PREAMBLE_LENGTH: u16 = 3;
+/// 55.555 kbit/s with 50 kHz deviation in a 125 kHz receiver bandwidth.
+pub const MODULATION: FskModulation = FskModulation::new(55_555, 50_000, 125_000);
+pub const BROADCAST_ADDRESS: u8 = 255;
+/// Target, sender and control byte after the length byte.
+pub const HEADER_LENGTH: usize = 3;
+/// Longest payload the driver sends.
+pub const MAX_DATA_LENGTH: usize = 61;
+/// Time `MySensorsTransport::send_to` waits for an ACK before repeating the packet.
+pub const ACK_TIMEOUT_MS: u32 = 40;
+/// Repetitions of a packet that wasn't acknowledged.
+pub const RETRIES: u8 = 2;
+
+// Control byte flags
+const CTL_SENDACK: u8 = 0x80;
+const CTL_REQACK: u8 = 0x40;
+
+/// The transport layer of a MySensors node or gateway, for the radio settings of
+/// `Rfm69::set_mysensors_profile`. The payloads are MySensors messages, which
+/// the application encodes and decodes.
+///
+/// Packets to a single node request an ACK, which `receive` sends back. Unlike
+/// `ReliableDatagram` there is no sequence number, a repeated packet whose ACK
+/// was lost is received twice.
+pub struct MySensorsTransport<SPI, RESET, INTR, D> {
+    radio: Rfm69<SPI, RESET, INTR, D>,
+    address: u8,
+}
+
+impl<SPI, RESET, INTR, D> MySensorsTransport<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    /// Configures the initialized `radio` for the MySensors network `network_id`,
+    /// as the node `address`. The gateway is node 0.
+    pub fn new(
+        mut radio: Rfm69<SPI, RESET, INTR, D>,
+        address: u8,
+        network_id: u8,
+    ) -> Result<Self, Rfm69Error> {
+        radio.set_mysensors_profile(network_id)?;
+        Ok(MySensorsTransport { radio, address })
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn radio(&mut self) -> &mut Rfm69<SPI, RESET, INTR, D> {
+        &mut self.radio
+    }
+
+    pub fn release(self) -> Rfm69<SPI, RESET, INTR, D> {
+        self.radio
+    }
+
+    /// Sends `data` to the node `to`, and like `sendWithRetry` of the driver
+    /// repeats it up to `RETRIES` times until it is acknowledged. Broadcasts are
+    /// sent once.
+    pub async fn send_to(&mut self, to: u8, data: &[u8]) -> Result<(), Rfm69Error> {
+        if to == BROADCAST_ADDRESS {
+            return self.send_frame(to, 0, data).await;
+        }
+
+        for attempt in 0..=RETRIES {
+            if attempt > 0 {
+                self.radio.record_retransmission();
+            }
+            self.send_frame(to, CTL_REQACK, data).await?;
+            if self.wait_ack(to).await? {
+                return Ok(());
+            }
+        }
+
+        self.radio.report_timeout().await?;
+        Err(Rfm69Error::AckTimeout)
+    }
+
+    async fn send_frame(&mut self, to: u8, control: u8, data: &[u8]) -> Result<(), Rfm69Error> {
+        if data.len() > MAX_DATA_LENGTH {
+            return Err(Rfm69Error::MessageTooLarge);
+        }
+
+        let mut frame = [0u8; HEADER_LENGTH + MAX_DATA_LENGTH];
+        frame[..HEADER_LENGTH].copy_from_slice(&[to, self.address, control]);
+        frame[HEADER_LENGTH..HEADER_LENGTH + data.len()].copy_from_slice(data);
+        self.radio
+            .send_raw(&frame[..HEADER_LENGTH + data.len()])
+            .await
+    }
+
+    async fn wait_ack(&mut self, from: u8) -> Result<bool, Rfm69Error> {
+        self.radio.set_mode(Rfm69Mode::Rx).await?;
+
+        let mut frame = [0u8; RF69_FIFO_SIZE];
+        for _ in 0..ACK_TIMEOUT_MS {
+            if self.radio.is_message_available()? {
+                let length = match self.radio.receive_raw(&mut frame) {
+                    Ok(length) => length,
+                    Err(Rfm69Error::CrcFailure | Rfm69Error::FifoOverrun) => continue,
+                    Err(error) => return Err(error),
+                };
+                if let [to, sender, control, ..] = frame[..length] {
+                    if to == self.address && sender == from && control & CTL_SENDACK != 0 {
+                        return Ok(true);
+                    }
+                }
+            }
+            self.radio.delay.delay_ms(1).await;
+        }
+
+        Ok(false)
+    }
+
+    /// Reads the received packet into `buffer` and acknowledges it if the sender
+    /// asked for it.
+    ///
+    /// Returns `Rfm69Error::NoMessage` for ACKs, packets to other nodes and
+    /// packets too short for the header.
+    pub async fn receive(&mut self, buffer: &mut [u8]) -> Result<Datagram, Rfm69Error> {
+        let mut frame = [0u8; RF69_FIFO_SIZE];
+        let length = self.radio.receive_raw(&mut frame)?;
+        if length < HEADER_LENGTH {
+            return Err(Rfm69Error::NoMessage);
+        }
+        let [to, from, control] = [frame[0], frame[1], frame[2]];
+        let broadcast = to == BROADCAST_ADDRESS;
+        if (to != self.address && !broadcast) || control & CTL_SENDACK != 0 {
+            return Err(Rfm69Error::NoMessage);
+        }
+
+        let data = &frame[HEADER_LENGTH..length];
+        if buffer.len() < data.len() {
+            return Err(Rfm69Error::BufferTooSmall);
+        }
+        buffer[..data.len()].copy_from_slice(data);
+
+        if control & CTL_REQACK != 0 && !broadcast {
+            self.send_frame(from, CTL_SENDACK, &[]).await?;
+        }
+        Ok(Datagram {
+            from,
+            length: data.len(),
+            broadcast,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registers::Register;
+    use crate::sim::{SimChannel, SimRfm69};
+    use embassy_futures::select::select;
+
+    async fn radio(channel: &SimChannel) -> SimRfm69 {
+        let mut radio = channel.radio();
+        radio.init().await.unwrap();
+        radio
+    }
+
+    #[tokio::test]
+    async fn test_profile() {
+        let channel = SimChannel::new(1);
+        let mut radio = radio(&channel).await;
+        assert_eq!(
+            radio.set_mysensors_profile(0),
+            Err(Rfm69Error::ConfigurationError)
+        );
+        radio.set_mysensors_profile(DEFAULT_NETWORK_ID).unwrap();
+
+        let mut raw = radio.raw();
+        let mut modem = [0u8; 5];
+        raw.read_many(Register::DataModul, &mut modem).unwrap();
+        assert_eq!(modem, [0x00, 0x02, 0x40, 0x03, 0x33]);
+        assert_eq!(raw.read_register(Register::RxBw).unwrap() & 0x1F, 0x02);
+        let mut sync = [0u8; 3];
+        raw.read_many(Register::SyncConfig, &mut sync).unwrap();
+        assert_eq!(sync, [0x88, SYNC_WORD, DEFAULT_NETWORK_ID]);
+        assert_eq!(raw.read_register(Register::PacketConfig1).unwrap(), 0x90);
+        assert_eq!(raw.read_register(Register::PreambleLsb).unwrap(), 3);
+
+        // Later packet settings keep the CRC on and whitening off
+        radio.set_node_address(Some(0x05)).unwrap();
+        let mut raw = radio.raw();
+        assert_eq!(raw.read_register(Register::PacketConfig1).unwrap(), 0x94);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_ack() {
+        let channel = SimChannel::new(1);
+        let mut node = MySensorsTransport::new(radio(&channel).await, 0x05, 100).unwrap();
+        let mut gateway = MySensorsTransport::new(radio(&channel).await, 0x00, 100).unwrap();
+        let mut sniffer = radio(&channel).await;
+        sniffer.set_mysensors_profile(100).unwrap();
+        sniffer.set_mode(Rfm69Mode::Rx).await.unwrap();
+
+        gateway.radio().set_mode(Rfm69Mode::Rx).await.unwrap();
+        let mut received = Vec::new();
+        let serve = async {
+            let mut buffer = [0u8; MAX_DATA_LENGTH];
+            loop {
+                gateway.radio().set_mode(Rfm69Mode::Rx).await.unwrap();
+                gateway.radio().wait_for_message().await.unwrap();
+                if let Ok(datagram) = gateway.receive(&mut buffer).await {
+                    received.push((datagram, buffer[..datagram.length].to_vec()));
+                }
+            }
+        };
+        select(node.send_to(0x00, &[0x01, 0x02, 0x03]), serve).await;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0.from, 0x05);
+        assert!(!received[0].0.broadcast);
+        assert_eq!(received[0].1, [0x01, 0x02, 0x03]);
+
+        // Broadcasts aren't acknowledged
+        gateway.radio().set_mode(Rfm69Mode::Rx).await.unwrap();
+        node.send_to(BROADCAST_ADDRESS, &[0x04]).await.unwrap();
+        let mut buffer = [0u8; MAX_DATA_LENGTH];
+        let datagram = gateway.receive(&mut buffer).await.unwrap();
+        assert!(datagram.broadcast);
+        assert_eq!(&buffer[..datagram.length], &[0x04]);
+        assert_eq!(channel.transmissions(), 3);
+
+        // The first packet on air, with the LowPowerLab header
+        let mut frame = [0u8; RF69_FIFO_SIZE];
+        let length = sniffer.receive_raw(&mut frame).unwrap();
+        assert_eq!(
+            &frame[..length],
+            &[0x00, 0x05, CTL_REQACK, 0x01, 0x02, 0x03]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ack_timeout() {
+        let channel = SimChannel::new(1);
+        let mut node = MySensorsTransport::new(radio(&channel).await, 0x05, 100).unwrap();
+        assert_eq!(
+            node.send_to(0x00, &[0x01]).await,
+            Err(Rfm69Error::AckTimeout)
+        );
+        assert_eq!(channel.transmissions(), 1 + RETRIES as u32);
+        assert_eq!(
+            node.send_to(0x00, &[0; MAX_DATA_LENGTH + 1]).await,
+            Err(Rfm69Error::MessageTooLarge)
+        );
+    }
+}
@@ -0,0 +1,66 @@
+// FNV-1a
+const FNV_OFFSET_BASIS: u32 = 0x811C_9DC5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Derives 8 sync word bytes from a network id, use the first 2 to 8 with
+/// `Rfm69::set_sync_words`. Integer ids can be passed as `&id.to_be_bytes()`,
+/// strings as `name.as_bytes()`.
+///
+/// Every byte has at least 3 bit transitions and none is 0x00, which the
+/// RFM69 doesn't support in the sync word. The first byte also differs from the
+/// 0x55/0xAA preamble so the sync word can't match inside the preamble.
+pub fn sync_words(network_id: &[u8]) -> [u8; 8] {
+    let mut state = network_id.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(FNV_PRIME)
+    });
+
+    let mut sync_words = [0u8; 8];
+    let mut index = 0;
+    while index < sync_words.len() {
+        state = next(state);
+        let byte = (state >> 24) as u8;
+        if is_good_sync_byte(byte) && (index > 0 || !matches!(byte, 0x55 | 0xAA)) {
+            sync_words[index] = byte;
+            index += 1;
+        }
+    }
+    sync_words
+}
+
+fn is_good_sync_byte(byte: u8) -> bool {
+    let transitions = ((byte ^ (byte >> 1)) & 0x7F).count_ones();
+    byte != 0x00 && transitions >= 3
+}
+
+// xorshift32, never leaves a zero state so it is bumped to 1
+fn next(state: u32) -> u32 {
+    let mut x = state.max(1);
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    x
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sync_words_properties() {
+        for id in 0u32..256 {
+            let words = sync_words(&id.to_be_bytes());
+            assert!(words.iter().all(|&byte| is_good_sync_byte(byte)));
+            assert!(words[0] != 0x55 && words[0] != 0xAA);
+        }
+    }
+
+    #[test]
+    fn test_sync_words_per_network() {
+        assert_eq!(sync_words(b"garden"), sync_words(b"garden"));
+        assert_ne!(sync_words(b"garden"), sync_words(b"garage"));
+        assert_ne!(
+            sync_words(&1u32.to_be_bytes()),
+            sync_words(&2u32.to_be_bytes())
+        );
+    }
+}
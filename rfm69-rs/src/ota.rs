@@ -0,0 +1,499 @@
+pub use crate::crc::Crc32;
+use crate::read_write::ReadWrite;
+use crate::reliable::ReliableDatagram;
+use crate::rfm69::Rfm69Error;
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+const OP_BEGIN: u8 = 0x01;
+const OP_CHUNK: u8 = 0x02;
+const OP_END: u8 = 0x03;
+
+// Opcode and offset in front of every chunk
+const CHUNK_HEADER_LENGTH: usize = 5;
+/// Image bytes carried by a single chunk.
+pub const CHUNK_SIZE: usize = RF69_MAX_MESSAGE_LEN - CHUNK_HEADER_LENGTH;
+/// Length of a response: opcode, status and next expected offset.
+pub const RESPONSE_LENGTH: usize = 6;
+// Block size used to read the image back for the CRC check
+const VERIFY_BLOCK_SIZE: usize = 64;
+
+/// Answer of the receiving node to an OTA message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum OtaStatus {
+    Ok = 0x00,
+    /// The chunk didn't start at the next expected offset, the sender continues
+    /// from the offset in the response.
+    WrongOffset = 0x01,
+    /// The stored image doesn't match the CRC announced at the beginning.
+    CrcMismatch = 0x02,
+    /// The image doesn't fit, or the store failed to write it.
+    StoreError = 0x03,
+    /// A chunk or the end arrived without a transfer in progress.
+    NoTransfer = 0x04,
+    /// The message is not a valid OTA message.
+    Malformed = 0x05,
+}
+
+impl OtaStatus {
+    fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0x00 => Some(OtaStatus::Ok),
+            0x01 => Some(OtaStatus::WrongOffset),
+            0x02 => Some(OtaStatus::CrcMismatch),
+            0x03 => Some(OtaStatus::StoreError),
+            0x04 => Some(OtaStatus::NoTransfer),
+            0x05 => Some(OtaStatus::Malformed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, defmt::Format)]
+pub enum OtaError {
+    Radio(Rfm69Error),
+    /// The receiving node refused the transfer.
+    Rejected(OtaStatus),
+    /// The response was not a valid OTA response.
+    InvalidResponse,
+}
+
+impl From<Rfm69Error> for OtaError {
+    fn from(error: Rfm69Error) -> Self {
+        OtaError::Radio(error)
+    }
+}
+
+/// Storage for a received firmware image, usually a spare flash region.
+pub trait FirmwareStore {
+    type Error;
+
+    /// Prepares to store an image of `size` bytes with the CRC-32 `crc`, e.g. by
+    /// erasing flash. Returns the number of bytes already stored by an
+    /// interrupted transfer of the same image, or 0 to start over.
+    fn begin(&mut self, size: u32, crc: u32) -> Result<u32, Self::Error>;
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Called once the whole image is stored and its CRC verified, e.g. to mark
+    /// it for the bootloader.
+    fn finish(&mut self) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+struct Transfer {
+    size: u32,
+    crc: u32,
+    received: u32,
+}
+
+/// Receiving side of an OTA image transfer, writing the image to a `FirmwareStore`.
+///
+/// A transfer starts with the image size and CRC, followed by the image in
+/// chunks of `CHUNK_SIZE` bytes, and ends with a CRC check of the stored image.
+/// Every message is acknowledged with the offset of the next expected chunk, so
+/// an interrupted transfer resumes where it stopped when the same image is sent again.
+pub struct OtaReceiver<S> {
+    store: S,
+    transfer: Option<Transfer>,
+    complete: bool,
+}
+
+impl<S: FirmwareStore> OtaReceiver<S> {
+    pub fn new(store: S) -> Self {
+        OtaReceiver {
+            store,
+            transfer: None,
+            complete: false,
+        }
+    }
+
+    pub fn store(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn release(self) -> S {
+        self.store
+    }
+
+    /// True once an image has been stored and verified.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Bytes received and total size of the transfer in progress.
+    pub fn progress(&self) -> Option<(u32, u32)> {
+        self.transfer
+            .map(|transfer| (transfer.received, transfer.size))
+    }
+
+    /// Handles the OTA message `request` and writes the response into `response`,
+    /// returning its length.
+    pub fn handle(&mut self, request: &[u8], response: &mut [u8; RESPONSE_LENGTH]) -> usize {
+        let op = request.first().copied().unwrap_or(0);
+        let status = match op {
+            OP_BEGIN => self.begin(&request[1..]),
+            OP_CHUNK => self.chunk(&request[1..]),
+            OP_END => self.end(),
+            _ => OtaStatus::Malformed,
+        };
+
+        let next_offset = self.transfer.map_or(0, |transfer| transfer.received);
+        response[0] = op;
+        response[1] = status as u8;
+        response[2..].copy_from_slice(&next_offset.to_be_bytes());
+        RESPONSE_LENGTH
+    }
+
+    /// Receives the next OTA message through `network` and answers it.
+    /// Returns true once the image has been stored and verified.
+    pub async fn receive<SPI, RESET, INTR, D>(
+        &mut self,
+        network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    ) -> Result<bool, Rfm69Error>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+        let request = network.receive_request(&mut buffer).await?;
+
+        let mut response = [0u8; RESPONSE_LENGTH];
+        let length = self.handle(&buffer[..request.length], &mut response);
+        network.respond(&request, &response[..length]).await?;
+        Ok(self.complete)
+    }
+
+    fn begin(&mut self, data: &[u8]) -> OtaStatus {
+        let (Some(size), Some(crc)) = (read_u32(data, 0), read_u32(data, 4)) else {
+            return OtaStatus::Malformed;
+        };
+
+        self.complete = false;
+        if let Some(transfer) = self.transfer {
+            if transfer.size == size && transfer.crc == crc {
+                return OtaStatus::Ok;
+            }
+        }
+
+        self.transfer = None;
+        match self.store.begin(size, crc) {
+            Ok(stored) => {
+                self.transfer = Some(Transfer {
+                    size,
+                    crc,
+                    received: stored.min(size),
+                });
+                OtaStatus::Ok
+            }
+            Err(_) => OtaStatus::StoreError,
+        }
+    }
+
+    fn chunk(&mut self, data: &[u8]) -> OtaStatus {
+        let Some(transfer) = self.transfer.as_mut() else {
+            return OtaStatus::NoTransfer;
+        };
+        let Some(offset) = read_u32(data, 0) else {
+            return OtaStatus::Malformed;
+        };
+        let chunk = &data[4..];
+
+        if offset != transfer.received {
+            return OtaStatus::WrongOffset;
+        }
+        if chunk.len() as u32 > transfer.size - transfer.received {
+            return OtaStatus::StoreError;
+        }
+        if self.store.write(offset, chunk).is_err() {
+            return OtaStatus::StoreError;
+        }
+
+        transfer.received += chunk.len() as u32;
+        OtaStatus::Ok
+    }
+
+    fn end(&mut self) -> OtaStatus {
+        let Some(transfer) = self.transfer else {
+            // The response to the last end was lost
+            return match self.complete {
+                true => OtaStatus::Ok,
+                false => OtaStatus::NoTransfer,
+            };
+        };
+        if transfer.received != transfer.size {
+            return OtaStatus::WrongOffset;
+        }
+
+        let mut crc = Crc32::new();
+        let mut block = [0u8; VERIFY_BLOCK_SIZE];
+        let mut offset = 0;
+        while offset < transfer.size {
+            let length = (transfer.size - offset).min(VERIFY_BLOCK_SIZE as u32) as usize;
+            if self.store.read(offset, &mut block[..length]).is_err() {
+                return OtaStatus::StoreError;
+            }
+            crc.update(&block[..length]);
+            offset += length as u32;
+        }
+
+        // Start over on a mismatch, resuming would keep the corrupted bytes
+        self.transfer = None;
+        if crc.finish() != transfer.crc {
+            return OtaStatus::CrcMismatch;
+        }
+        if self.store.finish().is_err() {
+            return OtaStatus::StoreError;
+        }
+
+        self.complete = true;
+        OtaStatus::Ok
+    }
+}
+
+/// Sends `image` to the node `to` running an `OtaReceiver`.
+///
+/// When the transfer fails, e.g. with `Rfm69Error::AckTimeout`, calling it
+/// again with the same image resumes where the receiver stopped.
+pub async fn send_image<SPI, RESET, INTR, D>(
+    network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    to: u8,
+    image: &[u8],
+) -> Result<(), OtaError>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let size = image.len() as u32;
+    let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+
+    message[0] = OP_BEGIN;
+    message[1..5].copy_from_slice(&size.to_be_bytes());
+    message[5..9].copy_from_slice(&Crc32::checksum(image).to_be_bytes());
+    let (_, mut offset) = exchange(network, to, &message[..9]).await?;
+
+    loop {
+        while offset < size {
+            let start = offset as usize;
+            let end = (start + CHUNK_SIZE).min(image.len());
+            message[0] = OP_CHUNK;
+            message[1..5].copy_from_slice(&offset.to_be_bytes());
+            message[CHUNK_HEADER_LENGTH..][..end - start].copy_from_slice(&image[start..end]);
+
+            let length = CHUNK_HEADER_LENGTH + end - start;
+            (_, offset) = exchange(network, to, &message[..length]).await?;
+        }
+
+        message[0] = OP_END;
+        match exchange(network, to, &message[..1]).await? {
+            (OtaStatus::Ok, _) => return Ok(()),
+            // The receiver is missing chunks
+            (_, next_offset) => offset = next_offset,
+        }
+    }
+}
+
+// Sends an OTA message and returns the status and the next offset expected by the receiver
+async fn exchange<SPI, RESET, INTR, D>(
+    network: &mut ReliableDatagram<SPI, RESET, INTR, D>,
+    to: u8,
+    message: &[u8],
+) -> Result<(OtaStatus, u32), OtaError>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let mut response = [0u8; RESPONSE_LENGTH];
+    let length = network
+        .send_to_wait_response(to, message, &mut response)
+        .await?;
+    if length != RESPONSE_LENGTH || response[0] != message[0] {
+        return Err(OtaError::InvalidResponse);
+    }
+
+    let next_offset = read_u32(&response, 2).ok_or(OtaError::InvalidResponse)?;
+    match OtaStatus::from_value(response[1]) {
+        Some(status @ (OtaStatus::Ok | OtaStatus::WrongOffset)) => Ok((status, next_offset)),
+        Some(status) => Err(OtaError::Rejected(status)),
+        None => Err(OtaError::InvalidResponse),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct RamStore {
+        image: [u8; 256],
+        stored: u32,
+        finished: bool,
+    }
+
+    impl FirmwareStore for RamStore {
+        type Error = ();
+
+        fn begin(&mut self, size: u32, _crc: u32) -> Result<u32, ()> {
+            match size as usize <= self.image.len() {
+                true => Ok(self.stored),
+                false => Err(()),
+            }
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            self.image[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<(), ()> {
+            let offset = offset as usize;
+            buffer.copy_from_slice(&self.image[offset..offset + buffer.len()]);
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<(), ()> {
+            self.finished = true;
+            Ok(())
+        }
+    }
+
+    fn receiver() -> OtaReceiver<RamStore> {
+        OtaReceiver::new(RamStore {
+            image: [0; 256],
+            stored: 0,
+            finished: false,
+        })
+    }
+
+    fn image() -> [u8; 130] {
+        core::array::from_fn(|i| i as u8)
+    }
+
+    fn begin(image: &[u8], crc: u32) -> Vec<u8> {
+        let mut message = vec![OP_BEGIN];
+        message.extend_from_slice(&(image.len() as u32).to_be_bytes());
+        message.extend_from_slice(&crc.to_be_bytes());
+        message
+    }
+
+    fn chunk(image: &[u8], offset: usize) -> Vec<u8> {
+        let mut message = vec![OP_CHUNK];
+        message.extend_from_slice(&(offset as u32).to_be_bytes());
+        let end = (offset + CHUNK_SIZE).min(image.len());
+        message.extend_from_slice(&image[offset..end]);
+        message
+    }
+
+    fn handle(receiver: &mut OtaReceiver<RamStore>, request: &[u8]) -> (OtaStatus, u32) {
+        let mut response = [0u8; RESPONSE_LENGTH];
+        receiver.handle(request, &mut response);
+        assert_eq!(response[0], request[0]);
+        (
+            OtaStatus::from_value(response[1]).unwrap(),
+            read_u32(&response, 2).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_resumed_transfer() {
+        let image = image();
+        let crc = Crc32::checksum(&image);
+        let mut receiver = receiver();
+
+        assert_eq!(
+            handle(&mut receiver, &begin(&image, crc)),
+            (OtaStatus::Ok, 0)
+        );
+        assert_eq!(
+            handle(&mut receiver, &chunk(&image, 0)),
+            (OtaStatus::Ok, 55)
+        );
+        // Retransmitted chunk whose response was lost
+        assert_eq!(
+            handle(&mut receiver, &chunk(&image, 0)),
+            (OtaStatus::WrongOffset, 55)
+        );
+        assert_eq!(
+            handle(&mut receiver, &[OP_END]),
+            (OtaStatus::WrongOffset, 55)
+        );
+
+        // The sender restarts and resumes
+        assert_eq!(
+            handle(&mut receiver, &begin(&image, crc)),
+            (OtaStatus::Ok, 55)
+        );
+        assert_eq!(receiver.progress(), Some((55, 130)));
+        assert_eq!(
+            handle(&mut receiver, &chunk(&image, 55)),
+            (OtaStatus::Ok, 110)
+        );
+        assert_eq!(
+            handle(&mut receiver, &chunk(&image, 110)),
+            (OtaStatus::Ok, 130)
+        );
+        assert!(!receiver.is_complete());
+        assert_eq!(handle(&mut receiver, &[OP_END]), (OtaStatus::Ok, 0));
+
+        assert!(receiver.is_complete());
+        assert!(receiver.store().finished);
+        assert_eq!(receiver.store().image[..130], image);
+        // Lost response to the end
+        assert_eq!(handle(&mut receiver, &[OP_END]), (OtaStatus::Ok, 0));
+    }
+
+    #[test]
+    fn test_resume_from_store() {
+        let image = image();
+        let mut receiver = receiver();
+        receiver.store().image[..55].copy_from_slice(&image[..55]);
+        receiver.store().stored = 55;
+
+        let request = begin(&image, Crc32::checksum(&image));
+        assert_eq!(handle(&mut receiver, &request), (OtaStatus::Ok, 55));
+    }
+
+    #[test]
+    fn test_rejected_transfer() {
+        let image = image();
+        let mut receiver = receiver();
+
+        assert_eq!(
+            handle(&mut receiver, &chunk(&image, 0)),
+            (OtaStatus::NoTransfer, 0)
+        );
+        assert_eq!(handle(&mut receiver, &[0x7F]), (OtaStatus::Malformed, 0));
+        assert_eq!(
+            handle(&mut receiver, &begin(&[0; 300], 0)),
+            (OtaStatus::StoreError, 0)
+        );
+
+        assert_eq!(
+            handle(&mut receiver, &begin(&image, 0x1234)),
+            (OtaStatus::Ok, 0)
+        );
+        for offset in (0..image.len()).step_by(CHUNK_SIZE) {
+            handle(&mut receiver, &chunk(&image, offset));
+        }
+        assert_eq!(
+            handle(&mut receiver, &[OP_END]),
+            (OtaStatus::CrcMismatch, 0)
+        );
+        assert!(!receiver.is_complete());
+        assert!(!receiver.store().finished);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::header::Header;
+use crate::interrupt::{InterruptState, RadioEvent};
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use crate::units::{Frequency, TxPowerDbm};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+use radio::{BasicInfo, Channel, Power, Receive, Rssi, Transmit};
+
+// The radio traits for stacks written against sx127x/sx128x style drivers. Data
+// is sent with the default header like `Rfm69::send`, and received without it.
+
+impl<SPI, RESET, INTR, D> Transmit for Rfm69<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    type Error = Rfm69Error;
+
+    fn start_transmit(&mut self, data: &[u8]) -> Result<(), Rfm69Error> {
+        Rfm69::start_transmit(self, Header::default(), data)
+    }
+
+    /// Polls for PacketSent, the radio returns to Standby once it is set.
+    fn check_transmit(&mut self) -> Result<bool, Rfm69Error> {
+        if self.interrupt_state() != InterruptState::Transmitting {
+            return Err(Rfm69Error::InvalidMode);
+        }
+
+        self.on_interrupt()?;
+        Ok(matches!(self.poll_event(), Some(RadioEvent::PacketSent)))
+    }
+}
+
+impl<SPI, RESET, INTR, D> Receive for Rfm69<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    type Error = Rfm69Error;
+    type Info = BasicInfo;
+
+    fn start_receive(&mut self) -> Result<(), Rfm69Error> {
+        Rfm69::start_receive(self)
+    }
+
+    /// Polls for a packet and reads it from the FIFO. Packets failing the CRC
+    /// check or lost to a FIFO overrun are an error unless `restart` is set, the
+    /// receiver keeps listening either way.
+    fn check_receive(&mut self, restart: bool) -> Result<bool, Rfm69Error> {
+        if self.interrupt_state() != InterruptState::Receiving {
+            return Err(Rfm69Error::InvalidMode);
+        }
+        if let Some(RadioEvent::PacketReceived(_)) = self.pending_event() {
+            return Ok(true);
+        }
+        // Also samples the RSSI of a packet being received
+        if !self.is_message_available()? {
+            return Ok(false);
+        }
+
+        self.on_interrupt()?;
+        let error = match self.pending_event() {
+            Some(RadioEvent::PacketReceived(_)) => return Ok(true),
+            Some(RadioEvent::CrcError) => Rfm69Error::CrcFailure,
+            Some(RadioEvent::FifoOverrun) => Rfm69Error::FifoOverrun,
+            _ => return Ok(false),
+        };
+        self.poll_event();
+        match restart {
+            true => Ok(false),
+            false => Err(error),
+        }
+    }
+
+    /// Copies the payload of the packet found by `check_receive` into `buff`.
+    /// The RSSI is `i16::MIN` if the packet wasn't seen while it was received.
+    fn get_received(&mut self, buff: &mut [u8]) -> Result<(usize, BasicInfo), Rfm69Error> {
+        let Some(RadioEvent::PacketReceived(packet)) = self.poll_event() else {
+            return Err(Rfm69Error::NoMessage);
+        };
+        let payload = packet.payload();
+        if buff.len() < payload.len() {
+            return Err(Rfm69Error::BufferTooSmall);
+        }
+        buff[..payload.len()].copy_from_slice(payload);
+
+        let rssi = self.packet_rssi().unwrap_or(i16::MIN);
+        Ok((payload.len(), BasicInfo::new(rssi, 0)))
+    }
+}
+
+impl<SPI, RESET, INTR, D> Rssi for Rfm69<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    type Error = Rfm69Error;
+
+    fn poll_rssi(&mut self) -> Result<i16, Rfm69Error> {
+        Ok(-(self.rssi()? as i16))
+    }
+}
+
+impl<SPI, RESET, INTR, D> Power for Rfm69<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    type Error = Rfm69Error;
+
+    /// Sets the output power in dBm, see `Rfm69::set_tx_power`.
+    fn set_power(&mut self, power: i8) -> Result<(), Rfm69Error> {
+        self.set_tx_power(TxPowerDbm::new(power))
+    }
+}
+
+impl<SPI, RESET, INTR, D> Channel for Rfm69<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    /// Carrier frequency, see `Rfm69::set_frequency`.
+    type Channel = Frequency;
+    type Error = Rfm69Error;
+
+    fn set_channel(&mut self, channel: &Frequency) -> Result<(), Rfm69Error> {
+        self.set_frequency(*channel)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::SimChannel;
+    use radio::ReceiveInfo;
+
+    #[tokio::test]
+    async fn test_radio_traits() {
+        let channel = SimChannel::new(1);
+        let mut sender = channel.radio();
+        let mut receiver = channel.radio();
+        sender.init().await.unwrap();
+        receiver.init().await.unwrap();
+        Channel::set_channel(&mut sender, &Frequency::from_mhz(868)).unwrap();
+        Channel::set_channel(&mut receiver, &Frequency::from_mhz(868)).unwrap();
+        Power::set_power(&mut sender, 10).unwrap();
+
+        Receive::start_receive(&mut receiver).unwrap();
+        assert_eq!(Receive::check_receive(&mut receiver, false), Ok(false));
+        assert_eq!(
+            Transmit::check_transmit(&mut sender),
+            Err(Rfm69Error::InvalidMode)
+        );
+
+        Transmit::start_transmit(&mut sender, &[0x01, 0x02, 0x03]).unwrap();
+        assert_eq!(Transmit::check_transmit(&mut sender), Ok(true));
+
+        assert_eq!(Receive::check_receive(&mut receiver, false), Ok(true));
+        let mut buffer = [0u8; 8];
+        let (length, info) = Receive::get_received(&mut receiver, &mut buffer).unwrap();
+        assert_eq!(&buffer[..length], &[0x01, 0x02, 0x03]);
+        assert_eq!(info.rssi(), -60);
+        assert_eq!(Rssi::poll_rssi(&mut receiver), Ok(-60));
+        assert_eq!(Receive::check_receive(&mut receiver, false), Ok(false));
+    }
+}
@@ -0,0 +1,259 @@
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error, Rfm69Mode};
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+// A probe carries the sequence number, the reply the sequence number and the
+// RSSI of the probe at the base
+const OP_PROBE: u8 = 0x41;
+const OP_PROBE_REPLY: u8 = 0x42;
+
+/// Time `RangeTester::probe` waits for the reply by default.
+pub const DEFAULT_TIMEOUT_MS: u32 = 100;
+
+/// Minimum, maximum and average of RSSI samples in dBm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct RssiStats {
+    pub samples: u32,
+    pub min_dbm: i16,
+    pub max_dbm: i16,
+    pub total_dbm: i64,
+}
+
+impl RssiStats {
+    pub fn average_dbm(&self) -> Option<i16> {
+        self.total_dbm
+            .checked_div(self.samples as i64)
+            .map(|average| average as i16)
+    }
+
+    fn record(&mut self, rssi_dbm: i16) {
+        if self.samples == 0 {
+            self.min_dbm = rssi_dbm;
+            self.max_dbm = rssi_dbm;
+        }
+        self.min_dbm = self.min_dbm.min(rssi_dbm);
+        self.max_dbm = self.max_dbm.max(rssi_dbm);
+        self.total_dbm += rssi_dbm as i64;
+        self.samples += 1;
+    }
+}
+
+/// Statistics of the probes sent from one location.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct RangeStats {
+    pub sent: u32,
+    /// Probes answered by the base.
+    pub acked: u32,
+    /// RSSI of the probes at the base, the uplink.
+    pub base_rssi: RssiStats,
+    /// RSSI of the replies at the mobile node, the downlink.
+    pub mobile_rssi: RssiStats,
+}
+
+/// Outcome of a single probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct ProbeResult {
+    pub sequence: u16,
+    pub base_rssi_dbm: i16,
+    pub mobile_rssi_dbm: i16,
+}
+
+// RSSI of the packet just read
+fn received_rssi_dbm<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+) -> Result<i16, Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    match radio.packet_rssi() {
+        Some(rssi_dbm) => Ok(rssi_dbm),
+        None => Ok(-(radio.rssi()? as i16)),
+    }
+}
+
+/// Mobile side of a range test, for site surveys.
+///
+/// The mobile node sends numbered probes to the base, which answers every probe
+/// with the RSSI it received it with, see `answer_probe`. The statistics of the
+/// probes add up until `next_location` is called, e.g. when the node is moved.
+pub struct RangeTester {
+    base: u8,
+    sequence: u16,
+    timeout_ms: u32,
+    stats: RangeStats,
+}
+
+impl RangeTester {
+    /// Probes the node `base`. The radio needs a node address for the replies.
+    pub fn new(base: u8) -> Self {
+        RangeTester {
+            base,
+            sequence: 0,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            stats: RangeStats::default(),
+        }
+    }
+
+    pub fn set_timeout_ms(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Statistics of the current location.
+    pub fn stats(&self) -> &RangeStats {
+        &self.stats
+    }
+
+    /// Returns the statistics of the current location and starts over.
+    pub fn next_location(&mut self) -> RangeStats {
+        core::mem::take(&mut self.stats)
+    }
+
+    /// Sends a probe and waits for the reply of the base. Returns `None` if it
+    /// didn't answer in time.
+    pub async fn probe<SPI, RESET, INTR, D>(
+        &mut self,
+        radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    ) -> Result<Option<ProbeResult>, Rfm69Error>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+        let [high, low] = sequence.to_be_bytes();
+        radio.send_to(self.base, &[OP_PROBE, high, low]).await?;
+        self.stats.sent += 1;
+
+        radio.set_mode(Rfm69Mode::Rx).await?;
+        let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+        for _ in 0..self.timeout_ms {
+            if radio.is_message_available()? {
+                let (header, length) = match radio.receive(&mut buffer).await {
+                    Ok(received) => received,
+                    Err(Rfm69Error::CrcFailure | Rfm69Error::FifoOverrun) => continue,
+                    Err(error) => return Err(error),
+                };
+                // Late replies to earlier probes are skipped
+                if let [OP_PROBE_REPLY, high, low, rssi_high, rssi_low] = buffer[..length] {
+                    if header.from == self.base && u16::from_be_bytes([high, low]) == sequence {
+                        let result = ProbeResult {
+                            sequence,
+                            base_rssi_dbm: i16::from_be_bytes([rssi_high, rssi_low]),
+                            mobile_rssi_dbm: received_rssi_dbm(radio)?,
+                        };
+                        self.stats.acked += 1;
+                        self.stats.base_rssi.record(result.base_rssi_dbm);
+                        self.stats.mobile_rssi.record(result.mobile_rssi_dbm);
+                        return Ok(Some(result));
+                    }
+                }
+            }
+            radio.delay.delay_ms(1).await;
+        }
+
+        Ok(None)
+    }
+}
+
+/// Base side of a range test: reads the received packet and answers it if it
+/// is a probe of a `RangeTester`. Returns `Rfm69Error::NoMessage` for other packets.
+pub async fn answer_probe<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+) -> Result<(), Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+    let (header, length) = radio.receive(&mut buffer).await?;
+    let [OP_PROBE, high, low] = buffer[..length] else {
+        return Err(Rfm69Error::NoMessage);
+    };
+
+    let [rssi_high, rssi_low] = received_rssi_dbm(radio)?.to_be_bytes();
+    radio
+        .send_to(
+            header.from,
+            &[OP_PROBE_REPLY, high, low, rssi_high, rssi_low],
+        )
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::{SimChannel, SimRfm69};
+    use embassy_futures::select::{select, Either};
+
+    async fn radio(channel: &SimChannel, address: u8) -> SimRfm69 {
+        let mut radio = channel.radio();
+        radio.init().await.unwrap();
+        radio.set_node_address(Some(address)).unwrap();
+        radio
+    }
+
+    async fn serve(base: &mut SimRfm69) {
+        loop {
+            base.set_mode(Rfm69Mode::Rx).await.unwrap();
+            base.wait_for_message().await.unwrap();
+            let _ = answer_probe(base).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let channel = SimChannel::new(1);
+        let mut mobile = radio(&channel, 0x02).await;
+        let mut base = radio(&channel, 0x01).await;
+        let mut tester = RangeTester::new(0x01);
+        channel.set_rssi_dbm(-70);
+
+        base.set_mode(Rfm69Mode::Rx).await.unwrap();
+        for sequence in 0..3 {
+            let result = match select(tester.probe(&mut mobile), serve(&mut base)).await {
+                Either::First(result) => result.unwrap(),
+                Either::Second(_) => unreachable!(),
+            };
+            assert_eq!(
+                result,
+                Some(ProbeResult {
+                    sequence,
+                    base_rssi_dbm: -70,
+                    mobile_rssi_dbm: -70,
+                })
+            );
+        }
+        assert_eq!(tester.stats().acked, 3);
+        assert_eq!(tester.stats().base_rssi.average_dbm(), Some(-70));
+
+        // Out of range
+        let stats = tester.next_location();
+        assert_eq!((stats.sent, stats.acked), (3, 3));
+        assert_eq!(stats.mobile_rssi.samples, 3);
+        tester.set_timeout_ms(5);
+        assert_eq!(tester.probe(&mut mobile).await, Ok(None));
+        assert_eq!((tester.stats().sent, tester.stats().acked), (1, 0));
+        assert_eq!(tester.stats().base_rssi.average_dbm(), None);
+    }
+
+    #[tokio::test]
+    async fn test_answer_other_packet() {
+        let channel = SimChannel::new(1);
+        let mut sender = radio(&channel, 0x02).await;
+        let mut base = radio(&channel, 0x01).await;
+
+        base.set_mode(Rfm69Mode::Rx).await.unwrap();
+        sender.send_to(0x01, &[0x01, 0x02]).await.unwrap();
+        assert_eq!(answer_probe(&mut base).await, Err(Rfm69Error::NoMessage));
+        assert_eq!(channel.transmissions(), 1);
+    }
+}
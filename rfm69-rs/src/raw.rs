@@ -0,0 +1,61 @@
+use crate::read_write::ReadWrite;
+use crate::registers::{Register, TypedRegister};
+use crate::rfm69::{Rfm69, Rfm69Error};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// Direct register access for settings the typed API doesn't cover, created by
+/// `Rfm69::raw`.
+///
+/// Writes go through the register shadow, but the driver doesn't learn about
+/// them otherwise: after changing e.g. the frequency or the mode this way, the
+/// driver still acts on the old value until it is set through its own API.
+pub struct Raw<'a, SPI, RESET, INTR, D> {
+    radio: &'a mut Rfm69<SPI, RESET, INTR, D>,
+}
+
+impl<'a, SPI, RESET, INTR, D> Raw<'a, SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    pub(crate) fn new(radio: &'a mut Rfm69<SPI, RESET, INTR, D>) -> Self {
+        Raw { radio }
+    }
+
+    /// Reads `register` over SPI, never from the register shadow.
+    pub fn read_register(&mut self, register: Register) -> Result<u8, Rfm69Error> {
+        self.radio.read_register(register)
+    }
+
+    /// Reads consecutive registers starting at `register` into `buffer`.
+    pub fn read_many(&mut self, register: Register, buffer: &mut [u8]) -> Result<(), Rfm69Error> {
+        self.radio.read_many(register, buffer)
+    }
+
+    pub fn write_register(&mut self, register: Register, value: u8) -> Result<(), Rfm69Error> {
+        self.radio.write_register(register, value)
+    }
+
+    /// Writes `values` to consecutive registers starting at `register`.
+    pub fn write_many(&mut self, register: Register, values: &[u8]) -> Result<(), Rfm69Error> {
+        self.radio.write_many(register, values)
+    }
+
+    /// Decodes a typed register, e.g. `read_typed::<Lna>()` for the gain picked
+    /// by the AGC. Read from the register shadow when possible.
+    pub fn read_typed<R: TypedRegister>(&mut self) -> Result<R, Rfm69Error> {
+        self.radio.read_typed()
+    }
+
+    /// Reads a typed register, lets `update` change some of its fields and writes
+    /// it back, leaving the other fields as they were.
+    pub fn modify<R: TypedRegister>(
+        &mut self,
+        update: impl FnOnce(&mut R),
+    ) -> Result<(), Rfm69Error> {
+        self.radio.modify(update)
+    }
+}
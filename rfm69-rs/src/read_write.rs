@@ -1,4 +1,5 @@
-use embedded_hal::spi::{Operation, SpiDevice};
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{Operation, SpiBus, SpiDevice};
 
 use crate::registers::Register;
 
@@ -33,4 +34,207 @@ where
         let mut operations = [Operation::Write(&read), Operation::TransferInPlace(buffer)];
         self.transaction(&mut operations)
     }
-}
\ No newline at end of file
+}
+
+/// Error of a `SpiBusDevice` transaction.
+#[derive(Debug, PartialEq)]
+pub enum SpiBusError<B, C> {
+    Bus(B),
+    ChipSelect(C),
+}
+
+/// Transport for a `SpiBus` owned exclusively by the radio, with the chip select
+/// driven manually through `cs`.
+///
+/// Use this when the bus isn't shared and no `SpiDevice` implementation, such as
+/// the ones from embedded-hal-bus or embassy-embedded-hal, is available.
+pub struct SpiBusDevice<BUS, CS> {
+    bus: BUS,
+    cs: CS,
+}
+
+impl<BUS, CS> SpiBusDevice<BUS, CS>
+where
+    BUS: SpiBus<u8>,
+    CS: OutputPin,
+{
+    /// Creates the transport and deasserts the chip select.
+    pub fn new(bus: BUS, mut cs: CS) -> Result<Self, CS::Error> {
+        cs.set_high()?;
+        Ok(SpiBusDevice { bus, cs })
+    }
+
+    /// Returns the bus and the chip select pin.
+    pub fn release(self) -> (BUS, CS) {
+        (self.bus, self.cs)
+    }
+
+    fn transaction(
+        &mut self,
+        reg: u8,
+        operation: impl FnOnce(&mut BUS) -> Result<(), BUS::Error>,
+    ) -> Result<(), SpiBusError<BUS::Error, CS::Error>> {
+        self.cs.set_low().map_err(SpiBusError::ChipSelect)?;
+
+        let result = self
+            .bus
+            .write(&[reg])
+            .and_then(|_| operation(&mut self.bus))
+            .and_then(|_| self.bus.flush());
+
+        // Always release the chip select, even if the transfer failed
+        let deassert = self.cs.set_high();
+        result.map_err(SpiBusError::Bus)?;
+        deassert.map_err(SpiBusError::ChipSelect)
+    }
+}
+
+impl<BUS, CS> ReadWrite for SpiBusDevice<BUS, CS>
+where
+    BUS: SpiBus<u8>,
+    CS: OutputPin,
+{
+    type Error = SpiBusError<BUS::Error, CS::Error>;
+
+    fn write_many(&mut self, reg: Register, data: &[u8]) -> core::result::Result<(), Self::Error> {
+        self.transaction(reg.write(), |bus| bus.write(data))
+    }
+
+    fn read_many(
+        &mut self,
+        reg: Register,
+        buffer: &mut [u8],
+    ) -> core::result::Result<(), Self::Error> {
+        self.transaction(reg.read(), |bus| bus.transfer_in_place(buffer))
+    }
+}
+
+/// Transport that repeats a failed register access, so a single glitch on long
+/// wires or a shared bus doesn't abort a send or receive. FIFO accesses aren't
+/// repeated, the failed transfer may already have moved bytes in or out of it.
+pub struct RetrySpi<S> {
+    spi: S,
+    attempts: u8,
+}
+
+impl<S: ReadWrite> RetrySpi<S> {
+    /// `attempts` includes the first access, 1 doesn't retry.
+    pub fn new(spi: S, attempts: u8) -> Self {
+        RetrySpi {
+            spi,
+            attempts: attempts.max(1),
+        }
+    }
+
+    pub fn release(self) -> S {
+        self.spi
+    }
+
+    fn attempts(&self, reg: Register) -> u8 {
+        match reg {
+            Register::Fifo => 1,
+            _ => self.attempts,
+        }
+    }
+}
+
+fn retry<E>(attempts: u8, mut access: impl FnMut() -> Result<(), E>) -> Result<(), E> {
+    let mut result = access();
+    for _ in 1..attempts {
+        if result.is_ok() {
+            break;
+        }
+        result = access();
+    }
+    result
+}
+
+impl<S: ReadWrite> ReadWrite for RetrySpi<S> {
+    type Error = S::Error;
+
+    fn write_many(&mut self, reg: Register, data: &[u8]) -> core::result::Result<(), Self::Error> {
+        let attempts = self.attempts(reg);
+        retry(attempts, || self.spi.write_many(reg, data))
+    }
+
+    fn read_many(
+        &mut self,
+        reg: Register,
+        buffer: &mut [u8],
+    ) -> core::result::Result<(), Self::Error> {
+        let attempts = self.attempts(reg);
+        retry(attempts, || self.spi.read_many(reg, buffer))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction as PinTransaction};
+    use embedded_hal_mock::eh1::spi::{Mock as SpiMock, Transaction as SpiTransaction};
+    use embedded_hal_mock::eh1::MockError;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_spi_bus_device() {
+        let bus = SpiMock::new(&[
+            SpiTransaction::write(Register::SyncValue1.write()),
+            SpiTransaction::write_vec(vec![0x2D, 0xD4]),
+            SpiTransaction::flush(),
+            SpiTransaction::write(Register::Version.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x24]),
+            SpiTransaction::flush(),
+        ]);
+        let cs = PinMock::new(&[
+            PinTransaction::set(State::High),
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+        ]);
+
+        let mut device = SpiBusDevice::new(bus, cs).unwrap();
+        device
+            .write_many(Register::SyncValue1, &[0x2D, 0xD4])
+            .unwrap();
+        let mut buffer = [0u8; 1];
+        device.read_many(Register::Version, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x24]);
+
+        let (mut bus, mut cs) = device.release();
+        bus.done();
+        cs.done();
+    }
+
+    #[test]
+    fn test_retry_spi() {
+        let bus = SpiMock::new(&[
+            SpiTransaction::write(Register::Version.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x24]),
+            SpiTransaction::flush(),
+        ]);
+        let glitch = || PinTransaction::set(State::Low).with_error(MockError::Io(ErrorKind::Other));
+        let cs = PinMock::new(&[
+            PinTransaction::set(State::High),
+            glitch(),
+            PinTransaction::set(State::Low),
+            PinTransaction::set(State::High),
+            // The FIFO isn't read again
+            glitch(),
+            // Neither is a register after the last attempt
+            glitch(),
+            glitch(),
+        ]);
+
+        let mut device = RetrySpi::new(SpiBusDevice::new(bus, cs).unwrap(), 2);
+        let mut buffer = [0u8; 1];
+        device.read_many(Register::Version, &mut buffer).unwrap();
+        assert_eq!(buffer, [0x24]);
+        assert!(device.read_many(Register::Fifo, &mut buffer).is_err());
+        assert!(device.write_many(Register::OpMode, &[0x04]).is_err());
+
+        let (mut bus, mut cs) = device.release().release();
+        bus.done();
+        cs.done();
+    }
+}
@@ -0,0 +1,101 @@
+use crate::settings::ModemConfigChoice;
+
+/// Regulatory regions with a preset frequency range, power limit and modem configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Region {
+    Eu868,
+    Us915,
+    Au915,
+    In865,
+    Ism433,
+}
+
+/// Limits and defaults of a `Region`.
+///
+/// `max_eirp_dbm` is compared against the configured output power, which
+/// assumes a 0 dBi antenna.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct RegionProfile {
+    pub min_hz: u32,
+    pub max_hz: u32,
+    pub max_eirp_dbm: i8,
+    pub default_frequency_mhz: u32,
+    pub modem_config: ModemConfigChoice,
+}
+
+impl RegionProfile {
+    pub fn contains(&self, frequency_hz: u32) -> bool {
+        frequency_hz >= self.min_hz && frequency_hz <= self.max_hz
+    }
+}
+
+impl Region {
+    pub fn profile(self) -> RegionProfile {
+        match self {
+            Self::Eu868 => RegionProfile {
+                min_hz: 863_000_000,
+                max_hz: 870_000_000,
+                max_eirp_dbm: 16,
+                default_frequency_mhz: 868,
+                modem_config: ModemConfigChoice::GfskRb19_2Fd38_4,
+            },
+            Self::Us915 => RegionProfile {
+                min_hz: 902_000_000,
+                max_hz: 928_000_000,
+                max_eirp_dbm: 30,
+                default_frequency_mhz: 915,
+                modem_config: ModemConfigChoice::GfskRb250Fd250,
+            },
+            Self::Au915 => RegionProfile {
+                min_hz: 915_000_000,
+                max_hz: 928_000_000,
+                max_eirp_dbm: 30,
+                default_frequency_mhz: 916,
+                modem_config: ModemConfigChoice::GfskRb250Fd250,
+            },
+            Self::In865 => RegionProfile {
+                min_hz: 865_000_000,
+                max_hz: 867_000_000,
+                max_eirp_dbm: 30,
+                default_frequency_mhz: 866,
+                modem_config: ModemConfigChoice::GfskRb19_2Fd38_4,
+            },
+            Self::Ism433 => RegionProfile {
+                min_hz: 433_050_000,
+                max_hz: 434_790_000,
+                max_eirp_dbm: 12,
+                default_frequency_mhz: 434,
+                modem_config: ModemConfigChoice::GfskRb4_8Fd9_6,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_frequency_in_range() {
+        [
+            Region::Eu868,
+            Region::Us915,
+            Region::Au915,
+            Region::In865,
+            Region::Ism433,
+        ]
+        .into_iter()
+        .for_each(|region| {
+            let profile = region.profile();
+            assert!(profile.contains(profile.default_frequency_mhz * 1_000_000));
+        });
+    }
+
+    #[test]
+    fn test_contains() {
+        let profile = Region::Eu868.profile();
+        assert!(profile.contains(863_000_000));
+        assert!(profile.contains(870_000_000));
+        assert!(!profile.contains(915_000_000));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::registers::{AddressFiltering, DcFree, PaLevel, PacketConfig2};
+use crate::settings::{RF69_FXOSC_HZ, RF_TESTPA1_BOOST, RF_TESTPA2_BOOST};
+
+/// Power amplifier configuration decoded from RegPaLevel, RegOcp, RegTestPa1
+/// and RegTestPa2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PaConfig {
+    pub pa_level: PaLevel,
+    pub ocp_on: bool,
+    /// The +20 dBm settings are on in RegTestPa1 and RegTestPa2.
+    pub high_power_boost: bool,
+    /// Output power in dBm, `None` for PA combinations the driver doesn't use.
+    pub output_power_dbm: Option<i8>,
+}
+
+/// Packet engine flags decoded from RegPacketConfig1 and RegPacketConfig2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PacketFlags {
+    pub variable_length: bool,
+    /// `None` for the reserved value.
+    pub dc_free: Option<DcFree>,
+    pub crc_on: bool,
+    pub crc_auto_clear_off: bool,
+    /// `None` for the reserved value.
+    pub address_filtering: Option<AddressFiltering>,
+    pub inter_packet_rx_delay: u8,
+    pub auto_rx_restart_on: bool,
+    pub aes_on: bool,
+}
+
+/// Bitrate in bps from RegBitrateMsb and RegBitrateLsb, 0 for a zero divider.
+pub fn bitrate_bps(msb: u8, lsb: u8) -> u32 {
+    match u16::from_be_bytes([msb, lsb]) {
+        0 => 0,
+        divider => (RF69_FXOSC_HZ + divider as u32 / 2) / divider as u32,
+    }
+}
+
+/// Frequency deviation in Hz from RegFdevMsb and RegFdevLsb.
+pub fn fdev_hz(msb: u8, lsb: u8) -> u32 {
+    synthesizer_steps_to_hz((u16::from_be_bytes([msb, lsb]) & 0x3FFF) as u32)
+}
+
+/// Carrier frequency in Hz from RegFrfMsb, RegFrfMid and RegFrfLsb.
+pub fn frequency_hz(msb: u8, mid: u8, lsb: u8) -> u32 {
+    synthesizer_steps_to_hz(u32::from_be_bytes([0, msb, mid, lsb]))
+}
+
+// Fstep = FXOSC / 2^19
+fn synthesizer_steps_to_hz(steps: u32) -> u32 {
+    ((steps as u64 * RF69_FXOSC_HZ as u64) >> 19) as u32
+}
+
+/// Receiver bandwidth in Hz from RegRxBw or RegAfcBw, halved in OOK.
+pub fn rx_bw_hz(rx_bw: u8, ook: bool) -> u32 {
+    // RxBw = FXOSC / (RxBwMant * 2^(RxBwExp + 2)), one more power of two in OOK
+    let mantissa = match (rx_bw >> 3) & 0x03 {
+        0 => 16,
+        1 => 20,
+        _ => 24,
+    };
+    let exponent = (rx_bw & 0x07) as u32 + if ook { 3 } else { 2 };
+    RF69_FXOSC_HZ / (mantissa << exponent)
+}
+
+/// Receiver bandwidth in kHz from RegRxBw or RegAfcBw, halved in OOK.
+pub fn rx_bw_khz(rx_bw: u8, ook: bool) -> f32 {
+    rx_bw_hz(rx_bw, ook) as f32 / 1000.0
+}
+
+/// Level in dBm of RegRssiValue or RegRssiThresh, in steps of -0.5 dB rounded
+/// towards 0.
+pub fn rssi_dbm(value: u8) -> i16 {
+    -(value as i16) / 2
+}
+
+/// Decodes the power amplifier registers. The output power with PA1 and PA2 is
+/// the one while transmitting with the boost settings found in RegTestPa1 and
+/// RegTestPa2, which the driver only turns on in Tx.
+pub fn pa_config(pa_level: u8, ocp: u8, test_pa1: u8, test_pa2: u8) -> PaConfig {
+    let pa_level = PaLevel::from_bits(pa_level);
+    let high_power_boost = test_pa1 == RF_TESTPA1_BOOST && test_pa2 == RF_TESTPA2_BOOST;
+    let offset = match (pa_level.pa0_on, pa_level.pa1_on, pa_level.pa2_on) {
+        (true, false, false) | (false, true, false) => Some(-18),
+        (false, true, true) if high_power_boost => Some(-11),
+        (false, true, true) => Some(-14),
+        _ => None,
+    };
+
+    PaConfig {
+        pa_level,
+        ocp_on: ocp & 0x10 != 0,
+        high_power_boost,
+        output_power_dbm: offset.map(|offset| offset + pa_level.output_power as i8),
+    }
+}
+
+/// Decodes RegPacketConfig1 and RegPacketConfig2.
+pub fn packet_flags(packet_config1: u8, packet_config2: u8) -> PacketFlags {
+    let packet_config2 = PacketConfig2::from_bits(packet_config2);
+    PacketFlags {
+        variable_length: packet_config1 & 0x80 != 0,
+        dc_free: DcFree::from_bits(packet_config1),
+        crc_on: packet_config1 & 0x10 != 0,
+        crc_auto_clear_off: packet_config1 & 0x08 != 0,
+        address_filtering: AddressFiltering::from_bits(packet_config1),
+        inter_packet_rx_delay: packet_config2.inter_packet_rx_delay,
+        auto_rx_restart_on: packet_config2.auto_rx_restart_on,
+        aes_on: packet_config2.aes_on,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_modem_values() {
+        assert_eq!(bitrate_bps(0x00, 0x80), 250_000);
+        assert_eq!(bitrate_bps(0x1A, 0x0B), 4_800);
+        assert_eq!(bitrate_bps(0x00, 0x00), 0);
+        assert_eq!(fdev_hz(0x10, 0x00), 250_000);
+        assert_eq!(frequency_hz(0xE4, 0xC0, 0x00), 915_000_000);
+        assert_eq!(rssi_dbm(0xE4), -114);
+    }
+
+    #[test]
+    fn test_rx_bw() {
+        assert_eq!(rx_bw_hz(0x55, false), 10_416);
+        assert_eq!(rx_bw_hz(0x55, true), 5_208);
+        assert_eq!(rx_bw_hz(0x42, false), 125_000);
+        assert_eq!(rx_bw_khz(0x42, false), 125.0);
+    }
+
+    #[test]
+    fn test_pa_config() {
+        // PA1 at +13 dBm
+        let config = pa_config(0x5F, 0x1A, 0x55, 0x70);
+        assert!(config.pa_level.pa1_on && !config.pa_level.pa2_on);
+        assert!(config.ocp_on);
+        assert!(!config.high_power_boost);
+        assert_eq!(config.output_power_dbm, Some(13));
+
+        // PA1 and PA2 at +20 dBm, boosted and with the overcurrent protection off
+        let config = pa_config(0x7F, 0x0F, 0x5D, 0x7C);
+        assert!(!config.ocp_on);
+        assert!(config.high_power_boost);
+        assert_eq!(config.output_power_dbm, Some(20));
+
+        // PA0 and PA1 together
+        assert_eq!(pa_config(0xDF, 0x1A, 0x55, 0x70).output_power_dbm, None);
+    }
+
+    #[test]
+    fn test_packet_flags() {
+        let flags = packet_flags(0xD4, 0x13);
+        assert!(flags.variable_length);
+        assert_eq!(flags.dc_free, Some(DcFree::Whitening));
+        assert!(flags.crc_on);
+        assert!(!flags.crc_auto_clear_off);
+        assert_eq!(
+            flags.address_filtering,
+            Some(AddressFiltering::NodeOrBroadcast)
+        );
+        assert_eq!(flags.inter_packet_rx_delay, 1);
+        assert!(flags.auto_rx_restart_on);
+        assert!(flags.aes_on);
+    }
+}
@@ -1,7 +1,7 @@
-
+use crate::rfm69::Rfm69Mode;
 
 #[allow(dead_code)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
 pub enum Register {
     Fifo = 0x00, // FIFO register: used for read/write access to the FIFO buffer.
     OpMode = 0x01, // Operating modes of the transceiver.
@@ -110,4 +110,547 @@ impl Register {
     pub fn addr(self) -> u8 {
         self as u8
     }
-}
\ No newline at end of file
+}
+
+/// RegDataModul DataMode field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DataMode {
+    Packet = 0x00,
+    ContinuousWithSync = 0x40,
+    Continuous = 0x60,
+}
+
+impl DataMode {
+    const MASK: u8 = 0x60;
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits & Self::MASK {
+            0x00 => Some(Self::Packet),
+            0x40 => Some(Self::ContinuousWithSync),
+            0x60 => Some(Self::Continuous),
+            _ => None,
+        }
+    }
+}
+
+/// RegDataModul ModulationType field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Modulation {
+    Fsk = 0x00,
+    Ook = 0x08,
+}
+
+impl Modulation {
+    const MASK: u8 = 0x18;
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits & Self::MASK {
+            0x00 => Some(Self::Fsk),
+            0x08 => Some(Self::Ook),
+            _ => None,
+        }
+    }
+}
+
+/// RegPacketConfig1 DcFree field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DcFree {
+    None = 0x00,
+    Manchester = 0x20,
+    Whitening = 0x40,
+}
+
+impl DcFree {
+    const MASK: u8 = 0x60;
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits & Self::MASK {
+            0x00 => Some(Self::None),
+            0x20 => Some(Self::Manchester),
+            0x40 => Some(Self::Whitening),
+            _ => None,
+        }
+    }
+}
+
+/// RegPacketConfig1 AddressFiltering field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum AddressFiltering {
+    None = 0x00,
+    Node = 0x02,
+    NodeOrBroadcast = 0x04,
+}
+
+impl AddressFiltering {
+    const MASK: u8 = 0x06;
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        match bits & Self::MASK {
+            0x00 => Some(Self::None),
+            0x02 => Some(Self::Node),
+            0x04 => Some(Self::NodeOrBroadcast),
+            _ => None,
+        }
+    }
+}
+
+/// RegOpMode. `from_bits` returns `None` for the reserved modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct OpMode {
+    pub sequencer_off: bool,
+    pub listen_on: bool,
+    pub listen_abort: bool,
+    pub mode: Rfm69Mode,
+}
+
+impl OpMode {
+    pub const MODE_MASK: u8 = 0x1C;
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        Some(OpMode {
+            sequencer_off: bits & 0x80 != 0,
+            listen_on: bits & 0x40 != 0,
+            listen_abort: bits & 0x20 != 0,
+            mode: Rfm69Mode::from_op_mode(bits)?,
+        })
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.sequencer_off as u8) << 7
+            | (self.listen_on as u8) << 6
+            | (self.listen_abort as u8) << 5
+            | self.mode as u8
+    }
+}
+
+/// RegDataModul. `from_bits` returns `None` for the reserved data modes and modulations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct DataModul {
+    pub data_mode: DataMode,
+    pub modulation: Modulation,
+    /// ModulationShaping, Gaussian filter in FSK or cutoff frequency in OOK.
+    pub shaping: u8,
+}
+
+impl DataModul {
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        Some(DataModul {
+            data_mode: DataMode::from_bits(bits)?,
+            modulation: Modulation::from_bits(bits)?,
+            shaping: bits & 0x03,
+        })
+    }
+
+    pub fn to_bits(self) -> u8 {
+        self.data_mode as u8 | self.modulation as u8 | (self.shaping & 0x03)
+    }
+}
+
+/// RegPaLevel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PaLevel {
+    pub pa0_on: bool,
+    pub pa1_on: bool,
+    pub pa2_on: bool,
+    /// Pout is -18 + output_power dBm with PA0 or PA1, -14 + output_power with PA1 and PA2.
+    pub output_power: u8,
+}
+
+impl PaLevel {
+    pub fn from_bits(bits: u8) -> Self {
+        PaLevel {
+            pa0_on: bits & 0x80 != 0,
+            pa1_on: bits & 0x40 != 0,
+            pa2_on: bits & 0x20 != 0,
+            output_power: bits & 0x1F,
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.pa0_on as u8) << 7
+            | (self.pa1_on as u8) << 6
+            | (self.pa2_on as u8) << 5
+            | (self.output_power & 0x1F)
+    }
+}
+
+/// RegLna.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Lna {
+    /// 200 ohm input impedance instead of 50 ohm.
+    pub zin_200_ohm: bool,
+    /// LnaCurrentGain, the gain picked by the AGC. Read only.
+    pub current_gain: u8,
+    /// LnaGainSelect, 0 lets the AGC pick the gain.
+    pub gain_select: u8,
+}
+
+impl Lna {
+    pub fn from_bits(bits: u8) -> Self {
+        Lna {
+            zin_200_ohm: bits & 0x80 != 0,
+            current_gain: (bits >> 3) & 0x07,
+            gain_select: bits & 0x07,
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.zin_200_ohm as u8) << 7 | (self.current_gain & 0x07) << 3 | (self.gain_select & 0x07)
+    }
+}
+
+/// RegDioMapping2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct DioMapping2 {
+    pub dio4_mapping: u8,
+    pub dio5_mapping: u8,
+    /// ClkOut, FXOSC divided by 2^`clk_out` up to 32, 6 for the RC oscillator and 7 for off.
+    pub clk_out: u8,
+}
+
+impl DioMapping2 {
+    pub const CLK_OUT_OFF: u8 = 0x07;
+
+    pub fn from_bits(bits: u8) -> Self {
+        DioMapping2 {
+            dio4_mapping: bits >> 6,
+            dio5_mapping: (bits >> 4) & 0x03,
+            clk_out: bits & 0x07,
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.dio4_mapping & 0x03) << 6 | (self.dio5_mapping & 0x03) << 4 | (self.clk_out & 0x07)
+    }
+}
+
+/// RegIrqFlags1, read only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct IrqFlags1 {
+    pub mode_ready: bool,
+    pub rx_ready: bool,
+    pub tx_ready: bool,
+    pub pll_lock: bool,
+    pub rssi: bool,
+    pub timeout: bool,
+    pub auto_mode: bool,
+    pub sync_address_match: bool,
+}
+
+impl IrqFlags1 {
+    pub fn from_bits(bits: u8) -> Self {
+        IrqFlags1 {
+            mode_ready: bits & 0x80 != 0,
+            rx_ready: bits & 0x40 != 0,
+            tx_ready: bits & 0x20 != 0,
+            pll_lock: bits & 0x10 != 0,
+            rssi: bits & 0x08 != 0,
+            timeout: bits & 0x04 != 0,
+            auto_mode: bits & 0x02 != 0,
+            sync_address_match: bits & 0x01 != 0,
+        }
+    }
+}
+
+/// RegIrqFlags2, read only apart from `fifo_overrun` which is cleared by writing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct IrqFlags2 {
+    pub fifo_full: bool,
+    pub fifo_not_empty: bool,
+    pub fifo_level: bool,
+    pub fifo_overrun: bool,
+    pub packet_sent: bool,
+    pub payload_ready: bool,
+    pub crc_ok: bool,
+}
+
+impl IrqFlags2 {
+    pub fn from_bits(bits: u8) -> Self {
+        IrqFlags2 {
+            fifo_full: bits & 0x80 != 0,
+            fifo_not_empty: bits & 0x40 != 0,
+            fifo_level: bits & 0x20 != 0,
+            fifo_overrun: bits & 0x10 != 0,
+            packet_sent: bits & 0x08 != 0,
+            payload_ready: bits & 0x04 != 0,
+            crc_ok: bits & 0x02 != 0,
+        }
+    }
+}
+
+/// RegPacketConfig1. `from_bits` returns `None` for the reserved DcFree and
+/// AddressFiltering values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PacketConfig1 {
+    pub variable_length: bool,
+    pub dc_free: DcFree,
+    pub crc_on: bool,
+    pub crc_auto_clear_off: bool,
+    pub address_filtering: AddressFiltering,
+}
+
+impl PacketConfig1 {
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        Some(PacketConfig1 {
+            variable_length: bits & 0x80 != 0,
+            dc_free: DcFree::from_bits(bits)?,
+            crc_on: bits & 0x10 != 0,
+            crc_auto_clear_off: bits & 0x08 != 0,
+            address_filtering: AddressFiltering::from_bits(bits)?,
+        })
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.variable_length as u8) << 7
+            | self.dc_free as u8
+            | (self.crc_on as u8) << 4
+            | (self.crc_auto_clear_off as u8) << 3
+            | self.address_filtering as u8
+    }
+}
+
+/// RegFifoThresh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct FifoThresh {
+    /// Start transmitting as soon as the FIFO isn't empty, instead of when
+    /// it holds more than `fifo_threshold` bytes.
+    pub tx_start_fifo_not_empty: bool,
+    pub fifo_threshold: u8,
+}
+
+impl FifoThresh {
+    pub fn from_bits(bits: u8) -> Self {
+        FifoThresh {
+            tx_start_fifo_not_empty: bits & 0x80 != 0,
+            fifo_threshold: bits & 0x7F,
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.tx_start_fifo_not_empty as u8) << 7 | (self.fifo_threshold & 0x7F)
+    }
+}
+
+/// RegPacketConfig2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct PacketConfig2 {
+    pub inter_packet_rx_delay: u8,
+    /// Forces the receiver into Wait mode, always reads back as false.
+    pub restart_rx: bool,
+    pub auto_rx_restart_on: bool,
+    pub aes_on: bool,
+}
+
+impl PacketConfig2 {
+    pub fn from_bits(bits: u8) -> Self {
+        PacketConfig2 {
+            inter_packet_rx_delay: bits >> 4,
+            restart_rx: bits & 0x04 != 0,
+            auto_rx_restart_on: bits & 0x02 != 0,
+            aes_on: bits & 0x01 != 0,
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        (self.inter_packet_rx_delay & 0x0F) << 4
+            | (self.restart_rx as u8) << 2
+            | (self.auto_rx_restart_on as u8) << 1
+            | self.aes_on as u8
+    }
+}
+
+/// A register struct tied to its address, for the read-modify-write helper
+/// `Rfm69::modify`, which decodes the register, lets a closure change some
+/// fields and writes every field back.
+pub trait TypedRegister: Sized {
+    const REGISTER: Register;
+
+    /// `None` for reserved values.
+    fn decode(bits: u8) -> Option<Self>;
+
+    fn encode(self) -> u8;
+}
+
+macro_rules! typed_register {
+    ($type:ident, |$bits:ident| $decode:expr) => {
+        impl TypedRegister for $type {
+            const REGISTER: Register = Register::$type;
+
+            fn decode($bits: u8) -> Option<Self> {
+                $decode
+            }
+
+            fn encode(self) -> u8 {
+                self.to_bits()
+            }
+        }
+    };
+}
+
+typed_register!(OpMode, |bits| OpMode::from_bits(bits));
+typed_register!(DataModul, |bits| DataModul::from_bits(bits));
+typed_register!(PaLevel, |bits| Some(PaLevel::from_bits(bits)));
+typed_register!(Lna, |bits| Some(Lna::from_bits(bits)));
+typed_register!(DioMapping2, |bits| Some(DioMapping2::from_bits(bits)));
+typed_register!(PacketConfig1, |bits| PacketConfig1::from_bits(bits));
+typed_register!(FifoThresh, |bits| Some(FifoThresh::from_bits(bits)));
+// RestartRx is a trigger, writing back a value read must not restart the receiver
+typed_register!(PacketConfig2, |bits| Some(PacketConfig2 {
+    restart_rx: false,
+    ..PacketConfig2::from_bits(bits)
+}));
+
+/// In-driver copy of the configuration registers, so read-modify-write
+/// operations don't need to read the register back over SPI.
+pub(crate) struct RegisterShadow {
+    values: [u8; 0x72],
+    valid: u128,
+}
+
+impl RegisterShadow {
+    pub(crate) const fn new() -> Self {
+        RegisterShadow {
+            values: [0; 0x72],
+            valid: 0,
+        }
+    }
+
+    /// Registers the chip changes on its own can't be cached.
+    fn is_cacheable(addr: u8) -> bool {
+        !matches!(
+            addr,
+            0x00 // Fifo
+            | 0x0A // Osc1, RcCalDone
+            | 0x1E..=0x24 // AFC, FEI and RSSI measurements
+            | 0x27 | 0x28 // IrqFlags
+            | 0x3D // PacketConfig2, RestartRx
+            | 0x3E..=0x4D // AesKey, write only, never kept in RAM
+            | 0x4E | 0x4F // Temperature measurement
+        ) && (addr as usize) < 0x72
+    }
+
+    pub(crate) fn get(&self, register: Register) -> Option<u8> {
+        self.get_addr(register.addr())
+    }
+
+    pub(crate) fn get_addr(&self, addr: u8) -> Option<u8> {
+        if Self::is_cacheable(addr) && self.valid & (1 << addr) != 0 {
+            Some(self.values[addr as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Records a burst of `values` written or read starting at `register`.
+    pub(crate) fn update(&mut self, register: Register, values: &[u8]) {
+        let start = register.addr();
+        // The FIFO address doesn't auto-increment
+        if start == Register::Fifo.addr() {
+            return;
+        }
+
+        values.iter().enumerate().for_each(|(offset, &value)| {
+            let addr = start as usize + offset;
+            if addr < 0x80 && Self::is_cacheable(addr as u8) {
+                self.values[addr] = value;
+                self.valid |= 1 << addr;
+            }
+        });
+    }
+
+    pub(crate) fn invalidate(&mut self) {
+        self.valid = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shadow_burst_update() {
+        let mut shadow = RegisterShadow::new();
+        assert_eq!(shadow.get(Register::DataModul), None);
+
+        shadow.update(Register::DataModul, &[0x01, 0x00, 0x80]);
+        assert_eq!(shadow.get(Register::DataModul), Some(0x01));
+        assert_eq!(shadow.get(Register::BitrateLsb), Some(0x80));
+        assert_eq!(shadow.get(Register::FdevMsb), None);
+
+        shadow.invalidate();
+        assert_eq!(shadow.get(Register::DataModul), None);
+    }
+
+    #[test]
+    fn test_shadow_volatile_registers() {
+        let mut shadow = RegisterShadow::new();
+
+        shadow.update(Register::Fifo, &[0x01, 0x02]);
+        assert_eq!(shadow.get(Register::OpMode), None);
+
+        shadow.update(Register::IrqFlags1, &[0x80, 0x00, 0xE4]);
+        assert_eq!(shadow.get(Register::IrqFlags1), None);
+        assert_eq!(shadow.get(Register::IrqFlags2), None);
+        assert_eq!(shadow.get(Register::RssiThresh), Some(0xE4));
+
+        shadow.update(Register::PacketConfig2, &[0x02; 18]);
+        assert_eq!(shadow.get(Register::AesKey1), None);
+        assert_eq!(shadow.get(Register::AesKey16), None);
+        assert_eq!(shadow.get(Register::Temp1), None);
+
+        shadow.update(Register::TestDagc, &[0x30]);
+        assert_eq!(shadow.get(Register::TestDagc), Some(0x30));
+    }
+
+    #[test]
+    fn test_op_mode_bits() {
+        let op_mode = OpMode::from_bits(0x84).unwrap();
+        assert!(op_mode.sequencer_off);
+        assert!(!op_mode.listen_on);
+        assert_eq!(op_mode.mode, Rfm69Mode::Standby);
+        assert_eq!(op_mode.to_bits(), 0x84);
+
+        // Reserved mode
+        assert_eq!(OpMode::from_bits(0x1C), None);
+    }
+
+    #[test]
+    fn test_typed_register() {
+        let dio_mapping2 = DioMapping2::decode(0x47).unwrap();
+        assert_eq!(dio_mapping2.dio4_mapping, 0x01);
+        assert_eq!(dio_mapping2.clk_out, DioMapping2::CLK_OUT_OFF);
+        assert_eq!(dio_mapping2.encode(), 0x47);
+        assert_eq!(<DioMapping2 as TypedRegister>::REGISTER, Register::DioMapping2);
+
+        // RestartRx isn't written back
+        let packet_config = PacketConfig2::decode(0x17).unwrap();
+        assert!(!packet_config.restart_rx && packet_config.aes_on);
+        assert_eq!(packet_config.encode(), 0x13);
+        assert_eq!(<OpMode as TypedRegister>::decode(0x1C), None);
+    }
+
+    #[test]
+    fn test_register_bits_round_trip() {
+        let data_modul = DataModul::from_bits(0x01).unwrap();
+        assert_eq!(data_modul.data_mode, DataMode::Packet);
+        assert_eq!(data_modul.modulation, Modulation::Fsk);
+        assert_eq!(data_modul.to_bits(), 0x01);
+
+        let packet_config = PacketConfig1::from_bits(0xD0).unwrap();
+        assert!(packet_config.variable_length);
+        assert_eq!(packet_config.dc_free, DcFree::Whitening);
+        assert!(packet_config.crc_on);
+        assert_eq!(packet_config.address_filtering, AddressFiltering::None);
+        assert_eq!(packet_config.to_bits(), 0xD0);
+        assert_eq!(PacketConfig1::from_bits(0x60), None);
+
+        assert_eq!(PaLevel::from_bits(0x5F).to_bits(), 0x5F);
+        assert_eq!(Lna::from_bits(0x88).to_bits(), 0x88);
+        assert_eq!(PacketConfig2::from_bits(0x13).to_bits(), 0x13);
+
+        let fifo_thresh = FifoThresh::from_bits(0x8F);
+        assert!(fifo_thresh.tx_start_fifo_not_empty);
+        assert_eq!(fifo_thresh.fifo_threshold, 15);
+        assert_eq!(fifo_thresh.to_bits(), 0x8F);
+    }
+}
@@ -0,0 +1,509 @@
+use crate::header::{Header, BROADCAST_ADDRESS, FLAGS_ACK, FLAGS_NO_ACK};
+use crate::read_write::ReadWrite;
+use crate::retry::{Jitter, RetryPolicy};
+use crate::rfm69::{Rfm69, Rfm69Error, Rfm69Mode};
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+const DEFAULT_TIMEOUT_MS: u32 = 200;
+
+/// A request received by `ReliableDatagram::receive_request`, answered with
+/// `ReliableDatagram::respond`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Request {
+    pub from: u8,
+    pub to: u8,
+    pub id: u8,
+    /// Number of payload bytes written to the receive buffer.
+    pub length: usize,
+    /// Sent with `send_to_no_ack`, `respond` doesn't answer it.
+    pub no_ack: bool,
+}
+
+// The last acknowledgement sent, repeated when the request is retransmitted
+struct Response {
+    to: u8,
+    id: u8,
+    payload: [u8; RF69_MAX_MESSAGE_LEN],
+    length: usize,
+}
+
+/// Acknowledged, addressed datagrams on top of `Rfm69`.
+///
+/// Every datagram sent to a single node is retransmitted until that node
+/// acknowledges it. The acknowledgement can carry a payload, so a request and
+/// its response take a single round trip.
+pub struct ReliableDatagram<SPI, RESET, INTR, D> {
+    radio: Rfm69<SPI, RESET, INTR, D>,
+    address: u8,
+    sequence: u8,
+    retry_policy: RetryPolicy,
+    jitter: Jitter,
+    timeout_ms: u32,
+    // Last sequence number received from each node, to drop retransmissions
+    seen_ids: [Option<u8>; 256],
+    last_response: Option<Response>,
+}
+
+impl<SPI, RESET, INTR, D> ReliableDatagram<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    pub fn new(radio: Rfm69<SPI, RESET, INTR, D>, address: u8) -> Self {
+        ReliableDatagram {
+            radio,
+            address,
+            sequence: 0,
+            retry_policy: RetryPolicy::default(),
+            // Spread the retries of different nodes until a better seed is set
+            jitter: Jitter::new((address as u32).wrapping_mul(0x9E37_79B9)),
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            seen_ids: [None; 256],
+            last_response: None,
+        }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn radio(&mut self) -> &mut Rfm69<SPI, RESET, INTR, D> {
+        &mut self.radio
+    }
+
+    pub fn release(self) -> Rfm69<SPI, RESET, INTR, D> {
+        self.radio
+    }
+
+    /// Retransmit policy used by `send_to_wait` and `send_to_wait_response`.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Seeds the jitter added to retransmit delays, e.g. from a unique id or RSSI noise.
+    pub fn set_jitter_seed(&mut self, seed: u32) {
+        self.jitter = Jitter::new(seed);
+    }
+
+    /// Time to wait for an acknowledgement before retransmitting.
+    pub fn set_timeout_ms(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Sends `data` to `to` and waits for the acknowledgement. Broadcasts are
+    /// sent once and not acknowledged.
+    pub async fn send_to_wait(&mut self, to: u8, data: &[u8]) -> Result<(), Rfm69Error> {
+        self.send_with_retry(to, data, self.retry_policy).await
+    }
+
+    /// Like `send_to_wait`, retransmitting according to `retry_policy` instead of
+    /// the configured policy.
+    pub async fn send_with_retry(
+        &mut self,
+        to: u8,
+        data: &[u8],
+        retry_policy: RetryPolicy,
+    ) -> Result<(), Rfm69Error> {
+        self.send_and_wait_ack(to, data, None, retry_policy).await?;
+        Ok(())
+    }
+
+    /// Sends `data` to `to` once, without waiting for an acknowledgement.
+    pub async fn send_to_no_ack(&mut self, to: u8, data: &[u8]) -> Result<(), Rfm69Error> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let header = Header {
+            to,
+            from: self.address,
+            id: self.sequence,
+            flags: FLAGS_NO_ACK,
+        };
+        self.radio.send_with_header(header, data).await
+    }
+
+    /// Sends `data` to `to` and copies the payload carried by the acknowledgement
+    /// into `response`, returning its length.
+    pub async fn send_to_wait_response(
+        &mut self,
+        to: u8,
+        data: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, Rfm69Error> {
+        self.send_and_wait_ack(to, data, Some(response), self.retry_policy)
+            .await
+    }
+
+    async fn send_and_wait_ack(
+        &mut self,
+        to: u8,
+        data: &[u8],
+        mut response: Option<&mut [u8]>,
+        retry_policy: RetryPolicy,
+    ) -> Result<usize, Rfm69Error> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let header = Header {
+            to,
+            from: self.address,
+            id: self.sequence,
+            flags: 0,
+        };
+
+        for attempt in 0..retry_policy.max_attempts.max(1) {
+            if attempt > 0 {
+                let delay = retry_policy.delay_ms(attempt - 1, self.jitter.next());
+                self.radio.delay.delay_ms(delay).await;
+                self.radio.record_retransmission();
+            }
+
+            self.radio.send_with_header(header, data).await?;
+            if to == BROADCAST_ADDRESS {
+                return Ok(0);
+            }
+
+            if let Some(length) = self.wait_ack(&header, response.as_deref_mut()).await? {
+                return Ok(length);
+            }
+        }
+
+        self.radio.report_timeout().await?;
+        Err(Rfm69Error::AckTimeout)
+    }
+
+    async fn wait_ack(
+        &mut self,
+        sent: &Header,
+        response: Option<&mut [u8]>,
+    ) -> Result<Option<usize>, Rfm69Error> {
+        self.radio.set_mode(Rfm69Mode::Rx).await?;
+
+        let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+        // Other packets arriving meanwhile don't extend the wait
+        let mut remaining_us = self.timeout_ms as u64 * 1000;
+        while let Some(waited_us) = self.radio.poll_for_message(Some(remaining_us)).await? {
+            remaining_us -= waited_us;
+            let (header, length) = match self.radio.receive(&mut buffer).await {
+                Ok(received) => received,
+                Err(
+                    Rfm69Error::CrcFailure
+                    | Rfm69Error::FifoOverrun
+                    | Rfm69Error::AuthenticationFailure,
+                ) => continue,
+                Err(error) => return Err(error),
+            };
+
+            if header.is_ack()
+                && header.to == self.address
+                && header.from == sent.to
+                && header.id == sent.id
+            {
+                let Some(response) = response else {
+                    return Ok(Some(0));
+                };
+                if response.len() < length {
+                    return Err(Rfm69Error::BufferTooSmall);
+                }
+                response[..length].copy_from_slice(&buffer[..length]);
+                return Ok(Some(length));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the received datagram into `buffer` and acknowledges it without a payload.
+    /// Returns the source address and the payload length.
+    pub async fn receive_from_ack(&mut self, buffer: &mut [u8]) -> Result<(u8, usize), Rfm69Error> {
+        let request = self.receive_request(buffer).await?;
+        self.respond(&request, &[]).await?;
+        Ok((request.from, request.length))
+    }
+
+    /// Reads the received datagram into `buffer` without acknowledging it, the
+    /// caller answers with `respond`.
+    ///
+    /// Returns `Rfm69Error::NoMessage` if the packet was not for this node, was an
+    /// acknowledgement or a retransmission. Retransmissions are acknowledged again.
+    pub async fn receive_request(&mut self, buffer: &mut [u8]) -> Result<Request, Rfm69Error> {
+        let (header, length) = self.radio.receive(buffer).await?;
+
+        if header.is_ack() || (header.to != self.address && header.to != BROADCAST_ADDRESS) {
+            return Err(Rfm69Error::NoMessage);
+        }
+
+        let request = Request {
+            from: header.from,
+            to: header.to,
+            id: header.id,
+            length,
+            no_ack: header.is_no_ack(),
+        };
+
+        if self.seen_ids[header.from as usize] == Some(header.id) {
+            // Our acknowledgement was lost, repeat it
+            let last = self
+                .last_response
+                .take()
+                .filter(|last| last.to == header.from && last.id == header.id);
+            let payload = last
+                .as_ref()
+                .map_or(&[][..], |last| &last.payload[..last.length]);
+            self.respond(&request, payload).await?;
+            return Err(Rfm69Error::NoMessage);
+        }
+
+        self.seen_ids[header.from as usize] = Some(header.id);
+        Ok(request)
+    }
+
+    /// Acknowledges `request`, carrying `payload` back to the sender. Broadcasts
+    /// and datagrams sent with `send_to_no_ack` are not acknowledged.
+    pub async fn respond(&mut self, request: &Request, payload: &[u8]) -> Result<(), Rfm69Error> {
+        if request.to == BROADCAST_ADDRESS || request.no_ack {
+            return Ok(());
+        }
+        if payload.len() > RF69_MAX_MESSAGE_LEN {
+            return Err(Rfm69Error::MessageTooLarge);
+        }
+
+        let header = Header {
+            to: request.from,
+            from: self.address,
+            id: request.id,
+            flags: FLAGS_ACK,
+        };
+        self.radio.send_with_header(header, payload).await?;
+
+        let mut response = Response {
+            to: request.from,
+            id: request.id,
+            payload: [0; RF69_MAX_MESSAGE_LEN],
+            length: payload.len(),
+        };
+        response.payload[..payload.len()].copy_from_slice(payload);
+        self.last_response = Some(response);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registers::Register;
+    use crate::rfm69::PollIntervals;
+    use crate::sim::SimChannel;
+    use crate::test_utils::{
+        check_expectations, read_many, read_register, send, setup_rfm, write_register,
+        DelayTransaction, GpioTransaction, State,
+    };
+    use embassy_futures::select::select;
+
+    #[tokio::test]
+    async fn test_send_to_wait_response() {
+        let mut reliable = ReliableDatagram::new(setup_rfm(), 0x01);
+
+        let spi_expectations = [
+            send(&[5, 0x02, 0x01, 0x01, 0x00, 0x10]),
+            // Wait for the acknowledgement
+            write_register(Register::OpMode, 0x10).to_vec(),
+            read_register(Register::IrqFlags1, 0x80).to_vec(),
+            read_many(Register::IrqFlags1, &[0x00, 0x04]).to_vec(),
+            read_register(Register::IrqFlags2, 0x06).to_vec(),
+            read_register(Register::Fifo, 6).to_vec(),
+            read_many(Register::Fifo, &[0x01, 0x02, 0x01, 0x80, 0x12, 0x34]).to_vec(),
+        ]
+        .concat();
+        let radio = reliable.radio();
+        radio.spi.update_expectations(&spi_expectations);
+        radio
+            .intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+
+        let mut response = [0u8; 8];
+        let length = reliable
+            .send_to_wait_response(0x02, &[0x10], &mut response)
+            .await
+            .unwrap();
+        assert_eq!(response[..length], [0x12, 0x34]);
+
+        check_expectations(reliable.radio());
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_backoff() {
+        let mut reliable = ReliableDatagram::new(setup_rfm(), 0x01);
+        reliable.set_timeout_ms(1);
+        reliable.radio().set_poll_intervals(PollIntervals {
+            message_us: 600,
+            ..PollIntervals::default()
+        });
+
+        // Polled until the timeout, not for a number of polls
+        let no_ack = [
+            write_register(Register::OpMode, 0x10),
+            read_register(Register::IrqFlags1, 0x80),
+            read_many(Register::IrqFlags1, &[0x00, 0x00]),
+            read_many(Register::IrqFlags1, &[0x00, 0x00]),
+            read_many(Register::IrqFlags1, &[0x00, 0x00]),
+        ]
+        .concat();
+        let spi_expectations = [
+            send(&[5, 0x02, 0x01, 0x01, 0x00, 0x10]),
+            no_ack.clone(),
+            send(&[5, 0x02, 0x01, 0x01, 0x00, 0x10]),
+            no_ack,
+        ]
+        .concat();
+        let radio = reliable.radio();
+        radio.spi.update_expectations(&spi_expectations);
+        radio.intr_pin.update_expectations(&[
+            GpioTransaction::wait_for_state(State::High),
+            GpioTransaction::wait_for_state(State::High),
+        ]);
+        radio.delay.update_expectations(&[
+            DelayTransaction::delay_us(600),
+            DelayTransaction::delay_us(400),
+            DelayTransaction::delay_ms(20),
+            DelayTransaction::delay_us(600),
+            DelayTransaction::delay_us(400),
+        ]);
+
+        let policy = RetryPolicy {
+            max_attempts: 2,
+            base_delay_ms: 20,
+            max_delay_ms: 100,
+            jitter_ms: 0,
+        };
+        assert_eq!(
+            reliable.send_with_retry(0x02, &[0x10], policy).await,
+            Err(Rfm69Error::AckTimeout)
+        );
+        let stats = reliable.radio().stats();
+        assert_eq!(
+            (stats.packets_sent, stats.retransmissions, stats.timeouts),
+            (2, 1, 1)
+        );
+
+        check_expectations(reliable.radio());
+    }
+
+    #[tokio::test]
+    async fn test_respond_repeats_lost_ack() {
+        let mut reliable = ReliableDatagram::new(setup_rfm(), 0x02);
+
+        let request = [
+            read_register(Register::IrqFlags2, 0x06),
+            read_register(Register::Fifo, 5),
+            read_many(Register::Fifo, &[0x02, 0x01, 0x07, 0x00, 0x10]),
+        ]
+        .concat();
+        let spi_expectations = [
+            request.clone(),
+            send(&[6, 0x01, 0x02, 0x07, 0x80, 0x12, 0x34]),
+            // The retransmitted request gets the same response
+            request,
+            send(&[6, 0x01, 0x02, 0x07, 0x80, 0x12, 0x34]),
+        ]
+        .concat();
+        let radio = reliable.radio();
+        radio.spi.update_expectations(&spi_expectations);
+        radio.intr_pin.update_expectations(&[
+            GpioTransaction::wait_for_state(State::High),
+            GpioTransaction::wait_for_state(State::High),
+        ]);
+
+        let mut buffer = [0u8; 8];
+        let request = reliable.receive_request(&mut buffer).await.unwrap();
+        assert_eq!(
+            request,
+            Request {
+                from: 0x01,
+                to: 0x02,
+                id: 0x07,
+                length: 1,
+                no_ack: false,
+            }
+        );
+        reliable.respond(&request, &[0x12, 0x34]).await.unwrap();
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            reliable.receive_request(&mut buffer).await,
+            Err(Rfm69Error::NoMessage)
+        );
+
+        check_expectations(reliable.radio());
+    }
+
+    #[tokio::test]
+    async fn test_first_request_with_id_zero() {
+        let mut reliable = ReliableDatagram::new(setup_rfm(), 0x02);
+
+        let spi_expectations = [
+            read_register(Register::IrqFlags2, 0x06),
+            read_register(Register::Fifo, 5),
+            read_many(Register::Fifo, &[0x02, 0x01, 0x00, 0x00, 0x10]),
+        ]
+        .concat();
+        reliable.radio().spi.update_expectations(&spi_expectations);
+
+        // Not taken for a retransmission, nothing was received from 0x01 yet
+        let mut buffer = [0u8; 8];
+        let request = reliable.receive_request(&mut buffer).await.unwrap();
+        assert_eq!((request.from, request.id), (0x01, 0x00));
+
+        check_expectations(reliable.radio());
+    }
+
+    #[tokio::test]
+    async fn test_lossy_channel() {
+        let channel = SimChannel::new(3);
+        let mut sender = channel.radio();
+        let mut receiver = channel.radio();
+        sender.init().await.unwrap();
+        receiver.init().await.unwrap();
+        let mut sender = ReliableDatagram::new(sender, 0x01);
+        let mut receiver = ReliableDatagram::new(receiver, 0x02);
+        sender.set_retry_policy(RetryPolicy {
+            max_attempts: 8,
+            ..RetryPolicy::default()
+        });
+        channel.set_loss_percent(25);
+
+        let mut received = Vec::new();
+        let serve = async {
+            let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+            loop {
+                receiver.radio().set_mode(Rfm69Mode::Rx).await.unwrap();
+                receiver.radio().wait_for_message().await.unwrap();
+                match receiver.receive_request(&mut buffer).await {
+                    Ok(request) => {
+                        received.push(buffer[..request.length].to_vec());
+                        receiver.respond(&request, &[request.id]).await.unwrap();
+                    }
+                    Err(Rfm69Error::NoMessage) => {}
+                    Err(error) => panic!("{:?}", error),
+                }
+            }
+        };
+        let send = async {
+            for value in 0..10u8 {
+                let mut response = [0u8; 1];
+                let length = sender
+                    .send_to_wait_response(0x02, &[value; 8], &mut response)
+                    .await
+                    .unwrap();
+                assert_eq!(&response[..length], &[value + 1]);
+            }
+        };
+        select(send, serve).await;
+
+        // Every datagram arrived once, some after retransmissions
+        let expected: Vec<_> = (0..10u8).map(|value| vec![value; 8]).collect();
+        assert_eq!(received, expected);
+        assert!(sender.radio().stats().retransmissions > 0);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// Pulse length of most EV1527 and PT2262 remotes, in microseconds.
+pub const DEFAULT_PULSE_US: u32 = 350;
+
+/// Length of an EV1527 or PT2262 frame, sync included, at one bit per pulse.
+pub const FRAME_LENGTH: usize = 16;
+
+const EV1527_ADDRESS_BITS: u32 = 20;
+const PT2262_TRITS: usize = 12;
+
+/// A PT2262 code position, set by an address pin tied high, low or left open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Trit {
+    Zero,
+    One,
+    Float,
+}
+
+// Writes pulses MSB first, one bit per pulse length
+struct PulseWriter {
+    frame: [u8; FRAME_LENGTH],
+    bit: usize,
+}
+
+impl PulseWriter {
+    fn new() -> Self {
+        PulseWriter {
+            frame: [0; FRAME_LENGTH],
+            bit: 0,
+        }
+    }
+
+    // Carrier on for `high` pulses, then off for `low` pulses
+    fn pulse(&mut self, high: usize, low: usize) {
+        for _ in 0..high {
+            self.frame[self.bit / 8] |= 0x80 >> (self.bit % 8);
+            self.bit += 1;
+        }
+        self.bit += low;
+    }
+
+    // 1 is a long pulse and a short gap, 0 a short pulse and a long gap
+    fn bit(&mut self, one: bool) {
+        match one {
+            true => self.pulse(3, 1),
+            false => self.pulse(1, 3),
+        }
+    }
+
+    fn sync(&mut self) {
+        self.pulse(1, 31);
+    }
+}
+
+/// EV1527 frame: the sync pulse, then the 20 bit `address` and the 4 `button`
+/// bits, MSB first.
+pub fn ev1527_frame(address: u32, button: u8) -> Result<[u8; FRAME_LENGTH], Rfm69Error> {
+    if address >> EV1527_ADDRESS_BITS != 0 || button > 0x0F {
+        return Err(Rfm69Error::ConfigurationError);
+    }
+
+    let mut writer = PulseWriter::new();
+    writer.sync();
+    let code = address << 4 | button as u32;
+    for bit in (0..EV1527_ADDRESS_BITS + 4).rev() {
+        writer.bit(code & (1 << bit) != 0);
+    }
+    Ok(writer.frame)
+}
+
+/// PT2262 frame: the 12 trits of `code`, usually 8 address and 4 data positions,
+/// then the sync pulse.
+pub fn pt2262_frame(code: &[Trit; PT2262_TRITS]) -> [u8; FRAME_LENGTH] {
+    let mut writer = PulseWriter::new();
+    for trit in code {
+        let (first, second) = match trit {
+            Trit::Zero => (false, false),
+            Trit::One => (true, true),
+            Trit::Float => (false, true),
+        };
+        writer.bit(first);
+        writer.bit(second);
+    }
+    writer.sync();
+    writer.frame
+}
+
+/// Presses `button` of the EV1527 remote `address`, sending the frame
+/// `repeats` times. Receivers usually want to see it at least 4 times.
+pub async fn send_ev1527<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    address: u32,
+    button: u8,
+    repeats: u8,
+) -> Result<(), Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let frame = ev1527_frame(address, button)?;
+    radio.transmit_ook(DEFAULT_PULSE_US, &frame, repeats).await
+}
+
+/// Sends the PT2262 `code` `repeats` times.
+pub async fn send_pt2262<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    code: &[Trit; PT2262_TRITS],
+    repeats: u8,
+) -> Result<(), Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    radio
+        .transmit_ook(DEFAULT_PULSE_US, &pt2262_frame(code), repeats)
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ev1527_frame() {
+        let frame = ev1527_frame(0xA5F0F, 0x3).unwrap();
+        // Sync: 1 pulse on, 31 off
+        assert_eq!(frame[..4], [0x80, 0x00, 0x00, 0x00]);
+        // Address 1010: long, short, long, short
+        assert_eq!(frame[4..6], [0xE8, 0xE8]);
+        // Button 0011
+        assert_eq!(frame[14..], [0x88, 0xEE]);
+
+        assert_eq!(
+            ev1527_frame(0x100000, 0),
+            Err(Rfm69Error::ConfigurationError)
+        );
+        assert_eq!(ev1527_frame(0, 0x10), Err(Rfm69Error::ConfigurationError));
+    }
+
+    #[test]
+    fn test_pt2262_frame() {
+        let mut code = [Trit::Zero; 12];
+        code[0] = Trit::One;
+        code[1] = Trit::Float;
+        let frame = pt2262_frame(&code);
+        // One: long, long. Float: short, long
+        assert_eq!(frame[..2], [0xEE, 0x8E]);
+        assert_eq!(frame[2..12], [0x88; 10]);
+        // Sync at the end
+        assert_eq!(frame[12..], [0x80, 0x00, 0x00, 0x00]);
+    }
+}
@@ -0,0 +1,102 @@
+/// How often and when to retransmit an unacknowledged datagram.
+///
+/// The delay before retry `n` is `base_delay_ms * 2^n`, capped at `max_delay_ms`,
+/// plus a random jitter of up to `jitter_ms` so colliding nodes don't retry in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct RetryPolicy {
+    /// Total number of transmissions, including the first one.
+    pub max_attempts: u8,
+    pub base_delay_ms: u32,
+    pub max_delay_ms: u32,
+    pub jitter_ms: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay_ms: 50,
+            max_delay_ms: 1_000,
+            jitter_ms: 50,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retry `retry`, counted from 0, with `random` picking the jitter.
+    pub fn delay_ms(&self, retry: u8, random: u32) -> u32 {
+        let backoff = self
+            .base_delay_ms
+            .checked_shl(retry as u32)
+            .filter(|delay| *delay >> retry == self.base_delay_ms)
+            .unwrap_or(u32::MAX)
+            .min(self.max_delay_ms);
+        let jitter = match self.jitter_ms {
+            0 => 0,
+            jitter_ms => (random as u64 % (jitter_ms as u64 + 1)) as u32,
+        };
+        backoff.saturating_add(jitter)
+    }
+}
+
+/// xorshift32, only used to spread retries, not suitable for anything security related.
+pub(crate) struct Jitter(u32);
+
+impl Jitter {
+    pub(crate) fn new(seed: u32) -> Self {
+        // xorshift never leaves 0
+        Jitter(seed.max(1))
+    }
+
+    pub(crate) fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 8,
+            base_delay_ms: 50,
+            max_delay_ms: 300,
+            jitter_ms: 0,
+        };
+        assert_eq!(policy.delay_ms(0, 7), 50);
+        assert_eq!(policy.delay_ms(1, 7), 100);
+        assert_eq!(policy.delay_ms(2, 7), 200);
+        assert_eq!(policy.delay_ms(3, 7), 300);
+        assert_eq!(policy.delay_ms(40, 7), 300);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let policy = RetryPolicy {
+            jitter_ms: 10,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay_ms(0, 0), 50);
+        assert_eq!(policy.delay_ms(0, 10), 60);
+        assert_eq!(policy.delay_ms(0, 11), 50);
+
+        let policy = RetryPolicy {
+            jitter_ms: u32::MAX,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay_ms(0, 7), 57);
+        assert_eq!(policy.delay_ms(0, u32::MAX), u32::MAX);
+
+        let mut first = Jitter::new(1);
+        let mut second = Jitter::new(2);
+        assert_ne!(first.next(), second.next());
+        assert_eq!(Jitter::new(0).next(), Jitter::new(1).next());
+    }
+}
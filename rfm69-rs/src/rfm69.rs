@@ -1,10 +1,45 @@
+use crate::beacon::BeaconFrame;
+use crate::ber::{self, BerReport, BER_PACKET_LENGTH};
+use crate::crc::Crc32;
+use crate::dump::RegisterDump;
+use crate::duty_cycle::{airtime_ms, DutyCycleAction, DutyCycleLimiter};
+use crate::ert::{self, ErtProtocol};
+use crate::header::{
+    Datagram, Header, PacketFilter, BROADCAST_ADDRESS, FLAGS_BROADCAST, HEADER_LENGTH,
+};
+use crate::interrupt::{InterruptState, RadioEvent, ReceivedPacket, MAX_PAYLOAD_LENGTH};
+use crate::link_stats::LinkStats;
+use crate::listen::{covering_preamble_length, ListenConfig};
+use crate::modulation::{FskModulation, ModulationError, Shaping};
+use crate::mysensors;
+use crate::network_id;
+use crate::raw::Raw;
 use crate::read_write::ReadWrite;
-use crate::registers::Register;
+use crate::region::Region;
+use crate::register_decoder::frequency_hz;
+use crate::registers::{
+    AddressFiltering, DataMode, DataModul, DcFree, DioMapping2, FifoThresh, IrqFlags1, IrqFlags2,
+    Lna, Modulation, OpMode, PaLevel, PacketConfig1, PacketConfig2, Register, RegisterShadow,
+    TypedRegister,
+};
+use crate::self_test::SelfTestReport;
+use crate::session::MAC_LENGTH;
 use crate::settings::{
-    ContinuousDagc, ModemConfigChoice, SyncConfiguration, RF69_FSTEP, RF69_FXOSC,
-    RF_DIOMAPPING1_DIO0_00, RF_DIOMAPPING1_DIO0_01, RF_PALEVEL_OUTPUTPOWER_11111,
-    RF_PALEVEL_PA0_ON, RF_PALEVEL_PA1_ON, RF_PALEVEL_PA2_ON,
+    ContinuousDagc, ModemConfigChoice, SyncConfiguration, TxStartCondition, RF69_FIFO_SIZE,
+    RF69_FIFO_THRESHOLD, RF69_FSTEP, RF69_FXOSC_HZ, RF_DIOMAPPING1_DIO0_00, RF_DIOMAPPING1_DIO0_01,
+    RF_IRQFLAGS2_FIFOOVERRUN, RF_OCP_OFF, RF_OCP_ON, RF_OSC1_RCCAL_DONE, RF_OSC1_RCCAL_START,
+    RF_RSSI_DONE, RF_RSSI_START, RF_TEMP1_MEAS_RUNNING, RF_TEMP1_MEAS_START,
+    RF_TESTLNA_HIGH_SENSITIVITY, RF_TESTLNA_NORMAL, RF_TESTPA1_BOOST, RF_TESTPA1_NORMAL,
+    RF_TESTPA2_BOOST, RF_TESTPA2_NORMAL,
 };
+use crate::stats::Stats;
+use crate::temperature::{TemperatureCompensation, TemperatureMonitor};
+use crate::test_pattern::TestPattern;
+use crate::units::{Frequency, TxPowerDbm};
+use crate::watchdog::{StuckCondition, Watchdog};
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use core::ops::RangeInclusive;
 use defmt::{debug, info, Format};
 use embedded_hal::{digital::InputPin, digital::OutputPin};
 use embedded_hal_async::{delay::DelayNs, digital::Wait};
@@ -15,21 +50,194 @@ pub struct Rfm69<SPI, RESET, INTR, D> {
     pub intr_pin: INTR,
     pub delay: D,
     tx_power: i8,
-    is_high_power: bool,
+    variant: Rfm69Variant,
+    chip_version: Option<ChipVersion>,
     current_mode: Rfm69Mode,
+    frequency: Frequency,
+    modem_config: ModemConfigChoice,
+    // Set by `set_fsk_modulation`, overrides the bitrate etc. of `modem_config`
+    modulation: Option<FskModulation>,
+    // Set by `set_shaping`, overrides the ModulationShaping of `modem_config`
+    shaping: Option<Shaping>,
+    // Set by the protocol profiles, overrides the PacketConfig1 of `modem_config`
+    packet_config: Option<PacketConfig1>,
+    preamble_length: u16,
+    sync_configuration: SyncConfiguration,
+    sync_words: [u8; 8],
+    sync_length: u8,
+    duty_cycle: Option<DutyCycleLimiter>,
+    region: Option<Region>,
+    node_address: Option<u8>,
+    broadcast_address: u8,
+    sequence: u8,
+    fifo_threshold: u8,
+    tx_start_condition: TxStartCondition,
+    temperature_compensation: Option<TemperatureCompensation>,
+    // Correction applied to FRF by `trim_frequency`
+    frequency_trim_ppm: f32,
+    link_stats: Option<LinkStats>,
+    // RSSI sampled at SyncAddressMatch of the packet being received, and of the last packet read
+    latched_rssi: Option<i16>,
+    packet_rssi: Option<i16>,
+    software_crc: bool,
+    // Set by `set_encryption_key`, the AES engine limits the packet length
+    encrypted: bool,
+    // The radio lost its configuration with encryption on, Tx and Rx are refused
+    // until `set_encryption_key` is called again
+    key_lost: bool,
+    // Last value written to RegPacketConfig2, which isn't kept in the shadow
+    packet_config2: PacketConfig2,
+    // Set by `set_authentication_key`
+    authentication: Option<Cmac<Aes128>>,
+    // Set by `set_payload_length`, packets are sent without a length byte
+    payload_length: Option<u8>,
+    packet_filter: Option<PacketFilter>,
+    sensitivity_boost: bool,
+    front_end_control: Option<FrontEndControl>,
+    watchdog: Option<Watchdog>,
+    poll_intervals: PollIntervals,
+    idle_policy: Option<IdlePolicy>,
+    // Time `wait_for_message` spent in Rx without a packet, and whether the idle
+    // policy took the radio out of Rx
+    rx_idle_us: u64,
+    idled: bool,
+    stats: Stats,
+    interrupt_state: InterruptState,
+    event: Option<RadioEvent>,
+    shadow: RegisterShadow,
+    // Last value written to RegOpMode, mode changes don't read it back
+    op_mode: OpMode,
 }
 
 #[derive(Debug, PartialEq, Format)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
 pub enum Rfm69Error {
+    #[cfg_attr(feature = "std", error("failed to reset the radio"))]
     ResetError,
+    #[cfg_attr(feature = "std", error("failed to read the interrupt pin"))]
+    InterruptPinError,
+    #[cfg_attr(feature = "std", error("SPI write failed"))]
     SpiWriteError,
+    #[cfg_attr(feature = "std", error("SPI read failed"))]
     SpiReadError,
+    #[cfg_attr(feature = "std", error("invalid configuration"))]
     ConfigurationError,
+    #[cfg_attr(feature = "std", error("message too large for a packet"))]
     MessageTooLarge,
+    #[cfg_attr(feature = "std", error("operation not allowed in the current mode"))]
     InvalidMode,
+    #[cfg_attr(feature = "std", error("duty cycle limit exceeded"))]
+    DutyCycleExceeded,
+    #[cfg_attr(feature = "std", error("frequency out of range"))]
+    FrequencyOutOfRange,
+    #[cfg_attr(feature = "std", error("Tx power out of range"))]
+    TxPowerOutOfRange,
+    #[cfg_attr(feature = "std", error("buffer too small"))]
+    BufferTooSmall,
+    #[cfg_attr(feature = "std", error("no message received"))]
+    NoMessage,
+    #[cfg_attr(feature = "std", error("CRC check failed"))]
+    CrcFailure,
+    /// The length byte of a received packet doesn't fit a header or the FIFO,
+    /// the packet was dropped.
+    #[cfg_attr(feature = "std", error("malformed packet"))]
+    MalformedPacket,
+    #[cfg_attr(feature = "std", error("no ACK received"))]
+    AckTimeout,
+    /// The radio didn't reach the expected state in time, e.g. the FIFO didn't
+    /// drain during a stream transmission.
+    #[cfg_attr(feature = "std", error("timed out waiting for the radio"))]
+    Timeout,
+    #[cfg_attr(feature = "std", error("invalid modulation: {0}"))]
+    InvalidModulation(ModulationError),
+    /// The FIFO overflowed before it was read, its contents were dropped.
+    #[cfg_attr(feature = "std", error("FIFO overrun"))]
+    FifoOverrun,
+    #[cfg_attr(feature = "std", error("high power mode refused: {0}"))]
+    PaBoost(PaBoostError),
+    /// The radio was stuck and has been reset by the watchdog.
+    #[cfg_attr(feature = "std", error("radio stuck and reset: {0}"))]
+    RadioStuck(StuckCondition),
+    /// RegVersion didn't read back an SX1231 revision after reset, e.g. the
+    /// module isn't powered or the SPI wiring is wrong.
+    #[cfg_attr(feature = "std", error("no RFM69 module found, version 0x{got:02X}"))]
+    ModuleNotFound { got: u8 },
+    /// The MAC of a received packet doesn't match, see `Rfm69::set_authentication_key`.
+    #[cfg_attr(feature = "std", error("packet authentication failed"))]
+    AuthenticationFailure,
+    /// The radio lost its configuration, and with it the AES key the driver
+    /// keeps no copy of. The radio neither sends nor receives until the key is
+    /// set again with `Rfm69::set_encryption_key`.
+    #[cfg_attr(feature = "std", error("AES key lost, set it again"))]
+    EncryptionKeyLost,
+}
+
+/// A configuration register whose value on the radio differs from the value
+/// the driver last wrote to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct RegisterMismatch {
+    pub register: u8,
+    pub expected: u8,
+    pub actual: u8,
+}
+
+/// Delays between status register reads when the driver waits on the radio by
+/// polling, see `Rfm69::set_poll_intervals`. The interrupt API doesn't poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct PollIntervals {
+    /// ModeReady after a mode change, which takes about 100 us from Standby.
+    pub mode_ready_us: u32,
+    /// PacketSent once DIO0 went high.
+    pub packet_sent_us: u32,
+    /// PayloadReady in `wait_for_message`, at first. The interval doubles every
+    /// time there is no packet, up to `message_max_us`.
+    pub message_us: u32,
+    pub message_max_us: u32,
+}
+
+impl Default for PollIntervals {
+    fn default() -> Self {
+        PollIntervals {
+            mode_ready_us: 100,
+            packet_sent_us: 100,
+            message_us: 100,
+            message_max_us: 10_000,
+        }
+    }
+}
+
+/// Takes the radio out of Rx after a period without packets, saving the ~16 mA
+/// the receiver draws on nodes that only occasionally expect traffic, see
+/// `Rfm69::set_idle_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct IdlePolicy {
+    /// Time `wait_for_message` spends in Rx without a packet before giving up.
+    pub timeout_ms: u32,
+    /// Standby, or Sleep for the lowest current at the cost of a slower restart.
+    pub mode: Rfm69Mode,
 }
 
-#[derive(Clone, Debug, PartialEq, Format)]
+/// Why a transmission or configuration at +18 to +20 dBm is refused. The high
+/// power settings of the RFM69HW need the overcurrent protection off, which the
+/// driver takes care of, and are limited to a 1% duty cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum PaBoostError {
+    /// No duty cycle limiter is set to keep track of the high power airtime.
+    #[cfg_attr(feature = "std", error("no duty cycle limiter set"))]
+    NoDutyCycleLimiter,
+    /// The transmission would exceed the 1% duty cycle.
+    #[cfg_attr(feature = "std", error("1% duty cycle exceeded"))]
+    DutyCycleExceeded,
+    /// A configuration snapshot has the overcurrent protection on at high power.
+    #[cfg_attr(feature = "std", error("overcurrent protection enabled"))]
+    OcpEnabled,
+    /// A configuration snapshot has the high power settings on outside of Tx.
+    #[cfg_attr(feature = "std", error("high power settings enabled outside of Tx"))]
+    BoostOutsideTx,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
 pub enum Rfm69Mode {
     Sleep = 0x00,
     Standby = 0x04,
@@ -38,14 +246,334 @@ pub enum Rfm69Mode {
     Rx = 0x10,
 }
 
+/// Drives the TX-enable and RX-enable pins of an external front end or antenna
+/// switch for the mode the radio enters, see `Rfm69::set_front_end_control`.
+pub type FrontEndControl = fn(Rfm69Mode);
+
+impl Rfm69Mode {
+    /// Decodes the Mode field of RegOpMode.
+    pub fn from_op_mode(op_mode: u8) -> Option<Self> {
+        match op_mode & OpMode::MODE_MASK {
+            0x00 => Some(Self::Sleep),
+            0x04 => Some(Self::Standby),
+            0x08 => Some(Self::Fs),
+            0x0C => Some(Self::Tx),
+            0x10 => Some(Self::Rx),
+            _ => None,
+        }
+    }
+}
+
+/// Module variant, selecting the power amplifier path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum Rfm69Variant {
+    /// RFM69W and RFM69CW, PA0 only, -18 dBm to +13 dBm.
+    Rfm69W,
+    /// RFM69HW and RFM69HCW, PA1 and PA2, -2 dBm to +20 dBm.
+    Rfm69Hw,
+}
+
+impl Rfm69Variant {
+    /// Output power range supported by the variant, in dBm.
+    pub fn tx_power_range(self) -> RangeInclusive<i8> {
+        match self {
+            Self::Rfm69W => -18..=13,
+            Self::Rfm69Hw => -2..=20,
+        }
+    }
+}
+
+/// Contents of RegVersion, identifying the SX1231 silicon revision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct ChipVersion(u8);
+
+impl ChipVersion {
+    /// Accepts the SX1231 revisions 0x21 to 0x23 and the SX1231H, 0x24, which
+    /// is also used by the HopeRF RFM69 modules.
+    pub fn from_register(version: u8) -> Option<Self> {
+        match version {
+            0x21..=0x24 => Some(ChipVersion(version)),
+            _ => None,
+        }
+    }
+
+    pub fn value(self) -> u8 {
+        self.0
+    }
+
+    pub fn full_revision(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub fn metal_mask_revision(self) -> u8 {
+        self.0 & 0x0F
+    }
+
+    /// True on the SX1231H, whose PA1 and PA2 reach +20 dBm with the high power
+    /// settings. The plain SX1231 stops at +17 dBm.
+    pub fn has_high_power_boost(self) -> bool {
+        self.0 >= 0x24
+    }
+}
+
+// RegOpMode after a reset
+const RESET_OP_MODE: OpMode = OpMode {
+    sequencer_off: false,
+    listen_on: false,
+    listen_abort: false,
+    mode: Rfm69Mode::Standby,
+};
+
+// RegPacketConfig2 after a reset
+const RESET_PACKET_CONFIG2: PacketConfig2 = PacketConfig2 {
+    inter_packet_rx_delay: 0,
+    restart_rx: false,
+    auto_rx_restart_on: true,
+    aes_on: false,
+};
+
+// Registers outside `write_configuration` that are restored from the shadow
+// when the radio lost its configuration
+const PRESERVED_REGISTERS: [Register; 2] = [Register::RssiThresh, Register::TestLna];
+
+// RegVersion reads in `init`, some modules take longer than the reset delay to
+// come out of power on reset
+const VERSION_READ_ATTEMPTS: usize = 5;
+const VERSION_RETRY_DELAY_MS: u32 = 5;
+
+// Written to the FIFO and read back by `self_test`
+const SELF_TEST_PATTERN: [u8; 4] = [0x55, 0xAA, 0x00, 0xFF];
+
+// Length byte in front of the header
+const FIFO_OVERHEAD: usize = HEADER_LENGTH + 1;
+// CRC-32 after the payload, see `set_software_crc`
+const SOFTWARE_CRC_LENGTH: usize = 4;
+// Longest packet the AES engine handles, one more with an address byte
+const AES_MAX_LENGTH: usize = 64;
+// RegPayloadLength in variable length mode, the FIFO minus the length byte
+const VARIABLE_PAYLOAD_LENGTH: u8 = (RF69_FIFO_SIZE - 1) as u8;
+// Longest variable length packet the length byte describes, sent by refilling the FIFO
+const STREAMED_PAYLOAD_LENGTH: usize = u8::MAX as usize;
+
+const CONFIG_SNAPSHOT_MAGIC: u8 = 0x69;
+
+// Contiguous blocks of configuration registers captured by `save_config`
+const CONFIG_BLOCKS: [(Register, usize); 12] = [
+    (Register::DataModul, 8),
+    (Register::AfcCtrl, 5),
+    (Register::PaLevel, 3),
+    (Register::Lna, 6),
+    (Register::DioMapping1, 2),
+    (Register::RssiThresh, 20),
+    (Register::PacketConfig2, 1),
+    (Register::TestLna, 1),
+    (Register::TestPa1, 1),
+    (Register::TestPa2, 1),
+    (Register::TestDagc, 1),
+    (Register::TestAfc, 1),
+];
+
+/// Size of the buffer needed by `save_config`.
+pub const CONFIG_SNAPSHOT_SIZE: usize = {
+    // Magic byte and tx power
+    let mut size = 2;
+    let mut index = 0;
+    while index < CONFIG_BLOCKS.len() {
+        size += CONFIG_BLOCKS[index].1;
+        index += 1;
+    }
+    size
+};
+
+/// Number of configuration registers checked by `verify_configuration`.
+pub const CONFIG_REGISTER_COUNT: usize = CONFIG_SNAPSHOT_SIZE - 2;
+
+/// Offset of `register` in a configuration snapshot.
+fn snapshot_offset(register: Register) -> Option<usize> {
+    let mut offset = 2;
+    for (start, length) in CONFIG_BLOCKS {
+        let addr = register.addr();
+        if addr >= start.addr() && addr < start.addr() + length as u8 {
+            return Some(offset + (addr - start.addr()) as usize);
+        }
+        offset += length;
+    }
+    None
+}
+
+// Truncated MAC of a packet, see `Rfm69::set_authentication_key`
+fn authenticate(mac: &Cmac<Aes128>, header: &[u8], payload: &[u8]) -> [u8; MAC_LENGTH] {
+    let mut mac = mac.clone();
+    mac.update(header);
+    mac.update(payload);
+    let mut tag = [0u8; MAC_LENGTH];
+    tag.copy_from_slice(&mac.finalize().into_bytes()[..MAC_LENGTH]);
+    tag
+}
+
+/// A combination of settings the radio can't work with, see `Rfm69Config::validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum ConfigError {
+    /// The sync word is on but `sync_words` starts with 0x00.
+    #[cfg_attr(feature = "std", error("empty sync word"))]
+    EmptySyncWord,
+    /// Address filtering needs the sync word to find the address byte.
+    #[cfg_attr(feature = "std", error("address filtering without a sync word"))]
+    AddressFilteringWithoutSync,
+    /// A fixed length packet must hold the header.
+    #[cfg_attr(feature = "std", error("fixed payload length too short"))]
+    PayloadLengthTooShort,
+    /// A fixed length packet must fit in the FIFO, and with encryption in the 64
+    /// bytes the AES engine handles, 65 with address filtering.
+    #[cfg_attr(feature = "std", error("fixed payload length too long"))]
+    PayloadLengthTooLong,
+    #[cfg_attr(feature = "std", error("invalid modulation: {0}"))]
+    Modulation(ModulationError),
+}
+
+impl From<ConfigError> for Rfm69Error {
+    fn from(error: ConfigError) -> Self {
+        match error {
+            ConfigError::Modulation(error) => Rfm69Error::InvalidModulation(error),
+            _ => Rfm69Error::ConfigurationError,
+        }
+    }
+}
+
+/// Radio configuration applied by `init_with_config`, e.g. stored in flash.
+///
+/// The sync word is made of the leading non-zero bytes of `sync_words`, the
+/// SX1231 doesn't allow 0x00 sync bytes.
+///
+/// `init_with_config` checks the combination of settings with `validate`. Use
+/// `checked` for configurations defined as constants, e.g.
+/// `const CONFIG: Rfm69Config = Rfm69Config { .. }.checked()` starting from
+/// `Rfm69Config::DEFAULT`, impossible combinations are then compile errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rfm69Config {
     pub sync_configuration: SyncConfiguration,
     pub sync_words: [u8; 8],
     pub modem_config: ModemConfigChoice,
     pub preamble_length: u16,
-    pub frequency: u32,
-    pub tx_power: i8,
+    pub frequency: Frequency,
+    pub tx_power: TxPowerDbm,
     pub is_high_power: bool,
+    /// Custom bitrate, deviation and receiver bandwidth replacing those of
+    /// `modem_config`, see `Rfm69::set_fsk_modulation`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub modulation: Option<FskModulation>,
+    /// Fixed length packets, see `Rfm69::set_payload_length`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub payload_length: Option<u8>,
+    /// See `Rfm69::set_node_address`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub node_address: Option<u8>,
+    /// Encryption will be turned on with `Rfm69::set_encryption_key`, which keeps
+    /// the key out of the configuration. Checks `payload_length` against the
+    /// limit of the AES engine.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub encrypted: bool,
+}
+
+impl Default for Rfm69Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Rfm69Config {
+    /// The configuration `init` programs without a region: GFSK at 250 kbit/s on
+    /// 915 MHz, 13 dBm on an RFM69HW, a 4 byte preamble and the 0x2D 0xD4 sync word.
+    pub const DEFAULT: Self = Rfm69Config {
+        sync_configuration: SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
+        sync_words: [0x2D, 0xD4, 0, 0, 0, 0, 0, 0],
+        modem_config: ModemConfigChoice::GfskRb250Fd250,
+        preamble_length: 4,
+        frequency: Frequency::from_mhz(915),
+        tx_power: TxPowerDbm::new(13),
+        is_high_power: true,
+        modulation: None,
+        payload_length: None,
+        node_address: None,
+        encrypted: false,
+    };
+
+    /// GFSK at 2.4 kbit/s with a narrow receiver bandwidth and an 8 byte preamble,
+    /// for the best sensitivity. A packet takes about 100 times longer on air than
+    /// with the default.
+    pub const fn long_range() -> Self {
+        Rfm69Config {
+            modem_config: ModemConfigChoice::GfskRb2_4Fd4_8,
+            preamble_length: 8,
+            ..Self::DEFAULT
+        }
+    }
+
+    /// GFSK at 250 kbit/s with a 3 byte preamble, the shortest airtime per
+    /// packet, for nodes close to each other.
+    pub const fn high_throughput() -> Self {
+        Rfm69Config {
+            preamble_length: 3,
+            ..Self::DEFAULT
+        }
+    }
+
+    /// Number of leading non-zero bytes of `sync_words`.
+    pub const fn sync_length(&self) -> u8 {
+        let mut length = 0;
+        while length < self.sync_words.len() && self.sync_words[length] != 0 {
+            length += 1;
+        }
+        length as u8
+    }
+
+    /// Checks that the radio can work with this combination of settings. The
+    /// frequency and output power depend on the module and the region, they are
+    /// checked by `init_with_config`.
+    pub const fn validate(&self) -> Result<(), ConfigError> {
+        let sync_on = !matches!(self.sync_configuration, SyncConfiguration::SyncOff);
+        if sync_on && self.sync_length() == 0 {
+            return Err(ConfigError::EmptySyncWord);
+        }
+        if !sync_on && self.node_address.is_some() {
+            return Err(ConfigError::AddressFilteringWithoutSync);
+        }
+        if let Some(length) = self.payload_length {
+            let max_length = match self.encrypted {
+                true => AES_MAX_LENGTH + self.node_address.is_some() as usize,
+                false => RF69_FIFO_SIZE,
+            };
+            if (length as usize) < HEADER_LENGTH {
+                return Err(ConfigError::PayloadLengthTooShort);
+            }
+            if length as usize > max_length {
+                return Err(ConfigError::PayloadLengthTooLong);
+            }
+        }
+        if let Some(modulation) = &self.modulation {
+            if let Err(error) = modulation.validate() {
+                return Err(ConfigError::Modulation(error));
+            }
+        }
+        Ok(())
+    }
+
+    /// `self` if `validate` passes. Panics otherwise, which is a compile error in
+    /// a constant.
+    pub const fn checked(self) -> Self {
+        match self.validate() {
+            Ok(()) => self,
+            Err(ConfigError::EmptySyncWord) => panic!("empty sync word"),
+            Err(ConfigError::AddressFilteringWithoutSync) => {
+                panic!("address filtering without a sync word")
+            }
+            Err(ConfigError::PayloadLengthTooShort) => panic!("fixed payload length too short"),
+            Err(ConfigError::PayloadLengthTooLong) => panic!("fixed payload length too long"),
+            Err(ConfigError::Modulation(_)) => panic!("invalid modulation"),
+        }
+    }
 }
 
 impl<SPI, RESET, INTR, D> Rfm69<SPI, RESET, INTR, D>
@@ -64,6 +592,9 @@ where
             .set_low()
             .map_err(|_| Rfm69Error::ResetError)?;
         self.delay.delay_ms(5).await;
+        self.shadow.invalidate();
+        self.op_mode = RESET_OP_MODE;
+        self.encrypted = false;
         Ok(())
     }
 
@@ -74,53 +605,575 @@ where
             intr_pin,
             delay,
             tx_power: 13,
-            is_high_power: true,
+            variant: Rfm69Variant::Rfm69Hw,
+            chip_version: None,
             current_mode: Rfm69Mode::Standby,
+            frequency: Frequency::from_mhz(915),
+            modem_config: ModemConfigChoice::GfskRb250Fd250,
+            modulation: None,
+            shaping: None,
+            packet_config: None,
+            preamble_length: 4,
+            sync_configuration: SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
+            sync_words: [0x2D, 0xD4, 0, 0, 0, 0, 0, 0],
+            sync_length: 2,
+            duty_cycle: None,
+            region: None,
+            node_address: None,
+            broadcast_address: BROADCAST_ADDRESS,
+            sequence: 0,
+            fifo_threshold: RF69_FIFO_THRESHOLD as u8,
+            tx_start_condition: TxStartCondition::FifoNotEmpty,
+            temperature_compensation: None,
+            frequency_trim_ppm: 0.0,
+            link_stats: None,
+            latched_rssi: None,
+            packet_rssi: None,
+            software_crc: false,
+            encrypted: false,
+            key_lost: false,
+            packet_config2: RESET_PACKET_CONFIG2,
+            authentication: None,
+            payload_length: None,
+            packet_filter: None,
+            sensitivity_boost: false,
+            front_end_control: None,
+            watchdog: None,
+            poll_intervals: PollIntervals::default(),
+            idle_policy: None,
+            rx_idle_us: 0,
+            idled: false,
+            stats: Stats::default(),
+            interrupt_state: InterruptState::Idle,
+            event: None,
+            shadow: RegisterShadow::new(),
+            op_mode: RESET_OP_MODE,
+        }
+    }
+
+    /// Returns the SPI device, the reset and DIO0 pins and the delay. The radio is
+    /// left in its current mode, `set_mode(Rfm69Mode::Sleep)` first to shut it down.
+    pub fn release(self) -> (SPI, RESET, INTR, D) {
+        (self.spi, self.reset_pin, self.intr_pin, self.delay)
+    }
+
+    /// Selects the power amplifier path of the module, defaults to `Rfm69Variant::Rfm69Hw`.
+    /// The new path is programmed by `init()` or the next `set_tx_power`.
+    pub fn set_variant(&mut self, variant: Rfm69Variant) {
+        self.variant = variant;
+    }
+
+    pub fn variant(&self) -> Rfm69Variant {
+        self.variant
+    }
+
+    /// Silicon revision read by `init()`.
+    pub fn chip_version(&self) -> Option<ChipVersion> {
+        self.chip_version
+    }
+
+    // The RFM69 module should return 0x24, raw SX1231 designs an older revision
+    async fn read_chip_version(&mut self) -> Result<ChipVersion, Rfm69Error> {
+        let mut version = 0;
+        for attempt in 0..VERSION_READ_ATTEMPTS {
+            if attempt > 0 {
+                self.delay.delay_ms(VERSION_RETRY_DELAY_MS).await;
+            }
+            version = self.read_register(Register::Version)?;
+            debug!("RFM69 version: {:?}", version);
+            if let Some(chip_version) = ChipVersion::from_register(version) {
+                return Ok(chip_version);
+            }
+        }
+        Err(Rfm69Error::ModuleNotFound { got: version })
+    }
+
+    /// Output power range of the module variant on this chip, in dBm.
+    pub fn tx_power_range(&self) -> RangeInclusive<i8> {
+        let range = self.variant.tx_power_range();
+        match self.high_power_boost() {
+            true => range,
+            false => *range.start()..=(*range.end()).min(17),
+        }
+    }
+
+    // Assumed available until the version has been read
+    fn high_power_boost(&self) -> bool {
+        self.chip_version
+            .is_none_or(|version| version.has_high_power_boost())
+    }
+
+    /// Selects the regulatory region used to validate `set_frequency` and `set_tx_power`.
+    ///
+    /// When set before `init()`, the region's default modem configuration and
+    /// frequency are programmed instead of the built-in defaults.
+    pub fn set_region(&mut self, region: Option<Region>) {
+        self.region = region;
+    }
+
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    /// Sets the address of this node, used as the source of `send_to` and to
+    /// filter received packets in hardware. Packets sent to the broadcast address
+    /// are still received. `None` disables address filtering.
+    pub fn set_node_address(&mut self, address: Option<u8>) -> Result<(), Rfm69Error> {
+        if address.is_some() && self.sync_configuration == SyncConfiguration::SyncOff {
+            return Err(ConfigError::AddressFilteringWithoutSync.into());
+        }
+        if let (None, Some(length)) = (address, self.payload_length) {
+            let aes_on = self.aes_on()?;
+            if !self.fixed_length_valid(length, aes_on, false) {
+                return Err(Rfm69Error::ConfigurationError);
+            }
+        }
+        if let Some(address) = address {
+            self.write_many(Register::NodeAddrs, &[address, self.broadcast_address])?;
+        }
+        self.node_address = address;
+        self.write_register(Register::PacketConfig1, self.packet_config1())
+    }
+
+    pub fn node_address(&self) -> Option<u8> {
+        self.node_address
+    }
+
+    /// Sets the address `broadcast()` sends to and that every node receives,
+    /// `BROADCAST_ADDRESS` by default.
+    pub fn set_broadcast_address(&mut self, address: u8) -> Result<(), Rfm69Error> {
+        self.write_register(Register::BroadcastAddrs, address)?;
+        self.broadcast_address = address;
+        Ok(())
+    }
+
+    pub fn broadcast_address(&self) -> u8 {
+        self.broadcast_address
+    }
+
+    // PacketConfig1 of the modem configuration or profile, with address filtering
+    // when a node address is set and fixed length packets after `set_payload_length`
+    fn packet_config1(&self) -> u8 {
+        let value = self.modem_config.values()[7];
+        let Some(packet_config) = self.packet_config.or(PacketConfig1::from_bits(value)) else {
+            return value;
+        };
+
+        let address_filtering = match self.node_address {
+            Some(_) => AddressFiltering::NodeOrBroadcast,
+            None => AddressFiltering::None,
+        };
+        PacketConfig1 {
+            variable_length: self.payload_length.is_none(),
+            address_filtering,
+            ..packet_config
+        }
+        .to_bits()
+    }
+
+    /// Switches to fixed length packets of `length` bytes, header and software CRC
+    /// included, to talk to peers that send fixed frames. `None` goes back to
+    /// variable length packets. Shorter payloads are padded with zeros, which
+    /// `receive` returns as part of the payload.
+    ///
+    /// A packet has to fit in the FIFO, and with encryption on in the 64 bytes
+    /// the AES engine handles, 65 with address filtering. Longer lengths are a
+    /// `Rfm69Error::ConfigurationError`.
+    pub fn set_payload_length(&mut self, length: Option<u8>) -> Result<(), Rfm69Error> {
+        if let Some(length) = length {
+            let aes_on = self.aes_on()?;
+            if !self.fixed_length_valid(length, aes_on, self.node_address.is_some()) {
+                return Err(Rfm69Error::ConfigurationError);
+            }
         }
+
+        // In variable length mode RegPayloadLength is the longest packet accepted
+        let payload_length = length.unwrap_or(VARIABLE_PAYLOAD_LENGTH);
+        self.write_register(Register::PayloadLength, payload_length)?;
+        self.payload_length = length;
+        self.write_register(Register::PacketConfig1, self.packet_config1())
+    }
+
+    pub fn payload_length(&self) -> Option<u8> {
+        self.payload_length
+    }
+
+    fn fixed_length_valid(&self, length: u8, aes_on: bool, address_filtering: bool) -> bool {
+        let max_length = match aes_on {
+            true => AES_MAX_LENGTH + address_filtering as usize,
+            false => RF69_FIFO_SIZE,
+        };
+        (HEADER_LENGTH + self.trailer_length()..=max_length).contains(&(length as usize))
+    }
+
+    /// Enables airtime accounting for the EU 868 MHz sub-bands, `send()` then
+    /// returns `DutyCycleExceeded` (or waits) once the hourly budget is used up.
+    pub fn set_duty_cycle_limiter(&mut self, limiter: Option<DutyCycleLimiter>) {
+        self.duty_cycle = limiter;
+    }
+
+    pub fn duty_cycle_limiter(&mut self) -> Option<&mut DutyCycleLimiter> {
+        self.duty_cycle.as_mut()
     }
 
     pub async fn init(&mut self) -> Result<(), Rfm69Error> {
+        let (modem_config, tx_power, frequency) = match self.region {
+            Some(region) => {
+                let profile = region.profile();
+                (
+                    profile.modem_config,
+                    TxPowerDbm::new(profile.max_eirp_dbm.min(13)),
+                    Frequency::from_mhz(profile.default_frequency_mhz),
+                )
+            }
+            None => {
+                let config = Rfm69Config::default();
+                (config.modem_config, config.tx_power, config.frequency)
+            }
+        };
+
+        // Addresses and packet lengths set before `init` are kept
+        self.init_with_config(Rfm69Config {
+            modem_config,
+            frequency,
+            tx_power,
+            is_high_power: self.variant == Rfm69Variant::Rfm69Hw,
+            payload_length: self.payload_length,
+            node_address: self.node_address,
+            ..Rfm69Config::DEFAULT
+        })
+        .await
+    }
+
+    /// Like `init`, programming `config` instead of the defaults. `is_high_power`
+    /// selects the RFM69HW variant.
+    pub async fn init_with_config(&mut self, config: Rfm69Config) -> Result<(), Rfm69Error> {
+        config.validate()?;
+        self.variant = match config.is_high_power {
+            true => Rfm69Variant::Rfm69Hw,
+            false => Rfm69Variant::Rfm69W,
+        };
+        self.check_frequency(config.frequency)?;
+        self.check_tx_power(config.tx_power.dbm())?;
+
         self.delay.delay_ms(10).await;
         self.reset().await?;
+        self.packet_config2 = RESET_PACKET_CONFIG2;
+        self.key_lost = false;
 
-        let version = self.read_register(Register::Version)?;
+        self.chip_version = Some(self.read_chip_version().await?);
+
+        // self.spi.write_many(Register::OpMode, &[0x04]);
+
+        self.sync_configuration = config.sync_configuration;
+        self.sync_words = config.sync_words;
+        self.sync_length = config.sync_length().max(1);
+        self.modem_config = config.modem_config;
+        self.modulation = config.modulation;
+        self.shaping = None;
+        self.packet_config = None;
+        self.payload_length = config.payload_length;
+        self.node_address = config.node_address;
+        self.preamble_length = config.preamble_length;
+        self.tx_power = config.tx_power.dbm();
+        self.frequency = config.frequency;
+
+        self.write_configuration()?;
+
+        self.set_mode(Rfm69Mode::Standby).await?;
+
+        Ok(())
+    }
+
+    /// Programs the configuration held by the driver into the radio, using the
+    /// address auto-increment to write contiguous registers in a single transaction.
+    fn write_configuration(&mut self) -> Result<(), Rfm69Error> {
+        let modem = self.modem_values();
 
-        debug!("RFM69 version: {:?}", version);
+        // DataModul, BitrateMsb/Lsb, FdevMsb/Lsb, then FrfMsb/Mid/Lsb
+        let mut buffer = [0u8; 8];
+        buffer[0..5].copy_from_slice(&modem[0..5]);
+        buffer[5..8].copy_from_slice(&self.frf(self.frequency));
+        self.write_many(Register::DataModul, &buffer)?;
 
-        // the RFM69 module should return 0x24
-        if version != 0x24 {
-            return Err(Rfm69Error::SpiReadError);
+        if self.pa_boost() {
+            self.write_register(Register::Ocp, RF_OCP_OFF)?;
         }
+        self.write_typed(self.pa_level(self.tx_power))?;
+
+        // Lna, RxBw and AfcBw
+        let lna = Lna {
+            zin_200_ohm: true,
+            current_gain: 0b001,
+            gain_select: 0,
+        };
+        self.write_many(Register::Lna, &[lna.to_bits(), modem[5], modem[6]])?;
+
+        // PreambleMsb/Lsb, SyncConfig, SyncValue1 to SyncValue8, then PacketConfig1
+        let mut buffer = [0u8; 13];
+        buffer[0..2].copy_from_slice(&self.preamble_length.to_be_bytes());
+        buffer[2] = self.sync_configuration.value(self.sync_length);
+        buffer[3..11].copy_from_slice(&self.sync_words);
+        buffer[11] = self.packet_config1();
+        buffer[12] = self.payload_length.unwrap_or(VARIABLE_PAYLOAD_LENGTH);
+        self.write_many(Register::PreambleMsb, &buffer)?;
 
-        // self.spi.write_many(Register::OpMode, &[0x04]);
+        if let Some(address) = self.node_address {
+            self.write_many(Register::NodeAddrs, &[address, self.broadcast_address])?;
+        }
+
+        self.set_fifo_threshold(RF69_FIFO_THRESHOLD as u8, TxStartCondition::FifoNotEmpty)?;
+
+        // If high power boost set previously, disable it
+        self.write_register(Register::TestPa1, RF_TESTPA1_NORMAL)?;
+        self.write_register(Register::TestPa2, RF_TESTPA2_NORMAL)?;
 
-        self.set_default_fifo_threshold()?;
         self.set_dagc(ContinuousDagc::ImprovedLowBeta1)?;
 
-        self.write_register(Register::Lna, 0x88)?;
-        let sync_word = [0x2D, 0xD4];
-        self.set_sync_words(
-            SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
-            &sync_word,
-        )?;
+        Ok(())
+    }
 
-        // If high power boost set previously, disable it
-        self.write_register(Register::TestPa1, 0x55)?;
-        self.write_register(Register::TestPa2, 0x70)?;
+    // A snapshot must not turn on the high power settings in Rx, or with the
+    // overcurrent protection on
+    fn check_pa_boost_snapshot(&self, snapshot: &[u8]) -> Result<(), Rfm69Error> {
+        let value = |register: Register| snapshot[snapshot_offset(register).unwrap()];
+        let error = |error| Err(Rfm69Error::PaBoost(error));
+
+        if value(Register::TestPa1) == RF_TESTPA1_BOOST
+            || value(Register::TestPa2) == RF_TESTPA2_BOOST
+        {
+            return error(PaBoostError::BoostOutsideTx);
+        }
+        if self.pa_boost_at(snapshot[1] as i8) {
+            if self.duty_cycle.is_none() {
+                return error(PaBoostError::NoDutyCycleLimiter);
+            }
+            // OcpOn
+            if value(Register::Ocp) & 0x10 != 0 {
+                return error(PaBoostError::OcpEnabled);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the main configuration registers against the configuration held by the driver.
+    fn configuration_matches(&mut self) -> Result<bool, Rfm69Error> {
+        let modem = self.modem_values();
+
+        // DataModul to FrfLsb
+        let mut registers = [0u8; 8];
+        self.read_many(Register::DataModul, &mut registers)?;
+        if registers[0..5] != modem[0..5] || registers[5..8] != self.frf(self.frequency) {
+            return Ok(false);
+        }
+
+        // PreambleMsb, PreambleLsb, SyncConfig and the sync words
+        let sync_length = self.sync_length as usize;
+        let mut registers = [0u8; 11];
+        self.read_many(Register::PreambleMsb, &mut registers[..3 + sync_length])?;
+        if registers[0..2] != self.preamble_length.to_be_bytes()
+            || registers[2] != self.sync_configuration.value(self.sync_length)
+            || registers[3..3 + sync_length] != self.sync_words[..sync_length]
+        {
+            return Ok(false);
+        }
+
+        Ok(
+            self.read_register(Register::PacketConfig1)? == self.packet_config1()
+                && self.read_register(Register::PaLevel)? == self.pa_level(self.tx_power).to_bits()
+                && self.read_typed::<PacketConfig2>()? == self.packet_config2,
+        )
+    }
+
+    // Programs the configuration again after the radio lost it, with the values
+    // of `PRESERVED_REGISTERS` taken from the shadow beforehand. The AES key isn't
+    // restored, the driver keeps no copy of it: with `encrypted` set, the key is
+    // lost and the radio is kept off the air until it is set again.
+    fn restore_configuration(
+        &mut self,
+        preserved: [Option<u8>; PRESERVED_REGISTERS.len()],
+        encrypted: bool,
+    ) -> Result<(), Rfm69Error> {
+        self.write_configuration()?;
+        for (register, value) in PRESERVED_REGISTERS.into_iter().zip(preserved) {
+            if let Some(value) = value {
+                self.write_register(register, value)?;
+            }
+        }
+
+        self.packet_config2.aes_on = false;
+        self.write_typed(self.packet_config2)?;
+        self.encrypted = false;
+        if encrypted {
+            self.key_lost = true;
+            return Err(Rfm69Error::EncryptionKeyLost);
+        }
+        Ok(())
+    }
+
+    /// Reads back the configuration registers and compares them to the values the
+    /// driver wrote, to detect SPI wiring problems or registers corrupted by a
+    /// brown-out. Registers not written or read since the register shadow was last
+    /// invalidated are not checked.
+    ///
+    /// The readback doesn't update the register shadow, which keeps the intended values.
+    pub fn verify_configuration(
+        &mut self,
+    ) -> Result<heapless::Vec<RegisterMismatch, CONFIG_REGISTER_COUNT>, Rfm69Error> {
+        let mut mismatches = heapless::Vec::new();
+
+        for (register, length) in CONFIG_BLOCKS {
+            let mut buffer = [0u8; CONFIG_REGISTER_COUNT];
+            let block = &mut buffer[..length];
+            self.spi
+                .read_many(register, block)
+                .map_err(|_| Rfm69Error::SpiReadError)?;
+
+            for (offset, &actual) in block.iter().enumerate() {
+                let addr = register.addr() + offset as u8;
+                if let Some(expected) = self.shadow.get_addr(addr) {
+                    if expected != actual {
+                        // Can't overflow, there is one entry per register
+                        let _ = mismatches.push(RegisterMismatch {
+                            register: addr,
+                            expected,
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Supervises the radio: a mode change without ModeReady, PayloadReady stuck
+    /// after reading a packet, or too many timeouts reported by `report_timeout`
+    /// trigger a `recover`. A stuck mode change fails with `Rfm69Error::RadioStuck`.
+    pub fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.watchdog = watchdog;
+    }
+
+    pub fn watchdog(&self) -> Option<&Watchdog> {
+        self.watchdog.as_ref()
+    }
+
+    /// Sets how often the blocking paths, `set_mode`, `send` and
+    /// `wait_for_message`, check the radio. Shorter intervals return sooner at
+    /// the cost of more SPI traffic.
+    pub fn set_poll_intervals(&mut self, poll_intervals: PollIntervals) {
+        self.poll_intervals = poll_intervals;
+    }
+
+    pub fn poll_intervals(&self) -> PollIntervals {
+        self.poll_intervals
+    }
+
+    /// Lets `wait_for_message` and `wait_for_message_timeout` put the radio in
+    /// Standby or Sleep once it spent `timeout_ms` in Rx without a packet, they
+    /// then return without one. The next call goes back to Rx. `None` keeps the
+    /// radio in Rx, other modes are a `Rfm69Error::ConfigurationError`.
+    pub fn set_idle_policy(&mut self, policy: Option<IdlePolicy>) -> Result<(), Rfm69Error> {
+        if let Some(policy) = policy {
+            if !matches!(policy.mode, Rfm69Mode::Standby | Rfm69Mode::Sleep) {
+                return Err(Rfm69Error::ConfigurationError);
+            }
+        }
+        self.idle_policy = policy;
+        Ok(())
+    }
 
-        self.set_modem_config(ModemConfigChoice::GfskRb250Fd250)?;
+    pub fn idle_policy(&self) -> Option<IdlePolicy> {
+        self.idle_policy
+    }
 
-        self.set_preamble_length(4)?;
+    /// Counts a timeout waiting on the radio, e.g. for an acknowledgement. The
+    /// watchdog recovers the radio after too many in a row.
+    pub async fn report_timeout(&mut self) -> Result<(), Rfm69Error> {
+        self.stats.record_timeout();
+        if self
+            .watchdog
+            .as_mut()
+            .is_some_and(|watchdog| watchdog.record_timeout())
+        {
+            self.recover(StuckCondition::Timeouts).await?;
+        }
+        Ok(())
+    }
 
-        self.set_tx_power(13)?;
+    /// Resets the radio and programs the configuration held by the driver again,
+    /// leaving it in Standby. Reports `condition` to the watchdog's recovery hook.
+    /// The reset clears the AES key, with encryption on this is
+    /// `Rfm69Error::EncryptionKeyLost`.
+    pub async fn recover(&mut self, condition: StuckCondition) -> Result<(), Rfm69Error> {
+        info!("RFM69 stuck ({:?}), resetting", condition);
+        // Taken before the reset clears the shadow and turns encryption off
+        let preserved = PRESERVED_REGISTERS.map(|register| self.shadow.get(register));
+        let encrypted = self.encrypted;
+        self.reset().await?;
+        // The radio comes out of reset in Standby
+        self.current_mode = Rfm69Mode::Standby;
+        self.latched_rssi = None;
+        let restored = self.restore_configuration(preserved, encrypted);
+
+        if let Some(on_recovery) = self
+            .watchdog
+            .as_mut()
+            .and_then(|watchdog| watchdog.record_recovery())
+        {
+            on_recovery(condition);
+        }
+        restored
+    }
 
-        self.set_frequency(915)?;
+    /// Puts the radio in Sleep mode. The RFM69 keeps its configuration while
+    /// sleeping, `wake()` checks it and restores it if needed.
+    pub async fn sleep(&mut self) -> Result<(), Rfm69Error> {
+        self.set_mode(Rfm69Mode::Sleep).await
+    }
 
+    /// Returns the radio to Standby mode, reprogramming the configuration if the
+    /// registers no longer match it (e.g. after a brown-out while sleeping).
+    ///
+    /// There is no reset and no `init()`, a radio that kept its configuration is
+    /// ready to send after a handful of register reads, well under a millisecond
+    /// instead of the ~20 ms of a full initialization. A module that doesn't answer
+    /// on the bus any more is `Rfm69Error::ModuleNotFound`. A radio that lost its
+    /// configuration with encryption on also lost the AES key, which is
+    /// `Rfm69Error::EncryptionKeyLost`.
+    pub async fn wake(&mut self) -> Result<(), Rfm69Error> {
+        // Checked first, a missing module would never report ModeReady
+        let version = self.read_register(Register::Version)?;
+        if ChipVersion::from_register(version).is_none() {
+            return Err(Rfm69Error::ModuleNotFound { got: version });
+        }
         self.set_mode(Rfm69Mode::Standby).await?;
 
+        if !self.configuration_matches()? {
+            info!("RFM69 configuration lost, restoring");
+            let preserved = PRESERVED_REGISTERS.map(|register| self.shadow.get(register));
+            self.shadow.invalidate();
+            self.restore_configuration(preserved, self.encrypted)?;
+        }
+
         Ok(())
     }
 
+    /// Puts the radio in its lowest power state, 0.1 uA typical in Sleep according
+    /// to the datasheet. On top of `sleep()` it leaves Listen mode, in case
+    /// `recv_low_power` was cancelled, and turns off ClkOut, which outputs FXOSC/32
+    /// after reset. The sequencer stays on, in Sleep it already keeps the crystal
+    /// oscillator and every other block off. `wake()` returns to Standby.
+    pub async fn power_down(&mut self) -> Result<(), Rfm69Error> {
+        self.modify(|dio_mapping2: &mut DioMapping2| {
+            dio_mapping2.clk_out = DioMapping2::CLK_OUT_OFF
+        })?;
+        if self.op_mode.listen_on {
+            self.exit_listen().await?;
+        }
+        self.set_mode(Rfm69Mode::Sleep).await
+    }
+
     pub fn read_all_registers(&mut self) -> Result<[(u8, u8); 84], Rfm69Error> {
         let mut registers = [0u8; 79];
         self.read_many(Register::OpMode, &mut registers)?;
@@ -155,63 +1208,499 @@ where
         Ok(mapped)
     }
 
-    pub fn read_revision(&mut self) -> Result<u8, Rfm69Error> {
-        self.read_register(Register::Version)
+    /// Reads all registers and decodes them into named fields, for diagnostics.
+    pub fn dump_registers(&mut self) -> Result<RegisterDump, Rfm69Error> {
+        let mut registers = [(0u8, 0u8); 85];
+        registers[..84].copy_from_slice(&self.read_all_registers()?);
+        registers[84] = (Register::Version.addr(), self.read_revision()?);
+        Ok(RegisterDump::from_registers(&registers))
     }
 
-    pub async fn read_temperature(&mut self) -> Result<f32, Rfm69Error> {
-        self.write_register(Register::Temp1, 0x08)?;
-        while self.read_register(Register::Temp1)? & 0x04 != 0x00 {
-            self.delay.delay_ms(10).await;
+    /// Captures the register configuration into `buffer`, which must hold at least
+    /// `CONFIG_SNAPSHOT_SIZE` bytes. Returns the number of bytes written.
+    ///
+    /// The AES key is write only and is not part of the snapshot.
+    pub fn save_config(&mut self, buffer: &mut [u8]) -> Result<usize, Rfm69Error> {
+        if buffer.len() < CONFIG_SNAPSHOT_SIZE {
+            return Err(Rfm69Error::BufferTooSmall);
         }
 
-        let temp = self.read_register(Register::Temp2)?;
-        Ok((166 as f32) - temp as f32)
-    }
+        buffer[0] = CONFIG_SNAPSHOT_MAGIC;
+        buffer[1] = self.tx_power as u8;
 
-    fn set_default_fifo_threshold(&mut self) -> Result<(), Rfm69Error> {
-        self.write_register(Register::FifoThresh, 0x8F)?;
-        Ok(())
-    }
+        let mut offset = 2;
+        for (register, length) in CONFIG_BLOCKS {
+            self.read_many(register, &mut buffer[offset..offset + length])?;
+            offset += length;
+        }
 
-    fn set_dagc(&mut self, value: ContinuousDagc) -> Result<(), Rfm69Error> {
-        self.write_register(Register::TestDagc, value as u8)?;
-        Ok(())
+        // RestartRx is a command, not configuration
+        if let Some(index) = snapshot_offset(Register::PacketConfig2) {
+            let packet_config = PacketConfig2 {
+                restart_rx: false,
+                ..PacketConfig2::from_bits(buffer[index])
+            };
+            buffer[index] = packet_config.to_bits();
+        }
+
+        Ok(offset)
     }
 
-    fn set_sync_words(
-        &mut self,
-        config: SyncConfiguration,
-        sync_words: &[u8],
-    ) -> Result<(), Rfm69Error> {
-        if sync_words.len() > 8 || sync_words.len() == 0 {
+    /// Writes a configuration captured by `save_config` back to the radio, e.g. after a
+    /// power cycle, and updates the driver's view of the configuration to match.
+    pub fn restore_config(&mut self, snapshot: &[u8]) -> Result<(), Rfm69Error> {
+        if snapshot.len() < CONFIG_SNAPSHOT_SIZE || snapshot[0] != CONFIG_SNAPSHOT_MAGIC {
             return Err(Rfm69Error::ConfigurationError);
         }
+        self.check_pa_boost_snapshot(snapshot)?;
 
-        let mut buffer = [0u8; 9]; // 1 byte for config + up to 8 bytes for sync words
+        let mut offset = 2;
+        for (register, length) in CONFIG_BLOCKS {
+            self.write_many(register, &snapshot[offset..offset + length])?;
+            offset += length;
+        }
 
-        // Add the config value to the first position
-        // We need to know how many sync words we have to set the correct config value
-        buffer[0] = config.value(sync_words.len() as u8);
-        // Add the sync words to the buffer
-        buffer[1..1 + sync_words.len()].copy_from_slice(sync_words);
-        // Write the config value first, then the sync words.
-        self.write_many(Register::SyncConfig, &buffer)?;
+        let value = |register: Register| snapshot[snapshot_offset(register).unwrap()];
+
+        self.tx_power = snapshot[1] as i8;
+        self.packet_config2 = PacketConfig2 {
+            restart_rx: false,
+            ..PacketConfig2::from_bits(value(Register::PacketConfig2))
+        };
+        self.encrypted = self.packet_config2.aes_on;
+
+        // FRF includes the correction of `trim_frequency`, which is taken out again
+        let frf = u32::from_be_bytes([
+            0,
+            value(Register::FrfMsb),
+            value(Register::FrfMid),
+            value(Register::FrfLsb),
+        ]);
+        let frf = (frf as f32 / (1.0 + self.frequency_trim_ppm / 1_000_000.0) + 0.5) as u32;
+        let [_, msb, mid, lsb] = frf.to_be_bytes();
+        self.frequency = Frequency::from_hz(frequency_hz(msb, mid, lsb));
+
+        let mut modem = [0u8; 8];
+        modem[0..5].copy_from_slice(&snapshot[2..7]);
+        modem[5] = value(Register::RxBw);
+        modem[6] = value(Register::AfcBw);
+        modem[7] = value(Register::PacketConfig1);
+        self.node_address = None;
+        self.payload_length = None;
+        if let Some(packet_config) = PacketConfig1::from_bits(modem[7]) {
+            if packet_config.address_filtering != AddressFiltering::None {
+                self.node_address = Some(value(Register::NodeAddrs));
+                self.broadcast_address = value(Register::BroadcastAddrs);
+            }
+            if !packet_config.variable_length {
+                self.payload_length = Some(value(Register::PayloadLength));
+            }
+            // The presets don't filter addresses
+            let packet_config = PacketConfig1 {
+                address_filtering: AddressFiltering::None,
+                ..packet_config
+            };
+            modem[7] = packet_config.to_bits();
+        }
+        if let Some(modem_config) = ModemConfigChoice::from_values(&modem) {
+            self.modem_config = modem_config;
+            self.modulation = None;
+            self.shaping = None;
+            self.packet_config = None;
+        }
+
+        self.preamble_length =
+            u16::from_be_bytes([value(Register::PreambleMsb), value(Register::PreambleLsb)]);
+
+        let (sync_configuration, sync_length) =
+            SyncConfiguration::from_value(value(Register::SyncConfig));
+        let sync_start = snapshot_offset(Register::SyncValue1).unwrap();
+        self.sync_configuration = sync_configuration;
+        self.sync_length = sync_length;
+        self.sync_words
+            .copy_from_slice(&snapshot[sync_start..sync_start + 8]);
 
         Ok(())
     }
 
-    fn set_modem_config(&mut self, config: ModemConfigChoice) -> Result<(), Rfm69Error> {
-        let values = config.values();
-
+    /// Sets the crystal temperature coefficient used by `trim_frequency`. `None`
+    /// removes the current correction at the next frequency change.
+    pub fn set_temperature_compensation(&mut self, compensation: Option<TemperatureCompensation>) {
+        self.temperature_compensation = compensation;
+        if compensation.is_none() {
+            self.frequency_trim_ppm = 0.0;
+        }
+    }
+
+    /// Measures the temperature and corrects the carrier frequency for the crystal
+    /// drift, keeping narrowband links on frequency without AFC. Call it
+    /// periodically, e.g. every few minutes. Returns the applied correction in ppm.
+    ///
+    /// FRF is only rewritten when the correction moves it by at least one step.
+    pub async fn trim_frequency(&mut self) -> Result<f32, Rfm69Error> {
+        let Some(compensation) = self.temperature_compensation else {
+            return Err(Rfm69Error::ConfigurationError);
+        };
+
+        let celsius = self.read_temperature().await?;
+        let previous = self.frf(self.frequency);
+        self.frequency_trim_ppm = compensation.correction_ppm(celsius);
+        let frf = self.frf(self.frequency);
+        if frf != previous {
+            self.write_many(Register::FrfMsb, &frf)?;
+        }
+
+        Ok(self.frequency_trim_ppm)
+    }
+
+    pub fn read_revision(&mut self) -> Result<u8, Rfm69Error> {
+        self.read_register(Register::Version)
+    }
+
+    /// Measures the temperature, switching to Standby for the measurement if
+    /// needed and back to the current mode afterwards.
+    pub async fn read_temperature(&mut self) -> Result<f32, Rfm69Error> {
+        // The sensor only works in Standby and Fs
+        let mode = self.current_mode;
+        if !matches!(mode, Rfm69Mode::Standby | Rfm69Mode::Fs) {
+            self.set_mode(Rfm69Mode::Standby).await?;
+        }
+        let temperature = self.measure_temperature().await;
+        self.set_mode(mode).await?;
+        temperature
+    }
+
+    /// Samples the temperature every `interval_ms`, see `TemperatureMonitor::next`.
+    pub fn monitor_temperature(
+        &mut self,
+        interval_ms: u32,
+        threshold_celsius: f32,
+    ) -> TemperatureMonitor<'_, SPI, RESET, INTR, D> {
+        TemperatureMonitor::new(self, interval_ms, threshold_celsius)
+    }
+
+    async fn measure_temperature(&mut self) -> Result<f32, Rfm69Error> {
+        self.write_register(Register::Temp1, RF_TEMP1_MEAS_START)?;
+        while self.read_register(Register::Temp1)? & RF_TEMP1_MEAS_RUNNING != 0x00 {
+            self.delay.delay_ms(10).await;
+        }
+
+        let temp = self.read_register(Register::Temp2)?;
+        Ok(166_f32 - temp as f32)
+    }
+
+    /// Checks the radio is healthy: the version register, the RC oscillator
+    /// calibration, the temperature sensor, an RSSI measurement in Rx and a FIFO
+    /// write and readback. Failed checks are recorded in the report, only SPI
+    /// errors are returned. Goes back to the current mode afterwards, with the
+    /// FIFO empty.
+    pub async fn self_test(&mut self) -> Result<SelfTestReport, Rfm69Error> {
+        let mode = self.current_mode;
+        self.set_mode(Rfm69Mode::Standby).await?;
+
+        let version = self.read_revision()?;
+
+        // The calibration and the temperature sensor only work in Standby
+        self.write_register(Register::Osc1, RF_OSC1_RCCAL_START)?;
+        let rc_calibrated = self
+            .wait_for_flag(Register::Osc1, RF_OSC1_RCCAL_DONE)
+            .await?;
+        let temperature_celsius = self.measure_temperature().await?;
+
+        self.set_mode(Rfm69Mode::Rx).await?;
+        self.write_register(Register::RssiConfig, RF_RSSI_START)?;
+        let rssi_done = self
+            .wait_for_flag(Register::RssiConfig, RF_RSSI_DONE)
+            .await?;
+        let rssi_dbm = if rssi_done {
+            Some(-(self.rssi()? as i16))
+        } else {
+            None
+        };
+        self.set_mode(Rfm69Mode::Standby).await?;
+
+        // Drop anything received while in Rx
+        self.flush_fifo()?;
+        self.write_many(Register::Fifo, &SELF_TEST_PATTERN)?;
+        let mut readback = [0u8; SELF_TEST_PATTERN.len()];
+        self.read_many(Register::Fifo, &mut readback)?;
+        self.flush_fifo()?;
+
+        self.set_mode(mode).await?;
+
+        Ok(SelfTestReport {
+            version,
+            chip_version: ChipVersion::from_register(version),
+            rc_calibrated,
+            temperature_celsius,
+            rssi_dbm,
+            fifo_ok: readback == SELF_TEST_PATTERN,
+        })
+    }
+
+    // Polls `register` until `flag` is set, giving up after 10 ms
+    async fn wait_for_flag(&mut self, register: Register, flag: u8) -> Result<bool, Rfm69Error> {
+        for _ in 0..10 {
+            if self.read_register(register)? & flag != 0 {
+                return Ok(true);
+            }
+            self.delay.delay_ms(1).await;
+        }
+        Ok(false)
+    }
+
+    /// Sets the FIFO level signalled by FifoLevel, used to refill the FIFO while
+    /// streaming, and when the transmitter starts. `level` must be below the FIFO size.
+    pub fn set_fifo_threshold(
+        &mut self,
+        level: u8,
+        tx_start_condition: TxStartCondition,
+    ) -> Result<(), Rfm69Error> {
+        if level as usize >= RF69_FIFO_SIZE {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+
+        let fifo_thresh = FifoThresh {
+            tx_start_fifo_not_empty: tx_start_condition == TxStartCondition::FifoNotEmpty,
+            fifo_threshold: level,
+        };
+        self.write_typed(fifo_thresh)?;
+        self.fifo_threshold = level;
+        self.tx_start_condition = tx_start_condition;
+        Ok(())
+    }
+
+    fn set_dagc(&mut self, value: ContinuousDagc) -> Result<(), Rfm69Error> {
+        self.write_register(Register::TestDagc, value as u8)?;
+        Ok(())
+    }
+
+    /// Switches the LNA to its high sensitivity mode through RegTestLna, at the
+    /// cost of a slightly higher current and a worse blocking immunity. The driver
+    /// applies it again every time the receiver is turned on, as not every module
+    /// keeps it across mode changes and resets.
+    pub fn set_sensitivity_boost(&mut self, enabled: bool) -> Result<(), Rfm69Error> {
+        let value = match enabled {
+            true => RF_TESTLNA_HIGH_SENSITIVITY,
+            false => RF_TESTLNA_NORMAL,
+        };
+        self.write_register(Register::TestLna, value)?;
+        self.sensitivity_boost = enabled;
+        Ok(())
+    }
+
+    pub fn sensitivity_boost(&self) -> bool {
+        self.sensitivity_boost
+    }
+
+    /// Calls `control` on every mode change, for boards with an external PA, LNA
+    /// or antenna switch that has to follow the radio. It is called before the
+    /// radio enters Tx, and after it entered any other mode, so the transmitter
+    /// never drives the receive path. Listen mode is reported as Rx.
+    pub fn set_front_end_control(&mut self, control: Option<FrontEndControl>) {
+        self.front_end_control = control;
+    }
+
+    fn control_front_end(&self, mode: Rfm69Mode) {
+        if let Some(control) = self.front_end_control {
+            control(mode);
+        }
+    }
+
+    /// Programs a sync word of `length` bytes derived from `network_id`, so radios
+    /// of separate deployments don't receive each other's packets. See
+    /// `network_id::sync_words`.
+    pub fn set_network_id(&mut self, network_id: &[u8], length: u8) -> Result<(), Rfm69Error> {
+        if !(2..=8).contains(&length) {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+
+        let config = match self.sync_configuration {
+            SyncConfiguration::SyncOff => SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
+            config => config,
+        };
+        let sync_words = network_id::sync_words(network_id);
+        self.set_sync_words(config, &sync_words[..length as usize])
+    }
+
+    pub fn set_sync_words(
+        &mut self,
+        config: SyncConfiguration,
+        sync_words: &[u8],
+    ) -> Result<(), Rfm69Error> {
+        if sync_words.len() > 8 || sync_words.is_empty() {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+        if config == SyncConfiguration::SyncOff && self.node_address.is_some() {
+            return Err(ConfigError::AddressFilteringWithoutSync.into());
+        }
+
+        let mut buffer = [0u8; 9]; // 1 byte for config + up to 8 bytes for sync words
+
+        // Add the config value to the first position
+        // We need to know how many sync words we have to set the correct config value
+        buffer[0] = config.value(sync_words.len() as u8);
+        // Add the sync words to the buffer
+        buffer[1..1 + sync_words.len()].copy_from_slice(sync_words);
+        // Write the config value first, then the sync words.
+        self.write_many(Register::SyncConfig, &buffer)?;
+        self.sync_configuration = config;
+        self.sync_words[..sync_words.len()].copy_from_slice(sync_words);
+        self.sync_length = sync_words.len() as u8;
+
+        Ok(())
+    }
+
+    pub fn set_modem_config(&mut self, config: ModemConfigChoice) -> Result<(), Rfm69Error> {
+        let values = config.values();
+
         self.write_many(Register::DataModul, &values[0..5])?;
         self.write_many(Register::RxBw, &values[5..7])?;
-        self.write_register(Register::PacketConfig1, values[7])?;
+        self.modem_config = config;
+        self.modulation = None;
+        self.shaping = None;
+        self.packet_config = None;
+        self.write_register(Register::PacketConfig1, self.packet_config1())?;
+
+        Ok(())
+    }
+
+    /// Selects the pulse shaping filter, e.g. a narrower Gaussian filter than the
+    /// BT = 1.0 of the GFSK presets to match a peer. The shaping must suit the
+    /// modulation of the modem configuration, and is kept until the next
+    /// `set_modem_config`.
+    pub fn set_shaping(&mut self, shaping: Shaping) -> Result<(), Rfm69Error> {
+        let data_modul: DataModul = self.read_typed()?;
+        if !shaping.supports(data_modul.modulation) {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+
+        self.write_typed(DataModul {
+            shaping: shaping.bits(),
+            ..data_modul
+        })?;
+        self.shaping = Some(shaping);
+        Ok(())
+    }
+
+    // Register values of `modem_config`, with the modulation and shaping set by
+    // `set_fsk_modulation` and `set_shaping`
+    fn modem_values(&self) -> [u8; 8] {
+        let mut modem = *self.modem_config.values();
+        if let Some(modulation) = self.modulation {
+            modem[1..5].copy_from_slice(&modulation.bitrate_deviation_values());
+            if let Ok(rx_bw) = modulation.rx_bw_value() {
+                modem[5..7].copy_from_slice(&[rx_bw, rx_bw]);
+            }
+        }
+        if let (Some(shaping), Some(data_modul)) = (self.shaping, DataModul::from_bits(modem[0])) {
+            modem[0] = DataModul {
+                shaping: shaping.bits(),
+                ..data_modul
+            }
+            .to_bits();
+        }
+        modem
+    }
+
+    /// Programs a custom FSK bitrate, frequency deviation and receiver bandwidth,
+    /// keeping the modulation shaping and packet format of the current preset.
+    ///
+    /// Returns `Rfm69Error::InvalidModulation` without touching the radio if the
+    /// combination can't be demodulated.
+    pub fn set_fsk_modulation(&mut self, modulation: FskModulation) -> Result<(), Rfm69Error> {
+        modulation
+            .validate()
+            .map_err(Rfm69Error::InvalidModulation)?;
+        let rx_bw = modulation
+            .rx_bw_value()
+            .map_err(Rfm69Error::InvalidModulation)?;
+
+        self.write_many(Register::BitrateMsb, &modulation.bitrate_deviation_values())?;
+        // RxBw and AfcBw
+        self.write_many(Register::RxBw, &[rx_bw, rx_bw])?;
+        self.modulation = Some(modulation);
+
+        Ok(())
+    }
+
+    /// Configures the receiver for the OOK broadcasts of Itron ERT utility meters
+    /// in `protocol`, on `ert::FREQUENCY`. The sync word is the end of the
+    /// preamble, the rest of the message is received as a fixed length packet
+    /// and decoded by `ert::receive_ert`.
+    ///
+    /// The radio can't send packets in this profile, call `init` to go back.
+    pub fn set_ert_profile(&mut self, protocol: ErtProtocol) -> Result<(), Rfm69Error> {
+        self.set_frequency(ert::FREQUENCY)?;
+
+        let ook = DataModul {
+            data_mode: DataMode::Packet,
+            modulation: Modulation::Ook,
+            shaping: 0,
+        };
+        self.write_typed(ook)?;
+        let divider = ((RF69_FXOSC_HZ + ert::CHIP_RATE / 2) / ert::CHIP_RATE) as u16;
+        self.write_many(Register::BitrateMsb, &divider.to_be_bytes())?;
+        // RxBw and AfcBw, the widest OOK bandwidth of 250 kHz as meters drift
+        self.write_many(Register::RxBw, &[0xE0, 0xE0])?;
+
+        let sync_configuration = SyncConfiguration::FifoFillAuto { sync_tolerance: 0 };
+        self.set_sync_words(sync_configuration, &protocol.sync_words())?;
+        // Manchester is decoded in software, the sync word is matched on the chips
+        self.packet_config = Some(PacketConfig1 {
+            variable_length: false,
+            dc_free: DcFree::None,
+            crc_on: false,
+            crc_auto_clear_off: false,
+            address_filtering: AddressFiltering::None,
+        });
+        self.node_address = None;
+        self.payload_length = Some(protocol.payload_length() as u8);
+        self.write_register(Register::PacketConfig1, self.packet_config1())?;
+        self.write_register(Register::PayloadLength, protocol.payload_length() as u8)?;
 
         Ok(())
     }
 
-    fn set_preamble_length(&mut self, preamble_length: u16) -> Result<(), Rfm69Error> {
+    /// Configures the radio like the MySensors RFM69 driver for the network
+    /// `network_id`, see `mysensors` for the settings. The frequency, output
+    /// power and encryption key are kept.
+    ///
+    /// The packets have their own header and no whitening or address filtering,
+    /// the node address is cleared. Send and receive them with
+    /// `mysensors::MySensorsTransport`. Call `init` to go back.
+    pub fn set_mysensors_profile(&mut self, network_id: u8) -> Result<(), Rfm69Error> {
+        // The RFM69 doesn't support 0x00 in the sync word
+        if network_id == 0 {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+        self.set_software_crc(false);
+        self.set_payload_length(None)?;
+        self.set_modem_config(ModemConfigChoice::FskRb55555Fd50)?;
+        self.set_fsk_modulation(mysensors::MODULATION)?;
+        self.set_preamble_length(mysensors::PREAMBLE_LENGTH)?;
+        let sync_configuration = SyncConfiguration::FifoFillAuto { sync_tolerance: 0 };
+        self.set_sync_words(sync_configuration, &[mysensors::SYNC_WORD, network_id])?;
+
+        self.packet_config = Some(PacketConfig1 {
+            variable_length: true,
+            dc_free: DcFree::None,
+            crc_on: true,
+            crc_auto_clear_off: false,
+            address_filtering: AddressFiltering::None,
+        });
+        self.node_address = None;
+        self.write_register(Register::PacketConfig1, self.packet_config1())
+    }
+
+    fn bitrate(&self) -> u32 {
+        match self.modulation {
+            Some(modulation) => modulation.bitrate,
+            None => self.modem_config.bitrate(),
+        }
+    }
+
+    pub fn set_preamble_length(&mut self, preamble_length: u16) -> Result<(), Rfm69Error> {
         // split the preamble length into two bytes
         let msb = (preamble_length >> 8) as u8;
         let lsb = preamble_length as u8;
@@ -220,769 +1709,5301 @@ where
         let buffer = [msb, lsb];
 
         self.write_many(Register::PreambleMsb, &buffer)?;
+        self.preamble_length = preamble_length;
+        Ok(())
+    }
+
+    /// Sets the carrier frequency, rounded to the 61 Hz synthesizer step.
+    pub fn set_frequency(&mut self, frequency: Frequency) -> Result<(), Rfm69Error> {
+        self.check_frequency(frequency)?;
+
+        let buffer = self.frf(frequency);
+        self.write_many(Register::FrfMsb, &buffer)?;
+        self.frequency = frequency;
         Ok(())
     }
 
-    fn set_frequency(&mut self, freq_mhz: u32) -> Result<(), Rfm69Error> {
-        let mut frf = (freq_mhz * RF69_FSTEP) as u32;
-        frf /= RF69_FXOSC as u32;
+    fn check_frequency(&self, frequency: Frequency) -> Result<(), Rfm69Error> {
+        match self.region {
+            Some(region) if !region.profile().contains(frequency.hz()) => {
+                Err(Rfm69Error::FrequencyOutOfRange)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn frf(&self, frequency: Frequency) -> [u8; 3] {
+        // Fstep = FXOSC / 2^19
+        let fxosc = RF69_FXOSC_HZ as u64;
+        let frf = ((frequency.hz() as u64 * RF69_FSTEP as u64 + fxosc / 2) / fxosc) as u32;
+        // Rounded to the nearest step, f32::round needs std
+        let trim = frf as f32 * self.frequency_trim_ppm / 1_000_000.0;
+        let trim = if trim < 0.0 { trim - 0.5 } else { trim + 0.5 } as i32;
+        let frf = frf.saturating_add_signed(trim);
 
         // split the frequency into three bytes
         let msb = ((frf >> 16) & 0xFF) as u8;
         let mid = ((frf >> 8) & 0xFF) as u8;
         let lsb = (frf & 0xFF) as u8;
 
-        let buffer = [msb, mid, lsb];
-        self.write_many(Register::FrfMsb, &buffer)?;
+        [msb, mid, lsb]
+    }
+
+    /// Sets the output power in dBm. From +18 dBm on the RFM69HW uses its high
+    /// power settings, which need a duty cycle limiter, see `PaBoostError`.
+    pub fn set_tx_power(&mut self, tx_power: TxPowerDbm) -> Result<(), Rfm69Error> {
+        let tx_power = tx_power.dbm();
+        self.check_tx_power(tx_power)?;
+
+        let pa_boost = self.pa_boost_at(tx_power);
+
+        // Overcurrent protection off before the power goes up, on after it went down
+        if pa_boost {
+            self.write_register(Register::Ocp, RF_OCP_OFF)?;
+        }
+        self.write_typed(self.pa_level(tx_power))?;
+        if !pa_boost && self.pa_boost() {
+            self.write_register(Register::Ocp, RF_OCP_ON)?;
+        }
+        self.tx_power = tx_power;
+        Ok(())
+    }
+
+    fn check_tx_power(&self, tx_power: i8) -> Result<(), Rfm69Error> {
+        if !self.tx_power_range().contains(&tx_power) {
+            return Err(Rfm69Error::TxPowerOutOfRange);
+        }
+        if let Some(region) = self.region {
+            if tx_power > region.profile().max_eirp_dbm {
+                return Err(Rfm69Error::TxPowerOutOfRange);
+            }
+        }
+        if self.pa_boost_at(tx_power) && self.duty_cycle.is_none() {
+            return Err(Rfm69Error::PaBoost(PaBoostError::NoDutyCycleLimiter));
+        }
         Ok(())
     }
 
-    pub fn set_tx_power(&mut self, tx_power: i8) -> Result<(), Rfm69Error> {
+    fn pa_level(&self, tx_power: i8) -> PaLevel {
         let pa_level;
 
-        if self.is_high_power {
+        if self.variant == Rfm69Variant::Rfm69Hw {
             let clamped_power = tx_power.clamp(-2, 20);
 
             if clamped_power <= 13 {
                 // -2dBm to +13dBm
                 // Need PA1 exclusivelly on RFM69HW
-                pa_level =
-                    RF_PALEVEL_PA1_ON | ((tx_power + 18) as u8 & RF_PALEVEL_OUTPUTPOWER_11111);
+                pa_level = PaLevel {
+                    pa0_on: false,
+                    pa1_on: true,
+                    pa2_on: false,
+                    output_power: (clamped_power + 18) as u8,
+                };
             } else if clamped_power >= 18 {
                 // +18dBm to +20dBm
                 // Need PA1+PA2
                 // Also need PA boost settings change when tx is turned on and off, see setModeTx()
-                pa_level = RF_PALEVEL_PA1_ON
-                    | RF_PALEVEL_PA2_ON
-                    | ((tx_power + 11) as u8 & RF_PALEVEL_OUTPUTPOWER_11111);
+                pa_level = PaLevel {
+                    pa0_on: false,
+                    pa1_on: true,
+                    pa2_on: true,
+                    output_power: (clamped_power + 11) as u8,
+                };
             } else {
                 // +14dBm to +17dBm
                 // Need PA1+PA2
-                pa_level = RF_PALEVEL_PA1_ON
-                    | RF_PALEVEL_PA2_ON
-                    | ((tx_power + 14) as u8 & RF_PALEVEL_OUTPUTPOWER_11111);
+                pa_level = PaLevel {
+                    pa0_on: false,
+                    pa1_on: true,
+                    pa2_on: true,
+                    output_power: (clamped_power + 14) as u8,
+                };
             }
         } else {
             let clamped_power = tx_power.clamp(-18, 13);
-            pa_level =
-                RF_PALEVEL_PA0_ON | ((clamped_power + 18) as u8 & RF_PALEVEL_OUTPUTPOWER_11111);
+            pa_level = PaLevel {
+                pa0_on: true,
+                pa1_on: false,
+                pa2_on: false,
+                output_power: (clamped_power + 18) as u8,
+            };
         }
 
-        self.write_register(Register::PaLevel, pa_level)?;
-        self.tx_power = tx_power;
-        Ok(())
+        pa_level
+    }
+
+    // +18 dBm to +20 dBm need the high power settings while transmitting
+    fn pa_boost(&self) -> bool {
+        self.pa_boost_at(self.tx_power)
+    }
+
+    fn pa_boost_at(&self, tx_power: i8) -> bool {
+        self.variant == Rfm69Variant::Rfm69Hw && self.high_power_boost() && tx_power >= 18
+    }
+
+    // Milliseconds to wait before `airtime` at high power, 0 at lower powers
+    fn pa_boost_wait_time_ms(&mut self, airtime: u32) -> Result<u64, Rfm69Error> {
+        if !self.pa_boost() {
+            return Ok(0);
+        }
+        let Some(limiter) = self.duty_cycle.as_mut() else {
+            return Err(Rfm69Error::PaBoost(PaBoostError::NoDutyCycleLimiter));
+        };
+        limiter
+            .pa_boost_wait_time_ms(airtime)
+            .ok_or(Rfm69Error::PaBoost(PaBoostError::DutyCycleExceeded))
+    }
+
+    fn record_airtime(&mut self, airtime: u32) {
+        let frequency_hz = self.frequency.hz();
+        let pa_boost = self.pa_boost();
+        if let Some(limiter) = self.duty_cycle.as_mut() {
+            limiter.record(frequency_hz, airtime);
+            if pa_boost {
+                limiter.record_pa_boost(airtime);
+            }
+        }
     }
 
     pub async fn set_mode(&mut self, mode: Rfm69Mode) -> Result<(), Rfm69Error> {
+        self.idled = false;
         if self.current_mode == mode {
             return Ok(());
         }
 
-        match mode {
-            Rfm69Mode::Rx => {
-                // If high power boost, return power amp to receive mode
-                if self.tx_power >= 18 {
-                    self.write_register(Register::TestPa1, 0x55)?;
-                    self.write_register(Register::TestPa2, 0x70)?;
+        self.switch_mode(mode)?;
+        let mut waited_us = 0u64;
+        while !IrqFlags1::from_bits(self.read_register(Register::IrqFlags1)?).mode_ready {
+            if let Some(watchdog) = &self.watchdog {
+                if waited_us >= watchdog.mode_ready_timeout_ms() as u64 * 1000 {
+                    self.recover(StuckCondition::ModeReady).await?;
+                    return Err(Rfm69Error::RadioStuck(StuckCondition::ModeReady));
                 }
             }
+            self.delay.delay_us(self.poll_intervals.mode_ready_us).await;
+            waited_us += self.poll_intervals.mode_ready_us as u64;
+        }
+
+        self.current_mode = mode;
+        if mode == Rfm69Mode::Rx {
+            self.rx_idle_us = 0;
+        }
+        Ok(())
+    }
 
+    // Requests `mode` without waiting for ModeReady
+    fn switch_mode(&mut self, mode: Rfm69Mode) -> Result<(), Rfm69Error> {
+        match mode {
             Rfm69Mode::Tx => {
                 // If high power boost, enable power amp
-                if self.tx_power >= 18 {
-                    self.write_register(Register::TestPa1, 0x5D)?;
-                    self.write_register(Register::TestPa2, 0x7C)?;
+                if self.pa_boost() {
+                    self.write_register(Register::TestPa1, RF_TESTPA1_BOOST)?;
+                    self.write_register(Register::TestPa2, RF_TESTPA2_BOOST)?;
                 }
 
                 // enable the packet sent interrupt
                 self.write_register(Register::DioMapping1, RF_DIOMAPPING1_DIO0_00)?;
             }
 
+            // If high power boost, return power amp to receive mode
+            _ if self.pa_boost() => {
+                self.write_register(Register::TestPa1, RF_TESTPA1_NORMAL)?;
+                self.write_register(Register::TestPa2, RF_TESTPA2_NORMAL)?;
+            }
+
             _ => {}
         }
 
-        // Read the current mode
-        let mut current_mode = self.read_register(Register::OpMode)?;
-        current_mode &= !0x1C;
-        current_mode |= mode.clone() as u8 & 0x1C;
+        if mode == Rfm69Mode::Rx && self.sensitivity_boost {
+            self.write_register(Register::TestLna, RF_TESTLNA_HIGH_SENSITIVITY)?;
+        }
 
-        // // Set the new mode
-        self.write_register(Register::OpMode, current_mode)?;
-        while (self.read_register(Register::IrqFlags1)? & 0x80) == 0x00 {
-            self.delay.delay_ms(10).await;
+        let op_mode = OpMode {
+            listen_abort: false,
+            mode,
+            ..self.op_mode
+        };
+
+        if mode == Rfm69Mode::Tx {
+            self.control_front_end(mode);
+        }
+        self.write_op_mode(op_mode)?;
+        if mode != Rfm69Mode::Tx {
+            self.control_front_end(mode);
         }
+        Ok(())
+    }
 
-        self.current_mode = mode;
+    fn write_op_mode(&mut self, op_mode: OpMode) -> Result<(), Rfm69Error> {
+        let on_air = matches!(op_mode.mode, Rfm69Mode::Tx | Rfm69Mode::Rx)
+            || (op_mode.listen_on && !op_mode.listen_abort);
+        if self.key_lost && on_air {
+            return Err(Rfm69Error::EncryptionKeyLost);
+        }
+        self.write_typed(op_mode)?;
+        self.op_mode = op_mode;
         Ok(())
     }
 
+    // Waits for DIO0 to go high
+    pub(crate) async fn wait_for_interrupt(&mut self) -> Result<(), Rfm69Error> {
+        self.intr_pin
+            .wait_for_high()
+            .await
+            .map_err(|_| Rfm69Error::InterruptPinError)
+    }
+
     async fn wait_packet_sent(&mut self) -> Result<(), Rfm69Error> {
-        self.intr_pin.wait_for_high().await.unwrap();
-        while (self.read_register(Register::IrqFlags2)? & 0x08) == 0 {
+        self.wait_for_interrupt().await?;
+        while !IrqFlags2::from_bits(self.read_register(Register::IrqFlags2)?).packet_sent {
             info!("Waiting for packet sent...");
-            self.delay.delay_ms(10).await;
+            self.delay
+                .delay_us(self.poll_intervals.packet_sent_us)
+                .await;
         }
         Ok(())
     }
 
+    /// Sends `data` with the default header, from and to the broadcast address.
+    /// Peers that check the TO byte need `send_to`, or `send_with_header` for
+    /// other header values.
     pub async fn send(&mut self, data: &[u8]) -> Result<(), Rfm69Error> {
-        const HEADER_LENGTH: usize = 5;
+        self.send_with_header(Header::default(), data).await
+    }
 
-        if data.len() > 60 {
-            return Err(Rfm69Error::MessageTooLarge);
-        }
+    /// Sends `data` with a preamble long enough to wake up a receiver in
+    /// `recv_low_power` with the same `listen` configuration. A listen period
+    /// longer than the longest preamble at the bitrate is `ConfigurationError`.
+    pub async fn send_wake(
+        &mut self,
+        listen: &ListenConfig,
+        data: &[u8],
+    ) -> Result<(), Rfm69Error> {
+        self.send_paging(listen.period_us(), Header::default(), data)
+            .await
+    }
 
-        let mut buffer: [u8; 65] = [0x00; 65];
-        let header = [0xFF, 0xFF, 0x00, 0x00];
-        buffer[0] = (data.len() + 4) as u8;
-        buffer[1..5].copy_from_slice(&header);
-        buffer[5..5 + data.len()].copy_from_slice(data);
+    /// Sends `data` preceded by `header` with a preamble lasting at least
+    /// `period_us`, so a receiver waking up every `period_us` to sample the
+    /// channel, e.g. with Rx duty-cycled by its MCU, is guaranteed to catch it.
+    /// A period longer than the longest preamble at the bitrate is
+    /// `ConfigurationError`, see `listen::covering_preamble_length`.
+    pub async fn send_paging(
+        &mut self,
+        period_us: u32,
+        header: Header,
+        data: &[u8],
+    ) -> Result<(), Rfm69Error> {
+        let preamble_length = covering_preamble_length(period_us, self.bitrate())
+            .ok_or(Rfm69Error::ConfigurationError)?;
+        self.send_with_preamble(preamble_length, header, data).await
+    }
 
-        self.write_many(Register::Fifo, &buffer[0..data.len() + HEADER_LENGTH])?;
+    /// Sends `data` preceded by `header` with a preamble of `preamble_length`
+    /// bytes, up to 65535, then restores the configured preamble length. The
+    /// duty cycle limiter accounts for the longer time on air.
+    pub async fn send_with_preamble(
+        &mut self,
+        preamble_length: u16,
+        header: Header,
+        data: &[u8],
+    ) -> Result<(), Rfm69Error> {
+        let configured = self.preamble_length;
+        self.set_preamble_length(preamble_length)?;
+        let result = self.send_with_header(header, data).await;
+        self.set_preamble_length(configured)?;
+        result
+    }
 
-        self.set_mode(Rfm69Mode::Tx).await?;
-        self.wait_packet_sent().await?;
-        self.set_mode(Rfm69Mode::Standby).await?;
+    /// Sends `data` to the node at address `to`.
+    pub async fn send_to(&mut self, to: u8, data: &[u8]) -> Result<(), Rfm69Error> {
+        let header = Header {
+            to,
+            from: self.node_address.unwrap_or(BROADCAST_ADDRESS),
+            id: self.next_sequence(),
+            flags: 0,
+        };
+        self.send_with_header(header, data).await
+    }
 
-        Ok(())
+    /// Sends `data` to every node, using the configured broadcast address.
+    pub async fn broadcast(&mut self, data: &[u8]) -> Result<(), Rfm69Error> {
+        let header = Header {
+            to: self.broadcast_address,
+            from: self.node_address.unwrap_or(BROADCAST_ADDRESS),
+            id: self.next_sequence(),
+            flags: FLAGS_BROADCAST,
+        };
+        self.send_with_header(header, data).await
     }
 
-    pub fn is_message_available(&mut self) -> Result<bool, Rfm69Error> {
-        if self.current_mode != Rfm69Mode::Rx {
-            return Err(Rfm69Error::InvalidMode);
-        }
-        Ok((self.read_register(Register::IrqFlags2)? & 0x04) == 0x04)
+    // Sequence number of addressed datagrams, lets receivers detect lost packets
+    fn next_sequence(&mut self) -> u8 {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.sequence
     }
 
-    pub async fn wait_for_message(&mut self) -> Result<(), Rfm69Error> {
-        while !self.is_message_available()? {
-            self.delay.delay_ms(1000).await;
+    /// Sends `data` preceded by `header`.
+    ///
+    /// Without AES, variable length packets of up to 255 bytes after the length byte
+    /// are sent even if they don't fit the FIFO: it is refilled on FifoLevel while
+    /// the first bytes go out. `receive` only takes packets that fit the FIFO, the
+    /// receiver must read longer ones while they arrive.
+    pub async fn send_with_header(
+        &mut self,
+        header: Header,
+        data: &[u8],
+    ) -> Result<(), Rfm69Error> {
+        if data.len() > self.max_payload_length() {
+            return self.send_streamed(header, data).await;
         }
-        Ok(())
+
+        let airtime = self.airtime_ms(self.fifo_length(data.len()));
+        self.check_duty_cycle(airtime).await?;
+
+        self.write_packet(header, data)?;
+        self.transmit_fifo(airtime).await
+    }
+
+    /// Sends `data` with the header bytes given one by one, e.g. to reply to a
+    /// RadioHead peer with its own ID, see `send_with_header`.
+    pub async fn send_with_fields(
+        &mut self,
+        to: u8,
+        from: u8,
+        id: u8,
+        flags: u8,
+        data: &[u8],
+    ) -> Result<(), Rfm69Error> {
+        let header = Header {
+            to,
+            from,
+            id,
+            flags,
+        };
+        self.send_with_header(header, data).await
     }
 
-    pub async fn receive(&mut self, buffer: &mut [u8; 65]) -> Result<usize, Rfm69Error> {
-        let message_len = self.read_register(Register::Fifo)?;
-        if buffer.len() < message_len as usize {
+    /// Sends `data` as the whole packet, without the header, for packet formats
+    /// of other stacks like `mysensors`, LowPowerLab nodes or custom firmware
+    /// whose first payload byte is application data. A length byte is put in
+    /// front of it in variable length mode, in fixed length mode it is padded
+    /// with zeros up to the payload length.
+    pub async fn send_raw(&mut self, data: &[u8]) -> Result<(), Rfm69Error> {
+        let variable_length = self.variable_length()?;
+        let (length, max_length) = match variable_length {
+            true => (data.len(), self.max_variable_length()),
+            false => {
+                let length = self.read_register_cached(Register::PayloadLength)? as usize;
+                let max_length = match self.encrypted {
+                    true => AES_MAX_LENGTH + self.node_address.is_some() as usize,
+                    false => RF69_FIFO_SIZE,
+                };
+                (length, max_length)
+            }
+        };
+        if data.len() > length || length > max_length {
             return Err(Rfm69Error::MessageTooLarge);
         }
 
-        let mut header = [0u8; 4];
-        self.read_many(Register::Fifo, &mut header).unwrap();
+        let airtime = self.airtime_ms(length + variable_length as usize);
+        self.check_duty_cycle(airtime).await?;
 
-        self.read_many(Register::Fifo, &mut buffer[0..(message_len - 4) as usize])
-            .unwrap();
-        Ok((message_len - 4) as usize)
+        if variable_length {
+            self.write_register(Register::Fifo, data.len() as u8)?;
+        }
+        let mut fifo = [0u8; RF69_FIFO_SIZE];
+        fifo[..data.len()].copy_from_slice(data);
+        self.write_many(Register::Fifo, &fifo[..length])?;
+        self.transmit_fifo(airtime).await
     }
 
-    pub fn rssi(&mut self) -> Result<u8, Rfm69Error> {
-        let rssi = self.read_register(Register::RssiValue)?;
-        Ok(rssi / 2)
-    }
+    // Sends the packet written to the FIFO and returns to Standby
+    // Sends a packet longer than the FIFO, writing the rest while the first bytes go out
+    async fn send_streamed(&mut self, header: Header, data: &[u8]) -> Result<(), Rfm69Error> {
+        // The AES engine and fixed length packets are limited to the FIFO
+        let length = self.fifo_length(data.len());
+        if self.payload_length.is_some() || self.encrypted || length > STREAMED_PAYLOAD_LENGTH + 1 {
+            return Err(Rfm69Error::MessageTooLarge);
+        }
 
-    fn write_register(&mut self, register: Register, value: u8) -> Result<(), Rfm69Error> {
-        self.write_many(register, &[value])?;
-        Ok(())
-    }
+        let airtime = self.airtime_ms(length);
+        self.check_duty_cycle(airtime).await?;
+
+        let mut buffer = [0u8; STREAMED_PAYLOAD_LENGTH + 1];
+        let length = self.encode_packet(header, data, &mut buffer);
+        let byte_us = 8_000_000u32.div_ceil(self.bitrate().max(1));
+        if let Err(error) = self
+            .fill_stream(buffer[..length].iter().copied(), length, byte_us)
+            .await
+        {
+            self.set_mode(Rfm69Mode::Standby).await?;
+            return Err(error);
+        }
 
-    fn read_register(&mut self, register: Register) -> Result<u8, Rfm69Error> {
-        let mut buffer = [0u8; 1];
-        self.spi
-            .read_many(register, &mut buffer)
-            .map_err(|_| Rfm69Error::SpiWriteError)?;
-        Ok(buffer[0])
-    }
+        // The radio appends the CRC once the FIFO is empty, PacketSent follows it
+        self.wait_packet_sent().await?;
+        self.set_mode(Rfm69Mode::Standby).await?;
 
-    fn write_many(&mut self, register: Register, values: &[u8]) -> Result<(), Rfm69Error> {
-        self.spi
-            .write_many(register, values)
-            .map_err(|_| Rfm69Error::SpiWriteError)?;
-        Ok(())
-    }
+        self.record_airtime(airtime);
+        self.stats.record_sent();
 
-    fn read_many(&mut self, register: Register, buffer: &mut [u8]) -> Result<(), Rfm69Error> {
-        self.spi
-            .read_many(register, buffer)
-            .map_err(|_| Rfm69Error::SpiReadError)?;
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
+    async fn transmit_fifo(&mut self, airtime: u32) -> Result<(), Rfm69Error> {
+        self.set_mode(Rfm69Mode::Tx).await?;
+        self.wait_packet_sent().await?;
+        self.set_mode(Rfm69Mode::Standby).await?;
 
-    use crate::settings::{ContinuousDagc, SyncConfiguration};
+        self.record_airtime(airtime);
+        self.stats.record_sent();
 
-    use super::*;
-    use embedded_hal_mock::eh1::delay::{CheckedDelay, Transaction as DelayTransaction};
-    use embedded_hal_mock::eh1::digital::{
-        Mock as DigitalMock, State, Transaction as GpioTransaction,
-    };
-    use embedded_hal_mock::eh1::spi::{Mock as SpiDevice, Transaction as SpiTransaction};
+        Ok(())
+    }
 
-    fn setup_rfm() -> Rfm69<SpiDevice<u8>, DigitalMock, DigitalMock, CheckedDelay> {
-        let spi_expectations = [];
-        let spi_device = SpiDevice::new(spi_expectations);
+    /// Encodes `data` preceded by `header` once, for `send_beacon`.
+    pub fn prepare_beacon(&self, header: Header, data: &[u8]) -> Result<BeaconFrame, Rfm69Error> {
+        if data.len() > self.max_payload_length() {
+            return Err(Rfm69Error::MessageTooLarge);
+        }
 
-        let reset_expectations = [];
-        let reset_pin = DigitalMock::new(reset_expectations);
+        let mut fifo = [0u8; RF69_FIFO_SIZE + 1];
+        let length = self.encode_packet(header, data, &mut fifo);
+        Ok(BeaconFrame {
+            fifo,
+            length,
+            airtime: self.airtime_ms(length),
+        })
+    }
+
+    /// Sends a frame prepared by `prepare_beacon` and puts the radio to Sleep,
+    /// without receiving anything, see `beacon::Beacon`.
+    ///
+    /// It takes fewer SPI operations than `send` and `sleep`: Tx and Sleep are
+    /// entered without polling ModeReady, PacketSent on DIO0 already follows
+    /// TxReady, and DIO0 is only mapped to PacketSent if it isn't yet. The PA boost
+    /// registers are still switched on for Tx and off before Sleep.
+    pub async fn send_beacon(&mut self, frame: &BeaconFrame) -> Result<(), Rfm69Error> {
+        self.check_duty_cycle(frame.airtime).await?;
+
+        // Waking up the oscillator takes a ModeReady poll, the FIFO is filled in Standby
+        self.set_mode(Rfm69Mode::Standby).await?;
+        self.write_many(Register::Fifo, &frame.fifo[..frame.length])?;
 
-        let intr_expectations = [];
-        let intr_pin = DigitalMock::new(intr_expectations);
+        if self.pa_boost() {
+            self.write_register(Register::TestPa1, RF_TESTPA1_BOOST)?;
+            self.write_register(Register::TestPa2, RF_TESTPA2_BOOST)?;
+        }
+        if self.shadow.get(Register::DioMapping1) != Some(RF_DIOMAPPING1_DIO0_00) {
+            self.write_register(Register::DioMapping1, RF_DIOMAPPING1_DIO0_00)?;
+        }
+        self.control_front_end(Rfm69Mode::Tx);
+        self.write_op_mode(OpMode {
+            listen_abort: false,
+            mode: Rfm69Mode::Tx,
+            ..self.op_mode
+        })?;
+        self.current_mode = Rfm69Mode::Tx;
+        self.wait_packet_sent().await?;
 
-        let delay_expectations = [];
-        let delay = CheckedDelay::new(delay_expectations);
+        // Restores the PA registers for the next mode
+        self.switch_mode(Rfm69Mode::Sleep)?;
+        self.current_mode = Rfm69Mode::Sleep;
 
-        Rfm69::new(spi_device, reset_pin, intr_pin, delay)
+        self.record_airtime(frame.airtime);
+        self.stats.record_sent();
+        Ok(())
     }
 
-    fn check_expectations(rfm: &mut Rfm69<SpiDevice<u8>, DigitalMock, DigitalMock, CheckedDelay>) {
-        rfm.reset_pin.done();
-        rfm.intr_pin.done();
-        rfm.delay.done();
-        rfm.spi.done();
+    fn write_packet(&mut self, header: Header, data: &[u8]) -> Result<(), Rfm69Error> {
+        let mut buffer = [0u8; RF69_FIFO_SIZE + 1];
+        let length = self.encode_packet(header, data, &mut buffer);
+        self.write_many(Register::Fifo, &buffer[..length])
+    }
+
+    // Writes the FIFO contents of a packet to `buffer`, returns their length
+    fn encode_packet(&self, header: Header, data: &[u8], buffer: &mut [u8]) -> usize {
+        let data_end = FIFO_OVERHEAD + self.padded_length(data.len());
+        let end = data_end + self.trailer_length();
+        buffer[0] = (end - 1) as u8;
+        buffer[1..FIFO_OVERHEAD].copy_from_slice(&header.to_bytes());
+        buffer[FIFO_OVERHEAD..FIFO_OVERHEAD + data.len()].copy_from_slice(data);
+        if self.software_crc {
+            let crc = Crc32::checksum(&buffer[1..data_end]);
+            buffer[data_end..data_end + SOFTWARE_CRC_LENGTH].copy_from_slice(&crc.to_be_bytes());
+        }
+        if let Some(mac) = &self.authentication {
+            let tag = authenticate(
+                mac,
+                &buffer[1..FIFO_OVERHEAD],
+                &buffer[FIFO_OVERHEAD..data_end],
+            );
+            buffer[end - MAC_LENGTH..end].copy_from_slice(&tag);
+        }
+        // Fixed length packets have no length byte
+        if self.payload_length.is_some() {
+            buffer.copy_within(1..end, 0);
+            return end - 1;
+        }
+        end
+    }
+
+    // Payload bytes sent for `data_length` bytes, fixed length packets are padded
+    fn padded_length(&self, data_length: usize) -> usize {
+        match self.payload_length {
+            Some(_) => self.max_payload_length(),
+            None => data_length,
+        }
+    }
+
+    /// Appends a CRC-32 over the header and the payload to every packet, and drops
+    /// received packets without a valid one with `Rfm69Error::CrcFailure`. For
+    /// networks that run with the radio CRC disabled, e.g. to talk to other
+    /// stacks. Both ends must enable it, it shortens the payload by 4 bytes.
+    pub fn set_software_crc(&mut self, enabled: bool) {
+        self.software_crc = enabled;
+    }
+
+    /// Appends an AES-CMAC over the header and the payload, truncated to
+    /// `session::MAC_LENGTH` bytes, to every packet, and drops received packets
+    /// without a valid one with `Rfm69Error::AuthenticationFailure`. The radio
+    /// AES only hides the payload, anyone can still send or alter a packet.
+    /// `key` can be the key of `set_encryption_key`, `None` turns it off. Both
+    /// ends must enable it, it shortens the payload by 4 bytes.
+    ///
+    /// It doesn't stop replayed packets, see `session::SecureSession`. The
+    /// driver keeps the expanded key, with the `zeroize` feature it is wiped
+    /// when it is replaced or turned off.
+    pub fn set_authentication_key(&mut self, key: Option<&[u8; 16]>) {
+        self.authentication = key.map(|key| <Cmac<Aes128> as Mac>::new(key.into()));
+    }
+
+    /// Longest payload `send` accepts with the current configuration. The packet
+    /// has to fit in the FIFO after the length byte, and with encryption on in
+    /// the 64 bytes the AES engine handles, 65 with address filtering. The header,
+    /// the software CRC and the MAC take their share of it.
+    pub fn max_payload_length(&self) -> usize {
+        let packet_length = match self.payload_length {
+            Some(length) => length as usize,
+            None => self.max_variable_length(),
+        };
+        packet_length.saturating_sub(HEADER_LENGTH + self.trailer_length())
+    }
+
+    // Longest variable length packet, without the length byte
+    fn max_variable_length(&self) -> usize {
+        let max_length = VARIABLE_PAYLOAD_LENGTH as usize;
+        match self.encrypted {
+            true => max_length.min(AES_MAX_LENGTH + self.node_address.is_some() as usize),
+            false => max_length,
+        }
+    }
+
+    fn trailer_length(&self) -> usize {
+        let crc_length = match self.software_crc {
+            true => SOFTWARE_CRC_LENGTH,
+            false => 0,
+        };
+        let mac_length = match self.authentication {
+            Some(_) => MAC_LENGTH,
+            None => 0,
+        };
+        crc_length + mac_length
+    }
+
+    // Bytes written to the FIFO for a payload of `data_length` bytes
+    fn fifo_length(&self, data_length: usize) -> usize {
+        match self.payload_length {
+            Some(length) => length as usize,
+            None => FIFO_OVERHEAD + data_length + self.trailer_length(),
+        }
+    }
+
+    /// Starts sending `data` without waiting for it to go out, for use without an
+    /// async executor. `on_interrupt` must be called on the rising edge of DIO0,
+    /// `poll_event` then reports `RadioEvent::PacketSent`.
+    ///
+    /// The duty cycle limiter can't delay here, an exhausted budget is an error.
+    pub fn start_transmit(&mut self, header: Header, data: &[u8]) -> Result<(), Rfm69Error> {
+        if data.len() > self.max_payload_length() {
+            return Err(Rfm69Error::MessageTooLarge);
+        }
+
+        let airtime = self.airtime_ms(self.fifo_length(data.len()));
+        if self.pa_boost_wait_time_ms(airtime)? > 0 {
+            return Err(Rfm69Error::PaBoost(PaBoostError::DutyCycleExceeded));
+        }
+        let frequency_hz = self.frequency.hz();
+        if let Some(limiter) = self.duty_cycle.as_mut() {
+            if limiter.wait_time_ms(frequency_hz, airtime) != Some(0) {
+                return Err(Rfm69Error::DutyCycleExceeded);
+            }
+        }
+        self.record_airtime(airtime);
+
+        self.write_packet(header, data)?;
+        self.switch_mode(Rfm69Mode::Tx)?;
+        self.current_mode = Rfm69Mode::Tx;
+        self.interrupt_state = InterruptState::Transmitting;
+        Ok(())
+    }
+
+    /// Starts receiving without waiting for a packet, for use without an async
+    /// executor. `on_interrupt` must be called on the rising edge of DIO0,
+    /// `poll_event` then reports the received packets.
+    pub fn start_receive(&mut self) -> Result<(), Rfm69Error> {
+        self.write_register(Register::DioMapping1, RF_DIOMAPPING1_DIO0_01)?;
+        self.switch_mode(Rfm69Mode::Rx)?;
+        self.current_mode = Rfm69Mode::Rx;
+        self.interrupt_state = InterruptState::Receiving;
+        Ok(())
+    }
+
+    /// Enters Rx with PayloadReady on DIO0, unless already receiving.
+    pub(crate) async fn enter_rx(&mut self) -> Result<(), Rfm69Error> {
+        if self.current_mode != Rfm69Mode::Rx {
+            self.write_register(Register::DioMapping1, RF_DIOMAPPING1_DIO0_01)?;
+            self.set_mode(Rfm69Mode::Rx).await?;
+        }
+        Ok(())
+    }
+
+    /// Advances the operation started by `start_transmit` or `start_receive`, to be
+    /// called from the DIO0 interrupt handler. Never blocks or awaits.
+    ///
+    /// After a transmission the radio returns to Standby, after a reception it
+    /// keeps listening. An event not taken by `poll_event` before the next one
+    /// completes is replaced.
+    pub fn on_interrupt(&mut self) -> Result<(), Rfm69Error> {
+        match self.interrupt_state {
+            InterruptState::Idle => Ok(()),
+            InterruptState::Transmitting => {
+                if !self.read_irq_flags()?.1.packet_sent {
+                    return Ok(());
+                }
+                self.switch_mode(Rfm69Mode::Standby)?;
+                self.current_mode = Rfm69Mode::Standby;
+                self.interrupt_state = InterruptState::Idle;
+                self.stats.record_sent();
+                self.event = Some(RadioEvent::PacketSent);
+                Ok(())
+            }
+            InterruptState::Receiving => {
+                let mut buffer = [0u8; MAX_PAYLOAD_LENGTH];
+                self.event = match self.read_packet(&mut buffer) {
+                    Ok((header, length)) => {
+                        self.restart_rx()?;
+                        Some(RadioEvent::PacketReceived(ReceivedPacket::new(
+                            header,
+                            &buffer[..length],
+                        )))
+                    }
+                    // Forged packets are dropped like filtered ones
+                    Err(Rfm69Error::NoMessage | Rfm69Error::AuthenticationFailure) => return Ok(()),
+                    Err(Rfm69Error::CrcFailure) => Some(RadioEvent::CrcError),
+                    Err(Rfm69Error::FifoOverrun) => Some(RadioEvent::FifoOverrun),
+                    Err(error) => return Err(error),
+                };
+                Ok(())
+            }
+        }
+    }
+
+    /// Takes the event completed by `on_interrupt`, if any.
+    pub fn poll_event(&mut self) -> Option<RadioEvent> {
+        self.event.take()
+    }
+
+    /// The event completed by `on_interrupt`, left for `poll_event`.
+    #[cfg(feature = "radio")]
+    pub(crate) fn pending_event(&self) -> Option<&RadioEvent> {
+        self.event.as_ref()
+    }
+
+    pub fn interrupt_state(&self) -> InterruptState {
+        self.interrupt_state
+    }
+
+    /// Time on air of a packet with `fifo_length` bytes written to the FIFO,
+    /// including preamble, sync word and CRC.
+    fn airtime_ms(&self, fifo_length: usize) -> u32 {
+        airtime_ms(self.packet_bytes(fifo_length), self.bitrate())
+    }
+
+    /// Time on air in microseconds of a packet carrying `data_length` payload bytes
+    /// after the header, from the first preamble bit until PayloadReady at the receiver.
+    pub fn airtime_us(&self, data_length: usize) -> u32 {
+        let bits = self.packet_bytes(self.fifo_length(data_length)) as u64 * 8 * 1_000_000;
+        bits.div_ceil(self.bitrate() as u64) as u32
+    }
+
+    fn packet_bytes(&self, fifo_length: usize) -> usize {
+        const CRC_LENGTH: usize = 2;
+        let sync_length = match self.sync_configuration {
+            SyncConfiguration::SyncOff => 0,
+            _ => self.sync_length as usize,
+        };
+        self.preamble_length as usize + sync_length + fifo_length + CRC_LENGTH
+    }
+
+    async fn check_duty_cycle(&mut self, airtime: u32) -> Result<(), Rfm69Error> {
+        let pa_boost_wait = self.pa_boost_wait_time_ms(airtime)?;
+        let Some(limiter) = self.duty_cycle.as_mut() else {
+            return Ok(());
+        };
+
+        let wait = limiter.wait_time_ms(self.frequency.hz(), airtime);
+        match wait.map(|wait| wait.max(pa_boost_wait)) {
+            Some(0) => Ok(()),
+            Some(wait) if limiter.action() == DutyCycleAction::Delay => {
+                self.delay.delay_ms(wait as u32).await;
+                Ok(())
+            }
+            Some(_) if wait == Some(0) => Err(Rfm69Error::PaBoost(PaBoostError::DutyCycleExceeded)),
+            _ => Err(Rfm69Error::DutyCycleExceeded),
+        }
+    }
+
+    /// Continuously transmits `length` bytes of a test pattern, without sync word,
+    /// length byte or CRC, so the occupied bandwidth and deviation can be measured
+    /// on a spectrum analyzer.
+    ///
+    /// The packet engine is put in unlimited length mode for the duration of the
+    /// transmission, and the previous packet configuration is restored afterwards.
+    pub async fn transmit_test_pattern(
+        &mut self,
+        pattern: TestPattern,
+        length: usize,
+    ) -> Result<(), Rfm69Error> {
+        let bitrate = self.bitrate();
+        self.transmit_unlimited(pattern.generator(), length, bitrate)
+            .await
+    }
+
+    /// Sends `packets` BER test packets for `receive_ber` on another radio: fixed
+    /// length packets of `ber::BER_PACKET_LENGTH` PN9 bytes after the sync word,
+    /// without CRC or address. The packet configuration is restored afterwards.
+    pub async fn transmit_ber(&mut self, packets: u32) -> Result<(), Rfm69Error> {
+        let packet = ber::ber_packet();
+        let airtime = airtime_ms(self.packet_bytes(BER_PACKET_LENGTH), self.bitrate());
+        let saved = self.set_ber_format().await?;
+        let mut result = Ok(());
+        for _ in 0..packets {
+            result = self.send_ber_packet(&packet, airtime).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        self.restore_ber_format(saved).await?;
+        result
+    }
+
+    async fn send_ber_packet(
+        &mut self,
+        packet: &[u8; BER_PACKET_LENGTH],
+        airtime: u32,
+    ) -> Result<(), Rfm69Error> {
+        self.check_duty_cycle(airtime).await?;
+        self.write_many(Register::Fifo, packet)?;
+        self.transmit_fifo(airtime).await
+    }
+
+    /// Receives the packets of `transmit_ber` on another radio and counts the bit
+    /// errors, by RSSI. The radio CRC is off, so corrupted packets are counted
+    /// rather than dropped. Stops after `packets` packets, or when none arrives
+    /// for `timeout_ms`. The packet configuration is restored afterwards.
+    pub async fn receive_ber(
+        &mut self,
+        packets: u32,
+        timeout_ms: u32,
+    ) -> Result<BerReport, Rfm69Error> {
+        let saved = self.set_ber_format().await?;
+        let mut report = BerReport::default();
+        let result = self
+            .count_bit_errors(&mut report, packets, timeout_ms)
+            .await;
+        self.restore_ber_format(saved).await?;
+        result.map(|_| report)
+    }
+
+    async fn count_bit_errors(
+        &mut self,
+        report: &mut BerReport,
+        packets: u32,
+        timeout_ms: u32,
+    ) -> Result<(), Rfm69Error> {
+        let mut packet = [0u8; BER_PACKET_LENGTH];
+        self.set_mode(Rfm69Mode::Rx).await?;
+        while report.packets < packets {
+            if !self.wait_for_message_timeout(Some(timeout_ms)).await? {
+                break;
+            }
+            if IrqFlags2::from_bits(self.read_register(Register::IrqFlags2)?).fifo_overrun {
+                self.discard_packet()?;
+                continue;
+            }
+            self.read_many(Register::Fifo, &mut packet)?;
+            let rssi_dbm = match self.latched_rssi.take() {
+                Some(rssi_dbm) => rssi_dbm,
+                None => -(self.rssi()? as i16),
+            };
+            report.record(rssi_dbm, &packet);
+        }
+        Ok(())
+    }
+
+    // Fixed length BER packets, returns PacketConfig1 and PayloadLength to restore
+    async fn set_ber_format(&mut self) -> Result<(u8, u8), Rfm69Error> {
+        self.set_mode(Rfm69Mode::Standby).await?;
+        let packet_config = self.read_register_cached(Register::PacketConfig1)?;
+        let payload_length = self.read_register_cached(Register::PayloadLength)?;
+
+        let ber_format = PacketConfig1 {
+            variable_length: false,
+            dc_free: DcFree::None,
+            crc_on: false,
+            crc_auto_clear_off: false,
+            address_filtering: AddressFiltering::None,
+        };
+        self.write_typed(ber_format)?;
+        self.write_register(Register::PayloadLength, BER_PACKET_LENGTH as u8)?;
+        Ok((packet_config, payload_length))
+    }
+
+    async fn restore_ber_format(
+        &mut self,
+        (packet_config, payload_length): (u8, u8),
+    ) -> Result<(), Rfm69Error> {
+        self.set_mode(Rfm69Mode::Standby).await?;
+        self.write_register(Register::PacketConfig1, packet_config)?;
+        self.write_register(Register::PayloadLength, payload_length)
+    }
+
+    /// Sends `frame` `repeats` times back to back in OOK, one bit every `pulse_us`
+    /// microseconds, MSB first, with the carrier on for 1 bits. There is no
+    /// preamble, sync word or CRC, as expected by the receivers of remote controlled
+    /// sockets and similar devices, see `remote`.
+    ///
+    /// The modulation and packet configuration are restored afterwards.
+    pub async fn transmit_ook(
+        &mut self,
+        pulse_us: u32,
+        frame: &[u8],
+        repeats: u8,
+    ) -> Result<(), Rfm69Error> {
+        // RegBitrate divides the crystal frequency
+        let divider = RF69_FXOSC_HZ as u64 * pulse_us as u64 / 1_000_000;
+        let divider = match u16::try_from(divider) {
+            Ok(divider) if divider > 0 => divider,
+            _ => return Err(Rfm69Error::ConfigurationError),
+        };
+        let length = frame.len() * repeats as usize;
+        if length == 0 {
+            return Ok(());
+        }
+
+        self.set_mode(Rfm69Mode::Standby).await?;
+
+        // Save the modulation, it is restored once the frames are sent
+        let data_modul = self.read_register_cached(Register::DataModul)?;
+        let bitrate = [
+            self.read_register_cached(Register::BitrateMsb)?,
+            self.read_register_cached(Register::BitrateLsb)?,
+        ];
+        let preamble = [
+            self.read_register_cached(Register::PreambleMsb)?,
+            self.read_register_cached(Register::PreambleLsb)?,
+        ];
+
+        let ook = DataModul {
+            data_mode: DataMode::Packet,
+            modulation: Modulation::Ook,
+            shaping: 0,
+        };
+        self.write_typed(ook)?;
+        self.write_many(Register::BitrateMsb, &divider.to_be_bytes())?;
+        self.write_many(Register::PreambleMsb, &[0x00, 0x00])?;
+
+        let result = self
+            .transmit_unlimited(frame.iter().copied().cycle(), length, 1_000_000 / pulse_us)
+            .await;
+
+        self.write_register(Register::DataModul, data_modul)?;
+        self.write_many(Register::BitrateMsb, &bitrate)?;
+        self.write_many(Register::PreambleMsb, &preamble)?;
+
+        result
+    }
+
+    // Sends `length` bytes of `data` at `bitrate` in unlimited length mode, without
+    // sync word, length byte or CRC
+    async fn transmit_unlimited(
+        &mut self,
+        data: impl Iterator<Item = u8>,
+        length: usize,
+        bitrate: u32,
+    ) -> Result<(), Rfm69Error> {
+        // Limited like packets, the pattern or frame occupies the channel all the same
+        let airtime = airtime_ms(length, bitrate);
+        self.check_duty_cycle(airtime).await?;
+
+        self.set_mode(Rfm69Mode::Standby).await?;
+
+        // Save the packet configuration, it is restored once the pattern is sent
+        let sync_config = self.read_register_cached(Register::SyncConfig)?;
+        let packet_config = self.read_register_cached(Register::PacketConfig1)?;
+        let payload_length = self.read_register_cached(Register::PayloadLength)?;
+
+        // Fixed length format with a payload length of 0 selects unlimited length mode
+        self.write_register(Register::SyncConfig, SyncConfiguration::SyncOff.value(1))?;
+        let fixed_length = PacketConfig1 {
+            variable_length: false,
+            dc_free: DcFree::None,
+            crc_on: false,
+            crc_auto_clear_off: false,
+            address_filtering: AddressFiltering::None,
+        };
+        self.write_typed(fixed_length)?;
+        self.write_register(Register::PayloadLength, 0x00)?;
+
+        let result = self.stream(data, length, bitrate).await;
+        self.record_airtime(airtime);
+
+        self.set_mode(Rfm69Mode::Standby).await?;
+        self.write_register(Register::SyncConfig, sync_config)?;
+        self.write_register(Register::PacketConfig1, packet_config)?;
+        self.write_register(Register::PayloadLength, payload_length)?;
+
+        result
+    }
+
+    async fn stream(
+        &mut self,
+        data: impl Iterator<Item = u8>,
+        length: usize,
+        bitrate: u32,
+    ) -> Result<(), Rfm69Error> {
+        let byte_us = 8_000_000u32.div_ceil(bitrate.max(1));
+        self.fill_stream(data, length, byte_us).await?;
+
+        // Wait for the FIFO to drain, giving up after twice the time on air of its contents
+        let timeout_us = 2 * (length.min(RF69_FIFO_SIZE) as u64 + 1) * byte_us as u64;
+        let mut waited_us = 0u64;
+        while IrqFlags2::from_bits(self.read_register(Register::IrqFlags2)?).fifo_not_empty {
+            self.wait_stream(byte_us, &mut waited_us, timeout_us)
+                .await?;
+        }
+
+        // The last byte is still in the shift register once the FIFO is empty
+        self.delay.delay_us(byte_us).await;
+
+        Ok(())
+    }
+
+    // Writes `length` bytes of `data` to the FIFO and starts the transmitter, returns
+    // once the last ones are written
+    async fn fill_stream(
+        &mut self,
+        data: impl Iterator<Item = u8>,
+        length: usize,
+        byte_us: u32,
+    ) -> Result<(), Rfm69Error> {
+        // With FifoLevel the transmitter waits for the FIFO to rise above the threshold,
+        // which fewer bytes never do
+        if self.tx_start_condition == TxStartCondition::FifoLevel
+            && length <= self.fifo_threshold as usize
+        {
+            let level = self.fifo_threshold;
+            self.set_fifo_threshold(level, TxStartCondition::FifoNotEmpty)?;
+            let result = self.refill_stream(data, length, byte_us).await;
+            self.set_fifo_threshold(level, TxStartCondition::FifoLevel)?;
+            return result;
+        }
+        self.refill_stream(data, length, byte_us).await
+    }
+
+    async fn refill_stream(
+        &mut self,
+        mut data: impl Iterator<Item = u8>,
+        length: usize,
+        byte_us: u32,
+    ) -> Result<(), Rfm69Error> {
+        let mut chunk = [0u8; RF69_FIFO_SIZE];
+
+        // The FIFO is polled once per byte sent, giving up after twice the time on air
+        let timeout_us = 2 * (length as u64 + 1) * byte_us as u64;
+        let mut waited_us = 0u64;
+
+        // Start the transmitter as soon as the FIFO is above the threshold, the rest
+        // is written while the first bytes go out
+        let count = length.min(self.fifo_threshold as usize + 1);
+        chunk[..count]
+            .iter_mut()
+            .for_each(|byte| *byte = data.next().unwrap_or(0));
+        self.write_many(Register::Fifo, &chunk[..count])?;
+        let mut remaining = length - count;
+
+        self.set_mode(Rfm69Mode::Tx).await?;
+
+        while remaining > 0 {
+            // Once the FIFO level drops to the threshold there is room for the rest of the FIFO
+            if !IrqFlags2::from_bits(self.read_register(Register::IrqFlags2)?).fifo_level {
+                let count = remaining.min(RF69_FIFO_SIZE - self.fifo_threshold as usize - 1);
+                chunk[..count]
+                    .iter_mut()
+                    .for_each(|byte| *byte = data.next().unwrap_or(0));
+                self.write_many(Register::Fifo, &chunk[..count])?;
+                remaining -= count;
+            } else {
+                self.wait_stream(byte_us, &mut waited_us, timeout_us)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn wait_stream(
+        &mut self,
+        byte_us: u32,
+        waited_us: &mut u64,
+        timeout_us: u64,
+    ) -> Result<(), Rfm69Error> {
+        if *waited_us >= timeout_us {
+            return Err(Rfm69Error::Timeout);
+        }
+        self.delay.delay_us(byte_us).await;
+        *waited_us += byte_us as u64;
+        Ok(())
+    }
+
+    /// Checks PayloadReady. While a packet is being received, the RSSI is sampled once
+    /// SyncAddressMatch is set, see `packet_rssi`.
+    ///
+    /// Also returns true after a FIFO overrun, so `receive` can report and clear it.
+    pub fn is_message_available(&mut self) -> Result<bool, Rfm69Error> {
+        if self.current_mode != Rfm69Mode::Rx {
+            return Err(Rfm69Error::InvalidMode);
+        }
+
+        let (irq_flags1, irq_flags2) = self.read_irq_flags()?;
+
+        if irq_flags1.sync_address_match {
+            if self.latched_rssi.is_none() {
+                self.latched_rssi = Some(-(self.rssi()? as i16));
+            }
+        } else if !irq_flags2.payload_ready {
+            // No packet in progress, e.g. the last one was dropped by the CRC check
+            self.latched_rssi = None;
+        }
+
+        Ok(irq_flags2.payload_ready || irq_flags2.fifo_overrun)
+    }
+
+    /// Reads RegIrqFlags1 and RegIrqFlags2 in a single transaction.
+    pub fn read_irq_flags(&mut self) -> Result<(IrqFlags1, IrqFlags2), Rfm69Error> {
+        let mut flags = [0u8; 2];
+        self.read_many(Register::IrqFlags1, &mut flags)?;
+        Ok((
+            IrqFlags1::from_bits(flags[0]),
+            IrqFlags2::from_bits(flags[1]),
+        ))
+    }
+
+    /// RSSI in dBm of the last packet read by `receive`, sampled when its sync word
+    /// matched rather than after the packet ended. `None` if the packet arrived
+    /// without `is_message_available` polling the radio while it was received.
+    pub fn packet_rssi(&self) -> Option<i16> {
+        self.packet_rssi
+    }
+
+    /// Polls until a packet is available. With an idle policy, see
+    /// `set_idle_policy`, it returns `Rfm69Error::NoMessage` once the radio was
+    /// taken out of Rx.
+    pub async fn wait_for_message(&mut self) -> Result<(), Rfm69Error> {
+        match self.wait_for_message_timeout(None).await? {
+            true => Ok(()),
+            false => Err(Rfm69Error::NoMessage),
+        }
+    }
+
+    /// Polls for a packet like `wait_for_message`, giving up after `timeout_ms` if
+    /// set. Returns whether a packet is available.
+    pub async fn wait_for_message_timeout(
+        &mut self,
+        timeout_ms: Option<u32>,
+    ) -> Result<bool, Rfm69Error> {
+        let timeout_us = timeout_ms.map(|timeout_ms| timeout_ms as u64 * 1000);
+        Ok(self.poll_for_message(timeout_us).await?.is_some())
+    }
+
+    // Like `wait_for_message_timeout`, returning the time waited until a packet
+    // was available, so callers can wait against a deadline across packets
+    pub(crate) async fn poll_for_message(
+        &mut self,
+        timeout_us: Option<u64>,
+    ) -> Result<Option<u64>, Rfm69Error> {
+        if self.idled {
+            self.set_mode(Rfm69Mode::Rx).await?;
+        }
+
+        let idle_timeout_us = self
+            .idle_policy
+            .map(|policy| policy.timeout_ms as u64 * 1000);
+        let mut interval_us = self.poll_intervals.message_us;
+        let mut waited_us = 0u64;
+        while !self.is_message_available()? {
+            if let (Some(policy), Some(idle_timeout_us)) = (self.idle_policy, idle_timeout_us) {
+                if self.rx_idle_us >= idle_timeout_us {
+                    self.set_mode(policy.mode).await?;
+                    self.idled = true;
+                    return Ok(None);
+                }
+            }
+
+            let mut delay_us = interval_us as u64;
+            if let Some(timeout_us) = timeout_us {
+                if waited_us >= timeout_us {
+                    return Ok(None);
+                }
+                delay_us = delay_us.min(timeout_us - waited_us);
+            }
+            if let Some(idle_timeout_us) = idle_timeout_us {
+                delay_us = delay_us.min(idle_timeout_us - self.rx_idle_us);
+            }
+            self.delay.delay_us(delay_us as u32).await;
+            waited_us += delay_us;
+            self.rx_idle_us += delay_us;
+            interval_us = interval_us
+                .saturating_mul(2)
+                .min(self.poll_intervals.message_max_us);
+        }
+        self.rx_idle_us = 0;
+        Ok(Some(waited_us))
+    }
+
+    /// Waits for a packet in Listen mode, where the radio wakes up on its own for a
+    /// short receive window every listen period, then reads it like `receive`.
+    ///
+    /// Packets are only caught reliably when sent with `send_wake` and the same
+    /// `listen` configuration. DIO0 signals PayloadReady, the MCU can sleep until then.
+    pub async fn recv_low_power(
+        &mut self,
+        listen: &ListenConfig,
+        buffer: &mut [u8],
+    ) -> Result<(Header, usize), Rfm69Error> {
+        self.set_mode(Rfm69Mode::Standby).await?;
+
+        // Listen1 to Listen3
+        let listen_registers = [
+            listen.listen1(),
+            listen.idle_coefficient,
+            listen.rx_coefficient,
+        ];
+        self.write_many(Register::Listen1, &listen_registers)?;
+        self.write_register(Register::DioMapping1, RF_DIOMAPPING1_DIO0_01)?;
+
+        let listen_on = OpMode {
+            sequencer_off: false,
+            listen_on: true,
+            listen_abort: false,
+            mode: Rfm69Mode::Standby,
+        };
+        self.write_op_mode(listen_on)?;
+        // The radio wakes up in Rx on its own
+        self.control_front_end(Rfm69Mode::Rx);
+
+        // Listen mode is left even if the pin can't be read
+        let woken = self.wait_for_interrupt().await;
+        self.exit_listen().await?;
+        woken?;
+        self.receive(buffer).await
+    }
+
+    async fn exit_listen(&mut self) -> Result<(), Rfm69Error> {
+        // ListenAbort has to be set in the same write that clears ListenOn
+        let abort = OpMode {
+            sequencer_off: false,
+            listen_on: false,
+            listen_abort: true,
+            mode: Rfm69Mode::Standby,
+        };
+        self.write_op_mode(abort)?;
+        self.write_op_mode(OpMode {
+            listen_abort: false,
+            ..abort
+        })?;
+        while !IrqFlags1::from_bits(self.read_register(Register::IrqFlags1)?).mode_ready {
+            self.delay.delay_us(self.poll_intervals.mode_ready_us).await;
+        }
+
+        self.control_front_end(Rfm69Mode::Standby);
+        self.current_mode = Rfm69Mode::Standby;
+        Ok(())
+    }
+
+    /// Like `receive`, returning the sender and whether the packet was broadcast.
+    ///
+    /// Packets addressed to another node are discarded with `Rfm69Error::NoMessage`,
+    /// in case hardware address filtering let them through.
+    pub async fn receive_from(&mut self, buffer: &mut [u8]) -> Result<Datagram, Rfm69Error> {
+        let (header, length) = self.receive(buffer).await?;
+        let broadcast = header.to == self.broadcast_address || header.is_broadcast();
+        match self.node_address {
+            Some(address) if header.to != address && !broadcast => Err(Rfm69Error::NoMessage),
+            _ => Ok(Datagram {
+                from: header.from,
+                length,
+                broadcast,
+            }),
+        }
+    }
+
+    /// Reads the payload of the received packet into `buffer` and returns its
+    /// header, with the sender, the destination, the id and the flags, along
+    /// with the payload length.
+    ///
+    /// If the payload doesn't fit in `buffer` it is discarded and
+    /// `Rfm69Error::BufferTooSmall` is returned. Packets failing the CRC check are
+    /// discarded with `Rfm69Error::CrcFailure` and the receiver is restarted.
+    pub async fn receive(&mut self, buffer: &mut [u8]) -> Result<(Header, usize), Rfm69Error> {
+        let received = self.read_packet(buffer)?;
+
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            watchdog.clear_timeouts();
+            // Reading the whole packet clears PayloadReady
+            if IrqFlags2::from_bits(self.read_register(Register::IrqFlags2)?).payload_ready {
+                self.recover(StuckCondition::PayloadReady).await?;
+            }
+        }
+
+        Ok(received)
+    }
+
+    /// Reads a packet without the header, e.g. after `set_ert_profile` or
+    /// `set_mysensors_profile` or from a `send_raw` peer, into `buffer` and
+    /// returns its length. The length byte of variable length packets isn't
+    /// copied.
+    pub fn receive_raw(&mut self, buffer: &mut [u8]) -> Result<usize, Rfm69Error> {
+        let result = self.read_raw_packet(buffer);
+        self.stats.record_receive(&result);
+        result
+    }
+
+    fn read_raw_packet(&mut self, buffer: &mut [u8]) -> Result<usize, Rfm69Error> {
+        let flags = IrqFlags2::from_bits(self.read_register(Register::IrqFlags2)?);
+        if flags.fifo_overrun {
+            self.discard_packet()?;
+            return Err(Rfm69Error::FifoOverrun);
+        }
+        if !flags.payload_ready {
+            return Err(Rfm69Error::NoMessage);
+        }
+
+        if !flags.crc_ok && self.crc_enabled()? {
+            self.discard_packet()?;
+            return Err(Rfm69Error::CrcFailure);
+        }
+
+        let length = match self.variable_length()? {
+            true => self.read_register(Register::Fifo)? as usize,
+            false => self.read_register_cached(Register::PayloadLength)? as usize,
+        };
+        if buffer.len() < length {
+            self.discard_packet()?;
+            return Err(Rfm69Error::BufferTooSmall);
+        }
+        self.read_many(Register::Fifo, &mut buffer[..length])?;
+        self.packet_rssi = self.latched_rssi.take();
+
+        Ok(length)
+    }
+
+    fn read_packet(&mut self, buffer: &mut [u8]) -> Result<(Header, usize), Rfm69Error> {
+        let result = self.read_fifo_packet(buffer);
+        self.stats.record_receive(&result);
+        result
+    }
+
+    fn read_fifo_packet(&mut self, buffer: &mut [u8]) -> Result<(Header, usize), Rfm69Error> {
+        let flags = IrqFlags2::from_bits(self.read_register(Register::IrqFlags2)?);
+        if flags.fifo_overrun {
+            // The FIFO holds pieces of several packets
+            self.discard_packet()?;
+            return Err(Rfm69Error::FifoOverrun);
+        }
+        if !flags.payload_ready {
+            return Err(Rfm69Error::NoMessage);
+        }
+
+        if !flags.crc_ok && self.crc_enabled()? {
+            self.discard_packet()?;
+            return Err(Rfm69Error::CrcFailure);
+        }
+
+        let message_len = match self.payload_length {
+            Some(length) => length,
+            None => self.read_register(Register::Fifo)?,
+        };
+        let packet_len = message_len as usize;
+        if !(HEADER_LENGTH..=RF69_FIFO_SIZE).contains(&packet_len) {
+            self.discard_packet()?;
+            return Err(Rfm69Error::MalformedPacket);
+        }
+
+        // Header, payload and trailer in one burst, the sooner the FIFO is empty
+        // the less likely the next packet overruns it
+        let mut packet = [0u8; RF69_FIFO_SIZE];
+        self.read_many(Register::Fifo, &mut packet[..packet_len])?;
+        let (header, fifo_payload) = packet[..packet_len].split_at(HEADER_LENGTH);
+        let header: [u8; HEADER_LENGTH] = header.try_into().unwrap();
+
+        if let Some(filter) = self.packet_filter {
+            let rssi_dbm = match self.latched_rssi {
+                Some(rssi_dbm) => rssi_dbm,
+                None => -(self.rssi()? as i16),
+            };
+            if !filter(&Header::from_bytes(header), rssi_dbm) {
+                self.discard_packet()?;
+                return Err(Rfm69Error::NoMessage);
+            }
+        }
+
+        let Some(payload_len) = fifo_payload.len().checked_sub(self.trailer_length()) else {
+            self.discard_packet()?;
+            return Err(Rfm69Error::CrcFailure);
+        };
+        if buffer.len() < payload_len {
+            return Err(Rfm69Error::BufferTooSmall);
+        }
+
+        let (payload, trailer) = fifo_payload.split_at(payload_len);
+        buffer[..payload_len].copy_from_slice(payload);
+        if self.software_crc {
+            let mut expected = Crc32::new();
+            expected.update(&header);
+            expected.update(payload);
+            if expected.finish().to_be_bytes() != trailer[..SOFTWARE_CRC_LENGTH] {
+                return Err(Rfm69Error::CrcFailure);
+            }
+        }
+        if let Some(mac) = &self.authentication {
+            if authenticate(mac, &header, payload) != trailer[trailer.len() - MAC_LENGTH..] {
+                return Err(Rfm69Error::AuthenticationFailure);
+            }
+        }
+        let header = Header::from_bytes(header);
+        self.packet_rssi = self.latched_rssi.take();
+
+        if self.link_stats.is_some() {
+            let rssi_dbm = match self.packet_rssi {
+                Some(rssi_dbm) => rssi_dbm,
+                None => -(self.rssi()? as i16),
+            };
+            let fei_hz = self.fei_hz()?;
+            if let Some(link_stats) = self.link_stats.as_mut() {
+                link_stats.record(header.from, header.id, rssi_dbm, fei_hz);
+            }
+        }
+
+        Ok((header, payload_len))
+    }
+
+    fn crc_enabled(&mut self) -> Result<bool, Rfm69Error> {
+        Ok(self.read_typed::<PacketConfig1>()?.crc_on)
+    }
+
+    // From the register, profiles like `set_ert_profile` bypass `payload_length`
+    fn variable_length(&mut self) -> Result<bool, Rfm69Error> {
+        Ok(self.read_typed::<PacketConfig1>()?.variable_length)
+    }
+
+    /// Restarts the receiver on its own after PayloadReady once the FIFO is read,
+    /// instead of waiting for a mode change or RestartRx. Needed by receivers of
+    /// back to back packets.
+    pub fn set_auto_rx_restart(&mut self, enabled: bool) -> Result<(), Rfm69Error> {
+        self.modify_packet_config2(|packet_config| packet_config.auto_rx_restart_on = enabled)
+    }
+
+    /// Delay between reading the last byte of a packet and the automatic receiver
+    /// restart, 2^`exponent` bit periods, up to 2^11. Set it to cover the ramp
+    /// down of the transmitter. `None` restarts immediately.
+    pub fn set_inter_packet_rx_delay(&mut self, exponent: Option<u8>) -> Result<(), Rfm69Error> {
+        // 12 and above disable the delay
+        const NO_DELAY: u8 = 0x0F;
+        let inter_packet_rx_delay = match exponent {
+            Some(exponent @ 0..=11) => exponent,
+            Some(_) => return Err(Rfm69Error::ConfigurationError),
+            None => NO_DELAY,
+        };
+        self.modify_packet_config2(|packet_config| {
+            packet_config.inter_packet_rx_delay = inter_packet_rx_delay
+        })
+    }
+
+    // Like `modify`, remembering the value for `wake` and `recover`
+    fn modify_packet_config2(
+        &mut self,
+        update: impl FnOnce(&mut PacketConfig2),
+    ) -> Result<(), Rfm69Error> {
+        let mut packet_config = self.read_typed::<PacketConfig2>()?;
+        update(&mut packet_config);
+        self.write_typed(packet_config)?;
+        self.packet_config2 = PacketConfig2 {
+            restart_rx: false,
+            ..packet_config
+        };
+        Ok(())
+    }
+
+    /// Encrypts the payload of every packet with the hardware AES-128 engine, both
+    /// ends need the same key. `None` turns encryption off. Encryption alone
+    /// doesn't stop replayed packets, see `session::SecureSession`.
+    pub fn set_encryption_key(&mut self, key: Option<&[u8; 16]>) -> Result<(), Rfm69Error> {
+        if let (Some(_), Some(length)) = (key, self.payload_length) {
+            if !self.fixed_length_valid(length, true, self.node_address.is_some()) {
+                return Err(Rfm69Error::ConfigurationError);
+            }
+        }
+        if let Some(key) = key {
+            self.write_many(Register::AesKey1, key)?;
+        }
+        self.modify_packet_config2(|packet_config| packet_config.aes_on = key.is_some())?;
+        self.encrypted = key.is_some();
+        self.key_lost = false;
+        Ok(())
+    }
+
+    /// Turns encryption off and overwrites the key registers with zeros, so the
+    /// key can't be read back from the radio. `set_encryption_key(None)` leaves
+    /// the key in the radio. The driver keeps no copy of this key in RAM.
+    pub fn clear_encryption_key(&mut self) -> Result<(), Rfm69Error> {
+        self.set_encryption_key(None)?;
+        self.write_many(Register::AesKey1, &[0; 16])
+    }
+
+    fn aes_on(&mut self) -> Result<bool, Rfm69Error> {
+        Ok(self.read_typed::<PacketConfig2>()?.aes_on)
+    }
+
+    /// Flushes the FIFO and restarts the receiver.
+    fn discard_packet(&mut self) -> Result<(), Rfm69Error> {
+        self.flush_fifo()?;
+        self.restart_rx()
+    }
+
+    /// Empties the FIFO and clears the FifoOverrun flag, e.g. after falling behind
+    /// on reading packets.
+    pub fn flush_fifo(&mut self) -> Result<(), Rfm69Error> {
+        // Writing FifoOverrun clears the FIFO
+        self.write_register(Register::IrqFlags2, RF_IRQFLAGS2_FIFOOVERRUN)
+    }
+
+    /// Sets RestartRx, dropping the packet being received and waiting for the next
+    /// preamble right away, without going through Standby. Only useful in Rx mode.
+    pub fn restart_rx(&mut self) -> Result<(), Rfm69Error> {
+        self.modify(|packet_config: &mut PacketConfig2| packet_config.restart_rx = true)?;
+        self.latched_rssi = None;
+        Ok(())
+    }
+
+    pub fn rssi(&mut self) -> Result<u8, Rfm69Error> {
+        let rssi = self.read_register(Register::RssiValue)?;
+        Ok(rssi / 2)
+    }
+
+    /// Sets the RSSI level in dBm, -127 to 0, above which the receiver starts
+    /// looking for a preamble.
+    pub fn set_rssi_threshold(&mut self, threshold_dbm: i16) -> Result<(), Rfm69Error> {
+        if !(-127..=0).contains(&threshold_dbm) {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+        self.write_register(Register::RssiThresh, (-threshold_dbm * 2) as u8)
+    }
+
+    /// Measures the noise floor as the average of `samples` RSSI measurements and
+    /// sets the RSSI threshold `margin_db` above it. Run it while the channel is
+    /// idle. Returns the new threshold in dBm, the radio goes back to the current
+    /// mode afterwards.
+    pub async fn calibrate_rssi_threshold(
+        &mut self,
+        samples: u8,
+        margin_db: u8,
+    ) -> Result<i16, Rfm69Error> {
+        if samples == 0 {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+
+        let mode = self.current_mode;
+        self.set_mode(Rfm69Mode::Rx).await?;
+
+        // In -0.5 dBm steps, like RegRssiValue
+        let mut total = 0u32;
+        let mut measured = 0u32;
+        for _ in 0..samples {
+            self.write_register(Register::RssiConfig, RF_RSSI_START)?;
+            if self
+                .wait_for_flag(Register::RssiConfig, RF_RSSI_DONE)
+                .await?
+            {
+                total += self.read_register(Register::RssiValue)? as u32;
+                measured += 1;
+            }
+        }
+        self.set_mode(mode).await?;
+
+        // The radio never completed a measurement
+        if measured == 0 {
+            return Err(Rfm69Error::Timeout);
+        }
+        let threshold = (total / measured).saturating_sub(margin_db as u32 * 2) as u8;
+        self.write_register(Register::RssiThresh, threshold)?;
+        Ok(-(threshold as i16) / 2)
+    }
+
+    /// Frequency error of the last FEI measurement in Hz.
+    pub fn fei_hz(&mut self) -> Result<i32, Rfm69Error> {
+        let mut fei = [0u8; 2];
+        self.read_many(Register::FeiMsb, &mut fei)?;
+        let steps = i16::from_be_bytes(fei) as i64;
+        Ok(((steps * RF69_FXOSC_HZ as i64) >> 19) as i32)
+    }
+
+    /// Drops received packets `filter` returns false for right after their header
+    /// is read, before they are returned by `receive`, reported by `on_interrupt`
+    /// or queued, so junk on a busy channel doesn't wake the application. Dropped
+    /// packets are reported as `Rfm69Error::NoMessage`. Without a latched RSSI,
+    /// filtering costs an extra SPI read per packet.
+    pub fn set_packet_filter(&mut self, filter: Option<PacketFilter>) {
+        self.packet_filter = filter;
+    }
+
+    /// Enables or disables per peer link statistics, updated by every received
+    /// packet. Enabling them costs two extra SPI reads per packet.
+    pub fn set_link_stats(&mut self, enabled: bool) {
+        self.link_stats = match enabled {
+            true => Some(self.link_stats.take().unwrap_or_default()),
+            false => None,
+        };
+    }
+
+    pub fn link_stats(&self) -> Option<&LinkStats> {
+        self.link_stats.as_ref()
+    }
+
+    /// Packets sent and received, and the errors seen since the counters were last reset.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Clears the counters, returning their values, e.g. to report them periodically.
+    pub fn reset_stats(&mut self) -> Stats {
+        core::mem::take(&mut self.stats)
+    }
+
+    pub(crate) fn record_retransmission(&mut self) {
+        self.stats.record_retransmission();
+    }
+
+    pub(crate) fn write_register(
+        &mut self,
+        register: Register,
+        value: u8,
+    ) -> Result<(), Rfm69Error> {
+        self.write_many(register, &[value])?;
+        Ok(())
+    }
+
+    pub(crate) fn read_register(&mut self, register: Register) -> Result<u8, Rfm69Error> {
+        let mut buffer = [0u8; 1];
+        self.spi
+            .read_many(register, &mut buffer)
+            .map_err(|_| Rfm69Error::SpiReadError)?;
+        self.shadow.update(register, &buffer);
+        Ok(buffer[0])
+    }
+
+    /// Decodes a typed register, read from the shadow when possible. Reserved
+    /// values are `Rfm69Error::ConfigurationError`.
+    pub(crate) fn read_typed<R: TypedRegister>(&mut self) -> Result<R, Rfm69Error> {
+        R::decode(self.read_register_cached(R::REGISTER)?).ok_or(Rfm69Error::ConfigurationError)
+    }
+
+    pub(crate) fn write_typed<R: TypedRegister>(&mut self, value: R) -> Result<(), Rfm69Error> {
+        self.write_register(R::REGISTER, value.encode())
+    }
+
+    /// Reads a typed register, lets `update` change some of its fields and writes
+    /// it back, e.g. `modify(|lna: &mut Lna| lna.gain_select = 1)`.
+    pub(crate) fn modify<R: TypedRegister>(
+        &mut self,
+        update: impl FnOnce(&mut R),
+    ) -> Result<(), Rfm69Error> {
+        let mut value = self.read_typed()?;
+        update(&mut value);
+        self.write_typed(value)
+    }
+
+    /// Reads a register from the shadow, falling back to SPI for volatile or unknown registers.
+    fn read_register_cached(&mut self, register: Register) -> Result<u8, Rfm69Error> {
+        match self.shadow.get(register) {
+            Some(value) => Ok(value),
+            None => self.read_register(register),
+        }
+    }
+
+    /// Forgets the register shadow, needed after changing registers through `spi` directly.
+    pub fn invalidate_register_cache(&mut self) {
+        self.shadow.invalidate();
+    }
+
+    /// Direct register access, for settings the typed API doesn't cover yet.
+    pub fn raw(&mut self) -> Raw<'_, SPI, RESET, INTR, D> {
+        Raw::new(self)
+    }
+
+    pub(crate) fn write_many(
+        &mut self,
+        register: Register,
+        values: &[u8],
+    ) -> Result<(), Rfm69Error> {
+        self.spi
+            .write_many(register, values)
+            .map_err(|_| Rfm69Error::SpiWriteError)?;
+        self.shadow.update(register, values);
+        Ok(())
+    }
+
+    pub(crate) fn read_many(
+        &mut self,
+        register: Register,
+        buffer: &mut [u8],
+    ) -> Result<(), Rfm69Error> {
+        self.spi
+            .read_many(register, buffer)
+            .map_err(|_| Rfm69Error::SpiReadError)?;
+        self.shadow.update(register, buffer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::settings::{ContinuousDagc, SyncConfiguration};
+    use crate::temperature::TemperatureEvent;
+
+    use super::*;
+    use crate::test_utils::{
+        check_expectations, read_many, read_register, send, setup_rfm, write_many, write_register,
+        DelayTransaction, GpioTransaction, SpiTransaction, State,
+    };
+    use embedded_hal_mock::eh1::MockError;
+    use std::io::ErrorKind;
+
+    #[tokio::test]
+    async fn test_release() {
+        let mut rfm = setup_rfm();
+        rfm.spi.update_expectations(&[
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Version.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x24]),
+            SpiTransaction::transaction_end(),
+        ]);
+
+        let (mut spi, mut reset_pin, mut intr_pin, mut delay) = rfm.release();
+        // The SPI device is usable on its own
+        let mut version = [0x00];
+        spi.read_many(Register::Version, &mut version).unwrap();
+        assert_eq!(version, [0x24]);
+
+        spi.done();
+        reset_pin.done();
+        intr_pin.done();
+        delay.done();
+    }
+
+    #[tokio::test]
+    async fn test_reset() {
+        let mut rfm = setup_rfm();
+
+        let reset_expectations = [
+            GpioTransaction::set(State::High),
+            GpioTransaction::set(State::Low),
+        ];
+        rfm.reset_pin.update_expectations(&reset_expectations);
+
+        let delay_expectations = [
+            DelayTransaction::delay_us(100),
+            DelayTransaction::delay_ms(5),
+        ];
+        rfm.delay.update_expectations(&delay_expectations);
+
+        rfm.reset().await.unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_read_temperature() {
+        let mut rfm = setup_rfm();
+
+        let temperature_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Temp1.write()),
+            SpiTransaction::write(0x08),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Temp1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x04]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Temp1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Temp2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x8D]),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&temperature_expectations);
+
+        let delay_expectations = [DelayTransaction::delay_ms(10)];
+        rfm.delay.update_expectations(&delay_expectations);
+
+        let temperature = rfm.read_temperature().await.unwrap();
+
+        assert_eq!(temperature, 25.0);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_self_test() {
+        let mut rfm = setup_rfm();
+
+        let read = |register: Register, value: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(register.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![value]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let write = |register: Register, values: Vec<u8>| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(register.write()),
+                SpiTransaction::write_vec(values),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+
+        let spi_expectations: Vec<SpiTransaction<u8>> = [
+            read(Register::Version, 0x24),
+            write(Register::Osc1, vec![0x80]),
+            read(Register::Osc1, 0x01),
+            read(Register::Osc1, 0x41),
+            write(Register::Temp1, vec![0x08]),
+            read(Register::Temp1, 0x00),
+            read(Register::Temp2, 0x8D),
+            // Rx for the RSSI measurement
+            write(Register::OpMode, vec![0x10]),
+            read(Register::IrqFlags1, 0x80),
+            write(Register::RssiConfig, vec![0x01]),
+            read(Register::RssiConfig, 0x02),
+            read(Register::RssiValue, 0xC8),
+            write(Register::OpMode, vec![0x04]),
+            read(Register::IrqFlags1, 0x80),
+            write(Register::IrqFlags2, vec![0x10]),
+            write(Register::Fifo, vec![0x55, 0xAA, 0x00, 0xFF]),
+        ]
+        .into_iter()
+        .flatten()
+        .chain([
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00; 4], vec![0x55, 0xAA, 0x00, 0xFF]),
+            SpiTransaction::transaction_end(),
+        ])
+        .chain(write(Register::IrqFlags2, vec![0x10]))
+        .collect();
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let delay_expectations = [DelayTransaction::delay_ms(1)];
+        rfm.delay.update_expectations(&delay_expectations);
+
+        let report = rfm.self_test().await.unwrap();
+        assert_eq!(
+            report,
+            SelfTestReport {
+                version: 0x24,
+                chip_version: ChipVersion::from_register(0x24),
+                rc_calibrated: true,
+                temperature_celsius: 25.0,
+                rssi_dbm: Some(-100),
+                fifo_ok: true,
+            }
+        );
+        assert!(report.passed());
+        assert_eq!(rfm.current_mode, Rfm69Mode::Standby);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_monitor_temperature() {
+        let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Rx;
+
+        let set_mode = |op_mode: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::OpMode.write()),
+                SpiTransaction::write(op_mode),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags1.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let read_temperature = |temp2: u8| {
+            let mut expectations = set_mode(0x04).to_vec();
+            expectations.extend([
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Temp1.write()),
+                SpiTransaction::write(0x08),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Temp1.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Temp2.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![temp2]),
+                SpiTransaction::transaction_end(),
+            ]);
+            // Back to Rx
+            expectations.extend(set_mode(0x10));
+            expectations
+        };
+
+        let mut spi_expectations = vec![];
+        // 30, 40, 41 and 39 degrees
+        for temp2 in [136, 126, 125, 127] {
+            spi_expectations.extend(read_temperature(temp2));
+        }
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.delay
+            .update_expectations(&vec![DelayTransaction::delay_ms(60_000); 3]);
+
+        let mut monitor = rfm.monitor_temperature(60_000, 40.0);
+        assert_eq!(monitor.next().await, Ok(TemperatureEvent::Reading(30.0)));
+        assert_eq!(
+            monitor.next().await,
+            Ok(TemperatureEvent::AboveThreshold(40.0))
+        );
+        assert_eq!(monitor.next().await, Ok(TemperatureEvent::Reading(41.0)));
+        assert_eq!(
+            monitor.next().await,
+            Ok(TemperatureEvent::BelowThreshold(39.0))
+        );
+        assert_eq!(rfm.current_mode, Rfm69Mode::Rx);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_fifo_threshold() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FifoThresh.write()),
+            SpiTransaction::write(0x8F),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FifoThresh.write()),
+            SpiTransaction::write(0x20),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_fifo_threshold(15, TxStartCondition::FifoNotEmpty)
+            .unwrap();
+        rfm.set_fifo_threshold(32, TxStartCondition::FifoLevel)
+            .unwrap();
+        assert_eq!(rfm.fifo_threshold, 32);
+        assert_eq!(
+            rfm.set_fifo_threshold(66, TxStartCondition::FifoLevel),
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_dagc() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestDagc.write()),
+            SpiTransaction::write(ContinuousDagc::ImprovedLowBeta1 as u8),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_dagc(ContinuousDagc::ImprovedLowBeta1).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_front_end_control() {
+        static MODES: std::sync::Mutex<Vec<Rfm69Mode>> = std::sync::Mutex::new(Vec::new());
+        fn control(mode: Rfm69Mode) {
+            MODES.lock().unwrap().push(mode);
+        }
+
+        let mut rfm = setup_rfm();
+        rfm.set_front_end_control(Some(control));
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DioMapping1.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x0C),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x10),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.switch_mode(Rfm69Mode::Tx).unwrap();
+        assert_eq!(*MODES.lock().unwrap(), [Rfm69Mode::Tx]);
+        rfm.switch_mode(Rfm69Mode::Rx).unwrap();
+        assert_eq!(*MODES.lock().unwrap(), [Rfm69Mode::Tx, Rfm69Mode::Rx]);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_raw() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestAfc.write()),
+            SpiTransaction::write(0x12),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Listen1.write()),
+            SpiTransaction::write_vec(vec![0x92, 0xF5, 0x20]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestAfc.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x12]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Listen1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00; 3], vec![0x92, 0xF5, 0x20]),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let mut raw = rfm.raw();
+        raw.write_register(Register::TestAfc, 0x12).unwrap();
+        raw.write_many(Register::Listen1, &[0x92, 0xF5, 0x20])
+            .unwrap();
+        assert_eq!(raw.read_register(Register::TestAfc), Ok(0x12));
+        let mut listen = [0u8; 3];
+        raw.read_many(Register::Listen1, &mut listen).unwrap();
+        assert_eq!(listen, [0x92, 0xF5, 0x20]);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_modify() {
+        let mut rfm = setup_rfm();
+        let spi_expectations = [
+            read_register(Register::Lna, 0x88),
+            write_register(Register::Lna, 0x89),
+            // The second time from the register shadow
+            write_register(Register::Lna, 0x8A),
+            read_register(Register::PacketConfig1, 0x60),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let mut raw = rfm.raw();
+        raw.modify(|lna: &mut Lna| lna.gain_select = 1).unwrap();
+        raw.modify(|lna: &mut Lna| lna.gain_select += 1).unwrap();
+        // Reserved DcFree value
+        assert_eq!(
+            raw.read_typed::<PacketConfig1>(),
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_sensitivity_boost() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestLna.write()),
+            SpiTransaction::write(0x2D),
+            SpiTransaction::transaction_end(),
+            // Applied again when the receiver is turned on
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestLna.write()),
+            SpiTransaction::write(0x2D),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x10),
+            SpiTransaction::transaction_end(),
+            // But not for other modes
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestLna.write()),
+            SpiTransaction::write(0x1B),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_sensitivity_boost(true).unwrap();
+        assert!(rfm.sensitivity_boost());
+        rfm.switch_mode(Rfm69Mode::Rx).unwrap();
+        rfm.switch_mode(Rfm69Mode::Standby).unwrap();
+        rfm.set_sensitivity_boost(false).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_power() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_sync_words() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.write()),
+            SpiTransaction::write_vec(vec![184, 1, 2, 3, 4, 5, 6, 7, 8]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let sync_words = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        rfm.set_sync_words(
+            SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
+            &sync_words,
+        )
+        .unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_network_id() {
+        let mut rfm = setup_rfm();
+
+        let sync_words = network_id::sync_words(b"garden");
+        let mut expected = vec![0x90];
+        expected.extend_from_slice(&sync_words[..3]);
+        expected.resize(9, 0);
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.write()),
+            SpiTransaction::write_vec(expected),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_network_id(b"garden", 3).unwrap();
+        assert_eq!(
+            rfm.set_network_id(b"garden", 1),
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_sync_words_clamp() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.write()),
+            SpiTransaction::write_vec(vec![191, 1, 2, 3, 4, 5, 6, 7, 8]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let sync_words = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+
+        // sync_tolerance is clamped to 7
+        rfm.set_sync_words(
+            SyncConfiguration::FifoFillAuto { sync_tolerance: 14 },
+            &sync_words,
+        )
+        .unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_sync_words_too_long() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let sync_words = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09];
+        assert_eq!(
+            rfm.set_sync_words(
+                SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
+                &sync_words
+            ),
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_sync_words_empty() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let sync_words = [];
+        assert_eq!(
+            rfm.set_sync_words(
+                SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
+                &sync_words
+            ),
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_modem_config() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DataModul.write()),
+            SpiTransaction::write_vec(vec![0x00, 0x3e, 0x80, 0x00, 0x52]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::RxBw.write()),
+            SpiTransaction::write_vec(vec![0xf4, 0xf4]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0xd0),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_modem_config(ModemConfigChoice::FskRb2Fd5).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_fsk_modulation() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::BitrateMsb.write()),
+            SpiTransaction::write_vec(vec![0x06, 0x83, 0x02, 0x75]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::RxBw.write()),
+            SpiTransaction::write_vec(vec![0xeb, 0xeb]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let modulation = FskModulation {
+            bitrate: 19_200,
+            deviation_hz: 38_400,
+            rx_bandwidth_hz: 50_000,
+        };
+        rfm.set_fsk_modulation(modulation).unwrap();
+        assert_eq!(rfm.bitrate(), 19_200);
+
+        // The preset's 41.7 kHz bandwidth is too narrow for 38.4 kHz + 9.6 kHz
+        let too_narrow = FskModulation {
+            rx_bandwidth_hz: 40_000,
+            ..modulation
+        };
+        assert_eq!(
+            rfm.set_fsk_modulation(too_narrow),
+            Err(Rfm69Error::InvalidModulation(
+                ModulationError::RxBandwidthTooNarrow
+            ))
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_packet_config2() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x02),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x03]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x53),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_auto_rx_restart(true).unwrap();
+        // AesOn is left alone
+        rfm.set_inter_packet_rx_delay(Some(5)).unwrap();
+        assert_eq!(
+            rfm.set_inter_packet_rx_delay(Some(12)),
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_software_crc() {
+        let mut rfm = setup_rfm();
+        rfm.op_mode = OpMode::from_bits(0xC4).unwrap();
+        rfm.set_software_crc(true);
+        assert_eq!(rfm.max_payload_length(), 57);
+
+        let header = [0x02, 0x01, 0x00, 0x00];
+        let crc = Crc32::checksum(&[0x02, 0x01, 0x00, 0x00, b'H', b'i']).to_be_bytes();
+        let mut fifo = vec![10];
+        fifo.extend_from_slice(&header);
+        fifo.extend_from_slice(b"Hi");
+        fifo.extend_from_slice(&crc);
+
+        let receive = |crc: [u8; 4]| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags2.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Fifo.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![10]),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Fifo.read()),
+                SpiTransaction::transfer_in_place(
+                    vec![0x00; 10],
+                    [header.to_vec(), b"Hi".to_vec(), crc.to_vec()].concat(),
+                ),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let mut corrupted = crc;
+        corrupted[3] ^= 0x01;
+
+        let spi_expectations = [
+            vec![
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Fifo.write()),
+                SpiTransaction::write_vec(fifo),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::DioMapping1.write()),
+                SpiTransaction::write(0x00),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::OpMode.write()),
+                SpiTransaction::write(0xCC),
+                SpiTransaction::transaction_end(),
+            ],
+            receive(crc).to_vec(),
+            receive(corrupted).to_vec(),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let header = Header::from_bytes(header);
+        rfm.start_transmit(header, b"Hi").unwrap();
+        assert_eq!(
+            rfm.start_transmit(header, &[0; 58]),
+            Err(Rfm69Error::MessageTooLarge)
+        );
+
+        let mut buffer = [0u8; 2];
+        assert_eq!(rfm.receive(&mut buffer).await, Ok((header, 2)));
+        assert_eq!(&buffer, b"Hi");
+        let mut buffer = [0u8; 2];
+        assert_eq!(rfm.receive(&mut buffer).await, Err(Rfm69Error::CrcFailure));
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_encryption_key() {
+        let mut rfm = setup_rfm();
+
+        let key = *b"0123456789abcdef";
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::AesKey1.write()),
+            SpiTransaction::write_vec(key.to_vec()),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x02]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x03),
+            SpiTransaction::transaction_end(),
+            // Turning encryption off only clears AesOn
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x03]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x02),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        // The AES engine takes 64 bytes after the length byte, 65 with an address byte
+        assert_eq!(rfm.max_payload_length(), 61);
+        rfm.set_encryption_key(Some(&key)).unwrap();
+        assert_eq!(rfm.max_payload_length(), 60);
+        assert_eq!(rfm.shadow.get(Register::AesKey1), None);
+        assert_eq!(rfm.shadow.get(Register::AesKey16), None);
+        rfm.node_address = Some(0x05);
+        assert_eq!(rfm.max_payload_length(), 61);
+        rfm.set_encryption_key(None).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_clear_encryption_key() {
+        let mut rfm = setup_rfm();
+        rfm.encrypted = true;
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x03]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x02),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::AesKey1.write()),
+            SpiTransaction::write_vec(vec![0; 16]),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.clear_encryption_key().unwrap();
+        assert!(!rfm.encrypted);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_restart_rx() {
+        let mut rfm = setup_rfm();
+        rfm.latched_rssi = Some(-80);
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x02]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x06),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.restart_rx().unwrap();
+        assert_eq!(rfm.latched_rssi, None);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_preamble_length() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PreambleMsb.write()),
+            SpiTransaction::write_vec(vec![0x00, 0xFF]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_preamble_length(255).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_get_revision() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Version.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x24]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let revision = rfm.read_revision().unwrap();
+        assert_eq!(revision, 0x24);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_frequency() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FrfMsb.write()),
+            SpiTransaction::write_vec(vec![0xE4, 0xC0, 0x00]),
+            SpiTransaction::transaction_end(),
+            // Between two synthesizer steps
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FrfMsb.write()),
+            SpiTransaction::write_vec(vec![0xD9, 0x13, 0x33]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_frequency(Frequency::from_mhz(915)).unwrap();
+        rfm.set_frequency(Frequency::from_khz(868_300)).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_trim_frequency() {
+        let mut rfm = setup_rfm();
+        rfm.set_temperature_compensation(Some(TemperatureCompensation {
+            reference_celsius: 25.0,
+            ppm_per_celsius: -2.0,
+        }));
+
+        let read_temperature = |temp2: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Temp1.write()),
+                SpiTransaction::write(0x08),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Temp1.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Temp2.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![temp2]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+
+        // 45 degrees, 40 ppm above 0xE4C000 for 915 MHz
+        let mut spi_expectations = read_temperature(121).to_vec();
+        spi_expectations.extend([
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FrfMsb.write()),
+            SpiTransaction::write_vec(vec![0xE4, 0xC2, 0x58]),
+            SpiTransaction::transaction_end(),
+        ]);
+        // Same temperature, FRF is left alone
+        spi_expectations.extend(read_temperature(121));
+        rfm.spi.update_expectations(&spi_expectations);
+
+        assert_eq!(rfm.trim_frequency().await, Ok(40.0));
+        assert_eq!(rfm.trim_frequency().await, Ok(40.0));
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_tx_power() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PaLevel.write()),
+            SpiTransaction::write(0x50),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_tx_power(TxPowerDbm::new(-2)).unwrap();
+        assert_eq!(rfm.tx_power, -2);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_set_tx_power_low_power_variant() {
+        let mut rfm = setup_rfm();
+        rfm.set_variant(Rfm69Variant::Rfm69W);
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PaLevel.write()),
+            SpiTransaction::write(0x80),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        // PA0 only, and no boost on the low power modules
+        rfm.set_tx_power(TxPowerDbm::new(-18)).unwrap();
+        assert_eq!(
+            rfm.set_tx_power(TxPowerDbm::new(14)),
+            Err(Rfm69Error::TxPowerOutOfRange)
+        );
+        assert_eq!(rfm.tx_power, -18);
+        assert!(!rfm.pa_boost());
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_sx1231_tx_power() {
+        let mut rfm = setup_rfm();
+        rfm.chip_version = ChipVersion::from_register(0x23);
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PaLevel.write()),
+            SpiTransaction::write(0x7F),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        // No +20 dBm mode without the H
+        assert_eq!(rfm.tx_power_range(), -2..=17);
+        assert_eq!(
+            rfm.set_tx_power(TxPowerDbm::new(18)),
+            Err(Rfm69Error::TxPowerOutOfRange)
+        );
+        rfm.set_tx_power(TxPowerDbm::new(17)).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_chip_version() {
+        let version = ChipVersion::from_register(0x24).unwrap();
+        assert_eq!(version.full_revision(), 2);
+        assert_eq!(version.metal_mask_revision(), 4);
+        assert!(version.has_high_power_boost());
+
+        assert!(!ChipVersion::from_register(0x21)
+            .unwrap()
+            .has_high_power_boost());
+        assert_eq!(ChipVersion::from_register(0x00), None);
+        assert_eq!(ChipVersion::from_register(0xFF), None);
+    }
+
+    #[test]
+    fn test_region_validation() {
+        let mut rfm = setup_rfm();
+        rfm.set_region(Some(Region::Eu868));
+
+        assert_eq!(
+            rfm.set_frequency(Frequency::from_mhz(915)),
+            Err(Rfm69Error::FrequencyOutOfRange)
+        );
+        assert_eq!(
+            rfm.set_tx_power(TxPowerDbm::new(20)),
+            Err(Rfm69Error::TxPowerOutOfRange)
+        );
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FrfMsb.write()),
+            SpiTransaction::write_vec(vec![0xD9, 0x00, 0x00]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_frequency(Frequency::from_mhz(868)).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_sleep() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.sleep().await.unwrap();
+        assert_eq!(rfm.current_mode, Rfm69Mode::Sleep);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_power_down() {
+        let mut rfm = setup_rfm();
+        // Left in Listen mode by a cancelled `recv_low_power`
+        rfm.op_mode = OpMode::from_bits(0x44).unwrap();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DioMapping2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x05]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DioMapping2.write()),
+            SpiTransaction::write(0x07),
+            SpiTransaction::transaction_end(),
+            // ListenAbort, then Standby
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x24),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.power_down().await.unwrap();
+        assert_eq!(rfm.current_mode, Rfm69Mode::Sleep);
+        assert!(!rfm.op_mode.listen_on);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_wake() {
+        let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Sleep;
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Version.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x24]),
+            SpiTransaction::transaction_end(),
+            // Back to Standby
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            // Read back the configuration
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DataModul.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+                vec![0x01, 0x00, 0x80, 0x10, 0x00, 0xE4, 0xC0, 0x00],
+            ),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PreambleMsb.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00, 0x00, 0x00, 0x00, 0x00],
+                vec![0x00, 0x04, 0x88, 0x2D, 0xD4],
+            ),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0xD0]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PaLevel.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x5F]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x02]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&[
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Version.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0xFF]),
+            SpiTransaction::transaction_end(),
+        ]);
+        assert_eq!(
+            rfm.wake().await,
+            Err(Rfm69Error::ModuleNotFound { got: 0xFF })
+        );
+        assert_eq!(rfm.current_mode, Rfm69Mode::Sleep);
+        rfm.spi.done();
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.wake().await.unwrap();
+        assert_eq!(rfm.current_mode, Rfm69Mode::Standby);
+
+        check_expectations(&mut rfm);
+    }
+
+    // Written by `write_configuration` with the default configuration
+    fn write_configuration_expectations() -> Vec<SpiTransaction<u8>> {
+        vec![
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DataModul.write()),
+            SpiTransaction::write_vec(vec![0x01, 0x00, 0x80, 0x10, 0x00, 0xE4, 0xC0, 0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PaLevel.write()),
+            SpiTransaction::write(0x5F),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Lna.write()),
+            SpiTransaction::write_vec(vec![0x88, 0xE0, 0xE0]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PreambleMsb.write()),
+            SpiTransaction::write_vec(vec![
+                0x00, 0x04, 0x88, 0x2D, 0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xD0, 0x41,
+            ]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FifoThresh.write()),
+            SpiTransaction::write(0x8F),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestPa1.write()),
+            SpiTransaction::write(0x55),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestPa2.write()),
+            SpiTransaction::write(0x70),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestDagc.write()),
+            SpiTransaction::write(0x30),
+            SpiTransaction::transaction_end(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_wake_restores_configuration() {
+        let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Sleep;
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Version.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x24]),
+            SpiTransaction::transaction_end(),
+            // Back to Standby
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            // Registers were reset
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DataModul.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+                vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ),
+            SpiTransaction::transaction_end(),
+        ]
+        .into_iter()
+        // Restore the configuration
+        .chain(write_configuration_expectations())
+        .chain(write_register(Register::PacketConfig2, 0x02))
+        .collect::<Vec<_>>();
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.wake().await.unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_wake_loses_encryption_key() {
+        let mut rfm = setup_rfm();
+        let key = [0x42; 16];
+
+        let wake = [
+            read_register(Register::Version, 0x24).to_vec(),
+            write_register(Register::OpMode, 0x04).to_vec(),
+            read_register(Register::IrqFlags1, 0x80).to_vec(),
+            // The bitrate and everything after it was reset
+            read_many(Register::DataModul, &[0x00; 8]).to_vec(),
+            write_configuration_expectations(),
+            write_register(Register::RssiThresh, 0xC8).to_vec(),
+            // Auto restart as configured, AES off without the key
+            write_register(Register::PacketConfig2, 0x52).to_vec(),
+        ];
+        let spi_expectations = [
+            &write_register(Register::RssiThresh, 0xC8)[..],
+            &read_register(Register::PacketConfig2, 0x02),
+            &write_register(Register::PacketConfig2, 0x52),
+            &write_many(Register::AesKey1, &key),
+            &read_register(Register::PacketConfig2, 0x52),
+            &write_register(Register::PacketConfig2, 0x53),
+            &wake.concat(),
+            // Refused before the radio goes on the air
+            &write_register(Register::DioMapping1, 0x00),
+            &write_many(Register::AesKey1, &key),
+            &read_register(Register::PacketConfig2, 0x52),
+            &write_register(Register::PacketConfig2, 0x53),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_rssi_threshold(-100).unwrap();
+        rfm.set_inter_packet_rx_delay(Some(5)).unwrap();
+        rfm.set_encryption_key(Some(&key)).unwrap();
+        rfm.current_mode = Rfm69Mode::Sleep;
+
+        assert_eq!(rfm.wake().await, Err(Rfm69Error::EncryptionKeyLost));
+        assert!(!rfm.encrypted);
+        assert_eq!(
+            rfm.set_mode(Rfm69Mode::Tx).await,
+            Err(Rfm69Error::EncryptionKeyLost)
+        );
+        rfm.set_encryption_key(Some(&key)).unwrap();
+        assert!(rfm.encrypted && !rfm.key_lost);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_validate_config() {
+        const CONFIG: Rfm69Config = Rfm69Config {
+            payload_length: Some(32),
+            node_address: Some(0x01),
+            encrypted: true,
+            ..Rfm69Config::DEFAULT
+        }
+        .checked();
+        let invalid = |config: Rfm69Config| config.validate().unwrap_err();
+
+        assert_eq!(
+            invalid(Rfm69Config {
+                sync_words: [0; 8],
+                ..CONFIG
+            }),
+            ConfigError::EmptySyncWord
+        );
+        assert_eq!(
+            invalid(Rfm69Config {
+                sync_configuration: SyncConfiguration::SyncOff,
+                ..CONFIG
+            }),
+            ConfigError::AddressFilteringWithoutSync
+        );
+        assert_eq!(
+            invalid(Rfm69Config {
+                payload_length: Some(0),
+                ..CONFIG
+            }),
+            ConfigError::PayloadLengthTooShort
+        );
+        // 64 bytes with AES, 65 with address filtering
+        let aes_max = Rfm69Config {
+            payload_length: Some(65),
+            ..CONFIG
+        };
+        assert_eq!(aes_max.validate(), Ok(()));
+        assert_eq!(
+            invalid(Rfm69Config {
+                node_address: None,
+                ..aes_max
+            }),
+            ConfigError::PayloadLengthTooLong
+        );
+        let too_fast = FskModulation {
+            bitrate: 400_000,
+            deviation_hz: 100_000,
+            rx_bandwidth_hz: 500_000,
+        };
+        assert_eq!(
+            invalid(Rfm69Config {
+                modulation: Some(too_fast),
+                ..CONFIG
+            }),
+            ConfigError::Modulation(ModulationError::BitrateOutOfRange)
+        );
+
+        // The same combinations through the setters
+        let mut rfm = setup_rfm();
+        rfm.sync_configuration = SyncConfiguration::SyncOff;
+        assert_eq!(
+            rfm.set_node_address(Some(0x01)),
+            Err(Rfm69Error::ConfigurationError)
+        );
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    #[should_panic(expected = "fixed payload length too long")]
+    fn test_checked_config() {
+        Rfm69Config {
+            payload_length: Some(70),
+            ..Rfm69Config::DEFAULT
+        }
+        .checked();
+    }
+
+    #[tokio::test]
+    async fn test_init_with_config() {
+        let mut rfm = setup_rfm();
+
+        let config = Rfm69Config {
+            sync_configuration: SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
+            sync_words: [0x2D, 0xD4, 0, 0, 0, 0, 0, 0],
+            modem_config: ModemConfigChoice::GfskRb250Fd250,
+            preamble_length: 4,
+            frequency: Frequency::from_mhz(915),
+            tx_power: TxPowerDbm::new(13),
+            is_high_power: true,
+            modulation: None,
+            payload_length: None,
+            node_address: None,
+            encrypted: false,
+        };
+        assert_eq!(config, Rfm69Config::default());
+        assert_eq!(
+            Rfm69Config::long_range().modem_config,
+            ModemConfigChoice::GfskRb2_4Fd4_8
+        );
+        assert_eq!(Rfm69Config::high_throughput().preamble_length, 3);
+        assert_eq!(
+            rfm.init_with_config(Rfm69Config {
+                sync_words: [0; 8],
+                ..config
+            })
+            .await,
+            Err(Rfm69Error::ConfigurationError)
+        );
+        assert_eq!(
+            rfm.init_with_config(Rfm69Config {
+                tx_power: TxPowerDbm::new(20),
+                is_high_power: false,
+                ..config
+            })
+            .await,
+            Err(Rfm69Error::TxPowerOutOfRange)
+        );
+        assert_eq!(
+            rfm.init_with_config(Rfm69Config {
+                payload_length: Some(2),
+                ..config
+            })
+            .await,
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        rfm.reset_pin.update_expectations(&[
+            GpioTransaction::set(State::High),
+            GpioTransaction::set(State::Low),
+        ]);
+        rfm.delay.update_expectations(&[
+            DelayTransaction::delay_ms(10),
+            DelayTransaction::delay_us(100),
+            DelayTransaction::delay_ms(5),
+        ]);
+        let spi_expectations: Vec<SpiTransaction<u8>> = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Version.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x24]),
+            SpiTransaction::transaction_end(),
+        ]
+        .into_iter()
+        .chain(write_configuration_expectations())
+        .collect();
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.init_with_config(config).await.unwrap();
+        assert_eq!(rfm.variant(), Rfm69Variant::Rfm69Hw);
+        assert_eq!(rfm.sync_length, 2);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_init_module_not_found() {
+        let mut rfm = setup_rfm();
+
+        rfm.reset_pin.update_expectations(&[
+            GpioTransaction::set(State::High),
+            GpioTransaction::set(State::Low),
+        ]);
+        let mut delay_expectations = vec![
+            DelayTransaction::delay_ms(10),
+            DelayTransaction::delay_us(100),
+            DelayTransaction::delay_ms(5),
+        ];
+        delay_expectations.extend(
+            (1..VERSION_READ_ATTEMPTS).map(|_| DelayTransaction::delay_ms(VERSION_RETRY_DELAY_MS)),
+        );
+        rfm.delay.update_expectations(&delay_expectations);
+        // Nothing on the bus, then a floating MISO
+        let spi_expectations: Vec<SpiTransaction<u8>> = [0x00, 0x00, 0x00, 0x00, 0xFF]
+            .into_iter()
+            .flat_map(|version| {
+                [
+                    SpiTransaction::transaction_start(),
+                    SpiTransaction::write(Register::Version.read()),
+                    SpiTransaction::transfer_in_place(vec![0x00], vec![version]),
+                    SpiTransaction::transaction_end(),
+                ]
+            })
+            .collect();
+        rfm.spi.update_expectations(&spi_expectations);
+
+        assert_eq!(
+            rfm.init().await,
+            Err(Rfm69Error::ModuleNotFound { got: 0xFF })
+        );
+        assert_eq!(rfm.chip_version(), None);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_watchdog() {
+        static RECOVERED: std::sync::Mutex<Vec<StuckCondition>> = std::sync::Mutex::new(Vec::new());
+        fn on_recovery(condition: StuckCondition) {
+            RECOVERED.lock().unwrap().push(condition);
+        }
+
+        let mut rfm = setup_rfm();
+        rfm.set_watchdog(Some(Watchdog::new(20, 2, Some(on_recovery))));
+        rfm.set_poll_intervals(PollIntervals {
+            mode_ready_us: 10_000,
+            ..PollIntervals::default()
+        });
+
+        let reset = [
+            GpioTransaction::set(State::High),
+            GpioTransaction::set(State::Low),
+        ];
+        rfm.reset_pin
+            .update_expectations(&[reset.as_slice(), &reset].concat());
+
+        let mut spi_expectations = vec![
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x10),
+            SpiTransaction::transaction_end(),
+        ];
+        // ModeReady never set
+        for _ in 0..3 {
+            spi_expectations.extend([
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags1.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+                SpiTransaction::transaction_end(),
+            ]);
+        }
+        for _ in 0..2 {
+            spi_expectations.extend(write_configuration_expectations());
+            spi_expectations.extend(write_register(Register::PacketConfig2, 0x02));
+        }
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let delay_expectations = [
+            DelayTransaction::delay_us(10_000),
+            DelayTransaction::delay_us(10_000),
+            DelayTransaction::delay_us(100),
+            DelayTransaction::delay_ms(5),
+            DelayTransaction::delay_us(100),
+            DelayTransaction::delay_ms(5),
+        ];
+        rfm.delay.update_expectations(&delay_expectations);
+
+        assert_eq!(
+            rfm.set_mode(Rfm69Mode::Rx).await,
+            Err(Rfm69Error::RadioStuck(StuckCondition::ModeReady))
+        );
+        assert_eq!(rfm.current_mode, Rfm69Mode::Standby);
+
+        // Recovered on the second timeout in a row
+        rfm.report_timeout().await.unwrap();
+        assert_eq!(rfm.watchdog().unwrap().recoveries(), 1);
+        rfm.report_timeout().await.unwrap();
+        assert_eq!(rfm.watchdog().unwrap().recoveries(), 2);
+        assert_eq!(
+            *RECOVERED.lock().unwrap(),
+            [StuckCondition::ModeReady, StuckCondition::Timeouts]
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_recover_loses_encryption_key() {
+        let mut rfm = setup_rfm();
+        let key = [0x42; 16];
+
+        let spi_expectations = [
+            &write_register(Register::RssiThresh, 0xC8)[..],
+            &write_register(Register::TestLna, 0x2D),
+            &read_register(Register::PacketConfig2, 0x02),
+            &write_register(Register::PacketConfig2, 0x00),
+            &write_many(Register::AesKey1, &key),
+            &read_register(Register::PacketConfig2, 0x00),
+            &write_register(Register::PacketConfig2, 0x01),
+            // After the reset
+            &write_configuration_expectations(),
+            &write_register(Register::RssiThresh, 0xC8),
+            &write_register(Register::TestLna, 0x2D),
+            &write_register(Register::PacketConfig2, 0x00),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.reset_pin.update_expectations(&[
+            GpioTransaction::set(State::High),
+            GpioTransaction::set(State::Low),
+        ]);
+        rfm.delay.update_expectations(&[
+            DelayTransaction::delay_us(100),
+            DelayTransaction::delay_ms(5),
+        ]);
+
+        rfm.set_rssi_threshold(-100).unwrap();
+        rfm.set_sensitivity_boost(true).unwrap();
+        rfm.set_auto_rx_restart(false).unwrap();
+        rfm.set_encryption_key(Some(&key)).unwrap();
+
+        assert_eq!(
+            rfm.recover(StuckCondition::Timeouts).await,
+            Err(Rfm69Error::EncryptionKeyLost)
+        );
+        assert!(!rfm.encrypted && rfm.key_lost);
+        assert_eq!(rfm.current_mode, Rfm69Mode::Standby);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_set_mode_rx() {
+        let mut rfm = setup_rfm();
+        rfm.set_poll_intervals(PollIntervals {
+            mode_ready_us: 250,
+            ..PollIntervals::default()
+        });
+        // Sequencer off and ListenOn, kept by mode changes
+        rfm.op_mode = OpMode::from_bits(0xC4).unwrap();
+        rfm.tx_power = 18;
+
+        let spi_expectations = [
+            // If high power boost, return power amp to receive mode
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestPa1.write()),
+            SpiTransaction::write(0x55),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestPa2.write()),
+            SpiTransaction::write(0x70),
+            SpiTransaction::transaction_end(),
+            // Set the new mode, leaving the other bits unchanged
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0xD0),
+            SpiTransaction::transaction_end(),
+            // Wait for the mode to change
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        let delay_expectations = [DelayTransaction::delay_us(250)];
+
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.delay.update_expectations(&delay_expectations);
+
+        rfm.set_mode(Rfm69Mode::Rx).await.unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_set_mode_tx() {
+        let mut rfm = setup_rfm();
+        // Sequencer off and ListenOn, kept by mode changes
+        rfm.op_mode = OpMode::from_bits(0xC4).unwrap();
+        rfm.tx_power = 18;
+
+        let spi_expectations = [
+            // If high power boost, return power amp to receive mode
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestPa1.write()),
+            SpiTransaction::write(0x5D),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::TestPa2.write()),
+            SpiTransaction::write(0x7C),
+            SpiTransaction::transaction_end(),
+            // Enable the packet sent interrupt
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DioMapping1.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            // // Set the new mode, leaving the other bits unchanged
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0xCC),
+            SpiTransaction::transaction_end(),
+            // // Wait for the mode to change
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        let delay_expectations = [DelayTransaction::delay_us(100)];
+
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.delay.update_expectations(&delay_expectations);
+
+        rfm.set_mode(Rfm69Mode::Tx).await.unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_fields() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            &write_many(Register::Fifo, &[6, 0x05, 0x01, 0x2A, 0x40, 0xAB, 0xCD])[..],
+            &write_register(Register::DioMapping1, 0x00),
+            &write_register(Register::OpMode, 0x0C),
+            &read_register(Register::IrqFlags1, 0x80),
+            &read_register(Register::IrqFlags2, 0x08),
+            &write_register(Register::OpMode, 0x04),
+            &read_register(Register::IrqFlags1, 0x80),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+
+        rfm.send_with_fields(0x05, 0x01, 0x2A, 0x40, &[0xAB, 0xCD])
+            .await
+            .unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_send_too_large() {
+        let mut rfm = setup_rfm();
+
+        // The length byte counts up to 255
+        let message = [b'a'; 252];
+        assert_eq!(rfm.send(&message).await, Err(Rfm69Error::MessageTooLarge));
+
+        // The AES engine is limited to the FIFO
+        rfm.encrypted = true;
+        let message = [b'a'; 70];
+        assert_eq!(rfm.send(&message).await, Err(Rfm69Error::MessageTooLarge));
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_send_streamed() {
+        let mut rfm = setup_rfm();
+
+        let header = Header {
+            to: 0x05,
+            from: 0x01,
+            id: 0x2A,
+            flags: 0x00,
+        };
+        let message = [0xAB; 70];
+
+        let spi_expectations = [
+            // Fill the FIFO above the threshold
+            &write_many(
+                Register::Fifo,
+                &[&[74, 0x05, 0x01, 0x2A, 0x00][..], &[0xAB; 11]].concat(),
+            )[..],
+            &write_register(Register::DioMapping1, 0x00),
+            &write_register(Register::OpMode, 0x0C),
+            &read_register(Register::IrqFlags1, 0x80),
+            // Write the rest once the FIFO level drops to the threshold
+            &read_register(Register::IrqFlags2, 0x60),
+            &read_register(Register::IrqFlags2, 0x40),
+            &write_many(Register::Fifo, &[0xAB; 50]),
+            &read_register(Register::IrqFlags2, 0x40),
+            &write_many(Register::Fifo, &[0xAB; 9]),
+            // PacketSent
+            &read_register(Register::IrqFlags2, 0x08),
+            &write_register(Register::OpMode, 0x04),
+            &read_register(Register::IrqFlags1, 0x80),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.delay
+            .update_expectations(&[DelayTransaction::delay_us(32)]);
+        rfm.intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+
+        rfm.send_with_header(header, &message).await.unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let mut rfm = setup_rfm();
+        rfm.op_mode = OpMode::from_bits(0xC4).unwrap();
+
+        let mut header = vec![17, 0xFF, 0xFF, 0x00, 0x00];
+        let mut message = "Hello, world!".as_bytes().to_vec();
+
+        header.append(&mut message);
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.write()),
+            SpiTransaction::write_vec(header),
+            SpiTransaction::transaction_end(),
+            // Enable the packet sent interrupt
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DioMapping1.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            // // Set the new mode, leaving the other bits unchanged
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0xCC),
+            SpiTransaction::transaction_end(),
+            // // Wait for the mode to change
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x08]),
+            SpiTransaction::transaction_end(),
+            // // // The current value of OpMode is known to the driver
+            // // // Set the new mode, leaving the other bits unchanged
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0xC4),
+            SpiTransaction::transaction_end(),
+            // // // // Wait for the mode to change
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+        ];
+
+        let delay_expectations = [DelayTransaction::delay_us(100)];
+
+        let intr_expectations = [GpioTransaction::wait_for_state(State::High)];
+
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.delay.update_expectations(&delay_expectations);
+        rfm.intr_pin.update_expectations(&intr_expectations);
+
+        let message = "Hello, world!".as_bytes();
+
+        rfm.send(message).await.unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_send_paging() {
+        let mut rfm = setup_rfm();
+        rfm.op_mode = OpMode::from_bits(0xC4).unwrap();
+
+        let spi_expectations = [
+            // 1 ms at 250 kbit/s and 4 bytes to lock
+            write_many(Register::PreambleMsb, &[0x00, 36]),
+            write_many(Register::Fifo, &[5, 0x02, 0xFF, 0x00, 0x00, 0x2A]),
+            write_register(Register::DioMapping1, 0x00),
+            write_register(Register::OpMode, 0xCC),
+            read_register(Register::IrqFlags1, 0x80),
+            read_register(Register::IrqFlags2, 0x08),
+            write_register(Register::OpMode, 0xC4),
+            read_register(Register::IrqFlags1, 0x80),
+            // The configured preamble is restored
+            write_many(Register::PreambleMsb, &[0x00, 4]),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+
+        let header = Header {
+            to: 0x02,
+            ..Header::default()
+        };
+        rfm.send_paging(1_000, header, &[0x2A]).await.unwrap();
+        // Back to 4 bytes of preamble
+        assert_eq!(rfm.airtime_us(1), 448);
+
+        // 10 s take more than 65535 bytes of preamble
+        assert_eq!(
+            rfm.send_paging(10_000_000, header, &[0x2A]).await,
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_send_beacon() {
+        let mut rfm = setup_rfm();
+        rfm.op_mode = OpMode::from_bits(0xC0).unwrap();
+        rfm.current_mode = Rfm69Mode::Sleep;
+
+        let frame = rfm.prepare_beacon(Header::default(), &[0x2A]).unwrap();
+        let beacon = [
+            // Standby, the FIFO is filled once the oscillator runs
+            write_register(Register::OpMode, 0xC4),
+            read_register(Register::IrqFlags1, 0x80),
+            write_many(Register::Fifo, &[5, 0xFF, 0xFF, 0x00, 0x00, 0x2A]),
+        ];
+        let sent = [
+            // Tx and Sleep without waiting for ModeReady
+            write_register(Register::OpMode, 0xCC),
+            read_register(Register::IrqFlags2, 0x08),
+            write_register(Register::OpMode, 0xC0),
+        ];
+        let spi_expectations = [
+            &beacon[..],
+            &[write_register(Register::DioMapping1, 0x00)],
+            &sent[..],
+            // DIO0 is still mapped to PacketSent
+            &beacon[..],
+            &sent[..],
+        ]
+        .concat()
+        .concat();
+
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.intr_pin.update_expectations(&[
+            GpioTransaction::wait_for_state(State::High),
+            GpioTransaction::wait_for_state(State::High),
+        ]);
+
+        rfm.send_beacon(&frame).await.unwrap();
+        rfm.send_beacon(&frame).await.unwrap();
+        assert_eq!(rfm.current_mode, Rfm69Mode::Sleep);
+        assert_eq!(rfm.stats().packets_sent, 2);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_send_duty_cycle_exceeded() {
+        fn clock() -> u64 {
+            0
+        }
+
+        let mut rfm = setup_rfm();
+        rfm.frequency = Frequency::from_mhz(868);
+        rfm.modem_config = ModemConfigChoice::FskRb2Fd5;
+
+        let mut limiter = DutyCycleLimiter::new(clock, DutyCycleAction::Error);
+        limiter.record(868_000_000, 35_990);
+        rfm.set_duty_cycle_limiter(Some(limiter));
+
+        // 4 preamble + 2 sync + 18 FIFO + 2 CRC bytes take 104ms at 2kbps
+        let message = "Hello, world!".as_bytes();
+        assert_eq!(rfm.send(message).await, Err(Rfm69Error::DutyCycleExceeded));
+        // 16 bytes take 64ms
+        assert_eq!(
+            rfm.transmit_test_pattern(TestPattern::Alternating, 16)
+                .await,
+            Err(Rfm69Error::DutyCycleExceeded)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_pa_boost_interlocks() {
+        fn clock() -> u64 {
+            0
+        }
+
+        let mut rfm = setup_rfm();
+        assert_eq!(
+            rfm.set_tx_power(TxPowerDbm::new(20)),
+            Err(Rfm69Error::PaBoost(PaBoostError::NoDutyCycleLimiter))
+        );
+
+        let spi_expectations = [
+            // Overcurrent protection off before the power goes up
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Ocp.write()),
+            SpiTransaction::write(0x0F),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PaLevel.write()),
+            SpiTransaction::write(0x7F),
+            SpiTransaction::transaction_end(),
+            // And on again after it went down
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PaLevel.write()),
+            SpiTransaction::write(0x5C),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Ocp.write()),
+            SpiTransaction::write(0x1A),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let mut limiter = DutyCycleLimiter::new(clock, DutyCycleAction::Error);
+        limiter.record_pa_boost(36_000);
+        rfm.set_duty_cycle_limiter(Some(limiter));
+        rfm.set_tx_power(TxPowerDbm::new(20)).unwrap();
+
+        // 1% of the hour used at high power, on a frequency without a regulatory limit
+        let message = "Hello, world!".as_bytes();
+        let error = Err(Rfm69Error::PaBoost(PaBoostError::DutyCycleExceeded));
+        assert_eq!(rfm.send(message).await, error);
+        assert_eq!(rfm.start_transmit(Header::default(), message), error);
+        assert_eq!(
+            rfm.transmit_test_pattern(TestPattern::Alternating, 16)
+                .await,
+            error
+        );
+
+        rfm.set_tx_power(TxPowerDbm::new(10)).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[test]
+    fn test_ert_profile() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FrfMsb.write()),
+            SpiTransaction::write_vec(vec![0xE4, 0x00, 0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DataModul.write()),
+            SpiTransaction::write(0x08),
+            SpiTransaction::transaction_end(),
+            // 32768 chips/s
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::BitrateMsb.write()),
+            SpiTransaction::write_vec(vec![0x03, 0xD1]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::RxBw.write()),
+            SpiTransaction::write_vec(vec![0xE0, 0xE0]),
+            SpiTransaction::transaction_end(),
+            // The last 16 preamble bits as 32 chips
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.write()),
+            SpiTransaction::write_vec(vec![0x98, 0x59, 0x99, 0x69, 0x55, 0, 0, 0, 0]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.write()),
+            SpiTransaction::write(19),
+            SpiTransaction::transaction_end(),
+            // A broadcast, the payload length is cached
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x04]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00; 19], vec![0x66; 19]),
+            SpiTransaction::transaction_end(),
+            // Fixed length without CRC, now filtering addresses
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::NodeAddrs.write()),
+            SpiTransaction::write_vec(vec![0x05, 0xFF]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        rfm.set_ert_profile(ErtProtocol::Scm).unwrap();
+        // Alternating bits, which fail the checksum
+        assert_eq!(
+            ert::receive_ert(&mut rfm, ErtProtocol::Scm),
+            Err(ert::ErtError::Checksum)
+        );
+        assert_eq!(rfm.payload_length(), Some(19));
+        rfm.set_node_address(Some(0x05)).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_transmit_ook() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            // Save the modulation
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DataModul.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::BitrateMsb.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x1A]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::BitrateLsb.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x0B]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PreambleMsb.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PreambleLsb.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x03]),
+            SpiTransaction::transaction_end(),
+            // OOK at 350 us per bit without preamble
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DataModul.write()),
+            SpiTransaction::write(0x08),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::BitrateMsb.write()),
+            SpiTransaction::write_vec(vec![0x2B, 0xC0]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PreambleMsb.write()),
+            SpiTransaction::write_vec(vec![0x00, 0x00]),
+            SpiTransaction::transaction_end(),
+            // Unlimited length mode without sync word
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x88]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0xD0]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x40]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            // The frame, twice
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.write()),
+            SpiTransaction::write_vec(vec![0x8E, 0x8E]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DioMapping1.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x0C),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.write()),
+            SpiTransaction::write(0x88),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0xD0),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.write()),
+            SpiTransaction::write(0x40),
+            SpiTransaction::transaction_end(),
+            // Restore the modulation
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DataModul.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::BitrateMsb.write()),
+            SpiTransaction::write_vec(vec![0x1A, 0x0B]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PreambleMsb.write()),
+            SpiTransaction::write_vec(vec![0x00, 0x03]),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+        // The last byte leaves the shift register, 2857 bit/s
+        rfm.delay
+            .update_expectations(&[DelayTransaction::delay_us(2801)]);
+
+        rfm.transmit_ook(350, &[0x8E], 2).await.unwrap();
+        assert_eq!(
+            rfm.transmit_ook(5_000, &[0x8E], 2).await,
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        check_expectations(&mut rfm);
     }
 
     #[tokio::test]
-    async fn test_reset() {
+    async fn test_transmit_test_pattern() {
         let mut rfm = setup_rfm();
 
-        let reset_expectations = [
-            GpioTransaction::set(State::High),
-            GpioTransaction::set(State::Low),
+        let spi_expectations = [
+            // Save the packet configuration
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x88]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0xD0]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x40]),
+            SpiTransaction::transaction_end(),
+            // Unlimited length mode without sync word
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            // Fill the FIFO above the threshold
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.write()),
+            SpiTransaction::write_vec(vec![0x55; 16]),
+            SpiTransaction::transaction_end(),
+            // Switch to Tx
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DioMapping1.write()),
+            SpiTransaction::write(0x00),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x0C),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            // Write the rest once the FIFO level drops to the threshold
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x60]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x40]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.write()),
+            SpiTransaction::write_vec(vec![0x55; 24]),
+            SpiTransaction::transaction_end(),
+            // Wait for the FIFO to drain
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x40]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            // Back to Standby
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            // Restore the packet configuration
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::SyncConfig.write()),
+            SpiTransaction::write(0x88),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0xD0),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.write()),
+            SpiTransaction::write(0x40),
+            SpiTransaction::transaction_end(),
         ];
-        rfm.reset_pin.update_expectations(&reset_expectations);
 
-        let delay_expectations = [
+        rfm.spi.update_expectations(&spi_expectations);
+        // One byte time at 250 kbit/s between the FIFO polls and after the FIFO drained
+        rfm.delay.update_expectations(&[
+            DelayTransaction::delay_us(32),
+            DelayTransaction::delay_us(32),
+            DelayTransaction::delay_us(32),
+        ]);
+
+        rfm.transmit_test_pattern(TestPattern::Alternating, 40)
+            .await
+            .unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_transmit_test_pattern_fifo_level() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            &write_register(Register::FifoThresh, 0x0F)[..],
+            &read_register(Register::SyncConfig, 0x88),
+            &read_register(Register::PacketConfig1, 0xD0),
+            &read_register(Register::PayloadLength, 0x40),
+            &write_register(Register::SyncConfig, 0x00),
+            &write_register(Register::PacketConfig1, 0x00),
+            &write_register(Register::PayloadLength, 0x00),
+            // Too short to rise above the threshold, the transmitter starts on FifoNotEmpty
+            &write_register(Register::FifoThresh, 0x8F),
+            &write_many(Register::Fifo, &[0x55; 4]),
+            &write_register(Register::DioMapping1, 0x00),
+            &write_register(Register::OpMode, 0x0C),
+            &read_register(Register::IrqFlags1, 0x80),
+            &write_register(Register::FifoThresh, 0x0F),
+            &read_register(Register::IrqFlags2, 0x00),
+            &write_register(Register::OpMode, 0x04),
+            &read_register(Register::IrqFlags1, 0x80),
+            &write_register(Register::SyncConfig, 0x88),
+            &write_register(Register::PacketConfig1, 0xD0),
+            &write_register(Register::PayloadLength, 0x40),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.delay
+            .update_expectations(&[DelayTransaction::delay_us(32)]);
+
+        rfm.set_fifo_threshold(15, TxStartCondition::FifoLevel)
+            .unwrap();
+        rfm.transmit_test_pattern(TestPattern::Alternating, 4)
+            .await
+            .unwrap();
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_transmit_test_pattern_timeout() {
+        let mut rfm = setup_rfm();
+
+        let stuck = read_register(Register::IrqFlags2, 0x40);
+        let spi_expectations = [
+            &read_register(Register::SyncConfig, 0x88)[..],
+            &read_register(Register::PacketConfig1, 0xD0),
+            &read_register(Register::PayloadLength, 0x40),
+            &write_register(Register::SyncConfig, 0x00),
+            &write_register(Register::PacketConfig1, 0x00),
+            &write_register(Register::PayloadLength, 0x00),
+            &write_many(Register::Fifo, &[0x55]),
+            &write_register(Register::DioMapping1, 0x00),
+            &write_register(Register::OpMode, 0x0C),
+            &read_register(Register::IrqFlags1, 0x80),
+            // The FIFO never drains
+            &stuck,
+            &stuck,
+            &stuck,
+            &stuck,
+            &stuck,
+            &write_register(Register::OpMode, 0x04),
+            &read_register(Register::IrqFlags1, 0x80),
+            &write_register(Register::SyncConfig, 0x88),
+            &write_register(Register::PacketConfig1, 0xD0),
+            &write_register(Register::PayloadLength, 0x40),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        // Twice the time on air of the byte
+        rfm.delay
+            .update_expectations(&vec![DelayTransaction::delay_us(32); 4]);
+
+        assert_eq!(
+            rfm.transmit_test_pattern(TestPattern::Alternating, 1).await,
+            Err(Rfm69Error::Timeout)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_receive() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![9]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00; 9],
+                vec![0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ),
+            SpiTransaction::transaction_end(),
+        ];
+
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let mut buffer = [0u8; 5];
+
+        let (header, message_len) = rfm.receive(&mut buffer).await.unwrap();
+        assert_eq!(
+            header,
+            Header {
+                to: 0x00,
+                from: 0x00,
+                id: 0x00,
+                flags: 0x00,
+            }
+        );
+        assert_eq!(message_len, 5);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_send_raw_fixed_length() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            read_register(Register::PacketConfig1, 0x00).to_vec(),
+            read_register(Register::PayloadLength, 4).to_vec(),
+            // Padded up to the payload length
+            send(&[0x10, 0x20, 0x30, 0x00]),
+            read_register(Register::PacketConfig1, 0x00).to_vec(),
+            read_register(Register::PayloadLength, RF69_FIFO_SIZE as u8).to_vec(),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+
+        rfm.send_raw(&[0x10, 0x20, 0x30]).await.unwrap();
+        assert_eq!(
+            rfm.send_raw(&[0x00; 5]).await,
+            Err(Rfm69Error::MessageTooLarge)
+        );
+
+        // The AES engine handles 64 bytes
+        rfm.invalidate_register_cache();
+        rfm.encrypted = true;
+        assert_eq!(
+            rfm.send_raw(&[0x10]).await,
+            Err(Rfm69Error::MessageTooLarge)
+        );
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_receive_buffer_too_small() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![9]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00; 9],
+                vec![0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05],
+            ),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let mut buffer = [0u8; 4];
+        assert_eq!(
+            rfm.receive(&mut buffer).await,
+            Err(Rfm69Error::BufferTooSmall)
+        );
+        assert_eq!(buffer, [0u8; 4]);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_receive_crc_failure() {
+        let mut rfm = setup_rfm();
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x04]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0xD0]),
+            SpiTransaction::transaction_end(),
+            // Flush the FIFO and restart the receiver
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.write()),
+            SpiTransaction::write(0x10),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x02]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x06),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let mut buffer = [0u8; 65];
+        assert_eq!(rfm.receive(&mut buffer).await, Err(Rfm69Error::NoMessage));
+        assert_eq!(rfm.receive(&mut buffer).await, Err(Rfm69Error::CrcFailure));
+        assert_eq!(rfm.reset_stats().crc_failures, 1);
+        assert_eq!(rfm.stats().crc_failures, 0);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_message_timeout() {
+        let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Rx;
+        rfm.set_poll_intervals(PollIntervals {
+            message_us: 100,
+            message_max_us: 400,
+            ..PollIntervals::default()
+        });
+
+        let flags = |flags2: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags1.read()),
+                SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![0x80, flags2]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let spi_expectations = [
+            flags(0x00),
+            flags(0x00),
+            flags(0x00),
+            flags(0x00),
+            flags(0x00),
+            // A packet on the first poll
+            flags(0x04),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        // Backing off, the last delay ends at the deadline
+        rfm.delay.update_expectations(&[
             DelayTransaction::delay_us(100),
-            DelayTransaction::delay_ms(5),
+            DelayTransaction::delay_us(200),
+            DelayTransaction::delay_us(400),
+            DelayTransaction::delay_us(300),
+        ]);
+
+        assert_eq!(rfm.wait_for_message_timeout(Some(1)).await, Ok(false));
+        assert_eq!(rfm.wait_for_message_timeout(Some(1)).await, Ok(true));
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_idle_policy() {
+        let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Rx;
+        rfm.set_poll_intervals(PollIntervals {
+            message_us: 400,
+            ..PollIntervals::default()
+        });
+        assert_eq!(
+            rfm.set_idle_policy(Some(IdlePolicy {
+                timeout_ms: 1,
+                mode: Rfm69Mode::Tx,
+            })),
+            Err(Rfm69Error::ConfigurationError)
+        );
+        rfm.set_idle_policy(Some(IdlePolicy {
+            timeout_ms: 1,
+            mode: Rfm69Mode::Standby,
+        }))
+        .unwrap();
+
+        let flags = |flags2: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags1.read()),
+                SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![0x80, flags2]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let mode = |op_mode: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::OpMode.write()),
+                SpiTransaction::write(op_mode),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags1.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let spi_expectations = [
+            flags(0x00).to_vec(),
+            flags(0x00).to_vec(),
+            flags(0x00).to_vec(),
+            // 1 ms in Rx without a packet
+            flags(0x00).to_vec(),
+            mode(0x04).to_vec(),
+            // Back to Rx on demand
+            mode(0x10).to_vec(),
+            flags(0x04).to_vec(),
+        ]
+        .concat();
+        rfm.spi.update_expectations(&spi_expectations);
+        rfm.delay.update_expectations(&[
+            DelayTransaction::delay_us(400),
+            DelayTransaction::delay_us(600),
+        ]);
+
+        assert_eq!(rfm.wait_for_message_timeout(Some(0)).await, Ok(false));
+        assert_eq!(rfm.wait_for_message().await, Err(Rfm69Error::NoMessage));
+        assert_eq!(rfm.current_mode, Rfm69Mode::Standby);
+        rfm.wait_for_message().await.unwrap();
+        assert_eq!(rfm.current_mode, Rfm69Mode::Rx);
+
+        check_expectations(&mut rfm);
+    }
+
+    #[tokio::test]
+    async fn test_receive_fifo_overrun() {
+        let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Rx;
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![0x00, 0x70]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x70]),
+            SpiTransaction::transaction_end(),
+            // Flush the FIFO and restart the receiver
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.write()),
+            SpiTransaction::write(0x10),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
         ];
-        rfm.delay.update_expectations(&delay_expectations);
+        rfm.spi.update_expectations(&spi_expectations);
 
-        rfm.reset().await.unwrap();
+        assert!(rfm.is_message_available().unwrap());
+        let mut buffer = [0u8; 65];
+        assert_eq!(rfm.receive(&mut buffer).await, Err(Rfm69Error::FifoOverrun));
+        assert_eq!(rfm.stats().fifo_overruns, 1);
 
         check_expectations(&mut rfm);
     }
 
-    #[tokio::test]
-    async fn test_read_temperature() {
+    #[test]
+    fn test_set_node_address() {
         let mut rfm = setup_rfm();
 
-        let temperature_expectations = [
+        let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Temp1.write()),
-            SpiTransaction::write(0x08),
+            SpiTransaction::write(Register::BroadcastAddrs.write()),
+            SpiTransaction::write(0xAA),
             SpiTransaction::transaction_end(),
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Temp1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x04]),
+            SpiTransaction::write(Register::NodeAddrs.write()),
+            SpiTransaction::write_vec(vec![0x05, 0xAA]),
             SpiTransaction::transaction_end(),
+            // Filter on the node and broadcast addresses
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Temp1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0xD4),
             SpiTransaction::transaction_end(),
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Temp2.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x8D]),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0xD0),
             SpiTransaction::transaction_end(),
         ];
-        rfm.spi.update_expectations(&temperature_expectations);
+        rfm.spi.update_expectations(&spi_expectations);
 
-        let delay_expectations = [DelayTransaction::delay_ms(10)];
-        rfm.delay.update_expectations(&delay_expectations);
+        rfm.set_broadcast_address(0xAA).unwrap();
+        assert_eq!(rfm.broadcast_address(), 0xAA);
 
-        let temperature = rfm.read_temperature().await.unwrap();
+        rfm.set_node_address(Some(0x05)).unwrap();
+        assert_eq!(rfm.node_address(), Some(0x05));
 
-        assert_eq!(temperature, 25.0);
+        rfm.set_node_address(None).unwrap();
+        assert_eq!(rfm.node_address(), None);
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_set_default_fifo_threshold() {
+    #[tokio::test]
+    async fn test_packet_filter() {
         let mut rfm = setup_rfm();
+        fn from_gateway(header: &Header, rssi_dbm: i16) -> bool {
+            header.from == 0x01 && rssi_dbm > -90
+        }
+        rfm.set_packet_filter(Some(from_gateway));
 
         let spi_expectations = [
+            // From another node, dropped
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::FifoThresh.write()),
-            SpiTransaction::write(0x8F),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00; 6],
+                vec![0xFF, 0x02, 0x01, 0x00, 0xAB, 0xCD],
+            ),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::RssiValue.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.write()),
+            SpiTransaction::write(0x10),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x02]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x06),
+            SpiTransaction::transaction_end(),
+            // From the gateway, with the RSSI latched at SyncAddressMatch
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00; 6],
+                vec![0xFF, 0x01, 0x02, 0x00, 0xAB, 0xCD],
+            ),
             SpiTransaction::transaction_end(),
         ];
-
         rfm.spi.update_expectations(&spi_expectations);
 
-        rfm.set_default_fifo_threshold().unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(rfm.receive(&mut buffer).await, Err(Rfm69Error::NoMessage));
+
+        rfm.latched_rssi = Some(-70);
+        let (header, length) = rfm.receive(&mut buffer).await.unwrap();
+        assert_eq!(header.from, 0x01);
+        assert_eq!(buffer[..length], [0xAB, 0xCD]);
+        assert_eq!(rfm.packet_rssi(), Some(-70));
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_set_dagc() {
+    #[tokio::test]
+    async fn test_set_payload_length() {
         let mut rfm = setup_rfm();
 
         let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::TestDagc.write()),
-            SpiTransaction::write(ContinuousDagc::ImprovedLowBeta1 as u8),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x02]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.write()),
+            SpiTransaction::write(10),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0x50),
+            SpiTransaction::transaction_end(),
+            // A packet without a length byte, padded to 10 bytes
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00; 10],
+                vec![0xFF, 0x01, 0x07, 0x00, 1, 2, 3, 0, 0, 0],
+            ),
+            SpiTransaction::transaction_end(),
+            // Too long for the AES engine
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x03]),
+            SpiTransaction::transaction_end(),
+            // Back to variable length packets
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PayloadLength.write()),
+            SpiTransaction::write(0x41),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::PacketConfig1.write()),
+            SpiTransaction::write(0xD0),
             SpiTransaction::transaction_end(),
         ];
-
         rfm.spi.update_expectations(&spi_expectations);
 
-        rfm.set_dagc(ContinuousDagc::ImprovedLowBeta1).unwrap();
+        rfm.set_payload_length(Some(10)).unwrap();
+        assert_eq!(rfm.payload_length(), Some(10));
+        assert_eq!(rfm.max_payload_length(), 6);
+
+        let mut buffer = [0u8; 8];
+        let (header, length) = rfm.receive(&mut buffer).await.unwrap();
+        assert_eq!(header.id, 0x07);
+        assert_eq!(buffer[..length], [1, 2, 3, 0, 0, 0]);
+
+        assert_eq!(
+            rfm.set_payload_length(Some(65)),
+            Err(Rfm69Error::ConfigurationError)
+        );
+        // A full FIFO is only allowed without encryption
+        rfm.payload_length = Some(66);
+        assert_eq!(
+            rfm.set_encryption_key(Some(&[0; 16])),
+            Err(Rfm69Error::ConfigurationError)
+        );
+
+        rfm.set_payload_length(None).unwrap();
+        assert_eq!(rfm.max_payload_length(), 61);
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_set_power() {
+    #[tokio::test]
+    async fn test_receive_from() {
         let mut rfm = setup_rfm();
+        rfm.node_address = Some(0x05);
+
+        let packet = |to: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags2.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Fifo.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![5]),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Fifo.read()),
+                SpiTransaction::transfer_in_place(vec![0x00; 5], vec![to, 0x09, 0x00, 0x00, 0x2A]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let spi_expectations = [packet(0x05), packet(0xFF), packet(0x06)].concat();
+        rfm.spi.update_expectations(&spi_expectations);
 
-        let spi_expectations = [];
+        let mut buffer = [0u8; 1];
+        let datagram = rfm.receive_from(&mut buffer).await.unwrap();
+        assert_eq!(datagram.from, 0x09);
+        assert_eq!(datagram.length, 1);
+        assert!(!datagram.broadcast);
+        assert_eq!(buffer, [0x2A]);
 
-        rfm.spi.update_expectations(&spi_expectations);
+        let mut buffer = [0u8; 1];
+        assert!(rfm.receive_from(&mut buffer).await.unwrap().broadcast);
+
+        let mut buffer = [0u8; 1];
+        assert_eq!(
+            rfm.receive_from(&mut buffer).await,
+            Err(Rfm69Error::NoMessage)
+        );
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_set_sync_words() {
+    #[tokio::test]
+    async fn test_receive_link_stats() {
         let mut rfm = setup_rfm();
+        rfm.set_link_stats(true);
 
         let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::SyncConfig.write()),
-            SpiTransaction::write_vec(vec![184, 1, 2, 3, 4, 5, 6, 7, 8]),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![4]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00; 4], vec![0xFF, 0x07, 0x03, 0x00]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::RssiValue.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x90]),
+            SpiTransaction::transaction_end(),
+            // -16 steps of 61 Hz
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::FeiMsb.read()),
+            SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![0xFF, 0xF0]),
             SpiTransaction::transaction_end(),
         ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        let mut buffer = [0u8; 4];
+        rfm.receive(&mut buffer).await.unwrap();
+
+        let peer = *rfm.link_stats().unwrap().peer(0x07).unwrap();
+        assert_eq!(peer.packets_received, 1);
+        assert_eq!(peer.last_rssi_dbm, -72);
+        assert_eq!(peer.last_fei_hz, -977);
+
+        rfm.set_link_stats(false);
+        assert!(rfm.link_stats().is_none());
+
+        check_expectations(&mut rfm);
+    }
 
+    #[tokio::test]
+    async fn test_rssi_latched_at_sync_match() {
+        let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Rx;
+
+        let irq_flags = |flags1: u8, flags2: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags1.read()),
+                SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![flags1, flags2]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let spi_expectations = [
+            // Sync word matched, sample the RSSI once
+            irq_flags(0xD9, 0x00).to_vec(),
+            vec![
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::RssiValue.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x50]),
+                SpiTransaction::transaction_end(),
+            ],
+            irq_flags(0xD9, 0x00).to_vec(),
+            irq_flags(0xD9, 0x06).to_vec(),
+            vec![
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::IrqFlags2.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Fifo.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![4]),
+                SpiTransaction::transaction_end(),
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(Register::Fifo.read()),
+                SpiTransaction::transfer_in_place(vec![0x00; 4], vec![0xFF, 0x07, 0x00, 0x00]),
+                SpiTransaction::transaction_end(),
+            ],
+        ]
+        .concat();
         rfm.spi.update_expectations(&spi_expectations);
 
-        let sync_words = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
-        rfm.set_sync_words(
-            SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
-            &sync_words,
-        )
-        .unwrap();
+        assert!(!rfm.is_message_available().unwrap());
+        assert!(!rfm.is_message_available().unwrap());
+        assert!(rfm.is_message_available().unwrap());
+
+        let mut buffer = [0u8; 4];
+        rfm.receive(&mut buffer).await.unwrap();
+        assert_eq!(rfm.packet_rssi(), Some(-40));
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_set_sync_words_clamp() {
+    #[tokio::test]
+    async fn test_recv_low_power() {
         let mut rfm = setup_rfm();
 
         let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::SyncConfig.write()),
-            SpiTransaction::write_vec(vec![191, 1, 2, 3, 4, 5, 6, 7, 8]),
+            SpiTransaction::write(Register::Listen1.write()),
+            SpiTransaction::write_vec(vec![0x98, 61, 16]),
+            SpiTransaction::transaction_end(),
+            // DIO0 is PayloadReady
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::DioMapping1.write()),
+            SpiTransaction::write(0x40),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x44),
+            SpiTransaction::transaction_end(),
+            // Leave Listen mode
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x24),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0x04),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![5]),
+            SpiTransaction::transaction_end(),
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00; 5], vec![0xFF, 0x02, 0x00, 0x00, 0x2A]),
             SpiTransaction::transaction_end(),
         ];
-
         rfm.spi.update_expectations(&spi_expectations);
+        rfm.intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
 
-        let sync_words = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
-
-        // sync_tolerance is clamped to 7
-        rfm.set_sync_words(
-            SyncConfiguration::FifoFillAuto { sync_tolerance: 14 },
-            &sync_words,
-        )
-        .unwrap();
+        let mut buffer = [0u8; 4];
+        let (_, length) = rfm
+            .recv_low_power(&ListenConfig::default(), &mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(buffer[..length], [0x2A]);
+        assert_eq!(rfm.current_mode, Rfm69Mode::Standby);
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_set_sync_words_too_long() {
+    #[tokio::test]
+    async fn test_recv_low_power_pin_error() {
         let mut rfm = setup_rfm();
 
-        let spi_expectations = [];
-
+        let spi_expectations = [
+            &write_many(Register::Listen1, &[0x98, 61, 16])[..],
+            &write_register(Register::DioMapping1, 0x40),
+            &write_register(Register::OpMode, 0x44),
+            // Listen mode is left all the same
+            &write_register(Register::OpMode, 0x24),
+            &write_register(Register::OpMode, 0x04),
+            &read_register(Register::IrqFlags1, 0x80),
+        ]
+        .concat();
         rfm.spi.update_expectations(&spi_expectations);
+        rfm.intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)
+                .with_error(MockError::Io(ErrorKind::Other))]);
 
-        let sync_words = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09];
+        let mut buffer = [0u8; 4];
         assert_eq!(
-            rfm.set_sync_words(
-                SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
-                &sync_words
-            ),
-            Err(Rfm69Error::ConfigurationError)
+            rfm.recv_low_power(&ListenConfig::default(), &mut buffer)
+                .await,
+            Err(Rfm69Error::InterruptPinError)
         );
+        assert_eq!(rfm.current_mode, Rfm69Mode::Standby);
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_set_sync_words_empty() {
+    #[tokio::test]
+    async fn test_receive_malformed_length() {
         let mut rfm = setup_rfm();
 
-        let spi_expectations = [];
-
-        rfm.spi.update_expectations(&spi_expectations);
-
-        let sync_words = [];
-        assert_eq!(
-            rfm.set_sync_words(
-                SyncConfiguration::FifoFillAuto { sync_tolerance: 0 },
-                &sync_words
-            ),
-            Err(Rfm69Error::ConfigurationError)
-        );
+        // Shorter than the header, and longer than the FIFO
+        let lengths = [2, 0xFF];
+        let spi_expectations = lengths.map(|length| {
+            [
+                &read_register(Register::IrqFlags2, 0x06)[..],
+                &read_register(Register::Fifo, length),
+                // Flushed, and the receiver restarted
+                &write_register(Register::IrqFlags2, 0x10),
+                &read_register(Register::PacketConfig2, 0x02),
+                &write_register(Register::PacketConfig2, 0x06),
+            ]
+            .concat()
+        });
+        rfm.spi.update_expectations(&spi_expectations.concat());
+
+        let mut buffer = [0u8; 8];
+        for _ in lengths {
+            assert_eq!(
+                rfm.receive(&mut buffer).await,
+                Err(Rfm69Error::MalformedPacket)
+            );
+        }
 
         check_expectations(&mut rfm);
     }
 
     #[test]
-    fn test_set_modem_config() {
+    fn test_read_irq_flags() {
         let mut rfm = setup_rfm();
 
         let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::DataModul.write()),
-            SpiTransaction::write_vec(vec![0x00, 0x3e, 0x80, 0x00, 0x52]),
-            SpiTransaction::transaction_end(),
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::RxBw.write()),
-            SpiTransaction::write_vec(vec![0xf4, 0xf4]),
-            SpiTransaction::transaction_end(),
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::PacketConfig1.write()),
-            SpiTransaction::write(0xd0),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![0xD9, 0x66]),
             SpiTransaction::transaction_end(),
         ];
-
         rfm.spi.update_expectations(&spi_expectations);
 
-        rfm.set_modem_config(ModemConfigChoice::FskRb2Fd5).unwrap();
+        let (irq_flags1, irq_flags2) = rfm.read_irq_flags().unwrap();
+        assert!(irq_flags1.mode_ready && irq_flags1.rx_ready && irq_flags1.rssi);
+        assert!(irq_flags1.sync_address_match && !irq_flags1.tx_ready);
+        assert!(irq_flags2.fifo_not_empty && irq_flags2.fifo_level);
+        assert!(irq_flags2.payload_ready && irq_flags2.crc_ok);
+        assert!(!irq_flags2.fifo_full && !irq_flags2.packet_sent);
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_set_preamble_length() {
-        let mut rfm = setup_rfm();
+    #[tokio::test]
+    async fn test_is_message_available() {
+        let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Rx;
+
+        let spi_expectations = [
+            SpiTransaction::transaction_start(),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![0x00, 0x04]),
+            SpiTransaction::transaction_end(),
+        ];
+        rfm.spi.update_expectations(&spi_expectations);
+
+        assert!(rfm.is_message_available().unwrap());
 
         let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::PreambleMsb.write()),
-            SpiTransaction::write_vec(vec![0x00, 0xFF]),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![0x00, 0x00]),
             SpiTransaction::transaction_end(),
         ];
-
         rfm.spi.update_expectations(&spi_expectations);
 
-        rfm.set_preamble_length(255).unwrap();
+        assert!(!rfm.is_message_available().unwrap());
+
+        rfm.current_mode = Rfm69Mode::Tx;
+        assert_eq!(rfm.is_message_available(), Err(Rfm69Error::InvalidMode));
 
         check_expectations(&mut rfm);
     }
 
-    #[test]
-    fn test_get_revision() {
+    #[tokio::test]
+    async fn test_calibrate_rssi_threshold() {
         let mut rfm = setup_rfm();
 
-        let spi_expectations = [
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Version.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x24]),
-            SpiTransaction::transaction_end(),
-        ];
-
+        let read = |register: Register, value: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(register.read()),
+                SpiTransaction::transfer_in_place(vec![0x00], vec![value]),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+        let write = |register: Register, value: u8| {
+            [
+                SpiTransaction::transaction_start(),
+                SpiTransaction::write(register.write()),
+                SpiTransaction::write(value),
+                SpiTransaction::transaction_end(),
+            ]
+        };
+
+        let spi_expectations: Vec<SpiTransaction<u8>> = [
+            write(Register::OpMode, 0x10),
+            read(Register::IrqFlags1, 0x80),
+            // -104 and -106 dBm
+            write(Register::RssiConfig, 0x01),
+            read(Register::RssiConfig, 0x02),
+            read(Register::RssiValue, 0xD0),
+            write(Register::RssiConfig, 0x01),
+            read(Register::RssiConfig, 0x02),
+            read(Register::RssiValue, 0xD4),
+            write(Register::OpMode, 0x04),
+            read(Register::IrqFlags1, 0x80),
+            write(Register::RssiThresh, 0xC6),
+            // RssiDone never set
+            write(Register::OpMode, 0x10),
+            read(Register::IrqFlags1, 0x80),
+            write(Register::RssiConfig, 0x01),
+        ]
+        .into_iter()
+        .chain((0..10).map(|_| read(Register::RssiConfig, 0x00)))
+        .chain([
+            write(Register::OpMode, 0x04),
+            read(Register::IrqFlags1, 0x80),
+        ])
+        .flatten()
+        .collect();
         rfm.spi.update_expectations(&spi_expectations);
+        rfm.delay
+            .update_expectations(&vec![DelayTransaction::delay_ms(1); 10]);
 
-        let revision = rfm.read_revision().unwrap();
-        assert_eq!(revision, 0x24);
+        assert_eq!(
+            rfm.calibrate_rssi_threshold(0, 6).await,
+            Err(Rfm69Error::ConfigurationError)
+        );
+        assert_eq!(rfm.calibrate_rssi_threshold(2, 6).await, Ok(-99));
+        assert_eq!(
+            rfm.calibrate_rssi_threshold(1, 6).await,
+            Err(Rfm69Error::Timeout)
+        );
+        assert_eq!(
+            rfm.set_rssi_threshold(-128),
+            Err(Rfm69Error::ConfigurationError)
+        );
 
         check_expectations(&mut rfm);
     }
 
     #[test]
-    fn test_set_frequency() {
+    fn test_rssi() {
         let mut rfm = setup_rfm();
+        rfm.current_mode = Rfm69Mode::Rx;
 
         let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::FrfMsb.write()),
-            SpiTransaction::write_vec(vec![0xE4, 0xC0, 0x00]),
+            SpiTransaction::write(Register::RssiValue.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x50]),
             SpiTransaction::transaction_end(),
         ];
-
         rfm.spi.update_expectations(&spi_expectations);
 
-        rfm.set_frequency(915).unwrap();
+        assert_eq!(rfm.rssi().unwrap(), 40);
 
         check_expectations(&mut rfm);
     }
 
+    fn config_snapshot() -> [u8; CONFIG_SNAPSHOT_SIZE] {
+        let mut snapshot = [0u8; CONFIG_SNAPSHOT_SIZE];
+        snapshot[0] = CONFIG_SNAPSHOT_MAGIC;
+        snapshot[1] = 10;
+        // DataModul, Bitrate, Fdev and Frf for GfskRb19_2Fd38_4 at 868 MHz
+        snapshot[2..10].copy_from_slice(&[0x01, 0x06, 0x83, 0x02, 0x75, 0xD9, 0x00, 0x00]);
+        // PaLevel
+        snapshot[15] = 0x5A;
+        // Lna, RxBw, AfcBw
+        snapshot[18..21].copy_from_slice(&[0x88, 0xF3, 0xF3]);
+        // RssiThresh up to PacketConfig1
+        snapshot[26..41].copy_from_slice(&[
+            0xE4, 0x00, 0x00, 0x00, 0x08, 0x90, 0xAA, 0xBB, 0xCC, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0xD0,
+        ]);
+        snapshot
+    }
+
+    fn config_blocks(snapshot: &[u8]) -> impl Iterator<Item = (Register, Vec<u8>)> + '_ {
+        let mut offset = 2;
+        CONFIG_BLOCKS.into_iter().map(move |(register, length)| {
+            let block = snapshot[offset..offset + length].to_vec();
+            offset += length;
+            (register, block)
+        })
+    }
+
     #[test]
-    fn test_set_tx_power() {
+    fn test_verify_configuration() {
         let mut rfm = setup_rfm();
 
-        let spi_expectations = [
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::PaLevel.write()),
-            SpiTransaction::write(0x50),
-            SpiTransaction::transaction_end(),
-        ];
+        let intended = config_snapshot();
+        for (register, block) in config_blocks(&intended) {
+            // Lna, RxBw and AfcBw never written
+            if register != Register::Lna {
+                rfm.shadow.update(register, &block);
+            }
+        }
 
+        let mut radio = intended;
+        radio[snapshot_offset(Register::PaLevel).unwrap()] = 0x7F;
+        radio[snapshot_offset(Register::SyncValue1).unwrap()] = 0xFF;
+        radio[snapshot_offset(Register::Lna).unwrap()] = 0x00;
+        // PacketConfig2 isn't shadowed
+        radio[snapshot_offset(Register::PacketConfig2).unwrap()] = 0x04;
+
+        let spi_expectations: Vec<SpiTransaction<u8>> = config_blocks(&radio)
+            .flat_map(|(register, block)| {
+                [
+                    SpiTransaction::transaction_start(),
+                    SpiTransaction::write(register.read()),
+                    SpiTransaction::transfer_in_place(vec![0x00; block.len()], block),
+                    SpiTransaction::transaction_end(),
+                ]
+            })
+            .collect();
         rfm.spi.update_expectations(&spi_expectations);
 
-        rfm.set_tx_power(-2).unwrap();
-        assert_eq!(rfm.tx_power, -2);
+        let mismatches = rfm.verify_configuration().unwrap();
+        assert_eq!(
+            mismatches[..],
+            [
+                RegisterMismatch {
+                    register: Register::PaLevel.addr(),
+                    expected: 0x5A,
+                    actual: 0x7F,
+                },
+                RegisterMismatch {
+                    register: Register::SyncValue1.addr(),
+                    expected: 0xAA,
+                    actual: 0xFF,
+                },
+            ]
+        );
+        assert_eq!(rfm.shadow.get(Register::PaLevel), Some(0x5A));
 
         check_expectations(&mut rfm);
     }
 
-    #[tokio::test]
-    async fn test_set_mode_rx() {
+    #[test]
+    fn test_set_shaping() {
         let mut rfm = setup_rfm();
-        rfm.tx_power = 18;
 
         let spi_expectations = [
-            // If high power boost, return power amp to receive mode
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::TestPa1.write()),
-            SpiTransaction::write(0x55),
-            SpiTransaction::transaction_end(),
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::TestPa2.write()),
-            SpiTransaction::write(0x70),
-            SpiTransaction::transaction_end(),
-            // Read the current value of OpMode
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::OpMode.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0xC4]),
-            SpiTransaction::transaction_end(),
-            // Set the new mode, leaving the other bits unchanged
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::OpMode.write()),
-            SpiTransaction::write(0xD0),
-            SpiTransaction::transaction_end(),
-            // Wait for the mode to change
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::write(Register::DataModul.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x01]),
             SpiTransaction::transaction_end(),
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::write(Register::DataModul.write()),
+            SpiTransaction::write(0x03),
             SpiTransaction::transaction_end(),
         ];
-
-        let delay_expectations = [DelayTransaction::delay_ms(10)];
-
         rfm.spi.update_expectations(&spi_expectations);
-        rfm.delay.update_expectations(&delay_expectations);
 
-        rfm.set_mode(Rfm69Mode::Rx).await.unwrap();
+        rfm.set_shaping(Shaping::GaussianBt0_3).unwrap();
+        // OOK filtering with an FSK preset
+        assert_eq!(
+            rfm.set_shaping(Shaping::OokCutoffBitrate),
+            Err(Rfm69Error::ConfigurationError)
+        );
+        // Kept when the configuration is written again
+        assert_eq!(rfm.modem_values()[0], 0x03);
 
         check_expectations(&mut rfm);
     }
 
-    #[tokio::test]
-    async fn test_set_mode_tx() {
+    #[test]
+    fn test_save_config() {
         let mut rfm = setup_rfm();
-        rfm.tx_power = 18;
-
-        let spi_expectations = [
-            // If high power boost, return power amp to receive mode
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::TestPa1.write()),
-            SpiTransaction::write(0x5D),
-            SpiTransaction::transaction_end(),
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::TestPa2.write()),
-            SpiTransaction::write(0x7C),
-            SpiTransaction::transaction_end(),
-            // // Read the current value of OpMode
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::OpMode.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0xC4]),
-            SpiTransaction::transaction_end(),
-            // // Set the new mode, leaving the other bits unchanged
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::OpMode.write()),
-            SpiTransaction::write(0xCC),
-            SpiTransaction::transaction_end(),
-            // // Wait for the mode to change
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
-            SpiTransaction::transaction_end(),
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
-            SpiTransaction::transaction_end(),
-        ];
+        rfm.tx_power = 10;
 
-        let delay_expectations = [DelayTransaction::delay_ms(10)];
+        let mut buffer = [0u8; CONFIG_SNAPSHOT_SIZE - 1];
+        assert_eq!(
+            rfm.save_config(&mut buffer),
+            Err(Rfm69Error::BufferTooSmall)
+        );
 
+        let mut radio = config_snapshot();
+        // RestartRx is not saved
+        radio[CONFIG_SNAPSHOT_SIZE - 6] = 0x06;
+
+        let spi_expectations: Vec<SpiTransaction<u8>> = config_blocks(&radio)
+            .flat_map(|(register, block)| {
+                [
+                    SpiTransaction::transaction_start(),
+                    SpiTransaction::write(register.read()),
+                    SpiTransaction::transfer_in_place(vec![0x00; block.len()], block),
+                    SpiTransaction::transaction_end(),
+                ]
+            })
+            .collect();
         rfm.spi.update_expectations(&spi_expectations);
-        rfm.delay.update_expectations(&delay_expectations);
 
-        rfm.set_mode(Rfm69Mode::Tx).await.unwrap();
+        let mut buffer = [0u8; CONFIG_SNAPSHOT_SIZE + 4];
+        assert_eq!(rfm.save_config(&mut buffer), Ok(CONFIG_SNAPSHOT_SIZE));
+
+        let mut expected = radio;
+        expected[CONFIG_SNAPSHOT_SIZE - 6] = 0x02;
+        assert_eq!(buffer[..CONFIG_SNAPSHOT_SIZE], expected);
 
         check_expectations(&mut rfm);
     }
 
-    #[tokio::test]
-    async fn test_send_too_large() {
+    #[test]
+    fn test_restore_config() {
         let mut rfm = setup_rfm();
 
-        let message = ['a' as u8; 70];
+        let mut snapshot = config_snapshot();
+        assert_eq!(
+            rfm.restore_config(&snapshot[..CONFIG_SNAPSHOT_SIZE - 1]),
+            Err(Rfm69Error::ConfigurationError)
+        );
+        snapshot[0] = 0x00;
+        assert_eq!(
+            rfm.restore_config(&snapshot),
+            Err(Rfm69Error::ConfigurationError)
+        );
+        snapshot[0] = CONFIG_SNAPSHOT_MAGIC;
+
+        let spi_expectations: Vec<SpiTransaction<u8>> = config_blocks(&snapshot)
+            .flat_map(|(register, block)| {
+                [
+                    SpiTransaction::transaction_start(),
+                    SpiTransaction::write(register.write()),
+                    SpiTransaction::write_vec(block),
+                    SpiTransaction::transaction_end(),
+                ]
+            })
+            .collect();
+        rfm.spi.update_expectations(&spi_expectations);
 
-        assert_eq!(rfm.send(&message).await, Err(Rfm69Error::MessageTooLarge));
+        // Never with the high power settings outside of Tx, or without the
+        // overcurrent protection off
+        let mut boosted = snapshot;
+        boosted[snapshot_offset(Register::TestPa1).unwrap()] = RF_TESTPA1_BOOST;
+        assert_eq!(
+            rfm.restore_config(&boosted),
+            Err(Rfm69Error::PaBoost(PaBoostError::BoostOutsideTx))
+        );
+        let mut boosted = snapshot;
+        boosted[1] = 20;
+        assert_eq!(
+            rfm.restore_config(&boosted),
+            Err(Rfm69Error::PaBoost(PaBoostError::NoDutyCycleLimiter))
+        );
+        rfm.duty_cycle = Some(DutyCycleLimiter::new(|| 0, DutyCycleAction::Error));
+        boosted[snapshot_offset(Register::Ocp).unwrap()] = RF_OCP_ON;
+        assert_eq!(
+            rfm.restore_config(&boosted),
+            Err(Rfm69Error::PaBoost(PaBoostError::OcpEnabled))
+        );
+
+        rfm.restore_config(&snapshot).unwrap();
+
+        assert_eq!(rfm.tx_power, 10);
+        assert_eq!(rfm.frequency, Frequency::from_mhz(868));
+        assert_eq!(rfm.modem_config, ModemConfigChoice::GfskRb19_2Fd38_4);
+        assert_eq!(rfm.preamble_length, 8);
+        assert_eq!(
+            rfm.sync_configuration,
+            SyncConfiguration::FifoFillAuto { sync_tolerance: 0 }
+        );
+        assert_eq!(rfm.sync_length, 3);
+        assert_eq!(rfm.sync_words, [0xAA, 0xBB, 0xCC, 0, 0, 0, 0, 0]);
+        assert_eq!(rfm.payload_length(), None);
 
         check_expectations(&mut rfm);
     }
 
-    #[tokio::test]
-    async fn test_send() {
+    #[test]
+    fn test_interrupt_receive() {
         let mut rfm = setup_rfm();
-
-        let mut header = vec![17, 0xFF, 0xFF, 0x00, 0x00];
-        let mut message = "Hello, world!".as_bytes().to_vec();
-
-        header.append(&mut message);
+        rfm.op_mode = OpMode::from_bits(0xC4).unwrap();
 
         let spi_expectations = [
+            // Map PayloadReady to DIO0 and enter Rx
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Fifo.write()),
-            SpiTransaction::write_vec(header),
-            SpiTransaction::transaction_end(),
-            // // Read the current value of OpMode
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::OpMode.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0xC4]),
+            SpiTransaction::write(Register::DioMapping1.write()),
+            SpiTransaction::write(0x40),
             SpiTransaction::transaction_end(),
-            // // Set the new mode, leaving the other bits unchanged
             SpiTransaction::transaction_start(),
             SpiTransaction::write(Register::OpMode.write()),
-            SpiTransaction::write(0xCC),
+            SpiTransaction::write(0xD0),
             SpiTransaction::transaction_end(),
-            // // Wait for the mode to change
+            // The interrupt reads the packet
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x06]),
             SpiTransaction::transaction_end(),
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![6]),
             SpiTransaction::transaction_end(),
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags2.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x08]),
+            SpiTransaction::write(Register::Fifo.read()),
+            SpiTransaction::transfer_in_place(
+                vec![0x00; 6],
+                vec![0x01, 0x02, 0x03, 0x00, 0xAB, 0xCD],
+            ),
             SpiTransaction::transaction_end(),
-            // // // Read the current value of OpMode
+            // And keeps listening
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::OpMode.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0xC0]),
+            SpiTransaction::write(Register::PacketConfig2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x02]),
             SpiTransaction::transaction_end(),
-            // // // Set the new mode, leaving the other bits unchanged
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::OpMode.write()),
-            SpiTransaction::write(0xC4),
+            SpiTransaction::write(Register::PacketConfig2.write()),
+            SpiTransaction::write(0x06),
             SpiTransaction::transaction_end(),
-            // // // // Wait for the mode to change
+            // A spurious interrupt
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags1.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x80]),
+            SpiTransaction::write(Register::IrqFlags2.read()),
+            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
             SpiTransaction::transaction_end(),
         ];
-
-        let delay_expectations = [DelayTransaction::delay_ms(10)];
-
         rfm.spi.update_expectations(&spi_expectations);
-        rfm.delay.update_expectations(&delay_expectations);
 
-        let message = "Hello, world!".as_bytes();
+        rfm.start_receive().unwrap();
+        assert_eq!(rfm.interrupt_state(), InterruptState::Receiving);
+        assert_eq!(rfm.poll_event(), None);
 
-        rfm.send(message).await.unwrap();
+        rfm.on_interrupt().unwrap();
+        let Some(RadioEvent::PacketReceived(packet)) = rfm.poll_event() else {
+            panic!("expected a packet");
+        };
+        assert_eq!(packet.header.to, 0x01);
+        assert_eq!(packet.header.from, 0x02);
+        assert_eq!(packet.payload(), &[0xAB, 0xCD]);
+
+        rfm.on_interrupt().unwrap();
+        assert_eq!(rfm.poll_event(), None);
+        assert_eq!(rfm.interrupt_state(), InterruptState::Receiving);
+        assert_eq!(rfm.current_mode, Rfm69Mode::Rx);
 
         check_expectations(&mut rfm);
     }
 
-    #[tokio::test]
-    async fn test_receive() {
+    #[test]
+    fn test_interrupt_transmit() {
         let mut rfm = setup_rfm();
+        rfm.op_mode = OpMode::from_bits(0xC4).unwrap();
 
         let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Fifo.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![9]),
+            SpiTransaction::write(Register::Fifo.write()),
+            SpiTransaction::write_vec(vec![6, 0x02, 0x01, 0x00, 0x00, 0xAB, 0xCD]),
             SpiTransaction::transaction_end(),
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Fifo.read()),
-            SpiTransaction::transfer_in_place(
-                vec![0x00, 0x00, 0x00, 0x00],
-                vec![0x00, 0x00, 0x00, 0x00],
-            ),
+            SpiTransaction::write(Register::DioMapping1.write()),
+            SpiTransaction::write(0x00),
             SpiTransaction::transaction_end(),
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::Fifo.read()),
-            SpiTransaction::transfer_in_place(
-                vec![0x00, 0x00, 0x00, 0x00, 0x00],
-                vec![0x00, 0x00, 0x00, 0x00, 0x00],
-            ),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0xCC),
             SpiTransaction::transaction_end(),
-        ];
-
-        rfm.spi.update_expectations(&spi_expectations);
-
-        let mut buffer = [0u8; 65];
-
-        let message_len = rfm.receive(&mut buffer).await.unwrap();
-        assert_eq!(message_len, 5);
-
-        check_expectations(&mut rfm);
-    }
-
-    #[tokio::test]
-    async fn test_is_message_available() {
-        let mut rfm = setup_rfm();
-        rfm.current_mode = Rfm69Mode::Rx;
-
-        let spi_expectations = [
+            // PacketSent is set, return to Standby
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags2.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x04]),
+            SpiTransaction::write(Register::IrqFlags1.read()),
+            SpiTransaction::transfer_in_place(vec![0x00, 0x00], vec![0xA0, 0x08]),
             SpiTransaction::transaction_end(),
-        ];
-        rfm.spi.update_expectations(&spi_expectations);
-
-        assert_eq!(rfm.is_message_available().unwrap(), true);
-
-        let spi_expectations = [
             SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::IrqFlags2.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x00]),
+            SpiTransaction::write(Register::OpMode.write()),
+            SpiTransaction::write(0xC4),
             SpiTransaction::transaction_end(),
         ];
         rfm.spi.update_expectations(&spi_expectations);
 
-        assert_eq!(rfm.is_message_available().unwrap(), false);
-
-        rfm.current_mode = Rfm69Mode::Tx;
-        assert_eq!(rfm.is_message_available(), Err(Rfm69Error::InvalidMode));
+        let header = Header {
+            to: 0x02,
+            from: 0x01,
+            id: 0,
+            flags: 0,
+        };
+        rfm.start_transmit(header, &[0xAB, 0xCD]).unwrap();
+        assert_eq!(rfm.interrupt_state(), InterruptState::Transmitting);
+
+        rfm.on_interrupt().unwrap();
+        assert_eq!(rfm.poll_event(), Some(RadioEvent::PacketSent));
+        assert_eq!(rfm.poll_event(), None);
+        assert_eq!(rfm.interrupt_state(), InterruptState::Idle);
+        assert_eq!(rfm.current_mode, Rfm69Mode::Standby);
 
         check_expectations(&mut rfm);
     }
 
+    #[cfg(feature = "std")]
     #[test]
-    fn test_rssi() {
-        let mut rfm = setup_rfm();
-        rfm.current_mode = Rfm69Mode::Rx;
-
-        let spi_expectations = [
-            SpiTransaction::transaction_start(),
-            SpiTransaction::write(Register::RssiValue.read()),
-            SpiTransaction::transfer_in_place(vec![0x00], vec![0x50]),
-            SpiTransaction::transaction_end(),
-        ];
-        rfm.spi.update_expectations(&spi_expectations);
-
-        assert_eq!(rfm.rssi().unwrap(), 40);
+    fn test_error_display() {
+        let error = Rfm69Error::InvalidModulation(ModulationError::ModulationIndexTooLow);
+        assert_eq!(
+            error.to_string(),
+            "invalid modulation: modulation index below 0.5"
+        );
 
-        check_expectations(&mut rfm);
+        let error: Box<dyn std::error::Error> = Box::new(Rfm69Error::AckTimeout);
+        assert_eq!(error.to_string(), "no ACK received");
     }
 }
@@ -0,0 +1,415 @@
+use crate::header::Header;
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use crate::session::{authenticate, COUNTER_LENGTH, MAC_LENGTH};
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// Bytes added to every command: the rolling counter and the MAC.
+pub const ROLLING_CODE_OVERHEAD: usize = COUNTER_LENGTH + MAC_LENGTH;
+/// Longest command carried by a single message.
+pub const MAX_COMMAND_LENGTH: usize = RF69_MAX_MESSAGE_LEN - ROLLING_CODE_OVERHEAD;
+/// Counters a receiver accepts ahead of the next expected one, for commands
+/// sent while it was out of range.
+pub const DEFAULT_ACCEPT_WINDOW: u32 = 16;
+/// Counters further ahead than the accept window, up to this one, are accepted
+/// once the next counter follows.
+pub const RESYNC_WINDOW: u32 = 32_768;
+
+#[derive(Debug, PartialEq, defmt::Format)]
+pub enum RollingCodeError {
+    Radio(Rfm69Error),
+    /// The counter store failed to load or save the counter.
+    StoreError,
+    /// The command isn't from the paired transmitter, or was altered.
+    BadMac,
+    /// The counter was already used.
+    Replayed,
+    /// The counter is ahead of the accept window. The command is rejected, the
+    /// next one is accepted if its counter follows.
+    ResyncPending,
+    /// The counter is ahead of the resync window, the transmitter has to be paired again.
+    OutOfWindow,
+    /// The message is shorter than `ROLLING_CODE_OVERHEAD`.
+    Malformed,
+    /// The transmitter used up its counters, pair it again with a new key.
+    CounterExhausted,
+}
+
+impl From<Rfm69Error> for RollingCodeError {
+    fn from(error: Rfm69Error) -> Self {
+        RollingCodeError::Radio(error)
+    }
+}
+
+/// Non-volatile storage for a rolling counter, e.g. a flash page or an EEPROM
+/// word. A fresh store loads 0.
+pub trait CounterStore {
+    type Error;
+
+    fn load(&mut self) -> Result<u32, Self::Error>;
+
+    /// Saves `counter`, it has to survive a power loss once this returns.
+    fn save(&mut self, counter: u32) -> Result<(), Self::Error>;
+}
+
+/// Transmitting side of a rolling code, e.g. a remote control.
+///
+/// Every command is followed by a counter and a MAC over the addresses, the
+/// counter and the command, like `session::SecureSession`. The store holds the
+/// next counter, which is saved before the command is sent, so a counter is
+/// never used twice even if the transmitter loses power.
+pub struct RollingCodeSender<S> {
+    mac: Cmac<Aes128>,
+    store: S,
+}
+
+impl<S: CounterStore> RollingCodeSender<S> {
+    /// `key` is shared with the receiver only, use one key per transmitter.
+    pub fn new(key: &[u8; 16], store: S) -> Self {
+        RollingCodeSender {
+            mac: <Cmac<Aes128> as Mac>::new(key.into()),
+            store,
+        }
+    }
+
+    pub fn store(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn release(self) -> S {
+        self.store
+    }
+
+    /// Writes `command` followed by the counter and the MAC into `message`,
+    /// returning its length. `header` must be the header the message is sent with.
+    pub fn seal(
+        &mut self,
+        header: &Header,
+        command: &[u8],
+        message: &mut [u8],
+    ) -> Result<usize, RollingCodeError> {
+        if command.len() > MAX_COMMAND_LENGTH {
+            return Err(Rfm69Error::MessageTooLarge.into());
+        }
+        let length = command.len() + ROLLING_CODE_OVERHEAD;
+        if message.len() < length {
+            return Err(Rfm69Error::BufferTooSmall.into());
+        }
+        let counter = self
+            .store
+            .load()
+            .map_err(|_| RollingCodeError::StoreError)?;
+        let next = counter
+            .checked_add(1)
+            .ok_or(RollingCodeError::CounterExhausted)?;
+        self.store
+            .save(next)
+            .map_err(|_| RollingCodeError::StoreError)?;
+
+        let (body, tag) = message[..length].split_at_mut(length - MAC_LENGTH);
+        body[..command.len()].copy_from_slice(command);
+        body[command.len()..].copy_from_slice(&counter.to_be_bytes());
+        tag.copy_from_slice(
+            &authenticate(&self.mac, header, body)
+                .finalize()
+                .into_bytes()[..MAC_LENGTH],
+        );
+        Ok(length)
+    }
+
+    /// Sends `command` with `header`, see `seal`.
+    pub async fn send_with_header<SPI, RESET, INTR, D>(
+        &mut self,
+        radio: &mut Rfm69<SPI, RESET, INTR, D>,
+        header: Header,
+        command: &[u8],
+    ) -> Result<(), RollingCodeError>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+        let length = self.seal(&header, command, &mut message)?;
+        radio.send_with_header(header, &message[..length]).await?;
+        Ok(())
+    }
+}
+
+/// Receiving side of a rolling code, e.g. a garage door or relay controller,
+/// paired with a single transmitter.
+///
+/// A command is accepted if its counter is at most `window` ahead of the next
+/// expected one, which skips the commands sent out of range. A counter up to
+/// `RESYNC_WINDOW` ahead is only accepted if the next message carries the
+/// following counter, so a single recorded message can't move the receiver
+/// far ahead. The store holds the next expected counter and is saved before a
+/// command is accepted.
+pub struct RollingCodeReceiver<S> {
+    mac: Cmac<Aes128>,
+    store: S,
+    window: u32,
+    // Counter of the message that started a resync
+    resync: Option<u32>,
+}
+
+impl<S: CounterStore> RollingCodeReceiver<S> {
+    pub fn new(key: &[u8; 16], store: S) -> Self {
+        RollingCodeReceiver {
+            mac: <Cmac<Aes128> as Mac>::new(key.into()),
+            store,
+            window: DEFAULT_ACCEPT_WINDOW,
+            resync: None,
+        }
+    }
+
+    pub fn store(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    pub fn release(self) -> S {
+        self.store
+    }
+
+    pub fn window(&self) -> u32 {
+        self.window
+    }
+
+    /// Sets the accept window, at least 1 and below `RESYNC_WINDOW`.
+    pub fn set_window(&mut self, window: u32) -> Result<(), RollingCodeError> {
+        if window == 0 || window >= RESYNC_WINDOW {
+            return Err(Rfm69Error::ConfigurationError.into());
+        }
+        self.window = window;
+        Ok(())
+    }
+
+    /// Checks the MAC and the counter of `message` received with `header`, and
+    /// returns the length of the command at its start.
+    pub fn open(&mut self, header: &Header, message: &[u8]) -> Result<usize, RollingCodeError> {
+        if message.len() < ROLLING_CODE_OVERHEAD {
+            return Err(RollingCodeError::Malformed);
+        }
+        let (body, tag) = message.split_at(message.len() - MAC_LENGTH);
+        authenticate(&self.mac, header, body)
+            .verify_truncated_left(tag)
+            .map_err(|_| RollingCodeError::BadMac)?;
+
+        let command_length = body.len() - COUNTER_LENGTH;
+        let counter = u32::from_be_bytes(body[command_length..].try_into().unwrap());
+        let expected = self
+            .store
+            .load()
+            .map_err(|_| RollingCodeError::StoreError)?;
+        let ahead = counter
+            .checked_sub(expected)
+            .ok_or(RollingCodeError::Replayed)?;
+        let resynced = self.resync.take() == Some(counter.wrapping_sub(1));
+        if ahead >= RESYNC_WINDOW {
+            return Err(RollingCodeError::OutOfWindow);
+        }
+        if ahead >= self.window && !resynced {
+            self.resync = Some(counter);
+            return Err(RollingCodeError::ResyncPending);
+        }
+
+        self.store
+            .save(counter.saturating_add(1))
+            .map_err(|_| RollingCodeError::StoreError)?;
+        Ok(command_length)
+    }
+
+    /// Reads the received message like `Rfm69::receive` and checks
+    /// it, see `open`. Returns the header and the length of the command.
+    pub async fn receive_with_header<SPI, RESET, INTR, D>(
+        &mut self,
+        radio: &mut Rfm69<SPI, RESET, INTR, D>,
+        buffer: &mut [u8],
+    ) -> Result<(Header, usize), RollingCodeError>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+        let (header, length) = radio.receive(&mut message).await?;
+        let command_length = self.open(&header, &message[..length])?;
+        if buffer.len() < command_length {
+            return Err(Rfm69Error::BufferTooSmall.into());
+        }
+        buffer[..command_length].copy_from_slice(&message[..command_length]);
+        Ok((header, command_length))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+    const HEADER: Header = Header {
+        to: 0x01,
+        from: 0x02,
+        id: 0,
+        flags: 0,
+    };
+
+    #[derive(Default)]
+    struct RamStore {
+        counter: u32,
+        saves: usize,
+        failing: bool,
+    }
+
+    impl CounterStore for RamStore {
+        type Error = ();
+
+        fn load(&mut self) -> Result<u32, ()> {
+            Ok(self.counter)
+        }
+
+        fn save(&mut self, counter: u32) -> Result<(), ()> {
+            if self.failing {
+                return Err(());
+            }
+            self.counter = counter;
+            self.saves += 1;
+            Ok(())
+        }
+    }
+
+    fn sealed(sender: &mut RollingCodeSender<RamStore>, command: &[u8]) -> Vec<u8> {
+        let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+        let length = sender.seal(&HEADER, command, &mut message).unwrap();
+        message[..length].to_vec()
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let mut sender = RollingCodeSender::new(&KEY, RamStore::default());
+        let mut receiver = RollingCodeReceiver::new(&KEY, RamStore::default());
+
+        let message = sealed(&mut sender, b"open");
+        assert_eq!(message.len(), 4 + ROLLING_CODE_OVERHEAD);
+        assert_eq!(message[4..8], [0, 0, 0, 0]);
+        assert_eq!(sender.store().counter, 1);
+        assert_eq!(receiver.open(&HEADER, &message), Ok(4));
+        assert_eq!(&message[..4], b"open");
+        assert_eq!(receiver.store().counter, 1);
+        assert_eq!(
+            receiver.open(&HEADER, &message),
+            Err(RollingCodeError::Replayed)
+        );
+
+        let mut forged = sealed(&mut sender, b"open");
+        forged[0] ^= 0x01;
+        assert_eq!(
+            receiver.open(&HEADER, &forged),
+            Err(RollingCodeError::BadMac)
+        );
+        assert_eq!(
+            receiver.open(&HEADER, &forged[..4]),
+            Err(RollingCodeError::Malformed)
+        );
+        assert_eq!(receiver.store().saves, 1);
+    }
+
+    #[test]
+    fn test_accept_window() {
+        let mut sender = RollingCodeSender::new(&KEY, RamStore::default());
+        let mut receiver = RollingCodeReceiver::new(&KEY, RamStore::default());
+
+        // Pressed out of range
+        let messages: Vec<_> = (0..20).map(|_| sealed(&mut sender, b"")).collect();
+        assert_eq!(receiver.open(&HEADER, &messages[15]), Ok(0));
+        assert_eq!(
+            receiver.open(&HEADER, &messages[3]),
+            Err(RollingCodeError::Replayed)
+        );
+        assert_eq!(receiver.open(&HEADER, &messages[19]), Ok(0));
+
+        assert_eq!(
+            receiver.set_window(0),
+            Err(RollingCodeError::Radio(Rfm69Error::ConfigurationError))
+        );
+        receiver.set_window(2).unwrap();
+        let messages: Vec<_> = (0..3).map(|_| sealed(&mut sender, b"")).collect();
+        assert_eq!(
+            receiver.open(&HEADER, &messages[2]),
+            Err(RollingCodeError::ResyncPending)
+        );
+        assert_eq!(receiver.store().counter, 20);
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut sender = RollingCodeSender::new(&KEY, RamStore::default());
+        let mut receiver = RollingCodeReceiver::new(&KEY, RamStore::default());
+
+        sender.store().counter = 1_000;
+        let first = sealed(&mut sender, b"");
+        let second = sealed(&mut sender, b"");
+        let third = sealed(&mut sender, b"");
+
+        // A single message ahead of the window isn't enough
+        assert_eq!(
+            receiver.open(&HEADER, &first),
+            Err(RollingCodeError::ResyncPending)
+        );
+        assert_eq!(
+            receiver.open(&HEADER, &third),
+            Err(RollingCodeError::ResyncPending)
+        );
+        assert_eq!(
+            receiver.open(&HEADER, &first),
+            Err(RollingCodeError::ResyncPending)
+        );
+        assert_eq!(receiver.open(&HEADER, &second), Ok(0));
+        assert_eq!(receiver.store().counter, 1_002);
+        assert_eq!(receiver.open(&HEADER, &third), Ok(0));
+
+        sender.store().counter = 1_003 + RESYNC_WINDOW;
+        let message = sealed(&mut sender, b"");
+        assert_eq!(
+            receiver.open(&HEADER, &message),
+            Err(RollingCodeError::OutOfWindow)
+        );
+    }
+
+    #[test]
+    fn test_store_errors() {
+        let mut sender = RollingCodeSender::new(&KEY, RamStore::default());
+        let mut receiver = RollingCodeReceiver::new(&KEY, RamStore::default());
+
+        let message = sealed(&mut sender, b"");
+        sender.store().failing = true;
+        let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+        assert_eq!(
+            sender.seal(&HEADER, b"", &mut buffer),
+            Err(RollingCodeError::StoreError)
+        );
+        assert_eq!(sender.store().counter, 1);
+
+        // Not accepted unless the counter was saved
+        receiver.store().failing = true;
+        assert_eq!(
+            receiver.open(&HEADER, &message),
+            Err(RollingCodeError::StoreError)
+        );
+        receiver.store().failing = false;
+        assert_eq!(receiver.open(&HEADER, &message), Ok(0));
+
+        sender.store().failing = false;
+        sender.store().counter = u32::MAX;
+        assert_eq!(
+            sender.seal(&HEADER, b"", &mut buffer),
+            Err(RollingCodeError::CounterExhausted)
+        );
+    }
+}
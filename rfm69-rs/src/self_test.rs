@@ -0,0 +1,37 @@
+use crate::rfm69::ChipVersion;
+use core::ops::RangeInclusive;
+
+/// Temperatures the uncalibrated sensor can plausibly report, anything outside
+/// points at a broken sensor or bad SPI reads.
+pub const PLAUSIBLE_TEMPERATURE_CELSIUS: RangeInclusive<f32> = -40.0..=85.0;
+
+/// Outcome of `Rfm69::self_test`.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct SelfTestReport {
+    /// Contents of RegVersion.
+    pub version: u8,
+    /// None if `version` isn't a known SX1231 revision.
+    pub chip_version: Option<ChipVersion>,
+    /// The RC oscillator calibration completed.
+    pub rc_calibrated: bool,
+    pub temperature_celsius: f32,
+    /// None if the RSSI measurement didn't complete.
+    pub rssi_dbm: Option<i16>,
+    /// A test pattern written to the FIFO was read back unchanged.
+    pub fifo_ok: bool,
+}
+
+impl SelfTestReport {
+    pub fn temperature_plausible(&self) -> bool {
+        PLAUSIBLE_TEMPERATURE_CELSIUS.contains(&self.temperature_celsius)
+    }
+
+    /// True if every check passed.
+    pub fn passed(&self) -> bool {
+        self.chip_version.is_some()
+            && self.rc_calibrated
+            && self.temperature_plausible()
+            && self.rssi_dbm.is_some()
+            && self.fifo_ok
+    }
+}
@@ -0,0 +1,385 @@
+use crate::header::Header;
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// Length of the big-endian message counter in front of the MAC.
+pub const COUNTER_LENGTH: usize = 4;
+/// Length of the truncated AES-CMAC at the end of every message.
+pub const MAC_LENGTH: usize = 4;
+/// Bytes added to every payload: the message counter and the MAC.
+pub const SESSION_OVERHEAD: usize = COUNTER_LENGTH + MAC_LENGTH;
+/// Payload carried by a single message.
+pub const MAX_PAYLOAD_LENGTH: usize = RF69_MAX_MESSAGE_LEN - SESSION_OVERHEAD;
+// Counters accepted out of order below the highest one received
+const REPLAY_WINDOW: u32 = 32;
+
+#[derive(Debug, PartialEq, defmt::Format)]
+pub enum SessionError {
+    Radio(Rfm69Error),
+    /// The MAC doesn't match, the message was forged, altered or sent with another key.
+    BadMac,
+    /// The counter was already received, or is too old to tell.
+    Replayed,
+    /// Every sender slot is taken by another node.
+    TooManyPeers,
+    /// The message is too short to carry a counter and a MAC.
+    Malformed,
+    /// The message counter reached its maximum, the key has to be changed.
+    CounterExhausted,
+}
+
+impl From<Rfm69Error> for SessionError {
+    fn from(error: Rfm69Error) -> Self {
+        SessionError::Radio(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+struct ReplayWindow {
+    address: u8,
+    highest: u32,
+    // Bit n is set if `highest - n` was received
+    received: u32,
+}
+
+impl ReplayWindow {
+    fn accepts(&self, counter: u32) -> bool {
+        match self.highest.checked_sub(counter) {
+            None => true,
+            Some(age) => age < REPLAY_WINDOW && self.received & (1 << age) == 0,
+        }
+    }
+
+    fn record(&mut self, counter: u32) {
+        match counter.checked_sub(self.highest) {
+            Some(0) => {}
+            Some(ahead) => {
+                self.received = self.received.checked_shl(ahead).unwrap_or(0) | 1;
+                self.highest = counter;
+            }
+            None => self.received |= 1 << (self.highest - counter),
+        }
+    }
+}
+
+// The MAC covers the addresses and flags, the counter and the payload. The id
+// is left out, it changes on retransmissions
+pub(crate) fn authenticate(mac: &Cmac<Aes128>, header: &Header, body: &[u8]) -> Cmac<Aes128> {
+    let mut mac = mac.clone();
+    mac.update(&[header.to, header.from, header.flags]);
+    mac.update(body);
+    mac
+}
+
+/// Authenticated messages with replay protection on top of `Rfm69`.
+///
+/// Every payload is followed by a message counter and a MAC over the addresses,
+/// the counter and the payload, computed with AES-CMAC in software and
+/// truncated to `MAC_LENGTH` bytes. A receiver accepts every counter once and
+/// tolerates reordering within the last 32 messages of each sender, tracking up
+/// to `N` senders. Combine it with `Rfm69::set_encryption_key` to also hide
+/// the payload.
+///
+/// The counters must survive a restart, or a recorded message is accepted
+/// again: persist `tx_counter` and `last_counter` and restore them with
+/// `set_tx_counter` and `set_last_counter`.
+///
+/// The session keeps the expanded key, with the `zeroize` feature it is wiped
+/// when the session is dropped.
+pub struct SecureSession<const N: usize> {
+    mac: Cmac<Aes128>,
+    tx_counter: u32,
+    peers: [Option<ReplayWindow>; N],
+}
+
+impl<const N: usize> SecureSession<N> {
+    /// `key` authenticates the messages, use a different key than the radio AES key.
+    pub fn new(key: &[u8; 16]) -> Self {
+        SecureSession {
+            mac: <Cmac<Aes128> as Mac>::new(key.into()),
+            tx_counter: 0,
+            peers: [None; N],
+        }
+    }
+
+    /// Counter of the next message sent.
+    pub fn tx_counter(&self) -> u32 {
+        self.tx_counter
+    }
+
+    pub fn set_tx_counter(&mut self, counter: u32) {
+        self.tx_counter = counter;
+    }
+
+    /// Highest counter received from `address`.
+    pub fn last_counter(&self, address: u8) -> Option<u32> {
+        self.window(address).map(|window| window.highest)
+    }
+
+    /// Rejects messages from `address` up to `counter`, e.g. after a restart.
+    pub fn set_last_counter(&mut self, address: u8, counter: u32) -> Result<(), SessionError> {
+        let window = ReplayWindow {
+            address,
+            highest: counter,
+            received: u32::MAX,
+        };
+        match self.window_slot(address) {
+            Some(slot) => {
+                *slot = Some(window);
+                Ok(())
+            }
+            None => Err(SessionError::TooManyPeers),
+        }
+    }
+
+    /// Writes `payload` followed by the counter and the MAC into `message`,
+    /// returning its length. `header` must be the header the message is sent with.
+    pub fn seal(
+        &mut self,
+        header: &Header,
+        payload: &[u8],
+        message: &mut [u8],
+    ) -> Result<usize, SessionError> {
+        if payload.len() > MAX_PAYLOAD_LENGTH {
+            return Err(Rfm69Error::MessageTooLarge.into());
+        }
+        let length = payload.len() + SESSION_OVERHEAD;
+        if message.len() < length {
+            return Err(Rfm69Error::BufferTooSmall.into());
+        }
+        let counter = self.tx_counter;
+        self.tx_counter = counter
+            .checked_add(1)
+            .ok_or(SessionError::CounterExhausted)?;
+
+        let (body, tag) = message[..length].split_at_mut(length - MAC_LENGTH);
+        body[..payload.len()].copy_from_slice(payload);
+        body[payload.len()..].copy_from_slice(&counter.to_be_bytes());
+        tag.copy_from_slice(
+            &authenticate(&self.mac, header, body)
+                .finalize()
+                .into_bytes()[..MAC_LENGTH],
+        );
+        Ok(length)
+    }
+
+    /// Checks the MAC and the counter of `message` received with `header`, and
+    /// returns the length of the payload at its start.
+    pub fn open(&mut self, header: &Header, message: &[u8]) -> Result<usize, SessionError> {
+        if message.len() < SESSION_OVERHEAD {
+            return Err(SessionError::Malformed);
+        }
+        let (body, tag) = message.split_at(message.len() - MAC_LENGTH);
+        authenticate(&self.mac, header, body)
+            .verify_truncated_left(tag)
+            .map_err(|_| SessionError::BadMac)?;
+
+        let payload_length = body.len() - COUNTER_LENGTH;
+        let counter = u32::from_be_bytes(body[payload_length..].try_into().unwrap());
+        // Only authenticated messages take a slot
+        let slot = self
+            .window_slot(header.from)
+            .ok_or(SessionError::TooManyPeers)?;
+        match slot {
+            Some(window) if !window.accepts(counter) => return Err(SessionError::Replayed),
+            Some(window) => window.record(counter),
+            None => {
+                *slot = Some(ReplayWindow {
+                    address: header.from,
+                    highest: counter,
+                    received: 1,
+                })
+            }
+        }
+        Ok(payload_length)
+    }
+
+    /// Sends `payload` with `header`, see `seal`.
+    pub async fn send_with_header<SPI, RESET, INTR, D>(
+        &mut self,
+        radio: &mut Rfm69<SPI, RESET, INTR, D>,
+        header: Header,
+        payload: &[u8],
+    ) -> Result<(), SessionError>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+        let length = self.seal(&header, payload, &mut message)?;
+        radio.send_with_header(header, &message[..length]).await?;
+        Ok(())
+    }
+
+    /// Reads the received message like `Rfm69::receive` and checks
+    /// it, see `open`. Returns the header and the length of the payload.
+    pub async fn receive_with_header<SPI, RESET, INTR, D>(
+        &mut self,
+        radio: &mut Rfm69<SPI, RESET, INTR, D>,
+        buffer: &mut [u8],
+    ) -> Result<(Header, usize), SessionError>
+    where
+        SPI: ReadWrite,
+        RESET: OutputPin,
+        INTR: InputPin + Wait,
+        D: DelayNs,
+    {
+        let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+        let (header, length) = radio.receive(&mut message).await?;
+        let payload_length = self.open(&header, &message[..length])?;
+        if buffer.len() < payload_length {
+            return Err(Rfm69Error::BufferTooSmall.into());
+        }
+        buffer[..payload_length].copy_from_slice(&message[..payload_length]);
+        Ok((header, payload_length))
+    }
+
+    fn window(&self, address: u8) -> Option<&ReplayWindow> {
+        self.peers
+            .iter()
+            .flatten()
+            .find(|window| window.address == address)
+    }
+
+    // The slot of `address`, or a free one
+    fn window_slot(&mut self, address: u8) -> Option<&mut Option<ReplayWindow>> {
+        let index = self
+            .peers
+            .iter()
+            .position(|slot| slot.is_some_and(|window| window.address == address))
+            .or_else(|| self.peers.iter().position(Option::is_none))?;
+        Some(&mut self.peers[index])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+
+    fn header(from: u8) -> Header {
+        Header {
+            to: 0x01,
+            from,
+            id: 0,
+            flags: 0,
+        }
+    }
+
+    fn sealed(sender: &mut SecureSession<1>, header: &Header, payload: &[u8]) -> Vec<u8> {
+        let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+        let length = sender.seal(header, payload, &mut message).unwrap();
+        message[..length].to_vec()
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let mut sender = SecureSession::<1>::new(&KEY);
+        let mut receiver = SecureSession::<1>::new(&KEY);
+        let header = header(0x02);
+
+        let message = sealed(&mut sender, &header, b"open relay");
+        assert_eq!(message.len(), 10 + SESSION_OVERHEAD);
+        assert_eq!(message[10..14], [0, 0, 0, 0]);
+        assert_eq!(sender.tx_counter(), 1);
+        assert_eq!(receiver.open(&header, &message), Ok(10));
+        assert_eq!(&message[..10], b"open relay");
+        assert_eq!(receiver.last_counter(0x02), Some(0));
+
+        // Retransmissions with a new id are still the same message
+        let retransmission = Header { id: 7, ..header };
+        assert_eq!(
+            receiver.open(&retransmission, &message),
+            Err(SessionError::Replayed)
+        );
+    }
+
+    #[test]
+    fn test_forged() {
+        let mut sender = SecureSession::<1>::new(&KEY);
+        let mut receiver = SecureSession::<1>::new(&KEY);
+        let header = header(0x02);
+
+        let mut message = sealed(&mut sender, &header, b"open relay");
+        let redirected = Header { to: 0x03, ..header };
+        assert_eq!(
+            receiver.open(&redirected, &message),
+            Err(SessionError::BadMac)
+        );
+        message[0] ^= 0x01;
+        assert_eq!(receiver.open(&header, &message), Err(SessionError::BadMac));
+        assert_eq!(
+            receiver.open(&header, &message[..4]),
+            Err(SessionError::Malformed)
+        );
+
+        let mut other_key = SecureSession::<1>::new(b"fedcba9876543210");
+        let message = sealed(&mut other_key, &header, b"open relay");
+        assert_eq!(receiver.open(&header, &message), Err(SessionError::BadMac));
+        assert_eq!(receiver.last_counter(0x02), None);
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut sender = SecureSession::<1>::new(&KEY);
+        let mut receiver = SecureSession::<1>::new(&KEY);
+        let header = header(0x02);
+
+        let messages: Vec<_> = (0..40).map(|_| sealed(&mut sender, &header, b"")).collect();
+        assert_eq!(receiver.open(&header, &messages[5]), Ok(0));
+        // Reordered
+        assert_eq!(receiver.open(&header, &messages[3]), Ok(0));
+        assert_eq!(
+            receiver.open(&header, &messages[3]),
+            Err(SessionError::Replayed)
+        );
+        assert_eq!(receiver.open(&header, &messages[39]), Ok(0));
+        // Older than the window
+        assert_eq!(
+            receiver.open(&header, &messages[6]),
+            Err(SessionError::Replayed)
+        );
+        assert_eq!(receiver.open(&header, &messages[8]), Ok(0));
+
+        // Another sender doesn't fit
+        let other = Header {
+            from: 0x03,
+            ..header
+        };
+        let message = sealed(&mut sender, &other, b"");
+        assert_eq!(
+            receiver.open(&other, &message),
+            Err(SessionError::TooManyPeers)
+        );
+    }
+
+    #[test]
+    fn test_restored_counters() {
+        let mut sender = SecureSession::<1>::new(&KEY);
+        let mut receiver = SecureSession::<1>::new(&KEY);
+        let header = header(0x02);
+
+        let old = sealed(&mut sender, &header, b"");
+        sender.set_tx_counter(100);
+        let new = sealed(&mut sender, &header, b"");
+
+        receiver.set_last_counter(0x02, 99).unwrap();
+        assert_eq!(receiver.open(&header, &old), Err(SessionError::Replayed));
+        assert_eq!(receiver.open(&header, &new), Ok(0));
+
+        sender.set_tx_counter(u32::MAX);
+        let mut message = [0u8; RF69_MAX_MESSAGE_LEN];
+        assert_eq!(
+            sender.seal(&header, b"", &mut message),
+            Err(SessionError::CounterExhausted)
+        );
+    }
+}
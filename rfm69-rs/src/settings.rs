@@ -2,17 +2,20 @@
 
 // The crystal oscillator frequency of the RF69 module
 pub const RF69_FXOSC: f32 = 32000000.0 / 1000000.0;
+pub const RF69_FXOSC_HZ: u32 = 32_000_000;
 
 // The Frequency Synthesizer step = RF69_FXOSC / 2^^19
 pub const RF69_FSTEP: u32 = 524288;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
 pub enum ContinuousDagc {
     NormalMode = 0x00,
     ImprovedLowBeta0 = 0x20,
     ImprovedLowBeta1 = 0x30,
 }
 
-
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SyncConfiguration {
     SyncOff,
     FifoFillAuto { sync_tolerance: u8 },
@@ -24,13 +27,34 @@ impl SyncConfiguration {
         match self {
             Self::SyncOff => 0x00,
             Self::FifoFillAuto { sync_tolerance } => {
-                0x80 | 0x00 | (sync_size.clamp(1, 8) - 1) << 3 | sync_tolerance.clamp(0, 7)
+                0x80 | (sync_size.clamp(1, 8) - 1) << 3 | sync_tolerance.clamp(0, 7)
             }
             Self::FifoFillManual { sync_tolerance } => {
                 0x80 | 0x40 | (sync_size.clamp(1, 8) - 1) << 3 | sync_tolerance.clamp(0, 7)
             }
         }
     }
+
+    /// Decodes a RegSyncConfig value into the configuration and the sync word size.
+    pub fn from_value(value: u8) -> (Self, u8) {
+        let sync_size = ((value >> 3) & 0x07) + 1;
+        let sync_tolerance = value & 0x07;
+        let config = match value & 0xC0 {
+            0x80 => Self::FifoFillAuto { sync_tolerance },
+            0xC0 => Self::FifoFillManual { sync_tolerance },
+            _ => Self::SyncOff,
+        };
+        (config, sync_size)
+    }
+}
+
+/// When the transmitter starts sending the packet written to the FIFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum TxStartCondition {
+    /// When the FIFO holds more bytes than the threshold.
+    FifoLevel,
+    /// As soon as the first byte is written.
+    FifoNotEmpty,
 }
 
 #[cfg(test)]
@@ -62,10 +86,31 @@ mod test {
             assert_eq!(test_case.0.value(test_case.1), test_case.2);
         });
     }
+
+    #[test]
+    fn test_sync_configuration_from_value() {
+        assert_eq!(
+            SyncConfiguration::from_value(0x88),
+            (SyncConfiguration::FifoFillAuto { sync_tolerance: 0 }, 2)
+        );
+        assert_eq!(
+            SyncConfiguration::from_value(255),
+            (SyncConfiguration::FifoFillManual { sync_tolerance: 7 }, 8)
+        );
+        assert_eq!(SyncConfiguration::from_value(0x00).0, SyncConfiguration::SyncOff);
+    }
+
+    #[test]
+    fn test_modem_config_bitrate() {
+        assert_eq!(ModemConfigChoice::GfskRb250Fd250.bitrate(), 250_000);
+        assert_eq!(ModemConfigChoice::FskRb2Fd5.bitrate(), 2_000);
+        assert_eq!(ModemConfigChoice::OokRb1_2Bw75.bitrate(), 1_200);
+    }
 }
 
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
 pub struct ModemConfig {
     reg_02: u8,
     reg_03: u8,
@@ -121,7 +166,8 @@ const CONFIG_MANCHESTER: u8 = RH_RF69_PACKETCONFIG1_PACKETFORMAT_VARIABLE
     | RH_RF69_PACKETCONFIG1_ADDRESSFILTERING_NONE;
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ModemConfigChoice {
     FskRb2Fd5,       // FSK, Whitening, Rb = 2kbs,    Fd = 5kHz
     FskRb2_4Fd4_8,   // FSK, Whitening, Rb = 2.4kbs,  Fd = 4.8kHz
@@ -156,6 +202,43 @@ pub enum ModemConfigChoice {
 
 
 impl ModemConfigChoice {
+    pub const ALL: [ModemConfigChoice; 27] = [
+        Self::FskRb2Fd5,
+        Self::FskRb2_4Fd4_8,
+        Self::FskRb4_8Fd9_6,
+        Self::FskRb9_6Fd19_2,
+        Self::FskRb19_2Fd38_4,
+        Self::FskRb38_4Fd76_8,
+        Self::FskRb57_6Fd120,
+        Self::FskRb125Fd125,
+        Self::FskRb250Fd250,
+        Self::FskRb55555Fd50,
+        Self::GfskRb2Fd5,
+        Self::GfskRb2_4Fd4_8,
+        Self::GfskRb4_8Fd9_6,
+        Self::GfskRb9_6Fd19_2,
+        Self::GfskRb19_2Fd38_4,
+        Self::GfskRb38_4Fd76_8,
+        Self::GfskRb57_6Fd120,
+        Self::GfskRb125Fd125,
+        Self::GfskRb250Fd250,
+        Self::GfskRb55555Fd50,
+        Self::OokRb1Bw1,
+        Self::OokRb1_2Bw75,
+        Self::OokRb2_4Bw4_8,
+        Self::OokRb4_8Bw9_6,
+        Self::OokRb9_6Bw19_2,
+        Self::OokRb19_2Bw38_4,
+        Self::OokRb32Bw64,
+    ];
+
+    /// Finds the preset matching the register values returned by `values()`.
+    pub fn from_values(values: &[u8; 8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|config| config.values() == values)
+    }
+
     const FSK_RB2_FD5: [u8; 8] = [CONFIG_FSK, 0x3e, 0x80, 0x00, 0x52, 0xf4, 0xf4, CONFIG_WHITE];
     const FSK_RB2_4FD4_8: [u8; 8] = [CONFIG_FSK, 0x34, 0x15, 0x00, 0x4f, 0xf4, 0xf4, CONFIG_WHITE];
     const FSK_RB4_8FD9_6: [u8; 8] = [CONFIG_FSK, 0x1a, 0x0b, 0x00, 0x9d, 0xf4, 0xf4, CONFIG_WHITE];
@@ -225,6 +308,13 @@ impl ModemConfigChoice {
     }
 }
 
+    /// Bitrate in bits per second.
+    pub fn bitrate(&self) -> u32 {
+        let values = self.values();
+        let divider = u16::from_be_bytes([values[1], values[2]]) as u32;
+        (RF69_FXOSC_HZ + divider / 2) / divider
+    }
+
 }
 
 pub const RF_PALEVEL_PA0_ON: u8 = 0x80;
@@ -234,4 +324,48 @@ pub const RF_PALEVEL_PA2_ON: u8 = 0x20;
 pub const RF_PALEVEL_OUTPUTPOWER_11111: u8 = 0x1f;
 
 pub const RF_DIOMAPPING1_DIO0_00: u8 = 0x00;
-pub const RF_DIOMAPPING1_DIO0_01: u8 = 0x40;
\ No newline at end of file
+pub const RF_DIOMAPPING1_DIO0_01: u8 = 0x40;
+// ClkOut field of RegDioMapping2, FXOSC/32 after reset
+pub const RF_DIOMAPPING2_CLKOUT_OFF: u8 = 0x07;
+pub const RF_IRQFLAGS1_MODEREADY: u8 = 0x80;
+
+pub const RF_IRQFLAGS2_FIFOFULL: u8 = 0x80;
+pub const RF_IRQFLAGS2_FIFONOTEMPTY: u8 = 0x40;
+pub const RF_IRQFLAGS2_FIFOLEVEL: u8 = 0x20;
+pub const RF_IRQFLAGS2_FIFOOVERRUN: u8 = 0x10;
+pub const RF_IRQFLAGS2_PACKETSENT: u8 = 0x08;
+pub const RF_IRQFLAGS2_PAYLOADREADY: u8 = 0x04;
+pub const RF_IRQFLAGS2_CRCOK: u8 = 0x02;
+
+pub const RF_TEMP1_MEAS_START: u8 = 0x08;
+pub const RF_TEMP1_MEAS_RUNNING: u8 = 0x04;
+
+pub const RF_OSC1_RCCAL_START: u8 = 0x80;
+pub const RF_OSC1_RCCAL_DONE: u8 = 0x40;
+
+pub const RF_RSSI_START: u8 = 0x01;
+pub const RF_RSSI_DONE: u8 = 0x02;
+
+// PA settings for +20 dBm on the high power modules, only allowed while transmitting
+pub const RF_TESTPA1_NORMAL: u8 = 0x55;
+pub const RF_TESTPA1_BOOST: u8 = 0x5D;
+pub const RF_TESTPA2_NORMAL: u8 = 0x70;
+pub const RF_TESTPA2_BOOST: u8 = 0x7C;
+
+// Overcurrent protection at 95 mA, it has to be off for +20 dBm
+pub const RF_OCP_ON: u8 = 0x1A;
+pub const RF_OCP_OFF: u8 = 0x0F;
+
+// LNA sensitivity, see `Rfm69::set_sensitivity_boost`
+pub const RF_TESTLNA_NORMAL: u8 = 0x1B;
+pub const RF_TESTLNA_HIGH_SENSITIVITY: u8 = 0x2D;
+
+// The FIFO is 66 bytes deep
+pub const RF69_FIFO_SIZE: usize = 66;
+
+// Largest payload of a single packet in every configuration, the RadioHead limit. See
+// `Rfm69::max_payload_length` for the limit of the current one
+pub const RF69_MAX_MESSAGE_LEN: usize = 60;
+
+// The FIFO threshold programmed by `init`
+pub const RF69_FIFO_THRESHOLD: usize = 15;
@@ -0,0 +1,185 @@
+use crate::header::Header;
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use core::cell::Cell;
+use core::ops::{Deref, DerefMut};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_sync::signal::Signal;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// `Rfm69` shared between tasks: any number of tasks send, one task receives.
+///
+/// The receiving task keeps the radio in Rx and waits for DIO0 while holding it.
+/// A sender interrupts that wait, and the receiver only takes the radio back once
+/// no sender is waiting for it. Packets arriving while a sender has the radio
+/// are lost.
+///
+/// `M` is the embassy-sync mutex kind, e.g. `CriticalSectionRawMutex` when
+/// sharing the radio with interrupt priority executors.
+pub struct SharedRfm69<M: RawMutex, SPI, RESET, INTR, D> {
+    radio: Mutex<M, Rfm69<SPI, RESET, INTR, D>>,
+    // Tasks waiting for or holding the radio through `lock`
+    senders: BlockingMutex<M, Cell<usize>>,
+    sender_waiting: Signal<M, ()>,
+    senders_done: Signal<M, ()>,
+}
+
+/// Exclusive access to the radio, returned by `SharedRfm69::lock`.
+pub struct SharedRfm69Guard<'a, M: RawMutex, SPI, RESET, INTR, D> {
+    shared: &'a SharedRfm69<M, SPI, RESET, INTR, D>,
+    radio: MutexGuard<'a, M, Rfm69<SPI, RESET, INTR, D>>,
+}
+
+impl<M, SPI, RESET, INTR, D> SharedRfm69<M, SPI, RESET, INTR, D>
+where
+    M: RawMutex,
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    /// Shares an initialized radio.
+    pub fn new(radio: Rfm69<SPI, RESET, INTR, D>) -> Self {
+        SharedRfm69 {
+            radio: Mutex::new(radio),
+            senders: BlockingMutex::new(Cell::new(0)),
+            sender_waiting: Signal::new(),
+            senders_done: Signal::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> Rfm69<SPI, RESET, INTR, D> {
+        self.radio.into_inner()
+    }
+
+    /// Waits for exclusive access to the radio, interrupting a pending
+    /// `receive`. Use it to configure the radio or for a sequence of operations.
+    pub async fn lock(&self) -> SharedRfm69Guard<'_, M, SPI, RESET, INTR, D> {
+        self.senders.lock(|senders| senders.set(senders.get() + 1));
+        self.sender_waiting.signal(());
+        SharedRfm69Guard {
+            shared: self,
+            radio: self.radio.lock().await,
+        }
+    }
+
+    pub async fn send(&self, data: &[u8]) -> Result<(), Rfm69Error> {
+        self.lock().await.send(data).await
+    }
+
+    pub async fn send_to(&self, to: u8, data: &[u8]) -> Result<(), Rfm69Error> {
+        self.lock().await.send_to(to, data).await
+    }
+
+    pub async fn send_with_header(&self, header: Header, data: &[u8]) -> Result<(), Rfm69Error> {
+        self.lock().await.send_with_header(header, data).await
+    }
+
+    /// Waits for a packet like `Rfm69::receive`. Only one task may receive.
+    pub async fn receive(&self, buffer: &mut [u8]) -> Result<(Header, usize), Rfm69Error> {
+        loop {
+            while self.senders.lock(Cell::get) > 0 {
+                self.senders_done.wait().await;
+            }
+
+            let mut radio = self.radio.lock().await;
+            radio.enter_rx().await?;
+            if radio.is_message_available()? {
+                return radio.receive(buffer).await;
+            }
+
+            let packet = radio.intr_pin.wait_for_high();
+            if let Either::First(result) = select(packet, self.sender_waiting.wait()).await {
+                result.map_err(|_| Rfm69Error::InterruptPinError)?;
+            }
+        }
+    }
+}
+
+impl<M: RawMutex, SPI, RESET, INTR, D> Deref for SharedRfm69Guard<'_, M, SPI, RESET, INTR, D> {
+    type Target = Rfm69<SPI, RESET, INTR, D>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.radio
+    }
+}
+
+impl<M: RawMutex, SPI, RESET, INTR, D> DerefMut for SharedRfm69Guard<'_, M, SPI, RESET, INTR, D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.radio
+    }
+}
+
+impl<M: RawMutex, SPI, RESET, INTR, D> Drop for SharedRfm69Guard<'_, M, SPI, RESET, INTR, D> {
+    fn drop(&mut self) {
+        let senders = self.shared.senders.lock(|senders| {
+            senders.set(senders.get() - 1);
+            senders.get()
+        });
+        if senders == 0 {
+            self.shared.senders_done.signal(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registers::Register;
+    use crate::test_utils::{
+        check_expectations, read_many, read_register, setup_rfm, write_register, GpioTransaction,
+        State,
+    };
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    #[tokio::test]
+    async fn test_receive() {
+        let spi_expectations = [
+            // Enter Rx with PayloadReady on DIO0
+            write_register(Register::DioMapping1, 0x40),
+            write_register(Register::OpMode, 0x10),
+            read_register(Register::IrqFlags1, 0x80),
+            // Nothing received yet, wait for DIO0
+            read_many(Register::IrqFlags1, &[0x80, 0x00]),
+            read_many(Register::IrqFlags1, &[0x80, 0x04]),
+            // Read the packet
+            read_register(Register::IrqFlags2, 0x06),
+            read_register(Register::Fifo, 6),
+            read_many(Register::Fifo, &[0x01, 0x02, 0x00, 0x00, 0xAB, 0xCD]),
+        ]
+        .concat();
+        let mut radio = setup_rfm();
+        radio.spi.update_expectations(&spi_expectations);
+        radio
+            .intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+        let shared = SharedRfm69::<NoopRawMutex, _, _, _, _>::new(radio);
+
+        let mut buffer = [0u8; 4];
+        let (header, length) = shared.receive(&mut buffer).await.unwrap();
+        assert_eq!((header.to, header.from), (0x01, 0x02));
+        assert_eq!(&buffer[..length], &[0xAB, 0xCD]);
+
+        check_expectations(&mut shared.into_inner());
+    }
+
+    #[tokio::test]
+    async fn test_lock() {
+        let radio = setup_rfm();
+        let shared = SharedRfm69::<NoopRawMutex, _, _, _, _>::new(radio);
+
+        {
+            let mut radio = shared.lock().await;
+            radio.set_link_stats(true);
+            assert_eq!(shared.senders.lock(Cell::get), 1);
+        }
+        assert_eq!(shared.senders.lock(Cell::get), 0);
+        assert!(shared.lock().await.link_stats().is_some());
+
+        check_expectations(&mut shared.into_inner());
+    }
+}
@@ -0,0 +1,715 @@
+use crate::read_write::ReadWrite;
+use crate::registers::{AddressFiltering, PacketConfig1, PacketConfig2, Register};
+use crate::retry::Jitter;
+use crate::rfm69::{Rfm69, Rfm69Mode};
+use crate::settings::{
+    RF69_FIFO_SIZE, RF_DIOMAPPING1_DIO0_01, RF_IRQFLAGS1_MODEREADY, RF_IRQFLAGS2_CRCOK,
+    RF_IRQFLAGS2_FIFONOTEMPTY, RF_IRQFLAGS2_FIFOOVERRUN, RF_IRQFLAGS2_PACKETSENT,
+    RF_IRQFLAGS2_PAYLOADREADY, RF_RSSI_DONE,
+};
+use core::convert::Infallible;
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+const IRQFLAGS1_RXREADY: u8 = 0x40;
+const IRQFLAGS1_TXREADY: u8 = 0x20;
+const IRQFLAGS1_SYNCADDRESSMATCH: u8 = 0x01;
+const VERSION: u8 = 0x24;
+
+/// Driver running on a simulated radio, see `SimChannel`.
+pub type SimRfm69 = Rfm69<SimSpi, SimResetPin, SimDio0, SimDelay>;
+
+/// In-memory RF channel connecting simulated radios, to test the protocol
+/// layers end to end on the host without hardware.
+///
+/// Each radio models the registers, FIFO, modes and interrupt flags the driver
+/// uses. A packet is sent as soon as a radio enters Tx and is received by every
+/// other radio in Rx with an empty FIFO whose address filter accepts it, so
+/// radios that are busy, or not listening, miss it like real ones would. The
+/// receiver frames it with its own packet format, fixed or variable length. AES,
+/// Listen mode and the analog parts are not modelled.
+///
+/// Losses and corruption are drawn from a seeded generator, so a test sees the
+/// same packets go missing on every run. Radios are driven from a single task,
+/// e.g. with `embassy_futures::join`, `SimDelay` yields instead of waiting.
+#[derive(Clone)]
+pub struct SimChannel {
+    state: Rc<RefCell<ChannelState>>,
+}
+
+struct ChannelState {
+    radios: Vec<RadioState>,
+    random: Jitter,
+    loss_percent: u8,
+    corruption_percent: u8,
+    rssi_dbm: i16,
+    transmissions: u32,
+    deliveries: u32,
+}
+
+struct RadioState {
+    registers: [u8; 0x80],
+    fifo: VecDeque<u8>,
+    fifo_overrun: bool,
+    packet_sent: bool,
+    payload_ready: bool,
+    crc_ok: bool,
+}
+
+impl RadioState {
+    fn new() -> Self {
+        let mut registers = [0u8; 0x80];
+        registers[Register::OpMode.addr() as usize] = Rfm69Mode::Standby as u8;
+        registers[Register::Version.addr() as usize] = VERSION;
+        RadioState {
+            registers,
+            fifo: VecDeque::new(),
+            fifo_overrun: false,
+            packet_sent: false,
+            payload_ready: false,
+            crc_ok: false,
+        }
+    }
+
+    fn register(&self, register: Register) -> u8 {
+        self.registers[register.addr() as usize]
+    }
+
+    fn mode(&self) -> Option<Rfm69Mode> {
+        Rfm69Mode::from_op_mode(self.register(Register::OpMode))
+    }
+
+    fn packet_config1(&self) -> Option<PacketConfig1> {
+        PacketConfig1::from_bits(self.register(Register::PacketConfig1))
+    }
+
+    fn clear_fifo(&mut self) {
+        self.fifo.clear();
+        self.fifo_overrun = false;
+        self.payload_ready = false;
+        self.crc_ok = false;
+    }
+
+    fn irq_flags1(&self) -> u8 {
+        let mut flags = RF_IRQFLAGS1_MODEREADY;
+        match self.mode() {
+            Some(Rfm69Mode::Rx) => flags |= IRQFLAGS1_RXREADY,
+            Some(Rfm69Mode::Tx) => flags |= IRQFLAGS1_TXREADY,
+            _ => {}
+        }
+        // Stays set until the packet is read, like on the radio
+        if self.payload_ready {
+            flags |= IRQFLAGS1_SYNCADDRESSMATCH;
+        }
+        flags
+    }
+
+    fn irq_flags2(&self) -> u8 {
+        let mut flags = 0;
+        if !self.fifo.is_empty() {
+            flags |= RF_IRQFLAGS2_FIFONOTEMPTY;
+        }
+        if self.fifo_overrun {
+            flags |= RF_IRQFLAGS2_FIFOOVERRUN;
+        }
+        if self.packet_sent {
+            flags |= RF_IRQFLAGS2_PACKETSENT;
+        }
+        if self.payload_ready {
+            flags |= RF_IRQFLAGS2_PAYLOADREADY;
+        }
+        if self.crc_ok {
+            flags |= RF_IRQFLAGS2_CRCOK;
+        }
+        flags
+    }
+
+    // DIO0 with the mappings the driver uses, PacketSent in Tx and PayloadReady in Rx
+    fn dio0(&self) -> bool {
+        let payload_ready_mapped =
+            self.register(Register::DioMapping1) & 0xC0 == RF_DIOMAPPING1_DIO0_01;
+        match self.mode() {
+            Some(Rfm69Mode::Tx) => !payload_ready_mapped && self.packet_sent,
+            Some(Rfm69Mode::Rx) => payload_ready_mapped && self.payload_ready,
+            _ => false,
+        }
+    }
+
+    // Whether the address filter of the packet engine lets `frame` through
+    fn accepts(&self, frame: &[u8], config: PacketConfig1) -> bool {
+        let address_offset = config.variable_length as usize;
+        let Some(&address) = frame.get(address_offset) else {
+            return false;
+        };
+        let node = self.register(Register::NodeAddrs);
+        let broadcast = self.register(Register::BroadcastAddrs);
+        match config.address_filtering {
+            AddressFiltering::None => true,
+            AddressFiltering::Node => address == node,
+            AddressFiltering::NodeOrBroadcast => address == node || address == broadcast,
+        }
+    }
+
+    fn read(&mut self, addr: u8) -> u8 {
+        match addr {
+            addr if addr == Register::Fifo.addr() => {
+                let value = self.fifo.pop_front().unwrap_or(0);
+                // PayloadReady is cleared once the last byte is read
+                if self.fifo.is_empty() {
+                    self.payload_ready = false;
+                }
+                value
+            }
+            addr if addr == Register::IrqFlags1.addr() => self.irq_flags1(),
+            addr if addr == Register::IrqFlags2.addr() => self.irq_flags2(),
+            addr if addr == Register::RssiConfig.addr() => RF_RSSI_DONE,
+            addr => self.registers[addr as usize & 0x7F],
+        }
+    }
+}
+
+impl ChannelState {
+    fn read(&mut self, radio: usize, addr: u8) -> u8 {
+        match addr {
+            addr if addr == Register::RssiValue.addr() => (-self.rssi_dbm * 2) as u8,
+            addr => self.radios[radio].read(addr),
+        }
+    }
+
+    fn write(&mut self, radio: usize, addr: u8, value: u8) {
+        let state = &mut self.radios[radio];
+        match addr {
+            addr if addr == Register::Fifo.addr() => {
+                if state.fifo.len() < RF69_FIFO_SIZE {
+                    state.fifo.push_back(value);
+                } else {
+                    state.fifo_overrun = true;
+                }
+            }
+            addr if addr == Register::OpMode.addr() => {
+                let previous = state.mode();
+                state.registers[addr as usize] = value;
+                let mode = state.mode();
+                if previous == mode {
+                    return;
+                }
+                state.packet_sent = false;
+                // The driver fills the FIFO before entering Tx, even from Rx
+                if mode == Some(Rfm69Mode::Rx) {
+                    state.clear_fifo();
+                }
+                if mode == Some(Rfm69Mode::Tx) {
+                    self.transmit(radio);
+                }
+            }
+            addr if addr == Register::IrqFlags2.addr() => {
+                // Writing FifoOverrun clears the FIFO
+                if value & RF_IRQFLAGS2_FIFOOVERRUN != 0 {
+                    state.clear_fifo();
+                }
+            }
+            addr if addr == Register::PacketConfig2.addr() => {
+                let packet_config = PacketConfig2::from_bits(value);
+                if packet_config.restart_rx {
+                    state.clear_fifo();
+                }
+                state.registers[addr as usize] = PacketConfig2 {
+                    restart_rx: false,
+                    ..packet_config
+                }
+                .to_bits();
+            }
+            addr if addr == Register::Version.addr() => {}
+            addr => state.registers[addr as usize & 0x7F] = value,
+        }
+    }
+
+    // Sends the packet in the FIFO of `radio` to every other radio in Rx
+    fn transmit(&mut self, radio: usize) {
+        let sender = &mut self.radios[radio];
+        let Some(config) = sender.packet_config1() else {
+            return;
+        };
+        let length = match config.variable_length {
+            true => sender.fifo.front().map_or(0, |&length| length as usize + 1),
+            false => sender.register(Register::PayloadLength) as usize,
+        };
+        if length == 0 || sender.fifo.len() < length {
+            return;
+        }
+        let frame: Vec<u8> = sender.fifo.drain(..length).collect();
+        sender.packet_sent = true;
+        self.transmissions += 1;
+
+        for receiver in 0..self.radios.len() {
+            if receiver == radio || self.radios[receiver].mode() != Some(Rfm69Mode::Rx) {
+                continue;
+            }
+            if self.percent() < self.loss_percent {
+                continue;
+            }
+
+            let mut frame = frame.clone();
+            let corrupted = self.percent() < self.corruption_percent;
+            if corrupted {
+                // Spare the length byte, the packet engine would lose the framing
+                let first = config.variable_length as usize;
+                let index = first + self.random.next() as usize % (frame.len() - first).max(1);
+                if let Some(byte) = frame.get_mut(index) {
+                    *byte ^= 1 << (self.random.next() % 8);
+                }
+            }
+
+            let state = &mut self.radios[receiver];
+            let Some(receiver_config) = state.packet_config1() else {
+                continue;
+            };
+            // Longer variable length packets are dropped, shorter fixed length
+            // ones would be completed with noise and fail the CRC
+            let max_length = state.register(Register::PayloadLength) as usize;
+            let length = match receiver_config.variable_length {
+                true => frame[0] as usize + 1,
+                false => max_length,
+            };
+            if length > frame.len()
+                || length > max_length + receiver_config.variable_length as usize
+            {
+                continue;
+            }
+            frame.truncate(length);
+            if state.payload_ready
+                || !state.fifo.is_empty()
+                || !state.accepts(&frame, receiver_config)
+            {
+                continue;
+            }
+            // Without CrcAutoClearOff the radio drops corrupted packets on its own
+            if corrupted && receiver_config.crc_on && !receiver_config.crc_auto_clear_off {
+                continue;
+            }
+
+            state.fifo.extend(frame);
+            state.payload_ready = true;
+            state.crc_ok = !corrupted;
+            self.deliveries += 1;
+        }
+    }
+
+    fn percent(&mut self) -> u8 {
+        (self.random.next() % 100) as u8
+    }
+}
+
+impl SimChannel {
+    /// Creates an empty channel, `seed` picks the packets lost or corrupted.
+    pub fn new(seed: u32) -> Self {
+        SimChannel {
+            state: Rc::new(RefCell::new(ChannelState {
+                radios: Vec::new(),
+                random: Jitter::new(seed),
+                loss_percent: 0,
+                corruption_percent: 0,
+                rssi_dbm: -60,
+                transmissions: 0,
+                deliveries: 0,
+            })),
+        }
+    }
+
+    /// Adds a radio to the channel, to be set up with `init` like a real one.
+    pub fn radio(&self) -> SimRfm69 {
+        let mut state = self.state.borrow_mut();
+        let radio = state.radios.len();
+        state.radios.push(RadioState::new());
+
+        let handle = |radio| SimRadio {
+            channel: self.state.clone(),
+            radio,
+        };
+        Rfm69::new(
+            SimSpi(handle(radio)),
+            SimResetPin(handle(radio)),
+            SimDio0(handle(radio)),
+            SimDelay,
+        )
+    }
+
+    /// Chance of each receiver missing a packet, in percent.
+    pub fn set_loss_percent(&self, percent: u8) {
+        self.state.borrow_mut().loss_percent = percent.min(100);
+    }
+
+    /// Chance of a bit of a received packet being flipped, in percent. With the
+    /// radio CRC on and CrcAutoClearOff off, such packets are dropped.
+    pub fn set_corruption_percent(&self, percent: u8) {
+        self.state.borrow_mut().corruption_percent = percent.min(100);
+    }
+
+    /// Signal strength reported by every radio, -127 to 0 dBm.
+    pub fn set_rssi_dbm(&self, rssi_dbm: i16) {
+        self.state.borrow_mut().rssi_dbm = rssi_dbm.clamp(-127, 0);
+    }
+
+    /// Packets sent by all radios.
+    pub fn transmissions(&self) -> u32 {
+        self.state.borrow().transmissions
+    }
+
+    /// Packets that made it into the FIFO of a receiver.
+    pub fn deliveries(&self) -> u32 {
+        self.state.borrow().deliveries
+    }
+}
+
+// A radio on the channel, shared by its SPI, pins and delay
+struct SimRadio {
+    channel: Rc<RefCell<ChannelState>>,
+    radio: usize,
+}
+
+impl SimRadio {
+    fn dio0(&self) -> bool {
+        self.channel.borrow().radios[self.radio].dio0()
+    }
+}
+
+/// SPI of a simulated radio, with the address auto-increment of the real one
+/// except on the FIFO.
+pub struct SimSpi(SimRadio);
+
+impl ReadWrite for SimSpi {
+    type Error = Infallible;
+
+    fn write_many(&mut self, reg: Register, data: &[u8]) -> Result<(), Infallible> {
+        let mut channel = self.0.channel.borrow_mut();
+        for (offset, &value) in data.iter().enumerate() {
+            let addr = match reg {
+                Register::Fifo => reg.addr(),
+                _ => reg.addr().wrapping_add(offset as u8) & 0x7F,
+            };
+            channel.write(self.0.radio, addr, value);
+        }
+        Ok(())
+    }
+
+    fn read_many(&mut self, reg: Register, buffer: &mut [u8]) -> Result<(), Infallible> {
+        let mut channel = self.0.channel.borrow_mut();
+        for (offset, value) in buffer.iter_mut().enumerate() {
+            let addr = match reg {
+                Register::Fifo => reg.addr(),
+                _ => reg.addr().wrapping_add(offset as u8) & 0x7F,
+            };
+            *value = channel.read(self.0.radio, addr);
+        }
+        Ok(())
+    }
+}
+
+/// RESET pin of a simulated radio, a high level restores the power on state.
+pub struct SimResetPin(SimRadio);
+
+impl ErrorType for SimResetPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for SimResetPin {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.0.channel.borrow_mut().radios[self.0.radio] = RadioState::new();
+        Ok(())
+    }
+}
+
+/// DIO0 of a simulated radio, signalling PacketSent in Tx and PayloadReady in Rx.
+pub struct SimDio0(SimRadio);
+
+impl SimDio0 {
+    async fn wait_for(&mut self, high: bool) {
+        while self.0.dio0() != high {
+            embassy_futures::yield_now().await;
+        }
+    }
+}
+
+impl ErrorType for SimDio0 {
+    type Error = Infallible;
+}
+
+impl InputPin for SimDio0 {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.0.dio0())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.0.dio0())
+    }
+}
+
+impl Wait for SimDio0 {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_for(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_for(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(false).await;
+        self.wait_for(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(true).await;
+        self.wait_for(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        let level = self.0.dio0();
+        self.wait_for(!level).await;
+        Ok(())
+    }
+}
+
+/// Delay of a simulated radio. Time doesn't pass, it only lets the other
+/// radios on the channel run.
+pub struct SimDelay;
+
+impl DelayNs for SimDelay {
+    async fn delay_ns(&mut self, _ns: u32) {
+        embassy_futures::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ber::{BerReport, BER_PACKET_LENGTH};
+    use crate::header::Header;
+    use crate::rfm69::{Rfm69Error, CONFIG_SNAPSHOT_SIZE};
+    use embassy_futures::join::join;
+
+    #[tokio::test]
+    async fn test_send_receive() {
+        let channel = SimChannel::new(1);
+        let mut sender = channel.radio();
+        let mut receiver = channel.radio();
+        let mut listener = channel.radio();
+        sender.init().await.unwrap();
+        receiver.init().await.unwrap();
+        listener.init().await.unwrap();
+        receiver.set_node_address(Some(0x02)).unwrap();
+        listener.set_node_address(Some(0x03)).unwrap();
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+        listener.set_mode(Rfm69Mode::Rx).await.unwrap();
+
+        sender.send_to(0x02, &[0x01, 0x02, 0x03]).await.unwrap();
+
+        // Only the addressed radio takes the packet
+        assert!(receiver.is_message_available().unwrap());
+        assert!(!listener.is_message_available().unwrap());
+        let mut buffer = [0u8; 8];
+        let (header, length) = receiver.receive(&mut buffer).await.unwrap();
+        assert_eq!(header.to, 0x02);
+        assert_eq!(&buffer[..length], &[0x01, 0x02, 0x03]);
+        assert_eq!(receiver.packet_rssi(), Some(-60));
+        assert!(!receiver.is_message_available().unwrap());
+
+        // A radio outside of Rx misses the broadcast
+        receiver.set_mode(Rfm69Mode::Standby).await.unwrap();
+        sender
+            .send_with_header(Header::default(), &[0x04])
+            .await
+            .unwrap();
+        assert!(listener.is_message_available().unwrap());
+        assert_eq!(channel.transmissions(), 2);
+        assert_eq!(channel.deliveries(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_receive_raw() {
+        let channel = SimChannel::new(1);
+        let mut sender = channel.radio();
+        let mut receiver = channel.radio();
+        sender.init().await.unwrap();
+        receiver.init().await.unwrap();
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+
+        // Only the length byte goes in front of the payload
+        sender.send_raw(&[0x10, 0x20, 0x30]).await.unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(receiver.receive_raw(&mut buffer), Ok(3));
+        assert_eq!(&buffer[..3], &[0x10, 0x20, 0x30]);
+
+        // A RadioHead packet read raw starts with its header
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+        sender.send(&[0x40]).await.unwrap();
+        assert_eq!(receiver.receive_raw(&mut buffer), Ok(5));
+        assert_eq!(&buffer[..5], &[0xFF, 0xFF, 0x00, 0x00, 0x40]);
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let channel = SimChannel::new(1);
+        let mut sender = channel.radio();
+        let mut receiver = channel.radio();
+        sender.init().await.unwrap();
+        receiver.init().await.unwrap();
+        let key = *b"0123456789abcdef";
+        sender.set_authentication_key(Some(&key));
+        receiver.set_authentication_key(Some(&key));
+        assert_eq!(sender.max_payload_length(), 57);
+
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+        sender.send(&[0x01, 0x02]).await.unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            receiver.receive(&mut buffer).await,
+            Ok((Header::default(), 2))
+        );
+        assert_eq!(&buffer[..2], &[0x01, 0x02]);
+
+        // The CRC comes first, the MAC last
+        sender.set_software_crc(true);
+        receiver.set_software_crc(true);
+        assert_eq!(sender.max_payload_length(), 53);
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+        sender.send(&[0x03]).await.unwrap();
+        assert_eq!(
+            receiver.receive(&mut buffer).await,
+            Ok((Header::default(), 1))
+        );
+
+        sender.set_authentication_key(Some(b"fedcba9876543210"));
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+        sender.send(&[0x03]).await.unwrap();
+        assert_eq!(
+            receiver.receive(&mut buffer).await,
+            Err(Rfm69Error::AuthenticationFailure)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixed_length() {
+        let channel = SimChannel::new(1);
+        let mut sender = channel.radio();
+        let mut receiver = channel.radio();
+        sender.init().await.unwrap();
+        receiver.init().await.unwrap();
+        sender.set_node_address(Some(0x01)).unwrap();
+        receiver.set_node_address(Some(0x02)).unwrap();
+        sender.set_payload_length(Some(12)).unwrap();
+        receiver.set_payload_length(Some(12)).unwrap();
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+
+        // The address byte comes first without the length byte, and the payload is padded
+        sender.send_to(0x02, &[0x01, 0x02, 0x03]).await.unwrap();
+        let mut buffer = [0u8; 8];
+        let (header, length) = receiver.receive(&mut buffer).await.unwrap();
+        assert_eq!((header.to, header.from), (0x02, 0x01));
+        assert_eq!(&buffer[..length], &[0x01, 0x02, 0x03, 0, 0, 0, 0, 0]);
+
+        // A restored configuration sends fixed length packets as well
+        let mut snapshot = [0u8; CONFIG_SNAPSHOT_SIZE];
+        sender.save_config(&mut snapshot).unwrap();
+        let mut restored = channel.radio();
+        restored.init().await.unwrap();
+        restored.restore_config(&snapshot).unwrap();
+        assert_eq!(restored.payload_length(), Some(12));
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+        restored.send_to(0x02, &[0x04]).await.unwrap();
+        let (header, length) = receiver.receive(&mut buffer).await.unwrap();
+        assert_eq!(header.from, 0x01);
+        assert_eq!(buffer[..length][0], 0x04);
+    }
+
+    // One packet at a time, the simulated receiver only runs when the sender yields
+    async fn transmit_ber(sender: &mut SimRfm69, packets: u32) {
+        for _ in 0..packets {
+            sender.transmit_ber(1).await.unwrap();
+            embassy_futures::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ber() {
+        let channel = SimChannel::new(3);
+        let mut sender = channel.radio();
+        let mut receiver = channel.radio();
+        sender.init().await.unwrap();
+        receiver.init().await.unwrap();
+        channel.set_rssi_dbm(-85);
+
+        let (report, _) = join(receiver.receive_ber(10, 10), transmit_ber(&mut sender, 10)).await;
+        let report = report.unwrap();
+        assert_eq!(report.packets, 10);
+        assert_eq!(report.total.bits, 10 * 8 * BER_PACKET_LENGTH as u64);
+        assert_eq!(report.total.errors, 0);
+        assert_eq!(report.by_rssi[BerReport::rssi_band(-85)], report.total);
+
+        // Corrupted packets get through without the CRC
+        channel.set_corruption_percent(100);
+        let (report, _) = join(receiver.receive_ber(5, 10), transmit_ber(&mut sender, 5)).await;
+        let report = report.unwrap();
+        assert_eq!(report.packets, 5);
+        assert!(report.total.errors > 0);
+
+        // The packet configuration is back
+        let mut buffer = [0u8; 8];
+        channel.set_corruption_percent(0);
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+        sender.send(&[0x01]).await.unwrap();
+        assert_eq!(
+            receiver.receive(&mut buffer).await,
+            Ok((Header::default(), 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_loss_and_corruption() {
+        let channel = SimChannel::new(7);
+        let mut sender = channel.radio();
+        let mut receiver = channel.radio();
+        sender.init().await.unwrap();
+        receiver.init().await.unwrap();
+        receiver.set_mode(Rfm69Mode::Rx).await.unwrap();
+
+        channel.set_loss_percent(100);
+        sender.send(&[0x01]).await.unwrap();
+        assert!(!receiver.is_message_available().unwrap());
+
+        // The radio CRC drops corrupted packets
+        channel.set_loss_percent(0);
+        channel.set_corruption_percent(100);
+        sender.send(&[0x01]).await.unwrap();
+        assert!(!receiver.is_message_available().unwrap());
+
+        // With CrcAutoClearOff they reach the driver, which drops them
+        let packet_config = receiver
+            .raw()
+            .read_register(Register::PacketConfig1)
+            .unwrap();
+        receiver
+            .raw()
+            .write_register(Register::PacketConfig1, packet_config | 0x08)
+            .unwrap();
+        sender.send(&[0x01, 0x02]).await.unwrap();
+        assert!(receiver.is_message_available().unwrap());
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            receiver.receive(&mut buffer).await,
+            Err(Rfm69Error::CrcFailure)
+        );
+        assert_eq!(channel.deliveries(), 1);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::header::{Header, BROADCAST_ADDRESS};
+use crate::interrupt::{InterruptState, RadioEvent, ReceivedPacket};
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use crate::settings::RF69_FIFO_SIZE;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+use smoltcp::phy::{self, Device, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+
+/// IP MTU of `Rfm69Device`, the minimum IPv6 requires.
+pub const MTU: usize = 1280;
+
+// Each fragment starts with its index in the frame, the top bit marks the last one
+const FRAGMENT_LAST: u8 = 0x80;
+
+/// `smoltcp::phy::Device` for a point-to-point IP link to the node `peer`. Frames
+/// larger than a radio packet are split into fragments sent one after another,
+/// a frame missing a fragment is dropped.
+///
+/// `poll` drives the radio and must be called on the rising edge of DIO0 and
+/// after `Interface::poll`, the radio listens whenever it isn't sending.
+pub struct Rfm69Device<SPI, RESET, INTR, D> {
+    radio: Rfm69<SPI, RESET, INTR, D>,
+    peer: u8,
+    rx: RxBuffer,
+    tx: TxBuffer,
+}
+
+struct RxBuffer {
+    data: [u8; MTU],
+    length: usize,
+    id: u8,
+    next_index: Option<u8>,
+    ready: bool,
+}
+
+impl RxBuffer {
+    fn push(&mut self, packet: &ReceivedPacket) {
+        let Some((&fragment, data)) = packet.payload().split_first() else {
+            return;
+        };
+        // The previous frame wasn't taken yet
+        if self.ready {
+            return;
+        }
+
+        let index = fragment & !FRAGMENT_LAST;
+        if index == 0 {
+            self.id = packet.header.id;
+            self.length = 0;
+            self.next_index = Some(0);
+        }
+        if self.next_index != Some(index)
+            || packet.header.id != self.id
+            || self.length + data.len() > MTU
+        {
+            self.next_index = None;
+            return;
+        }
+
+        self.data[self.length..self.length + data.len()].copy_from_slice(data);
+        self.length += data.len();
+        self.next_index = Some((index + 1) & !FRAGMENT_LAST);
+        if fragment & FRAGMENT_LAST != 0 {
+            self.next_index = None;
+            self.ready = true;
+        }
+    }
+}
+
+struct TxBuffer {
+    data: [u8; MTU],
+    length: usize,
+    // Start of the next fragment, the buffer is free once the last one went to the FIFO
+    offset: usize,
+    id: u8,
+    index: u8,
+}
+
+impl TxBuffer {
+    fn is_free(&self) -> bool {
+        self.offset == self.length
+    }
+}
+
+impl<SPI, RESET, INTR, D> Rfm69Device<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    /// Wraps an initialized radio. Frames are addressed to `peer` and only its
+    /// packets are received.
+    pub fn new(radio: Rfm69<SPI, RESET, INTR, D>, peer: u8) -> Self {
+        Rfm69Device {
+            radio,
+            peer,
+            rx: RxBuffer {
+                data: [0; MTU],
+                length: 0,
+                id: 0,
+                next_index: None,
+                ready: false,
+            },
+            tx: TxBuffer {
+                data: [0; MTU],
+                length: 0,
+                offset: 0,
+                id: 0,
+                index: 0,
+            },
+        }
+    }
+
+    pub fn radio(&mut self) -> &mut Rfm69<SPI, RESET, INTR, D> {
+        &mut self.radio
+    }
+
+    pub fn release(self) -> Rfm69<SPI, RESET, INTR, D> {
+        self.radio
+    }
+
+    /// Collects a received fragment or starts sending the next one, then
+    /// listens. A frame the radio refuses to send, e.g. because the duty cycle
+    /// is exhausted, is dropped and the error returned.
+    pub fn poll(&mut self) -> Result<(), Rfm69Error> {
+        self.radio.on_interrupt()?;
+        if let Some(RadioEvent::PacketReceived(packet)) = self.radio.poll_event() {
+            if packet.header.from == self.peer {
+                self.rx.push(&packet);
+            }
+        }
+
+        if self.radio.interrupt_state() == InterruptState::Transmitting {
+            return Ok(());
+        }
+        if !self.tx.is_free() {
+            return self.transmit_fragment();
+        }
+        if self.radio.interrupt_state() != InterruptState::Receiving {
+            self.radio.start_receive()?;
+        }
+        Ok(())
+    }
+
+    fn transmit_fragment(&mut self) -> Result<(), Rfm69Error> {
+        let tx = &mut self.tx;
+        let chunk = self.radio.max_payload_length().saturating_sub(1);
+        let end = (tx.offset + chunk).min(tx.length);
+        let mut fragment = [0u8; RF69_FIFO_SIZE];
+        fragment[0] = match end == tx.length {
+            true => tx.index | FRAGMENT_LAST,
+            false => tx.index,
+        };
+        fragment[1..=end - tx.offset].copy_from_slice(&tx.data[tx.offset..end]);
+
+        let header = Header {
+            to: self.peer,
+            from: self.radio.node_address().unwrap_or(BROADCAST_ADDRESS),
+            id: tx.id,
+            flags: 0,
+        };
+        if let Err(error) = self
+            .radio
+            .start_transmit(header, &fragment[..=end - tx.offset])
+        {
+            tx.offset = tx.length;
+            return Err(error);
+        }
+        tx.offset = end;
+        tx.index = (tx.index + 1) & !FRAGMENT_LAST;
+        Ok(())
+    }
+}
+
+/// A frame reassembled by `Rfm69Device`.
+pub struct RxToken<'a> {
+    rx: &'a mut RxBuffer,
+}
+
+impl phy::RxToken for RxToken<'_> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let result = f(&self.rx.data[..self.rx.length]);
+        self.rx.ready = false;
+        result
+    }
+}
+
+/// Queues a frame for `Rfm69Device::poll` to send.
+pub struct TxToken<'a> {
+    tx: &'a mut TxBuffer,
+}
+
+impl phy::TxToken for TxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let result = f(&mut self.tx.data[..len]);
+        self.tx.length = len;
+        self.tx.offset = 0;
+        self.tx.id = self.tx.id.wrapping_add(1);
+        self.tx.index = 0;
+        result
+    }
+}
+
+impl<SPI, RESET, INTR, D> Device for Rfm69Device<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    type RxToken<'a>
+        = RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(RxToken<'_>, TxToken<'_>)> {
+        if !self.rx.ready || !self.tx.is_free() {
+            return None;
+        }
+        Some((RxToken { rx: &mut self.rx }, TxToken { tx: &mut self.tx }))
+    }
+
+    /// Only one frame is queued, the next can be sent once its last fragment is
+    /// in the FIFO.
+    fn transmit(&mut self, _timestamp: Instant) -> Option<TxToken<'_>> {
+        match self.tx.is_free() {
+            true => Some(TxToken { tx: &mut self.tx }),
+            false => None,
+        }
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ip;
+        capabilities.max_transmission_unit = MTU;
+        capabilities.max_burst_size = Some(1);
+        capabilities
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::{SimChannel, SimDelay, SimDio0, SimResetPin, SimSpi};
+    use smoltcp::phy::{RxToken as _, TxToken as _};
+
+    type SimDevice = Rfm69Device<SimSpi, SimResetPin, SimDio0, SimDelay>;
+
+    async fn device(channel: &SimChannel, address: u8, peer: u8) -> SimDevice {
+        let mut radio = channel.radio();
+        radio.init().await.unwrap();
+        radio.set_node_address(Some(address)).unwrap();
+        Rfm69Device::new(radio, peer)
+    }
+
+    #[tokio::test]
+    async fn test_fragmented_frame() {
+        let channel = SimChannel::new(1);
+        let mut first = device(&channel, 0x01, 0x02).await;
+        let mut second = device(&channel, 0x02, 0x01).await;
+        let mut stranger = device(&channel, 0x03, 0x02).await;
+        second.poll().unwrap();
+        stranger.poll().unwrap();
+
+        let frame: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let now = Instant::from_millis(0);
+        first
+            .transmit(now)
+            .unwrap()
+            .consume(frame.len(), |buffer| buffer.copy_from_slice(&frame));
+        assert!(first.transmit(now).is_none());
+
+        let mut received = None;
+        for _ in 0..20 {
+            first.poll().unwrap();
+            second.poll().unwrap();
+            if let Some((rx, _)) = second.receive(now) {
+                received = Some(rx.consume(|data| data.to_vec()));
+                break;
+            }
+        }
+        assert_eq!(received, Some(frame));
+        assert!(second.receive(now).is_none());
+        assert!(first.transmit(now).is_some());
+        // Each fragment carries 59 bytes of the frame
+        assert_eq!(channel.transmissions(), 4);
+
+        stranger.poll().unwrap();
+        assert!(stranger.receive(now).is_none());
+        assert_eq!(first.capabilities().max_transmission_unit, MTU);
+    }
+}
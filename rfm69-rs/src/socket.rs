@@ -0,0 +1,136 @@
+use crate::header::Datagram;
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// Connectionless datagram socket on top of `Rfm69`, following the UDP socket
+/// pattern of embedded-nal with node addresses in place of socket addresses:
+/// `bind` to the local node address, then `send_to` and `recv_from` any peer.
+///
+/// Datagrams are sent once and not acknowledged, see `reliable` for that.
+pub struct DatagramSocket<SPI, RESET, INTR, D> {
+    radio: Rfm69<SPI, RESET, INTR, D>,
+}
+
+impl<SPI, RESET, INTR, D> DatagramSocket<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    /// Wraps an initialized radio, the socket keeps the node address already set.
+    pub fn new(radio: Rfm69<SPI, RESET, INTR, D>) -> Self {
+        DatagramSocket { radio }
+    }
+
+    pub fn radio(&mut self) -> &mut Rfm69<SPI, RESET, INTR, D> {
+        &mut self.radio
+    }
+
+    pub fn release(self) -> Rfm69<SPI, RESET, INTR, D> {
+        self.radio
+    }
+
+    /// Binds the socket to the node `address`, the radio then drops datagrams
+    /// for other nodes in hardware.
+    pub fn bind(&mut self, address: u8) -> Result<(), Rfm69Error> {
+        self.radio.set_node_address(Some(address))
+    }
+
+    pub fn local_address(&self) -> Option<u8> {
+        self.radio.node_address()
+    }
+
+    /// Sends `data` to the node `remote`, or to every node if `remote` is the
+    /// broadcast address. The socket must be bound.
+    pub async fn send_to(&mut self, remote: u8, data: &[u8]) -> Result<(), Rfm69Error> {
+        if self.local_address().is_none() {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+        match remote == self.radio.broadcast_address() {
+            true => self.radio.broadcast(data).await,
+            false => self.radio.send_to(remote, data).await,
+        }
+    }
+
+    /// Waits for a datagram to this node or a broadcast, reads it into `buffer`
+    /// and returns its sender. The socket must be bound.
+    pub async fn recv_from(&mut self, buffer: &mut [u8]) -> Result<Datagram, Rfm69Error> {
+        if self.local_address().is_none() {
+            return Err(Rfm69Error::ConfigurationError);
+        }
+        loop {
+            self.radio.enter_rx().await?;
+            if self.radio.is_message_available()? {
+                match self.radio.receive_from(buffer).await {
+                    Err(Rfm69Error::NoMessage) => continue,
+                    result => return result,
+                }
+            }
+            self.radio.wait_for_interrupt().await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::header::BROADCAST_ADDRESS;
+    use crate::sim::SimChannel;
+    use embassy_futures::join::{join, join3};
+
+    #[tokio::test]
+    async fn test_send_recv() {
+        let channel = SimChannel::new(1);
+        let mut sockets = [channel.radio(), channel.radio(), channel.radio()];
+        for radio in sockets.iter_mut() {
+            radio.init().await.unwrap();
+        }
+        let [mut first, mut second, mut third] = sockets.map(DatagramSocket::new);
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            first.send_to(0x02, &[0x01]).await,
+            Err(Rfm69Error::ConfigurationError)
+        );
+        assert_eq!(
+            first.recv_from(&mut buffer).await,
+            Err(Rfm69Error::ConfigurationError)
+        );
+        first.bind(0x01).unwrap();
+        second.bind(0x02).unwrap();
+        third.bind(0x03).unwrap();
+        assert_eq!(first.local_address(), Some(0x01));
+
+        let (received, sent) = join(second.recv_from(&mut buffer), async {
+            first.send_to(0x02, &[0xAB, 0xCD]).await
+        })
+        .await;
+        sent.unwrap();
+        let datagram = received.unwrap();
+        assert_eq!(datagram.from, 0x01);
+        assert!(!datagram.broadcast);
+        assert_eq!(&buffer[..datagram.length], &[0xAB, 0xCD]);
+
+        let mut second_buffer = [0u8; 8];
+        let mut third_buffer = [0u8; 8];
+        let (second_received, third_received, sent) = join3(
+            second.recv_from(&mut second_buffer),
+            third.recv_from(&mut third_buffer),
+            first.send_to(BROADCAST_ADDRESS, &[0x42]),
+        )
+        .await;
+        sent.unwrap();
+        for (received, buffer) in [
+            (second_received, second_buffer),
+            (third_received, third_buffer),
+        ] {
+            let datagram = received.unwrap();
+            assert_eq!(datagram.from, 0x01);
+            assert!(datagram.broadcast);
+            assert_eq!(&buffer[..datagram.length], &[0x42]);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::rfm69::Rfm69Error;
+
+/// Counters of the traffic handled by the driver, see `Rfm69::stats`. They wrap
+/// around on overflow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub struct Stats {
+    pub packets_sent: u32,
+    pub packets_received: u32,
+    pub crc_failures: u32,
+    pub fifo_overruns: u32,
+    /// Timeouts reported with `Rfm69::report_timeout`, e.g. missing acknowledgements.
+    pub timeouts: u32,
+    /// Packets sent again by `ReliableDatagram` or `RfStream`.
+    pub retransmissions: u32,
+}
+
+impl Stats {
+    pub(crate) fn record_receive<T>(&mut self, result: &Result<T, Rfm69Error>) {
+        let counter = match result {
+            Ok(_) => &mut self.packets_received,
+            Err(Rfm69Error::CrcFailure) => &mut self.crc_failures,
+            Err(Rfm69Error::FifoOverrun) => &mut self.fifo_overruns,
+            Err(_) => return,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+
+    pub(crate) fn record_sent(&mut self) {
+        self.packets_sent = self.packets_sent.wrapping_add(1);
+    }
+
+    pub(crate) fn record_timeout(&mut self) {
+        self.timeouts = self.timeouts.wrapping_add(1);
+    }
+
+    pub(crate) fn record_retransmission(&mut self) {
+        self.retransmissions = self.retransmissions.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_receive() {
+        let mut stats = Stats::default();
+        stats.record_receive(&Ok(()));
+        stats.record_receive::<()>(&Err(Rfm69Error::CrcFailure));
+        stats.record_receive::<()>(&Err(Rfm69Error::FifoOverrun));
+        stats.record_receive::<()>(&Err(Rfm69Error::NoMessage));
+        stats.record_receive::<()>(&Err(Rfm69Error::CrcFailure));
+
+        assert_eq!(
+            stats,
+            Stats {
+                packets_received: 1,
+                crc_failures: 2,
+                fifo_overruns: 1,
+                ..Stats::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use crate::header::{Header, FLAGS_ACK, FLAGS_STREAM};
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error, Rfm69Mode};
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use heapless::Deque;
+
+/// Acknowledgement number and receive window in front of the data of a segment.
+const SEGMENT_OVERHEAD: usize = 2;
+
+/// Stream bytes carried by a single packet.
+pub const SEGMENT_LENGTH: usize = RF69_MAX_MESSAGE_LEN - SEGMENT_OVERHEAD;
+
+const DEFAULT_TIMEOUT_MS: u32 = 200;
+const DEFAULT_MAX_ATTEMPTS: u8 = 8;
+
+#[derive(Clone, Copy)]
+struct Segment {
+    data: [u8; SEGMENT_LENGTH],
+    length: usize,
+}
+
+impl Segment {
+    fn new(data: &[u8]) -> Self {
+        let mut segment = Segment {
+            data: [0; SEGMENT_LENGTH],
+            length: data.len(),
+        };
+        segment.data[..data.len()].copy_from_slice(data);
+        segment
+    }
+}
+
+/// Reliable, ordered byte stream to a single peer, e.g. to bridge a serial port.
+///
+/// Writes are cut into segments of up to `SEGMENT_LENGTH` bytes, numbered in the
+/// header id. Up to `N` segments are in flight, fewer if the peer has less room
+/// left in its receive queue. Every segment is acknowledged with the next
+/// expected number and the free room, and unacknowledged segments are sent again
+/// after the timeout. Both ends must use the same `N`, below 128.
+///
+/// The radio is only serviced while `read`, `write`, `flush` or `poll` run, so
+/// the peer's segments are acknowledged late if none of them is called.
+pub struct RfStream<SPI, RESET, INTR, D, const N: usize> {
+    radio: Rfm69<SPI, RESET, INTR, D>,
+    address: u8,
+    peer: u8,
+    timeout_ms: u32,
+    max_attempts: u8,
+    // Sent segments waiting for an acknowledgement, the first one is `tx_base`
+    tx: Deque<Segment, N>,
+    tx_base: u8,
+    peer_window: usize,
+    elapsed_ms: u32,
+    attempts: u8,
+    // Received segments not read yet, `rx_offset` bytes of the first one were read
+    rx: Deque<Segment, N>,
+    rx_offset: usize,
+    rx_next: u8,
+    // Window sent in the last acknowledgement
+    advertised_window: usize,
+}
+
+impl<SPI, RESET, INTR, D, const N: usize> RfStream<SPI, RESET, INTR, D, N>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    pub fn new(radio: Rfm69<SPI, RESET, INTR, D>, address: u8, peer: u8) -> Self {
+        const { assert!(N > 0 && N < 128) };
+        RfStream {
+            radio,
+            address,
+            peer,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            tx: Deque::new(),
+            tx_base: 0,
+            peer_window: N,
+            elapsed_ms: 0,
+            attempts: 0,
+            rx: Deque::new(),
+            rx_offset: 0,
+            rx_next: 0,
+            advertised_window: N,
+        }
+    }
+
+    pub fn radio(&mut self) -> &mut Rfm69<SPI, RESET, INTR, D> {
+        &mut self.radio
+    }
+
+    pub fn release(self) -> Rfm69<SPI, RESET, INTR, D> {
+        self.radio
+    }
+
+    /// Time to wait for an acknowledgement before sending the segments in flight again.
+    pub fn set_timeout_ms(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Transmissions of a segment without any answer from the peer before
+    /// `Rfm69Error::AckTimeout` is returned.
+    pub fn set_max_attempts(&mut self, max_attempts: u8) {
+        self.max_attempts = max_attempts;
+    }
+
+    /// Bytes received and not read yet.
+    pub fn available(&self) -> usize {
+        self.rx.iter().map(|segment| segment.length).sum::<usize>() - self.rx_offset
+    }
+
+    /// Handles one packet from the peer, or waits 1 ms if there is none, and
+    /// sends the segments in flight again once they timed out.
+    pub async fn poll(&mut self) -> Result<(), Rfm69Error> {
+        if !self.tx.is_empty() && self.elapsed_ms >= self.timeout_ms {
+            self.attempts += 1;
+            if self.attempts >= self.max_attempts {
+                self.radio.report_timeout().await?;
+                return Err(Rfm69Error::AckTimeout);
+            }
+            self.retransmit().await?;
+        }
+
+        self.radio.set_mode(Rfm69Mode::Rx).await?;
+        if !self.radio.is_message_available()? {
+            self.radio.delay.delay_ms(1).await;
+            self.elapsed_ms = self.elapsed_ms.saturating_add(1);
+            return Ok(());
+        }
+
+        let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+        let (header, length) = match self.radio.receive(&mut buffer).await {
+            Ok(received) => received,
+            Err(
+                Rfm69Error::CrcFailure
+                | Rfm69Error::FifoOverrun
+                | Rfm69Error::AuthenticationFailure,
+            ) => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        if !header.is_stream()
+            || header.from != self.peer
+            || header.to != self.address
+            || length < SEGMENT_OVERHEAD
+        {
+            return Ok(());
+        }
+
+        self.handle_ack(buffer[0], buffer[1] as usize);
+        if !header.is_ack() {
+            let data = &buffer[SEGMENT_OVERHEAD..length];
+            if header.id == self.rx_next && !self.rx.is_full() {
+                // Can't fail, the queue is not full
+                let _ = self.rx.push_back(Segment::new(data));
+                self.rx_next = self.rx_next.wrapping_add(1);
+            }
+            // Duplicates and segments out of order are acknowledged too, the peer
+            // sends them again
+            self.send_segment(0, FLAGS_ACK, &[]).await?;
+        }
+        Ok(())
+    }
+
+    fn handle_ack(&mut self, ack: u8, window: usize) {
+        // The peer is alive even if it had no room for the segment
+        self.attempts = 0;
+        self.peer_window = window.min(N);
+
+        let acknowledged = ack.wrapping_sub(self.tx_base) as usize;
+        if acknowledged > 0 && acknowledged <= self.tx.len() {
+            for _ in 0..acknowledged {
+                self.tx.pop_front();
+            }
+            self.tx_base = ack;
+            self.elapsed_ms = 0;
+        }
+    }
+
+    async fn retransmit(&mut self) -> Result<(), Rfm69Error> {
+        for index in 0..self.tx.len() {
+            let segment = self.tx.iter().nth(index).copied().unwrap();
+            let id = self.tx_base.wrapping_add(index as u8);
+            self.send_segment(id, 0, &segment.data[..segment.length])
+                .await?;
+            self.radio.record_retransmission();
+        }
+        self.elapsed_ms = 0;
+        Ok(())
+    }
+
+    // Sends a segment carrying the current acknowledgement number and window
+    async fn send_segment(&mut self, id: u8, flags: u8, data: &[u8]) -> Result<(), Rfm69Error> {
+        let header = Header {
+            to: self.peer,
+            from: self.address,
+            id,
+            flags: FLAGS_STREAM | flags,
+        };
+        self.advertised_window = N - self.rx.len();
+
+        let mut payload = [0u8; RF69_MAX_MESSAGE_LEN];
+        payload[0] = self.rx_next;
+        payload[1] = self.advertised_window as u8;
+        payload[SEGMENT_OVERHEAD..SEGMENT_OVERHEAD + data.len()].copy_from_slice(data);
+        self.radio
+            .send_with_header(header, &payload[..SEGMENT_OVERHEAD + data.len()])
+            .await
+    }
+}
+
+impl embedded_io_async::Error for Rfm69Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Rfm69Error::AckTimeout | Rfm69Error::Timeout => ErrorKind::TimedOut,
+            Rfm69Error::MessageTooLarge | Rfm69Error::BufferTooSmall => ErrorKind::InvalidInput,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl<SPI, RESET, INTR, D, const N: usize> ErrorType for RfStream<SPI, RESET, INTR, D, N> {
+    type Error = Rfm69Error;
+}
+
+impl<SPI, RESET, INTR, D, const N: usize> Read for RfStream<SPI, RESET, INTR, D, N>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    /// Waits for at least one byte from the peer.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.rx.is_empty() {
+            self.poll().await?;
+        }
+
+        let mut length = 0;
+        while let Some(segment) = self.rx.front() {
+            let data = &segment.data[self.rx_offset..segment.length];
+            let count = data.len().min(buf.len() - length);
+            buf[length..length + count].copy_from_slice(&data[..count]);
+            length += count;
+            self.rx_offset += count;
+            if self.rx_offset < segment.length {
+                break;
+            }
+            self.rx.pop_front();
+            self.rx_offset = 0;
+        }
+
+        // The peer stopped sending when the queue was full, tell it there is room again
+        if self.advertised_window == 0 && !self.rx.is_full() {
+            self.send_segment(0, FLAGS_ACK, &[]).await?;
+        }
+        Ok(length)
+    }
+}
+
+impl<SPI, RESET, INTR, D, const N: usize> Write for RfStream<SPI, RESET, INTR, D, N>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    /// Sends up to `SEGMENT_LENGTH` bytes once the peer has room for them,
+    /// without waiting for the acknowledgement.
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A single segment is always sent, its retransmissions probe a closed window
+        while self.tx.len() >= self.peer_window.max(1) {
+            self.poll().await?;
+        }
+
+        let segment = Segment::new(&buf[..buf.len().min(SEGMENT_LENGTH)]);
+        let id = self.tx_base.wrapping_add(self.tx.len() as u8);
+        if self.tx.is_empty() {
+            self.elapsed_ms = 0;
+        }
+        // Can't fail, at most N segments are in flight
+        let _ = self.tx.push_back(segment);
+        self.send_segment(id, 0, &segment.data[..segment.length])
+            .await?;
+        Ok(segment.length)
+    }
+
+    /// Waits until the peer acknowledged every segment.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        while !self.tx.is_empty() {
+            self.poll().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registers::Register;
+    use crate::sim::SimChannel;
+    use crate::test_utils::{
+        check_expectations, read_many, read_register, send, setup_rfm, write_register,
+        CheckedDelay, DigitalMock, GpioTransaction, SpiDevice, SpiTransaction, State,
+    };
+    use embassy_futures::select::select;
+
+    type Stream = RfStream<SpiDevice<u8>, DigitalMock, DigitalMock, CheckedDelay, 4>;
+
+    // A packet from the peer while in Rx
+    fn receive(packet: Vec<u8>) -> Vec<SpiTransaction<u8>> {
+        [
+            read_many(Register::IrqFlags1, &[0x00, 0x04]),
+            read_register(Register::IrqFlags2, 0x06),
+            read_register(Register::Fifo, packet.len() as u8),
+            read_many(Register::Fifo, &packet),
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn test_write() {
+        let spi_expectations = [
+            send(&[7, 0x02, 0x01, 0x00, 0x08, 0x00, 0x04, b'a']),
+            // Wait for the acknowledgement of segment 0
+            write_register(Register::OpMode, 0x10).to_vec(),
+            read_register(Register::IrqFlags1, 0x80).to_vec(),
+            receive(vec![0x01, 0x02, 0x00, 0x88, 0x01, 0x04]),
+        ]
+        .concat();
+        let mut stream: Stream = RfStream::new(setup_rfm(), 0x01, 0x02);
+        let radio = stream.radio();
+        radio.spi.update_expectations(&spi_expectations);
+        radio
+            .intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+
+        assert_eq!(stream.write(b"a").await, Ok(1));
+        stream.flush().await.unwrap();
+        assert!(stream.tx.is_empty());
+        assert_eq!(stream.tx_base, 1);
+
+        check_expectations(&mut stream.release());
+    }
+
+    #[tokio::test]
+    async fn test_read() {
+        let spi_expectations = [
+            write_register(Register::OpMode, 0x10).to_vec(),
+            read_register(Register::IrqFlags1, 0x80).to_vec(),
+            receive(vec![0x01, 0x02, 0x00, 0x08, 0x00, 0x04, b'a', b'b', b'c']),
+            // Acknowledge segment 0, 3 segments of room left
+            send(&[6, 0x02, 0x01, 0x00, 0x88, 0x01, 0x03]),
+        ]
+        .concat();
+        let mut stream: Stream = RfStream::new(setup_rfm(), 0x01, 0x02);
+        let radio = stream.radio();
+        radio.spi.update_expectations(&spi_expectations);
+        radio
+            .intr_pin
+            .update_expectations(&[GpioTransaction::wait_for_state(State::High)]);
+
+        let mut buffer = [0u8; 2];
+        assert_eq!(stream.read(&mut buffer).await, Ok(2));
+        assert_eq!(&buffer, b"ab");
+        assert_eq!(stream.available(), 1);
+        assert_eq!(stream.read(&mut buffer).await, Ok(1));
+        assert_eq!(buffer[0], b'c');
+
+        check_expectations(&mut stream.release());
+    }
+
+    #[tokio::test]
+    async fn test_lossy_channel() {
+        let channel = SimChannel::new(5);
+        let mut radio = channel.radio();
+        let mut peer_radio = channel.radio();
+        radio.init().await.unwrap();
+        peer_radio.init().await.unwrap();
+        let mut stream: RfStream<_, _, _, _, 4> = RfStream::new(radio, 0x01, 0x02);
+        let mut peer: RfStream<_, _, _, _, 4> = RfStream::new(peer_radio, 0x02, 0x01);
+        stream.set_timeout_ms(50);
+        peer.set_timeout_ms(50);
+        channel.set_loss_percent(20);
+
+        // Several segments, the last one partly filled
+        let data: Vec<u8> = (0..3 * SEGMENT_LENGTH + 10).map(|i| i as u8).collect();
+        let mut received = vec![0u8; data.len()];
+        let read = async {
+            peer.read_exact(&mut received).await.unwrap();
+            // Keep acknowledging until the writer is done
+            loop {
+                peer.poll().await.unwrap();
+            }
+        };
+        let write = async {
+            stream.write_all(&data).await.unwrap();
+            stream.flush().await.unwrap();
+        };
+        select(write, read).await;
+
+        assert_eq!(received, data);
+        assert!(stream.radio().stats().retransmissions > 0);
+    }
+}
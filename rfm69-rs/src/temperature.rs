@@ -0,0 +1,105 @@
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+// The sensor has a 1 degree resolution, stay above the threshold until the
+// temperature drops this far below it
+const HYSTERESIS_CELSIUS: f32 = 1.0;
+
+/// Crystal frequency error over temperature, used by `Rfm69::trim_frequency`.
+///
+/// The on-chip sensor is not calibrated, so `reference_celsius` and the
+/// coefficient should be measured with the same sensor reading.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub struct TemperatureCompensation {
+    /// Sensor reading at which the carrier is on frequency.
+    pub reference_celsius: f32,
+    /// Carrier frequency error per degree above the reference, in ppm.
+    pub ppm_per_celsius: f32,
+}
+
+impl TemperatureCompensation {
+    /// Correction to apply to the carrier at `celsius`, in ppm.
+    pub fn correction_ppm(&self, celsius: f32) -> f32 {
+        -(celsius - self.reference_celsius) * self.ppm_per_celsius
+    }
+}
+
+/// Reported by `TemperatureMonitor::next`.
+#[derive(Debug, Clone, Copy, PartialEq, defmt::Format)]
+pub enum TemperatureEvent {
+    Reading(f32),
+    /// The temperature rose to or above the threshold.
+    AboveThreshold(f32),
+    /// The temperature dropped below the threshold again.
+    BelowThreshold(f32),
+}
+
+/// Periodic temperature sampling, created by `Rfm69::monitor_temperature`.
+pub struct TemperatureMonitor<'a, SPI, RESET, INTR, D> {
+    radio: &'a mut Rfm69<SPI, RESET, INTR, D>,
+    interval_ms: u32,
+    threshold_celsius: f32,
+    above: Option<bool>,
+}
+
+impl<'a, SPI, RESET, INTR, D> TemperatureMonitor<'a, SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    pub(crate) fn new(
+        radio: &'a mut Rfm69<SPI, RESET, INTR, D>,
+        interval_ms: u32,
+        threshold_celsius: f32,
+    ) -> Self {
+        TemperatureMonitor {
+            radio,
+            interval_ms,
+            threshold_celsius,
+            above: None,
+        }
+    }
+
+    /// Waits for the next sample, the first one is taken right away. Reports a
+    /// threshold event when the temperature crossed the threshold since the
+    /// previous sample, and a plain reading otherwise.
+    pub async fn next(&mut self) -> Result<TemperatureEvent, Rfm69Error> {
+        if self.above.is_some() {
+            self.radio.delay.delay_ms(self.interval_ms).await;
+        }
+        let celsius = self.radio.read_temperature().await?;
+
+        let was_above = self.above.unwrap_or(false);
+        let above = match was_above {
+            false => celsius >= self.threshold_celsius,
+            true => celsius > self.threshold_celsius - HYSTERESIS_CELSIUS,
+        };
+        self.above = Some(above);
+
+        Ok(match (was_above, above) {
+            (false, true) => TemperatureEvent::AboveThreshold(celsius),
+            (true, false) => TemperatureEvent::BelowThreshold(celsius),
+            _ => TemperatureEvent::Reading(celsius),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_correction() {
+        let compensation = TemperatureCompensation {
+            reference_celsius: 25.0,
+            ppm_per_celsius: -2.0,
+        };
+        assert_eq!(compensation.correction_ppm(25.0), 0.0);
+        assert_eq!(compensation.correction_ppm(45.0), 40.0);
+        assert_eq!(compensation.correction_ppm(-5.0), -60.0);
+    }
+}
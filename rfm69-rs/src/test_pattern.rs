@@ -0,0 +1,100 @@
+/// Bit patterns that can be transmitted for RF measurements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum TestPattern {
+    /// Pseudo random PN9 sequence (x^9 + x^5 + 1), seeded with all ones.
+    Pn9,
+    /// Alternating 0/1 bits (0x55), producing a square wave modulation.
+    Alternating,
+}
+
+impl TestPattern {
+    pub fn generator(self) -> TestPatternGenerator {
+        match self {
+            Self::Pn9 => TestPatternGenerator::Pn9(Pn9::new()),
+            Self::Alternating => TestPatternGenerator::Alternating,
+        }
+    }
+}
+
+/// Endless byte source for a `TestPattern`.
+pub enum TestPatternGenerator {
+    Pn9(Pn9),
+    Alternating,
+}
+
+impl Iterator for TestPatternGenerator {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        match self {
+            Self::Pn9(pn9) => pn9.next(),
+            Self::Alternating => Some(0x55),
+        }
+    }
+}
+
+/// PN9 generator, producing the same sequence as the CCITT / TI data whitening LFSR.
+pub struct Pn9 {
+    state: u16,
+}
+
+impl Pn9 {
+    pub fn new() -> Self {
+        Pn9 { state: 0x1FF }
+    }
+}
+
+impl Default for Pn9 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iterator for Pn9 {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = (self.state & 0xFF) as u8;
+        for _ in 0..8 {
+            let bit = (self.state ^ (self.state >> 5)) & 0x01;
+            self.state = (self.state >> 1) | (bit << 8);
+        }
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pn9_sequence() {
+        let mut pn9 = Pn9::new();
+        let expected = [0xFF, 0xE1, 0x1D, 0x9A, 0xED, 0x85, 0x33, 0x24];
+
+        expected.iter().for_each(|byte| {
+            assert_eq!(pn9.next(), Some(*byte));
+        });
+    }
+
+    #[test]
+    fn test_pn9_period() {
+        // A maximal length 9 bit LFSR repeats after 511 bits, so the byte
+        // stream repeats after 511 bytes.
+        let first: [u8; 8] = core::array::from_fn({
+            let mut pn9 = Pn9::new();
+            move |_| pn9.next().unwrap()
+        });
+        let mut pn9 = Pn9::new().skip(511);
+        first.iter().for_each(|byte| {
+            assert_eq!(pn9.next(), Some(*byte));
+        });
+    }
+
+    #[test]
+    fn test_alternating() {
+        let mut generator = TestPattern::Alternating.generator();
+        assert_eq!(generator.next(), Some(0x55));
+        assert_eq!(generator.next(), Some(0x55));
+    }
+}
@@ -0,0 +1,102 @@
+use crate::registers::Register;
+use crate::rfm69::Rfm69;
+pub use embedded_hal_mock::eh1::delay::{CheckedDelay, Transaction as DelayTransaction};
+pub use embedded_hal_mock::eh1::digital::{
+    Mock as DigitalMock, State, Transaction as GpioTransaction,
+};
+pub use embedded_hal_mock::eh1::spi::{Mock as SpiDevice, Transaction as SpiTransaction};
+
+// Mocked radio for unit tests of code built on `Rfm69`, e.g.
+//
+//     let mut rfm = setup_rfm();
+//     rfm.spi.update_expectations(&write_register(Register::PacketConfig2, 0x02));
+//     ...
+//     check_expectations(&mut rfm);
+
+/// `Rfm69` on embedded-hal-mock devices.
+pub type MockRfm69 = Rfm69<SpiDevice<u8>, DigitalMock, DigitalMock, CheckedDelay>;
+
+/// A radio whose mocks expect nothing yet, add the expected transactions with
+/// `update_expectations`. The driver isn't initialized.
+pub fn setup_rfm() -> MockRfm69 {
+    Rfm69::new(
+        SpiDevice::new(&[]),
+        DigitalMock::new(&[]),
+        DigitalMock::new(&[]),
+        CheckedDelay::new(&[]),
+    )
+}
+
+/// Panics if an expected transaction is left on any of the mocks.
+pub fn check_expectations(rfm: &mut MockRfm69) {
+    rfm.reset_pin.done();
+    rfm.intr_pin.done();
+    rfm.delay.done();
+    rfm.spi.done();
+}
+
+/// SPI transactions of a single register write.
+pub fn write_register(register: Register, value: u8) -> [SpiTransaction<u8>; 4] {
+    write_many(register, &[value])
+}
+
+/// SPI transactions of a single register read, returning `value`.
+pub fn read_register(register: Register, value: u8) -> [SpiTransaction<u8>; 4] {
+    read_many(register, &[value])
+}
+
+/// SPI transactions of a burst write starting at `register`.
+pub fn write_many(register: Register, data: &[u8]) -> [SpiTransaction<u8>; 4] {
+    [
+        SpiTransaction::transaction_start(),
+        SpiTransaction::write(register.write()),
+        SpiTransaction::write_vec(data.to_vec()),
+        SpiTransaction::transaction_end(),
+    ]
+}
+
+/// SPI transactions of a burst read starting at `register`, returning `data`.
+pub fn read_many(register: Register, data: &[u8]) -> [SpiTransaction<u8>; 4] {
+    [
+        SpiTransaction::transaction_start(),
+        SpiTransaction::write(register.read()),
+        SpiTransaction::transfer_in_place(vec![0x00; data.len()], data.to_vec()),
+        SpiTransaction::transaction_end(),
+    ]
+}
+
+/// SPI transactions of `Rfm69::send` from Standby or Rx, writing `fifo`,
+/// the length byte followed by the header and the payload.
+pub fn send(fifo: &[u8]) -> Vec<SpiTransaction<u8>> {
+    [
+        write_many(Register::Fifo, fifo),
+        write_register(Register::DioMapping1, 0x00),
+        write_register(Register::OpMode, 0x0C),
+        read_register(Register::IrqFlags1, 0x80),
+        read_register(Register::IrqFlags2, 0x08),
+        write_register(Register::OpMode, 0x04),
+        read_register(Register::IrqFlags1, 0x80),
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_harness() {
+        let mut rfm = setup_rfm();
+        rfm.spi.update_expectations(
+            &[
+                read_register(Register::PacketConfig2, 0x03),
+                write_register(Register::PacketConfig2, 0x02),
+            ]
+            .concat(),
+        );
+
+        rfm.set_encryption_key(None).unwrap();
+
+        check_expectations(&mut rfm);
+    }
+}
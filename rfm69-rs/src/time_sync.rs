@@ -0,0 +1,226 @@
+use crate::header::{Header, BROADCAST_ADDRESS, FLAGS_BEACON, FLAGS_BROADCAST};
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error};
+use core::convert::Infallible;
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+/// Payload length of a beacon, the reference time in microseconds.
+pub const BEACON_LENGTH: usize = 8;
+
+// Weight of a new drift estimate, 1/2^DRIFT_AVERAGE_SHIFT
+const DRIFT_AVERAGE_SHIFT: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+struct Sample {
+    local_us: u64,
+    reference_us: u64,
+}
+
+/// Clock synchronized to the beacons of a reference node.
+///
+/// Every beacon carries the reference time at the moment it is received, so the
+/// offset to the reference is known right after a beacon. The drift between two
+/// beacons corrects the time in between, so beacons can be minutes apart.
+///
+/// The synchronizer has no time source of its own, `clock` must return a
+/// monotonic time in microseconds.
+pub struct TimeSync {
+    clock: fn() -> u64,
+    last: Option<Sample>,
+    // Reference clock rate relative to the local clock, in parts per billion
+    drift_ppb: i64,
+}
+
+impl TimeSync {
+    pub fn new(clock: fn() -> u64) -> Self {
+        TimeSync {
+            clock,
+            last: None,
+            drift_ppb: 0,
+        }
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.last.is_some()
+    }
+
+    /// The reference time in microseconds, or `None` before the first beacon.
+    pub fn synchronized_now(&self) -> Option<u64> {
+        let last = self.last?;
+        let elapsed = (self.clock)().saturating_sub(last.local_us);
+        let correction = elapsed as i128 * self.drift_ppb as i128 / 1_000_000_000;
+        let now = last.reference_us as i128 + elapsed as i128 + correction;
+        Some(now.max(0) as u64)
+    }
+
+    /// Reference time minus local time at the last beacon, in microseconds.
+    pub fn offset_us(&self) -> Option<i64> {
+        self.last
+            .map(|last| last.reference_us.wrapping_sub(last.local_us) as i64)
+    }
+
+    /// How much faster the reference clock runs than the local one, in parts per billion.
+    pub fn drift_ppb(&self) -> i64 {
+        self.drift_ppb
+    }
+
+    /// Forgets the reference, e.g. after switching to another network.
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.drift_ppb = 0;
+    }
+
+    /// The time sent in beacons: the synchronized time, so beacons can be relayed,
+    /// or the local time on the reference node.
+    pub fn beacon_time_us(&self) -> u64 {
+        self.synchronized_now().unwrap_or_else(|| (self.clock)())
+    }
+
+    /// Accounts a beacon with the reference time `reference_us`, received at the
+    /// local time `local_us`.
+    pub fn record_beacon(&mut self, reference_us: u64, local_us: u64) {
+        let sample = Sample {
+            local_us,
+            reference_us,
+        };
+
+        match self.last {
+            Some(last) if local_us > last.local_us && reference_us > last.reference_us => {
+                let local_elapsed = (local_us - last.local_us) as i128;
+                let reference_elapsed = (reference_us - last.reference_us) as i128;
+                let drift =
+                    ((reference_elapsed - local_elapsed) * 1_000_000_000 / local_elapsed) as i64;
+                self.drift_ppb = match self.drift_ppb {
+                    0 => drift,
+                    average => average + ((drift - average) >> DRIFT_AVERAGE_SHIFT),
+                };
+            }
+            // First beacon, or the reference restarted
+            _ => self.drift_ppb = 0,
+        }
+
+        self.last = Some(sample);
+    }
+}
+
+/// Broadcasts a beacon with the time of `sync` at the moment the receivers get it.
+pub async fn send_beacon<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    sync: &TimeSync,
+) -> Result<(), Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let header = Header {
+        to: radio.broadcast_address(),
+        from: radio.node_address().unwrap_or(BROADCAST_ADDRESS),
+        id: 0,
+        flags: FLAGS_BROADCAST | FLAGS_BEACON,
+    };
+    // The receivers timestamp the beacon at PayloadReady
+    let time_us = sync.beacon_time_us() + radio.airtime_us(BEACON_LENGTH) as u64;
+    radio.send_with_header(header, &time_us.to_be_bytes()).await
+}
+
+/// Sends a beacon every `period_ms`, only returns on an error.
+pub async fn beacon_loop<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    sync: &TimeSync,
+    period_ms: u32,
+) -> Result<Infallible, Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    loop {
+        send_beacon(radio, sync).await?;
+        radio.delay.delay_ms(period_ms).await;
+    }
+}
+
+/// Reads the received packet and synchronizes `sync` to it if it is a beacon,
+/// returning the address of the sender. Call it as soon as
+/// `Rfm69::is_message_available` returns true.
+///
+/// Returns `Rfm69Error::NoMessage` if the packet was not a beacon.
+pub async fn receive_beacon<SPI, RESET, INTR, D>(
+    radio: &mut Rfm69<SPI, RESET, INTR, D>,
+    sync: &mut TimeSync,
+) -> Result<u8, Rfm69Error>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    let local_us = (sync.clock)();
+    let mut buffer = [0u8; BEACON_LENGTH];
+    let (header, length) = match radio.receive(&mut buffer).await {
+        Err(Rfm69Error::BufferTooSmall) => return Err(Rfm69Error::NoMessage),
+        result => result?,
+    };
+    if !header.is_beacon() || length != BEACON_LENGTH {
+        return Err(Rfm69Error::NoMessage);
+    }
+
+    sync.record_beacon(u64::from_be_bytes(buffer), local_us);
+    Ok(header.from)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_offset() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        fn clock() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+        let mut sync = TimeSync::new(clock);
+        assert_eq!(sync.synchronized_now(), None);
+        assert_eq!(sync.beacon_time_us(), 0);
+
+        NOW.store(1_000, Ordering::Relaxed);
+        sync.record_beacon(5_000_000, 1_000);
+        assert!(sync.is_synchronized());
+        assert_eq!(sync.offset_us(), Some(4_999_000));
+        assert_eq!(sync.drift_ppb(), 0);
+
+        NOW.store(3_000, Ordering::Relaxed);
+        assert_eq!(sync.synchronized_now(), Some(5_002_000));
+        assert_eq!(sync.beacon_time_us(), 5_002_000);
+
+        sync.reset();
+        assert_eq!(sync.synchronized_now(), None);
+    }
+
+    #[test]
+    fn test_drift() {
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        fn clock() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+        let mut sync = TimeSync::new(clock);
+
+        // The reference runs 100 ppm fast
+        sync.record_beacon(1_000_000, 0);
+        sync.record_beacon(11_001_000, 10_000_000);
+        assert_eq!(sync.drift_ppb(), 100_000);
+
+        NOW.store(20_000_000, Ordering::Relaxed);
+        assert_eq!(sync.synchronized_now(), Some(21_002_000));
+
+        // The reference restarted
+        sync.record_beacon(1_000, 30_000_000);
+        assert_eq!(sync.drift_ppb(), 0);
+        assert_eq!(sync.offset_us(), Some(-29_999_000));
+    }
+}
@@ -0,0 +1,191 @@
+use crate::read_write::ReadWrite;
+use crate::rfm69::{Rfm69, Rfm69Error, Rfm69Mode};
+use embedded_hal::{digital::InputPin, digital::OutputPin};
+use embedded_hal_async::{delay::DelayNs, digital::Wait};
+
+#[cfg(any(test, feature = "std"))]
+use crate::settings::RF69_MAX_MESSAGE_LEN;
+#[cfg(any(test, feature = "std"))]
+use std::collections::VecDeque;
+
+/// The operations application logic needs from a packet radio, implemented by
+/// `Rfm69` and by `MockRadio`, so that logic can be unit tested on the host.
+#[allow(async_fn_in_trait)]
+pub trait Radio {
+    /// Sends `data` and returns once it went out, leaving the radio in Standby.
+    async fn send(&mut self, data: &[u8]) -> Result<(), Rfm69Error>;
+
+    /// Reads the received packet into `buffer` and returns its length,
+    /// `Rfm69Error::NoMessage` if there is none.
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Rfm69Error>;
+
+    async fn set_mode(&mut self, mode: Rfm69Mode) -> Result<(), Rfm69Error>;
+
+    /// Current RSSI in dBm.
+    fn rssi(&mut self) -> Result<i16, Rfm69Error>;
+}
+
+impl<SPI, RESET, INTR, D> Radio for Rfm69<SPI, RESET, INTR, D>
+where
+    SPI: ReadWrite,
+    RESET: OutputPin,
+    INTR: InputPin + Wait,
+    D: DelayNs,
+{
+    async fn send(&mut self, data: &[u8]) -> Result<(), Rfm69Error> {
+        Rfm69::send(self, data).await
+    }
+
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Rfm69Error> {
+        let (_, length) = Rfm69::receive(self, buffer).await?;
+        Ok(length)
+    }
+
+    async fn set_mode(&mut self, mode: Rfm69Mode) -> Result<(), Rfm69Error> {
+        Rfm69::set_mode(self, mode).await
+    }
+
+    fn rssi(&mut self) -> Result<i16, Rfm69Error> {
+        Ok(-(Rfm69::rssi(self)? as i16))
+    }
+}
+
+/// `Radio` without hardware: `receive` returns the packets and errors queued
+/// with `push_received` and `push_receive_error`, `send` records the packets.
+#[cfg(any(test, feature = "std"))]
+pub struct MockRadio {
+    mode: Rfm69Mode,
+    rssi_dbm: i16,
+    received: VecDeque<Result<Vec<u8>, Rfm69Error>>,
+    sent: Vec<Vec<u8>>,
+}
+
+#[cfg(any(test, feature = "std"))]
+impl Default for MockRadio {
+    fn default() -> Self {
+        MockRadio {
+            mode: Rfm69Mode::Standby,
+            rssi_dbm: -60,
+            received: VecDeque::new(),
+            sent: Vec::new(),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl MockRadio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a packet for `receive`.
+    pub fn push_received(&mut self, data: &[u8]) {
+        self.received.push_back(Ok(data.to_vec()));
+    }
+
+    /// Queues an error for `receive`, e.g. `Rfm69Error::CrcFailure`.
+    pub fn push_receive_error(&mut self, error: Rfm69Error) {
+        self.received.push_back(Err(error));
+    }
+
+    /// Packets sent so far, oldest first.
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+
+    pub fn take_sent(&mut self) -> Vec<Vec<u8>> {
+        core::mem::take(&mut self.sent)
+    }
+
+    pub fn mode(&self) -> Rfm69Mode {
+        self.mode
+    }
+
+    pub fn set_rssi_dbm(&mut self, rssi_dbm: i16) {
+        self.rssi_dbm = rssi_dbm;
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl Radio for MockRadio {
+    async fn send(&mut self, data: &[u8]) -> Result<(), Rfm69Error> {
+        if data.len() > RF69_MAX_MESSAGE_LEN {
+            return Err(Rfm69Error::MessageTooLarge);
+        }
+        self.sent.push(data.to_vec());
+        self.mode = Rfm69Mode::Standby;
+        Ok(())
+    }
+
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, Rfm69Error> {
+        let data = self.received.pop_front().ok_or(Rfm69Error::NoMessage)??;
+        if buffer.len() < data.len() {
+            return Err(Rfm69Error::BufferTooSmall);
+        }
+        buffer[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    async fn set_mode(&mut self, mode: Rfm69Mode) -> Result<(), Rfm69Error> {
+        self.mode = mode;
+        Ok(())
+    }
+
+    fn rssi(&mut self) -> Result<i16, Rfm69Error> {
+        Ok(self.rssi_dbm)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sim::SimChannel;
+
+    // Application logic under test: answers every packet with its bytes reversed
+    async fn echo_reversed(radio: &mut impl Radio) -> Result<(), Rfm69Error> {
+        radio.set_mode(Rfm69Mode::Rx).await?;
+        let mut buffer = [0u8; RF69_MAX_MESSAGE_LEN];
+        let length = radio.receive(&mut buffer).await?;
+        buffer[..length].reverse();
+        radio.send(&buffer[..length]).await
+    }
+
+    #[tokio::test]
+    async fn test_mock_radio() {
+        let mut radio = MockRadio::new();
+        radio.push_received(&[0x01, 0x02, 0x03]);
+        radio.push_receive_error(Rfm69Error::CrcFailure);
+
+        echo_reversed(&mut radio).await.unwrap();
+        assert_eq!(radio.sent(), &[vec![0x03, 0x02, 0x01]]);
+        assert_eq!(radio.mode(), Rfm69Mode::Standby);
+
+        assert_eq!(echo_reversed(&mut radio).await, Err(Rfm69Error::CrcFailure));
+        assert_eq!(echo_reversed(&mut radio).await, Err(Rfm69Error::NoMessage));
+        assert_eq!(radio.mode(), Rfm69Mode::Rx);
+
+        radio.set_rssi_dbm(-90);
+        assert_eq!(Radio::rssi(&mut radio), Ok(-90));
+        assert_eq!(radio.take_sent().len(), 1);
+        assert!(radio.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rfm69_radio() {
+        let channel = SimChannel::new(1);
+        let mut client = channel.radio();
+        let mut server = channel.radio();
+        client.init().await.unwrap();
+        server.init().await.unwrap();
+        server.set_mode(Rfm69Mode::Rx).await.unwrap();
+
+        Radio::send(&mut client, &[0x01, 0x02]).await.unwrap();
+        client.set_mode(Rfm69Mode::Rx).await.unwrap();
+        echo_reversed(&mut server).await.unwrap();
+
+        let mut buffer = [0u8; 4];
+        let length = Radio::receive(&mut client, &mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], &[0x02, 0x01]);
+        assert_eq!(Radio::rssi(&mut server), Ok(-60));
+    }
+}
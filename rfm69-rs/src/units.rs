@@ -0,0 +1,72 @@
+/// A carrier frequency, stored in Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Frequency(u32);
+
+impl Frequency {
+    pub const fn from_hz(hz: u32) -> Self {
+        Frequency(hz)
+    }
+
+    pub const fn from_khz(khz: u32) -> Self {
+        Frequency(khz * 1_000)
+    }
+
+    pub const fn from_mhz(mhz: u32) -> Self {
+        Frequency(mhz * 1_000_000)
+    }
+
+    pub const fn hz(self) -> u32 {
+        self.0
+    }
+}
+
+/// `868.MHz()` and other fugit rates, e.g. from a board support crate.
+#[cfg(feature = "fugit")]
+impl<const NOM: u32, const DENOM: u32> From<fugit::Rate<u32, NOM, DENOM>> for Frequency {
+    fn from(rate: fugit::Rate<u32, NOM, DENOM>) -> Self {
+        Frequency(rate.to_Hz())
+    }
+}
+
+/// An output power in dBm. The range the radio accepts depends on the module
+/// variant, see `Rfm69::tx_power_range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TxPowerDbm(i8);
+
+impl TxPowerDbm {
+    pub const fn new(dbm: i8) -> Self {
+        TxPowerDbm(dbm)
+    }
+
+    pub const fn dbm(self) -> i8 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frequency() {
+        assert_eq!(Frequency::from_mhz(868), Frequency::from_khz(868_000));
+        assert_eq!(Frequency::from_khz(433_920).hz(), 433_920_000);
+        assert!(Frequency::from_mhz(915) > Frequency::from_mhz(868));
+        assert_eq!(TxPowerDbm::new(-2).dbm(), -2);
+    }
+
+    #[cfg(feature = "fugit")]
+    #[test]
+    fn test_fugit() {
+        assert_eq!(
+            Frequency::from(fugit::HertzU32::MHz(868)),
+            Frequency::from_mhz(868)
+        );
+        assert_eq!(
+            Frequency::from(fugit::KilohertzU32::kHz(433_920)),
+            Frequency::from_khz(433_920)
+        );
+    }
+}
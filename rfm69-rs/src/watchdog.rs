@@ -0,0 +1,100 @@
+/// Symptom of a radio that stopped responding, e.g. after a brown-out or ESD event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum StuckCondition {
+    /// ModeReady wasn't set within `mode_ready_timeout_ms` of a mode change.
+    #[cfg_attr(feature = "std", error("ModeReady timed out"))]
+    ModeReady,
+    /// PayloadReady was still set after the packet was read from the FIFO.
+    #[cfg_attr(feature = "std", error("PayloadReady still set after reading"))]
+    PayloadReady,
+    /// `max_timeouts` timeouts were reported in a row without a packet received.
+    #[cfg_attr(feature = "std", error("too many timeouts in a row"))]
+    Timeouts,
+}
+
+/// Called after the radio was reset and reconfigured. Settings the driver doesn't
+/// keep, such as the AES key, have to be set again from here.
+pub type RecoveryHook = fn(StuckCondition);
+
+/// Supervisor recovering a stuck radio with a hardware reset, see `Rfm69::set_watchdog`.
+#[derive(Debug, Clone, Copy)]
+pub struct Watchdog {
+    mode_ready_timeout_ms: u32,
+    max_timeouts: u8,
+    on_recovery: Option<RecoveryHook>,
+    timeouts: u8,
+    recoveries: u32,
+}
+
+impl Watchdog {
+    pub fn new(
+        mode_ready_timeout_ms: u32,
+        max_timeouts: u8,
+        on_recovery: Option<RecoveryHook>,
+    ) -> Self {
+        Watchdog {
+            mode_ready_timeout_ms,
+            max_timeouts: max_timeouts.max(1),
+            on_recovery,
+            timeouts: 0,
+            recoveries: 0,
+        }
+    }
+
+    pub fn mode_ready_timeout_ms(&self) -> u32 {
+        self.mode_ready_timeout_ms
+    }
+
+    /// Number of times the radio was recovered.
+    pub fn recoveries(&self) -> u32 {
+        self.recoveries
+    }
+
+    /// Counts a timeout, returns true once `max_timeouts` are reached in a row.
+    pub(crate) fn record_timeout(&mut self) -> bool {
+        self.timeouts += 1;
+        if self.timeouts < self.max_timeouts {
+            return false;
+        }
+        self.timeouts = 0;
+        true
+    }
+
+    pub(crate) fn clear_timeouts(&mut self) {
+        self.timeouts = 0;
+    }
+
+    pub(crate) fn record_recovery(&mut self) -> Option<RecoveryHook> {
+        self.timeouts = 0;
+        self.recoveries += 1;
+        self.on_recovery
+    }
+}
+
+impl Default for Watchdog {
+    /// ModeReady normally takes well under 10 ms, even from Sleep.
+    fn default() -> Self {
+        Watchdog::new(100, 5, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_timeout() {
+        let mut watchdog = Watchdog::new(100, 3, None);
+        assert!(!watchdog.record_timeout());
+        assert!(!watchdog.record_timeout());
+        watchdog.clear_timeouts();
+        assert!(!watchdog.record_timeout());
+        assert!(!watchdog.record_timeout());
+        assert!(watchdog.record_timeout());
+        assert!(!watchdog.record_timeout());
+
+        assert!(watchdog.record_recovery().is_none());
+        assert_eq!(watchdog.recoveries(), 1);
+    }
+}